@@ -0,0 +1,110 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ethers::abi::{Abi, Event, EventParam, ParamType, RawLog};
+use ethers::types::{Bytes, Log, H160, H256, U256, U64};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use tasks::decoders::DecoderRegistry;
+use tasks::metrics::Metrics;
+use tasks::log_processing::{build_event_map, decode_log, parse_decoded_log, process_log, process_log_sync};
+
+
+// The Swap log used by `test_process_log` in `log_processing.rs`.
+fn swap_log() -> Log {
+    Log {
+        address: H160::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap(),
+        topics: vec![
+            H256::from_str("0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67").unwrap(),
+            H256::from_str("0x000000000000000000000000d7f3fbe8c72a961a5515203eada59750437fa762").unwrap(),
+            H256::from_str("0x0000000000000000000000001c09a10047fcc944efde9226e259eddfde2c1cf0").unwrap()
+        ],
+        data: Bytes::from(hex::decode("0000000000000000000000000000000000000000000000000000000d92cae287fffffffffffffffffffffffffffffffffffffffffffffffdfe6d04e32064349f0000000000000000000000000000000000006270c87ad64fc69a7baa1492b4f20000000000000000000000000000000000000000000000017c7599806e23275900000000000000000000000000000000000000000000000000000000000317ce").unwrap()),
+        block_hash: Some(H256::from_str("0x1a65b8bb49fe739ae92ed688ab765cafe4dbcdd2b6c442e48a682ce2c0e451ee").unwrap()),
+        block_number: Some(U64::from(18326572)),
+        transaction_hash: Some(H256::from_str("0x13f84c56285e67f705bca6cb865610deda492752c0face651e0b3cb7893500f3").unwrap()),
+        transaction_index: Some(U64::from(7)),
+        log_index: Some(U256::from(49)),
+        transaction_log_index: None,
+        log_type: None,
+        removed: Some(false),
+    }
+}
+
+fn load_abi() -> Abi {
+    let abi_json = std::fs::read_to_string("src/abi.json").unwrap();
+    serde_json::from_str(&abi_json).unwrap()
+}
+
+// The ABI with `extra` more events, like the ABI of a large contract or of several merged.
+fn large_abi(extra: usize) -> Abi {
+    let mut abi = load_abi();
+    for i in 0..extra {
+        let name = format!("Event{}", i);
+        let event = Event {
+            name: name.clone(),
+            inputs: vec![EventParam { name: "value".to_string(), kind: ParamType::Uint(256), indexed: false }],
+            anonymous: false,
+        };
+        abi.events.insert(name, vec![event]);
+    }
+    abi
+}
+
+fn bench_process_log(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let log = swap_log();
+    let decoders = DecoderRegistry::default();
+    let metrics = Metrics::default();
+
+    // The event map built from every event in the ABI, as the stream loop does
+    let full_map = build_event_map(&load_abi()).unwrap();
+    // The same map reduced to the Swap event, and grown to a thousand events. The log's event
+    // is looked up by topic0, so all three should take the same time.
+    let swap_only_map: HashMap<_, _> = full_map
+        .iter()
+        .filter(|(_, (event_name, _))| event_name == "Swap")
+        .map(|(hash, entry)| (*hash, entry.clone()))
+        .collect();
+    let large_map = build_event_map(&large_abi(1_000)).unwrap();
+
+    let mut group = c.benchmark_group("process_log");
+    group.bench_function("swap_only_map", |b| {
+        b.to_async(&runtime).iter(|| process_log(log.clone(), &swap_only_map, &decoders, &metrics))
+    });
+    group.bench_function("full_abi_map", |b| {
+        b.to_async(&runtime).iter(|| process_log(log.clone(), &full_map, &decoders, &metrics))
+    });
+    group.bench_function("large_abi_map", |b| {
+        b.to_async(&runtime).iter(|| process_log(log.clone(), &large_map, &decoders, &metrics))
+    });
+    // The decode workers' path, without the runtime
+    group.bench_function("sync_full_abi_map", |b| {
+        b.iter(|| process_log_sync(log.clone(), &full_map, &decoders, &metrics))
+    });
+    group.finish();
+}
+
+fn bench_decode_log(c: &mut Criterion) {
+    let log = swap_log();
+    let event_map = build_event_map(&load_abi()).unwrap();
+
+    c.bench_function("decode_log", |b| {
+        b.iter(|| decode_log(&log, &event_map))
+    });
+}
+
+fn bench_parse_decoded_log(c: &mut Criterion) {
+    let log = swap_log();
+    let abi = load_abi();
+    let event = abi.event("Swap").unwrap();
+    let decoded = event
+        .parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() })
+        .unwrap();
+
+    c.bench_function("parse_decoded_log", |b| {
+        b.iter(|| parse_decoded_log(decoded.clone(), &log))
+    });
+}
+
+criterion_group!(benches, bench_process_log, bench_decode_log, bench_parse_decoded_log);
+criterion_main!(benches);
@@ -0,0 +1,208 @@
+use ethers::abi::Abi;
+use lazy_static::lazy_static;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::TaskError;
+use crate::log_processing::build_event_map;
+
+
+/// The Uniswap V3 pool ABI compiled into the binary, used unless `--abi` points elsewhere.
+pub const DEFAULT_ABI_JSON: &str = include_str!("abi.json");
+
+lazy_static! {
+    static ref DEFAULT_ABI: Abi = serde_json::from_str(DEFAULT_ABI_JSON).expect("embedded abi.json is a valid ABI");
+}
+
+/// default_abi The embedded Uniswap V3 pool ABI, parsed on first use.
+pub fn default_abi() -> &'static Abi {
+    &DEFAULT_ABI
+}
+
+/// The environment variable naming the ABI JSON file when `--abi` isn't given.
+pub const ABI_PATH_VAR: &str = "ABI_PATH";
+
+/// AbiOrigin Where the ABI to decode the logs with comes from, in order of precedence:
+/// the `--abi` files, the file named by `ABI_PATH`, or the embedded pool ABI.
+///
+/// The embedded ABI needs no file, so the binary runs from any directory. Only a file
+/// named explicitly, by the flag or the variable, fails the run when it can't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiOrigin {
+    Flag(Vec<PathBuf>),
+    Env(PathBuf),
+    Embedded,
+}
+
+impl AbiOrigin {
+    /// The source of the ABI given the `--abi` paths and the value of `ABI_PATH`, if set.
+    /// A blank variable counts as unset.
+    pub fn resolve(flag: &[PathBuf], env: Option<String>) -> Self {
+        if !flag.is_empty() {
+            return AbiOrigin::Flag(flag.to_vec());
+        }
+        match env.filter(|path| !path.trim().is_empty()) {
+            Some(path) => AbiOrigin::Env(PathBuf::from(path.trim())),
+            None => AbiOrigin::Embedded,
+        }
+    }
+
+    /// The source of the ABI given the `--abi` paths and the environment.
+    pub fn from_env(flag: &[PathBuf]) -> Self {
+        AbiOrigin::resolve(flag, std::env::var(ABI_PATH_VAR).ok())
+    }
+
+    /// Read and parse the ABI, merging the `--abi` files.
+    pub fn load(&self) -> Result<Abi, TaskError> {
+        match self {
+            AbiOrigin::Flag(paths) => read_abis(paths),
+            AbiOrigin::Env(path) => read_abi(path),
+            AbiOrigin::Embedded => Ok(default_abi().clone()),
+        }
+    }
+}
+
+impl fmt::Display for AbiOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiOrigin::Flag(paths) => {
+                let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+                write!(f, "{} (--abi)", paths.join(", "))
+            }
+            AbiOrigin::Env(path) => write!(f, "{} ({})", path.display(), ABI_PATH_VAR),
+            AbiOrigin::Embedded => f.write_str("the embedded Uniswap V3 pool ABI"),
+        }
+    }
+}
+
+/// read_abi Reads and parses the ABI JSON file at `path`.
+pub fn read_abi(path: &Path) -> Result<Abi, TaskError> {
+    let abi_json = std::fs::read_to_string(path)
+        .map_err(|source| TaskError::ReadAbi { path: path.display().to_string(), source })?;
+    serde_json::from_str(&abi_json).map_err(TaskError::ParseAbi)
+}
+
+/// read_abis Reads the ABI JSON files at `paths`, e.g. of a pool, a router and its
+/// tokens, and merges them with `merge_abis`.
+pub fn read_abis(paths: &[PathBuf]) -> Result<Abi, TaskError> {
+    let abis = paths.iter().map(|path| read_abi(path)).collect::<Result<Vec<_>, _>>()?;
+    merge_abis(abis)
+}
+
+/// merge_abis One ABI with the events, functions and errors of every one of `abis`, so a
+/// single event map decodes the logs of several contracts.
+///
+/// A definition found in several ABIs, such as the ERC-20 `Transfer` of two tokens, is
+/// kept once. Events sharing a signature hash but laid out differently, e.g. indexing
+/// other parameters, fail the merge with `TaskError::AmbiguousAbi`, since only one of
+/// them could be decoded.
+pub fn merge_abis(abis: impl IntoIterator<Item = Abi>) -> Result<Abi, TaskError> {
+    let mut abis = abis.into_iter();
+    let mut merged = abis.next().unwrap_or_default();
+    for abi in abis {
+        for (name, events) in abi.events {
+            extend_unique(merged.events.entry(name).or_default(), events);
+        }
+        for (name, functions) in abi.functions {
+            extend_unique(merged.functions.entry(name).or_default(), functions);
+        }
+        for (name, errors) in abi.errors {
+            extend_unique(merged.errors.entry(name).or_default(), errors);
+        }
+    }
+    build_event_map(&merged)?;
+    Ok(merged)
+}
+
+// Add the `items` not in `into` yet.
+fn extend_unique<T: PartialEq>(into: &mut Vec<T>, items: Vec<T>) {
+    for item in items {
+        if !into.contains(&item) {
+            into.push(item);
+        }
+    }
+}
+
+/// check_events Fails with `TaskError::MissingEvents` when the ABI doesn't define every
+/// one of `events`, so a wrong ABI file or a typo is reported at startup instead of
+/// looking like a pool without swaps.
+pub fn check_events<S: AsRef<str>>(abi: &Abi, events: &[S]) -> Result<(), TaskError> {
+    let missing: Vec<String> = events
+        .iter()
+        .map(|event| event.as_ref())
+        .filter(|event| !abi.events.contains_key(*event))
+        .map(String::from)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let mut available: Vec<String> = abi.events.keys().cloned().collect();
+    available.sort();
+    Err(TaskError::MissingEvents { missing, available })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_abi_has_pool_events() {
+        let abi = default_abi();
+        for event in ["Swap", "Mint", "Burn"] {
+            assert!(abi.events.contains_key(event), "missing {} event", event);
+        }
+    }
+
+    #[test]
+    fn test_abi_source_precedence() {
+        let flag = vec![PathBuf::from("router.json")];
+        assert_eq!(AbiOrigin::resolve(&flag, Some("pool.json".to_string())), AbiOrigin::Flag(flag.clone()));
+        assert_eq!(AbiOrigin::resolve(&[], Some(" pool.json ".to_string())), AbiOrigin::Env(PathBuf::from("pool.json")));
+        assert_eq!(AbiOrigin::resolve(&[], Some(String::new())), AbiOrigin::Embedded);
+        assert_eq!(AbiOrigin::resolve(&[], None), AbiOrigin::Embedded);
+
+        assert_eq!(&AbiOrigin::Embedded.load().unwrap(), default_abi());
+        assert_eq!(AbiOrigin::Env(PathBuf::from("pool.json")).to_string(), "pool.json (ABI_PATH)");
+        // A path given explicitly must exist
+        let missing = AbiOrigin::Env(PathBuf::from("no_such_abi.json")).load().unwrap_err();
+        assert!(matches!(missing, TaskError::ReadAbi { .. }), "{:?}", missing);
+    }
+
+    #[test]
+    fn test_check_events() {
+        assert!(check_events(default_abi(), &["Swap", "Burn"]).is_ok());
+
+        let err = check_events(default_abi(), &["Swap", "Swapp", "Transfer"]).unwrap_err();
+        match &err {
+            TaskError::MissingEvents { missing, available } => {
+                assert_eq!(missing, &["Swapp", "Transfer"]);
+                assert!(available.contains(&"Swap".to_string()));
+            }
+            other => panic!("Expected MissingEvents, got {:?}", other),
+        }
+        assert!(err.to_string().starts_with("The ABI has no Swapp, Transfer event"));
+    }
+
+    #[test]
+    fn test_merge_abis() {
+        let erc20 = ethers::abi::parse_abi(&[
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+        ]).unwrap();
+        let merged = merge_abis([default_abi().clone(), erc20.clone(), erc20]).unwrap();
+        assert!(merged.events.contains_key("Swap"));
+        assert_eq!(merged.events["Transfer"].len(), 1);
+
+        // An ERC-721 Transfer has the ERC-20 signature but indexes the token id
+        let erc721 = ethers::abi::parse_abi(&["event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)"]).unwrap();
+        let erc20 = ethers::abi::parse_abi(&["event Transfer(address indexed from, address indexed to, uint256 value)"]).unwrap();
+        assert!(matches!(merge_abis([erc20, erc721]), Err(TaskError::AmbiguousAbi(_))));
+    }
+
+    #[test]
+    fn test_read_abi_missing_file() {
+        let err = read_abi(Path::new("does/not/exist.json")).unwrap_err();
+        assert!(matches!(err, TaskError::ReadAbi { .. }));
+    }
+}
@@ -0,0 +1,365 @@
+//! Where contract ABIs are fetched from.
+//!
+//! Etherscan only knows contracts verified with it, and often has no verified ABI on
+//! newer chains. `fetch_abi` tries several `AbiSource`s in turn until one returns an ABI
+//! that parses:
+//!
+//! | Source       | Endpoint                                            | Key                 |
+//! |--------------|-----------------------------------------------------|---------------------|
+//! | `Etherscan`  | `getabi` of the Etherscan API                       | `ETHERSCAN_API_KEY` |
+//! | `Sourcify`   | `metadata.json` of a full, then partial, match      | none                |
+//! | `Blockscout` | `getabi` of the Etherscan-compatible Blockscout API | none                |
+//!
+//! Etherscan rate limits even light use of the free tier, so its rate limit answers are
+//! retried with an exponential backoff before the next source is tried.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use dotenv::dotenv;
+use ethers::abi::Abi;
+use reqwest::blocking::Client as BlockingClient;
+use serde_json::Value;
+
+use crate::api_keys::is_rate_limit_message;
+use crate::error::{env_var, TaskError};
+
+
+/// AbiSource A service returning the ABI of verified contracts.
+pub trait AbiSource {
+    /// The name reported when the source fails.
+    fn name(&self) -> &str;
+
+    /// The JSON ABI of the contract at `address`.
+    fn fetch_abi(&self, address: &str) -> Result<Value, Box<dyn Error>>;
+}
+
+
+/// Etherscan The Etherscan API, or any explorer with the same API and an API key.
+pub struct Etherscan {
+    pub api_url: String,
+    pub api_key: String,
+    /// How many times a rate limited request is retried. Other errors, such as an
+    /// unverified contract, are never retried.
+    pub max_retries: u32,
+    /// The delay before the first retry, doubling for every following one.
+    pub backoff_base: Duration,
+}
+
+impl Etherscan {
+    pub const MAINNET_API_URL: &'static str = "https://api.etherscan.io/api";
+    pub const DEFAULT_RETRIES: u32 = 4;
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+    /// Mainnet Etherscan with the key in `ETHERSCAN_API_KEY`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        dotenv().ok();
+        Ok(Etherscan {
+            api_url: Etherscan::MAINNET_API_URL.to_string(),
+            api_key: env_var("ETHERSCAN_API_KEY")?,
+            max_retries: Etherscan::DEFAULT_RETRIES,
+            backoff_base: Etherscan::DEFAULT_BACKOFF,
+        })
+    }
+}
+
+impl AbiSource for Etherscan {
+    fn name(&self) -> &str {
+        "Etherscan"
+    }
+
+    fn fetch_abi(&self, address: &str) -> Result<Value, Box<dyn Error>> {
+        let client = BlockingClient::new();
+        let query = [("module", "contract"), ("action", "getabi"), ("address", address), ("apikey", &self.api_key)];
+        retry_rate_limited(self.name(), self.max_retries, self.backoff_base, || {
+            let response: Value = client.get(&self.api_url).query(&query).send()?.json()?;
+            parse_getabi_response(address, &response)
+        })
+    }
+}
+
+
+/// Sourcify The Sourcify repository of verified contracts, keyed by chain id.
+pub struct Sourcify {
+    pub repository_url: String,
+    pub chain_id: u64,
+}
+
+impl Sourcify {
+    pub const REPOSITORY_URL: &'static str = "https://repo.sourcify.dev";
+
+    pub fn new(chain_id: u64) -> Self {
+        Sourcify { repository_url: Sourcify::REPOSITORY_URL.to_string(), chain_id }
+    }
+}
+
+impl AbiSource for Sourcify {
+    fn name(&self) -> &str {
+        "Sourcify"
+    }
+
+    fn fetch_abi(&self, address: &str) -> Result<Value, Box<dyn Error>> {
+        // Sourcify files contracts under their checksummed address
+        let address = crate::log_processing::to_checksum_address(crate::log_processing::parse_address(address)?.as_bytes());
+        let client = BlockingClient::new();
+        for kind in ["full_match", "partial_match"] {
+            let url = format!("{}/contracts/{}/{}/{}/metadata.json", self.repository_url, kind, self.chain_id, address);
+            let response = client.get(&url).send()?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                continue;
+            }
+            return abi_from_metadata(&response.error_for_status()?.json()?);
+        }
+        Err(format!("{} isn't verified on chain {}", address, self.chain_id).into())
+    }
+}
+
+
+/// Blockscout A Blockscout explorer, e.g. `https://eth.blockscout.com`.
+pub struct Blockscout {
+    pub base_url: String,
+}
+
+impl Blockscout {
+    pub const MAINNET_URL: &'static str = "https://eth.blockscout.com";
+
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Blockscout { base_url: base_url.into() }
+    }
+}
+
+impl AbiSource for Blockscout {
+    fn name(&self) -> &str {
+        "Blockscout"
+    }
+
+    fn fetch_abi(&self, address: &str) -> Result<Value, Box<dyn Error>> {
+        let url = format!("{}/api", self.base_url.trim_end_matches('/'));
+        let query = [("module", "contract"), ("action", "getabi"), ("address", address)];
+        let response: Value = BlockingClient::new().get(&url).query(&query).send()?.json()?;
+        parse_getabi_response(address, &response)
+    }
+}
+
+
+/// AbiSourceKind The ABI sources that can be picked by name, e.g. `etherscan,sourcify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiSourceKind {
+    Etherscan,
+    Sourcify,
+    Blockscout,
+}
+
+impl AbiSourceKind {
+    /// The order sources are tried in when none is configured.
+    pub const DEFAULT_ORDER: [AbiSourceKind; 3] = [AbiSourceKind::Etherscan, AbiSourceKind::Sourcify, AbiSourceKind::Blockscout];
+
+    /// The mainnet source of this kind. Fails for Etherscan without an API key.
+    pub fn mainnet(self) -> Result<Box<dyn AbiSource>, Box<dyn Error>> {
+        Ok(match self {
+            AbiSourceKind::Etherscan => Box::new(Etherscan::from_env()?),
+            AbiSourceKind::Sourcify => Box::new(Sourcify::new(1)),
+            AbiSourceKind::Blockscout => Box::new(Blockscout::new(Blockscout::MAINNET_URL)),
+        })
+    }
+}
+
+impl FromStr for AbiSourceKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_str() {
+            "etherscan" => Ok(AbiSourceKind::Etherscan),
+            "sourcify" => Ok(AbiSourceKind::Sourcify),
+            "blockscout" => Ok(AbiSourceKind::Blockscout),
+            _ => Err(format!("Unknown ABI source {:?}, expected etherscan, sourcify or blockscout", name)),
+        }
+    }
+}
+
+impl fmt::Display for AbiSourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AbiSourceKind::Etherscan => "etherscan",
+            AbiSourceKind::Sourcify => "sourcify",
+            AbiSourceKind::Blockscout => "blockscout",
+        })
+    }
+}
+
+
+/// mainnet_sources The mainnet sources of `order`, in that order. Sources that can't be
+/// set up, Etherscan without an API key, are left out with a warning.
+pub fn mainnet_sources(order: &[AbiSourceKind]) -> Vec<Box<dyn AbiSource>> {
+    order
+        .iter()
+        .filter_map(|kind| match kind.mainnet() {
+            Ok(source) => Some(source),
+            Err(e) => {
+                eprintln!("Warning: skipping the {} ABI source: {}", kind, e);
+                None
+            }
+        })
+        .collect()
+}
+
+
+/// fetch_abi The ABI of the contract at `address` from the first of `sources` that
+/// returns one parsing as an `Abi`.
+///
+/// Fails with the error of every source when none does.
+pub fn fetch_abi(sources: &[Box<dyn AbiSource>], address: &str) -> Result<Abi, Box<dyn Error>> {
+    let mut failures = Vec::new();
+    for source in sources {
+        let abi = source
+            .fetch_abi(address)
+            .and_then(|abi| serde_json::from_value::<Abi>(abi).map_err(Into::into));
+        match abi {
+            Ok(abi) => return Ok(abi),
+            Err(e) => failures.push(format!("{}: {}", source.name(), e)),
+        }
+    }
+    if failures.is_empty() {
+        return Err("No ABI source configured".into());
+    }
+    Err(format!("No ABI source returned the ABI of {}:\n  {}", address, failures.join("\n  ")).into())
+}
+
+
+// Run `request`, retrying it after a growing delay while it fails with a rate limit.
+// Fails with `TaskError::AbiSource` once the retries are used up.
+fn retry_rate_limited<T>(
+    name: &str,
+    max_retries: u32,
+    backoff_base: Duration,
+    mut request: impl FnMut() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        let err = match request() {
+            Ok(value) => return Ok(value),
+            Err(err) if is_rate_limit_message(&err.to_string()) => err,
+            Err(err) => return Err(err),
+        };
+        if attempt >= max_retries {
+            return Err(TaskError::AbiSource { name: name.to_string(), attempts: attempt + 1, message: err.to_string() }.into());
+        }
+        std::thread::sleep(backoff_base.saturating_mul(1 << attempt.min(16)));
+        attempt += 1;
+    }
+}
+
+// The ABI in the answer of an Etherscan-style `getabi` call, which holds it as a JSON string.
+fn parse_getabi_response(address: &str, response: &Value) -> Result<Value, Box<dyn Error>> {
+    match response["result"].as_str() {
+        Some(abi) if response["status"] == "1" => Ok(serde_json::from_str(abi)?),
+        _ => Err(format!(
+            "Error fetching ABI for {}. Error: {}",
+            address,
+            response["result"].as_str().or(response["message"].as_str()).unwrap_or("Unknown error")
+        )
+        .into()),
+    }
+}
+
+// The ABI in a Solidity metadata file, as published by Sourcify.
+fn abi_from_metadata(metadata: &Value) -> Result<Value, Box<dyn Error>> {
+    match &metadata["output"]["abi"] {
+        Value::Array(_) => Ok(metadata["output"]["abi"].clone()),
+        _ => Err("The metadata has no output.abi".into()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    struct Fixed(&'static str, Result<Value, &'static str>);
+
+    impl AbiSource for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn fetch_abi(&self, _address: &str) -> Result<Value, Box<dyn Error>> {
+            self.1.clone().map_err(Into::into)
+        }
+    }
+
+    fn swap_abi() -> Value {
+        serde_json::from_str(crate::abi::DEFAULT_ABI_JSON).unwrap()
+    }
+
+    #[test]
+    fn test_sources_are_tried_in_order() {
+        let sources: Vec<Box<dyn AbiSource>> = vec![
+            Box::new(Fixed("Etherscan", Err("Contract source code not verified"))),
+            Box::new(Fixed("Sourcify", Ok(json!({"not": "an abi"})))),
+            Box::new(Fixed("Blockscout", Ok(swap_abi()))),
+        ];
+        let abi = fetch_abi(&sources, "0xpool").unwrap();
+        assert!(abi.event("Swap").is_ok());
+
+        let err = fetch_abi(&sources[..2], "0xpool").unwrap_err().to_string();
+        assert!(err.contains("Etherscan: Contract source code not verified"));
+        assert!(err.contains("Sourcify: "));
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let ok = json!({"status": "1", "message": "OK", "result": "[]"});
+        assert_eq!(parse_getabi_response("0xpool", &ok).unwrap(), json!([]));
+        let unverified = json!({"status": "0", "message": "NOTOK", "result": "Contract source code not verified"});
+        assert!(parse_getabi_response("0xpool", &unverified).unwrap_err().to_string().ends_with("Contract source code not verified"));
+
+        let metadata = json!({"compiler": {}, "output": {"abi": swap_abi()}});
+        assert_eq!(abi_from_metadata(&metadata).unwrap(), swap_abi());
+        assert!(abi_from_metadata(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_only_rate_limits_are_retried() {
+        let rate_limited = json!({"status": "0", "message": "NOTOK", "result": "Max rate limit reached"});
+        let mut requests = 0;
+        let abi = retry_rate_limited("Etherscan", 3, Duration::ZERO, || {
+            requests += 1;
+            match requests {
+                1 | 2 => parse_getabi_response("0xpool", &rate_limited),
+                _ => Ok(json!([])),
+            }
+        });
+        assert_eq!((abi.unwrap(), requests), (json!([]), 3));
+
+        let mut requests = 0;
+        let err = retry_rate_limited("Etherscan", 2, Duration::ZERO, || {
+            requests += 1;
+            parse_getabi_response("0xpool", &rate_limited)
+        })
+        .unwrap_err();
+        assert_eq!(requests, 3);
+        assert!(matches!(err.downcast_ref::<TaskError>(), Some(TaskError::AbiSource { attempts: 3, .. })));
+
+        // An unverified contract stays unverified
+        let unverified = json!({"status": "0", "message": "NOTOK", "result": "Contract source code not verified"});
+        let mut requests = 0;
+        let err = retry_rate_limited("Etherscan", 2, Duration::ZERO, || {
+            requests += 1;
+            parse_getabi_response("0xpool", &unverified)
+        })
+        .unwrap_err();
+        assert_eq!(requests, 1);
+        assert!(err.downcast_ref::<TaskError>().is_none());
+    }
+
+    #[test]
+    fn test_source_kind_names() {
+        assert_eq!("Sourcify".parse(), Ok(AbiSourceKind::Sourcify));
+        assert!("etherscan2".parse::<AbiSourceKind>().is_err());
+        for kind in AbiSourceKind::DEFAULT_ORDER {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+    }
+}
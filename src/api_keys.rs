@@ -0,0 +1,209 @@
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{env_var, TaskError};
+
+
+/// How long a key that hit a rate limit is skipped.
+pub const THROTTLE_DURATION: Duration = Duration::from_secs(60);
+
+
+/// ApiKeys A pool of provider API keys handed out round-robin.
+///
+/// Every connection or request takes the next key, spreading the load over the per-key
+/// rate limits. A key reported with `mark_throttled` is skipped until its throttle
+/// expires, unless every key is throttled, in which case the one freed first is used.
+pub struct ApiKeys {
+    keys: Vec<String>,
+    next: AtomicUsize,
+    throttled_until: Mutex<Vec<Option<Instant>>>,
+}
+
+impl ApiKeys {
+    /// A pool of `keys`, None if there are none.
+    pub fn new(keys: Vec<String>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        let throttled_until = Mutex::new(vec![None; keys.len()]);
+        Some(ApiKeys { keys, next: AtomicUsize::new(0), throttled_until })
+    }
+
+    /// The keys of the comma-separated list in the environment variable `name`.
+    pub fn from_env(name: &'static str) -> Result<Self, TaskError> {
+        ApiKeys::new(split_keys(&env_var(name)?)).ok_or(TaskError::MissingEnvVar(name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The index and value of the next key to use.
+    pub fn next_key(&self) -> (usize, &str) {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let throttled_until = self.throttled_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let index = (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|&index| throttled_until[index].is_none_or(|until| until <= now))
+            .unwrap_or_else(|| {
+                // Every key is throttled, use the one freed first
+                (0..self.keys.len()).min_by_key(|&index| throttled_until[index]).unwrap_or(0)
+            });
+        (index, &self.keys[index])
+    }
+
+    /// Skip the key at `index` for `duration`.
+    pub fn mark_throttled(&self, index: usize, duration: Duration) {
+        let mut throttled_until = self.throttled_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(until) = throttled_until.get_mut(index) {
+            *until = Some(Instant::now() + duration);
+        }
+    }
+}
+
+// Never print the keys themselves.
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeys({} keys)", self.keys.len())
+    }
+}
+
+
+/// split_keys The non-empty keys of a comma-separated list.
+pub fn split_keys(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|key| !key.is_empty()).map(String::from).collect()
+}
+
+/// is_rate_limit_message Whether a provider error message reports a rate limit.
+pub fn is_rate_limit_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("429") || message.contains("too many requests") || message.contains("rate limit")
+}
+
+
+/// RotatingHttp An HTTP transport sending each request with the next key of an `ApiKeys`.
+///
+/// A request answered with a rate limit error marks its key throttled and is retried
+/// with the next key, until every key has been tried once.
+pub struct RotatingHttp {
+    keys: Arc<ApiKeys>,
+    clients: Vec<Http>,
+}
+
+impl RotatingHttp {
+    /// A transport for the endpoint built by `url` from each key.
+    pub fn new(keys: Arc<ApiKeys>, url: impl Fn(&str) -> String) -> Result<Self, <Http as FromStr>::Err> {
+        let clients = keys.keys.iter().map(|key| Http::from_str(&url(key))).collect::<Result<_, _>>()?;
+        Ok(RotatingHttp { keys, clients })
+    }
+}
+
+impl RotatingHttp {
+    /// A transport for the one endpoint `url`, whose key, if any, is part of the URL.
+    pub fn single(url: &str) -> Result<Self, <Http as FromStr>::Err> {
+        let keys = ApiKeys::new(vec![String::new()]).expect("one key");
+        Ok(RotatingHttp { keys: Arc::new(keys), clients: vec![Http::from_str(url)?] })
+    }
+}
+
+impl fmt::Debug for RotatingHttp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RotatingHttp({:?})", self.keys)
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RotatingHttp {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut attempts = 0;
+        loop {
+            let (index, _) = self.keys.next_key();
+            match self.clients[index].request(method, &params).await {
+                Err(err) if is_rate_limited(&err) => {
+                    self.keys.mark_throttled(index, THROTTLE_DURATION);
+                    attempts += 1;
+                    if attempts >= self.clients.len() {
+                        return Err(err);
+                    }
+                    eprintln!("Warning: API key {} is rate limited, retrying with the next one", index);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+// Infura answers a rate limited request with a JSON-RPC error, other providers with
+// a plain 429 body that fails to parse.
+fn is_rate_limited(err: &HttpClientError) -> bool {
+    match err {
+        HttpClientError::JsonRpcError(err) => err.code == 429 || err.code == -32005 || is_rate_limit_message(&err.message),
+        HttpClientError::SerdeJson { text, .. } => is_rate_limit_message(text),
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(n: usize) -> ApiKeys {
+        ApiKeys::new((0..n).map(|i| format!("key{}", i)).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let keys = keys(3);
+        let order: Vec<_> = (0..4).map(|_| keys.next_key().1.to_string()).collect();
+        assert_eq!(order, vec!["key0", "key1", "key2", "key0"]);
+    }
+
+    #[test]
+    fn test_throttled_keys_are_skipped() {
+        let keys = keys(3);
+        keys.mark_throttled(1, THROTTLE_DURATION);
+        let order: Vec<_> = (0..3).map(|_| keys.next_key().0).collect();
+        assert_eq!(order, vec![0, 2, 2]);
+
+        // With every key throttled, the one freed first is used
+        keys.mark_throttled(0, THROTTLE_DURATION * 2);
+        keys.mark_throttled(2, THROTTLE_DURATION * 3);
+        assert_eq!(keys.next_key().0, 1);
+
+        // An expired throttle no longer applies
+        keys.mark_throttled(1, Duration::ZERO);
+        assert_eq!(keys.next_key().0, 1);
+    }
+
+    #[test]
+    fn test_split_keys() {
+        assert_eq!(split_keys(" a, b,,c "), vec!["a", "b", "c"]);
+        assert!(ApiKeys::new(split_keys(" , ")).is_none());
+    }
+
+    #[test]
+    fn test_is_rate_limit_message() {
+        assert!(is_rate_limit_message("HTTP error 429 Too Many Requests"));
+        assert!(is_rate_limit_message("daily request count exceeded, request rate limited"));
+        assert!(!is_rate_limit_message("execution reverted"));
+    }
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::candles::PriceQuote;
+use crate::data_store::{decimal_string, DecodedData};
+
+
+/// BlockSummary The swaps of one pool in one block, rolled up.
+///
+/// The net amounts are the sums of the signed swap amounts, the pool's balance change
+/// over the block, and are serialized as decimal strings like the swap amounts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub block_number: u64,
+    pub swaps: u64,
+    #[serde(with = "decimal_string")]
+    pub net_amount0: i128,
+    #[serde(with = "decimal_string")]
+    pub net_amount1: i128,
+    /// The price after the last swap of the block, quoted like the candles.
+    pub close: f64,
+}
+
+
+/// BlockSummaryStore A destination for completed block summaries, separate from the
+/// swap `DataStore`.
+#[async_trait]
+pub trait BlockSummaryStore: Send + Sync {
+    /// Store the summary of one block of the pool at `address`.
+    async fn store_block_summary(&self, address: &str, summary: &BlockSummary) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+
+/// BlockAggregator Rolls a stream of swaps up into one `BlockSummary` per block.
+///
+/// Swaps can come in out of order, so every block stays open until the chain head is
+/// far enough past it for its last logs to have been decoded, see `close_before`. The
+/// close is the price after the swap with the highest log index, whatever the order the
+/// swaps came in. Swaps of a block that was already summarized are too late and dropped.
+pub struct BlockAggregator {
+    quote: PriceQuote,
+    // The open blocks, with the log index of the swap their close comes from
+    open: BTreeMap<u64, (BlockSummary, u64)>,
+    // The last block summarized, later swaps of it or of older blocks are too late
+    last_closed: Option<u64>,
+}
+
+impl BlockAggregator {
+    pub fn new(quote: PriceQuote) -> Self {
+        BlockAggregator { quote, open: BTreeMap::new(), last_closed: None }
+    }
+
+    /// Add the swap at `log_index` of block `block_number`.
+    pub fn push(&mut self, block_number: u64, log_index: u64, data: &DecodedData) {
+        if self.last_closed.is_some_and(|closed| block_number <= closed) {
+            tracing::warn!(tx_hash = %data.transaction_hash, block = block_number, "Swap came in after its block was summarized");
+            return;
+        }
+        let price = self.quote.price(data.sqrtPriceX96);
+        match self.open.get_mut(&block_number) {
+            Some((summary, close_index)) => {
+                summary.swaps += 1;
+                summary.net_amount0 = summary.net_amount0.saturating_add(data.amount0);
+                summary.net_amount1 = summary.net_amount1.saturating_add(data.amount1);
+                if log_index >= *close_index {
+                    summary.close = price;
+                    *close_index = log_index;
+                }
+            }
+            None => {
+                let summary = BlockSummary { block_number, swaps: 1, net_amount0: data.amount0, net_amount1: data.amount1, close: price };
+                self.open.insert(block_number, (summary, log_index));
+            }
+        }
+    }
+
+    /// Take the summaries of the open blocks the chain head `latest_block` is more than
+    /// one block past, oldest first. The one block of slack leaves the logs of the latest
+    /// blocks that are still being decoded time to come in.
+    pub fn close_before(&mut self, latest_block: u64) -> Vec<BlockSummary> {
+        let still_open = self.open.split_off(&latest_block.saturating_sub(1));
+        let closed = std::mem::replace(&mut self.open, still_open);
+        self.note_closed(closed)
+    }
+
+    /// Take the summaries of every open block, e.g. on shutdown, complete or not.
+    pub fn flush(&mut self) -> Vec<BlockSummary> {
+        let closed = std::mem::take(&mut self.open);
+        self.note_closed(closed)
+    }
+
+    fn note_closed(&mut self, closed: BTreeMap<u64, (BlockSummary, u64)>) -> Vec<BlockSummary> {
+        if let Some(&block_number) = closed.keys().next_back() {
+            self.last_closed = Some(block_number);
+        }
+        closed.into_values().map(|(summary, _)| summary).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_priced_swap;
+
+    #[test]
+    fn test_blocks_stay_open_until_behind_the_head() {
+        let mut aggregator = BlockAggregator::new(PriceQuote::default());
+        aggregator.push(10, 4, &test_priced_swap(5, -10, 2 << 96));
+        aggregator.push(11, 0, &test_priced_swap(1, -2, 1 << 96));
+        // Came in late, but block 10 is still open, and the swap at log 4 stays its close
+        aggregator.push(10, 1, &test_priced_swap(-3, 6, 1 << 96));
+
+        assert_eq!(aggregator.close_before(11), vec![]);
+        let summaries = aggregator.close_before(12);
+        assert_eq!(summaries, vec![BlockSummary { block_number: 10, swaps: 2, net_amount0: 2, net_amount1: -4, close: 4.0 }]);
+
+        // Block 10 was summarized, its late swaps are dropped
+        aggregator.push(10, 7, &test_priced_swap(7, -14, 1 << 96));
+        assert_eq!(aggregator.close_before(13).iter().map(|summary| (summary.block_number, summary.swaps)).collect::<Vec<_>>(), vec![(11, 1)]);
+        assert_eq!(aggregator.flush(), vec![]);
+    }
+
+    #[test]
+    fn test_flush_takes_every_open_block() {
+        let mut aggregator = BlockAggregator::new(PriceQuote::default());
+        aggregator.push(11, 0, &test_priced_swap(1, -2, 1 << 96));
+        aggregator.push(10, 0, &test_priced_swap(5, -10, 1 << 96));
+
+        let blocks: Vec<_> = aggregator.flush().iter().map(|summary| summary.block_number).collect();
+        assert_eq!(blocks, vec![10, 11]);
+        aggregator.push(11, 1, &test_priced_swap(5, -10, 1 << 96));
+        assert_eq!(aggregator.flush(), vec![]);
+    }
+
+    #[test]
+    fn test_summary_amounts_serialize_as_strings() {
+        let summary = BlockSummary { block_number: 1, swaps: 1, net_amount0: -(1 << 60), net_amount1: 1, close: 0.5 };
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["net_amount0"], "-1152921504606846976");
+        assert_eq!(serde_json::from_value::<BlockSummary>(json).unwrap(), summary);
+    }
+}
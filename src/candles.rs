@@ -0,0 +1,333 @@
+use async_trait::async_trait;
+use ethers::types::U256;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::data_store::DecodedData;
+
+
+/// Candle The open/high/low/close price and traded volume of one interval.
+///
+/// Prices are derived from `sqrtPriceX96` and oriented by the aggregator's `PriceQuote`,
+/// volumes are the summed absolute swap amounts of each token in raw units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Unix timestamp of the start of the interval.
+    pub open_time: u64,
+    /// Length of the interval in seconds.
+    pub interval: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume0: u128,
+    pub volume1: u128,
+    pub swaps: u64,
+}
+
+
+/// CandleStore A destination for completed candles, separate from the swap `DataStore`.
+#[async_trait]
+pub trait CandleStore: Send + Sync {
+    /// Store one completed candle of the pool at `address`.
+    async fn store_candle(&self, address: &str, candle: &Candle) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+
+/// CandleAggregator Folds a stream of swaps into fixed-interval candles.
+///
+/// Swaps are bucketed on their block timestamp. Swaps can come in out of order, so a
+/// bucket stays open until a later one was opened and the chain head is far enough past
+/// its last block for that block's last logs to have been decoded, see `close_before`.
+/// The open and close are the prices of the bucket's first and last swaps by block and
+/// log index, whatever the order they came in. Swaps of a bucket that was already
+/// closed are too late and dropped.
+pub struct CandleAggregator {
+    interval: u64,
+    quote: PriceQuote,
+    // The open buckets by open time
+    open: BTreeMap<u64, OpenCandle>,
+    // The open time of the latest bucket opened
+    latest: Option<u64>,
+    // The open time of the last bucket closed, later swaps of it or of older buckets are too late
+    last_closed: Option<u64>,
+}
+
+// A candle still taking swaps, with the (block number, log index) positions of the
+// swaps its open and close come from.
+struct OpenCandle {
+    candle: Candle,
+    first: (u64, u64),
+    last: (u64, u64),
+}
+
+impl CandleAggregator {
+    /// An aggregator building candles of `interval` seconds, at least one, with prices
+    /// quoted as set by `quote`.
+    pub fn new(interval: u64, quote: PriceQuote) -> Self {
+        CandleAggregator { interval: interval.max(1), quote, open: BTreeMap::new(), latest: None, last_closed: None }
+    }
+
+    /// Add the swap at `log_index` of block `block_number`, mined at `timestamp`.
+    pub fn push(&mut self, timestamp: u64, block_number: u64, log_index: u64, data: &DecodedData) {
+        let open_time = timestamp - timestamp % self.interval;
+        if self.last_closed.is_some_and(|closed| open_time <= closed) {
+            tracing::warn!(tx_hash = %data.transaction_hash, interval = self.interval, "Swap came in after its candle was closed");
+            return;
+        }
+        let price = self.quote.price(data.sqrtPriceX96);
+        let volume0 = data.amount0.unsigned_abs();
+        let volume1 = data.amount1.unsigned_abs();
+        let position = (block_number, log_index);
+        self.latest = self.latest.max(Some(open_time));
+
+        match self.open.get_mut(&open_time) {
+            Some(open) => {
+                let candle = &mut open.candle;
+                candle.high = candle.high.max(price);
+                candle.low = candle.low.min(price);
+                candle.volume0 = candle.volume0.saturating_add(volume0);
+                candle.volume1 = candle.volume1.saturating_add(volume1);
+                candle.swaps += 1;
+                if position < open.first {
+                    candle.open = price;
+                    open.first = position;
+                }
+                if position >= open.last {
+                    candle.close = price;
+                    open.last = position;
+                }
+            }
+            None => {
+                let candle = Candle {
+                    open_time,
+                    interval: self.interval,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume0,
+                    volume1,
+                    swaps: 1,
+                };
+                self.open.insert(open_time, OpenCandle { candle, first: position, last: position });
+            }
+        }
+    }
+
+    /// Take the candles complete once the chain head is at `latest_block`, oldest first:
+    /// those a later bucket was opened after, whose last block the head is more than one
+    /// block past. The one block of slack leaves the logs of the latest blocks that are
+    /// still being decoded time to come in.
+    pub fn close_before(&mut self, latest_block: u64) -> Vec<Candle> {
+        let Some(latest) = self.latest else { return Vec::new() };
+        let mut closed = Vec::new();
+        while let Some(entry) = self.open.first_entry() {
+            let (block_number, _) = entry.get().last;
+            if *entry.key() >= latest || block_number.saturating_add(1) >= latest_block {
+                break;
+            }
+            self.last_closed = Some(*entry.key());
+            closed.push(entry.remove().candle);
+        }
+        closed
+    }
+
+    /// Take every open candle, e.g. on shutdown, oldest first.
+    pub fn flush(&mut self) -> Vec<Candle> {
+        if let Some(&open_time) = self.open.keys().next_back() {
+            self.last_closed = Some(open_time);
+        }
+        std::mem::take(&mut self.open).into_values().map(|open| open.candle).collect()
+    }
+}
+
+
+/// sqrt_price_x96_to_price Converts a Uniswap V3 `sqrtPriceX96` into the price of
+/// token0 in token1, `(sqrtPriceX96 / 2^96)^2`, in raw units.
+pub fn sqrt_price_x96_to_price(sqrt_price_x96: U256) -> f64 {
+    let sqrt_price = u256_to_f64(sqrt_price_x96) / 2f64.powi(96);
+    sqrt_price * sqrt_price
+}
+
+// The nearest f64 to `value` its top 128 bits give, more than an f64 can hold anyway.
+fn u256_to_f64(value: U256) -> f64 {
+    let shift = value.bits().saturating_sub(128);
+    (value >> shift).as_u128() as f64 * 2f64.powi(shift as i32)
+}
+
+
+/// price_from_sqrt_price_x96 The price of token0 in token1 encoded by `sqrt_price_x96`,
+/// in whole tokens given their decimals.
+pub fn price_from_sqrt_price_x96(sqrt_price_x96: U256, decimals0: u8, decimals1: u8) -> f64 {
+    PriceQuote { invert: false, decimals0, decimals1 }.price(sqrt_price_x96)
+}
+
+/// price_from_tick The price of token0 in token1 at `tick`, `1.0001^tick`, in whole
+/// tokens given their decimals.
+///
+/// A pool's tick is the price rounded down to a tick, so this is at most one tick
+/// (0.01%) below the price derived from `sqrtPriceX96`.
+pub fn price_from_tick(tick: i32, decimals0: u8, decimals1: u8) -> f64 {
+    1.0001f64.powi(tick) * 10f64.powi(i32::from(decimals0) - i32::from(decimals1))
+}
+
+/// How far apart, relatively, the tick and `sqrtPriceX96` prices of a swap may be: one
+/// tick of rounding, plus some room for the float arithmetic.
+pub const TICK_PRICE_TOLERANCE: f64 = 2e-4;
+
+/// tick_matches_price Whether `tick` and `sqrt_price_x96` encode the same price, within
+/// `TICK_PRICE_TOLERANCE`. A mismatch means one of them was decoded wrong. A zero
+/// `sqrt_price_x96` has no price to check against.
+pub fn tick_matches_price(tick: i32, sqrt_price_x96: U256) -> bool {
+    if sqrt_price_x96.is_zero() {
+        return true;
+    }
+    let ratio = price_from_sqrt_price_x96(sqrt_price_x96, 0, 0) / price_from_tick(tick, 0, 0);
+    (ratio - 1.0).abs() <= TICK_PRICE_TOLERANCE
+}
+
+
+/// PriceQuote How prices derived from `sqrtPriceX96` are scaled and oriented.
+///
+/// `sqrtPriceX96` encodes token1 per token0 in raw units, so which way round the price
+/// reads depends on the pool's token ordering: in the USDC/WETH pool token0 is USDC, and
+/// the raw price is WETH wei per USDC unit. The decimals are applied first, turning it
+/// into whole token1 per whole token0 (WETH per USDC), then `invert` takes the
+/// reciprocal, giving token0 per token1 (USDC per WETH). Left at 0, the decimals keep
+/// the price in raw units, and an inverted raw price is token0 units per token1 unit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriceQuote {
+    /// Quote token0 per token1 instead of token1 per token0.
+    pub invert: bool,
+    /// Decimals of token0, e.g. 6 for USDC.
+    pub decimals0: u8,
+    /// Decimals of token1, e.g. 18 for WETH.
+    pub decimals1: u8,
+}
+
+impl PriceQuote {
+    /// The price encoded by `sqrt_price_x96`, scaled and oriented. A zero price stays zero.
+    pub fn price(&self, sqrt_price_x96: U256) -> f64 {
+        let price = sqrt_price_x96_to_price(sqrt_price_x96)
+            * 10f64.powi(i32::from(self.decimals0) - i32::from(self.decimals1));
+        if self.invert && price != 0.0 {
+            1.0 / price
+        } else {
+            price
+        }
+    }
+}
+
+
+/// parse_interval Parses a candle interval such as `30s`, `1m`, `5m` or `1h` into seconds.
+pub fn parse_interval(interval: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid interval {:?}, expected e.g. 1m, 5m or 1h", interval);
+
+    let unit_seconds = match interval.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    // The unit is a single ASCII character
+    match interval[..interval.len() - 1].parse::<u64>() {
+        Ok(count) if count > 0 => Ok(count * unit_seconds),
+        _ => Err(invalid()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_priced_swap;
+
+    #[test]
+    fn test_candle_rollover() {
+        let one = 1u128 << 96;
+        let mut candles = CandleAggregator::new(60, PriceQuote::default());
+
+        candles.push(120, 10, 0, &test_priced_swap(10, -10, one));
+        candles.push(179, 11, 3, &test_priced_swap(1, -1, one / 2));
+        candles.push(180, 12, 0, &test_priced_swap(7, -7, one));
+        // Came in late, but the 120 bucket is still open, and its close stays the swap of block 11
+        candles.push(130, 11, 1, &test_priced_swap(-5, 5, 2 * one));
+
+        // The head must be more than one block past block 11
+        assert_eq!(candles.close_before(12), vec![]);
+        let closed = candles.close_before(13);
+        assert_eq!(closed.len(), 1);
+        let candle = &closed[0];
+        assert_eq!(candle.open_time, 120);
+        assert_eq!((candle.open, candle.high, candle.low, candle.close), (1.0, 4.0, 0.25, 0.25));
+        assert_eq!((candle.volume0, candle.volume1, candle.swaps), (16, 16, 3));
+
+        // The 120 bucket was closed, its late swaps are dropped
+        candles.push(100, 9, 0, &test_priced_swap(1_000, -1_000, 100 * one));
+        // The latest bucket stays open until a later one is opened
+        assert_eq!(candles.close_before(100), vec![]);
+        let flushed: Vec<_> = candles.flush().iter().map(|candle| (candle.open_time, candle.swaps)).collect();
+        assert_eq!(flushed, vec![(180, 1)]);
+    }
+
+    #[test]
+    fn test_open_is_the_first_swap_by_position() {
+        let one = 1u128 << 96;
+        let mut candles = CandleAggregator::new(60, PriceQuote::default());
+        candles.push(125, 10, 5, &test_priced_swap(1, -1, 2 * one));
+        candles.push(120, 10, 2, &test_priced_swap(1, -1, one));
+
+        let candle = candles.flush().remove(0);
+        assert_eq!((candle.open, candle.close), (1.0, 4.0));
+    }
+
+    #[test]
+    fn test_price_quote_usdc_weth() {
+        // sqrtPriceX96 after the Swap in tx 0x13f84c56...00f3 of the USDC/WETH pool
+        let sqrt_price_x96 = U256::from(1996611740862433600358475292128498u128);
+
+        let weth_per_usdc = PriceQuote { invert: false, decimals0: 6, decimals1: 18 };
+        assert!((weth_per_usdc.price(sqrt_price_x96) - 0.000635079).abs() < 1e-9);
+
+        let usdc_per_weth = PriceQuote { invert: true, ..weth_per_usdc };
+        assert!((usdc_per_weth.price(sqrt_price_x96) - 1574.606).abs() < 1e-3);
+
+        // Without decimals the price stays in raw units
+        assert!((PriceQuote::default().price(sqrt_price_x96) - 635079470.08).abs() < 1e-2);
+        assert_eq!(usdc_per_weth.price(U256::zero()), 0.0);
+    }
+
+    #[test]
+    fn test_price_from_tick_matches_sqrt_price() {
+        // The Swap in tx 0x13f84c56...00f3 of the USDC/WETH pool
+        let (tick, sqrt_price_x96) = (202702, U256::from(1996611740862433600358475292128498u128));
+
+        let from_tick = price_from_tick(tick, 6, 18);
+        let from_sqrt_price = price_from_sqrt_price_x96(sqrt_price_x96, 6, 18);
+        assert!(from_tick <= from_sqrt_price);
+        assert!((from_sqrt_price / from_tick - 1.0).abs() < TICK_PRICE_TOLERANCE);
+        assert!(tick_matches_price(tick, sqrt_price_x96));
+
+        // A tick decoded with the wrong sign is flagged
+        assert!(!tick_matches_price(-tick, sqrt_price_x96));
+        assert!(!tick_matches_price(tick + 10, sqrt_price_x96));
+        assert_eq!(price_from_tick(0, 0, 0), 1.0);
+
+        // Uniswap's MAX_SQRT_RATIO, at the highest tick, is above what a u128 holds
+        let max_sqrt_ratio = U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap();
+        assert!(tick_matches_price(887272, max_sqrt_ratio));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("1m"), Ok(60));
+        assert_eq!(parse_interval("5m"), Ok(300));
+        assert_eq!(parse_interval("1h"), Ok(3600));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("5").is_err());
+        assert!(parse_interval("").is_err());
+    }
+}
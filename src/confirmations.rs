@@ -0,0 +1,282 @@
+use ethers::types::{Log, H256, U256};
+use lru::LruCache;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+
+use crate::data_store::DecodedData;
+
+
+// Identifies a log within its block: the transaction hash and the log index.
+pub(crate) type LogKey = (Option<H256>, Option<U256>);
+
+/// PendingBuffer Holds decoded swaps until their block is deep enough to be stored.
+///
+/// Logs near the head can still be reorged out, so instead of storing them right away
+/// they are kept here keyed by block number, and released by `drain_confirmed` once
+/// `latest_block - log_block >= confirmations`. A log the provider reports as `removed`
+/// is dropped from the buffer so it never reaches storage. The other decoded events are
+/// held back the same way in a buffer of their own.
+pub struct PendingBuffer<T = DecodedData> {
+    confirmations: u64,
+    pending: BTreeMap<u64, Vec<(LogKey, T)>>,
+}
+
+impl<T> PendingBuffer<T> {
+    pub fn new(confirmations: u64) -> Self {
+        PendingBuffer { confirmations, pending: BTreeMap::new() }
+    }
+
+    /// Buffer the swap decoded from `log` until it is confirmed.
+    pub fn push(&mut self, log: &Log, data: T) {
+        let block_number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+        self.pending
+            .entry(block_number)
+            .or_default()
+            .push(((log.transaction_hash, log.log_index), data));
+    }
+
+    /// Drop the buffered swap decoded from `log`, which has been removed by a reorg.
+    /// Returns whether a swap was dropped.
+    pub fn remove(&mut self, log: &Log) -> bool {
+        let block_number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+        let key = (log.transaction_hash, log.log_index);
+
+        let Some(swaps) = self.pending.get_mut(&block_number) else {
+            return false;
+        };
+        let before = swaps.len();
+        swaps.retain(|(swap_key, _)| *swap_key != key);
+        let removed = swaps.len() != before;
+        if swaps.is_empty() {
+            self.pending.remove(&block_number);
+        }
+        removed
+    }
+
+    /// Drop the buffered swaps of block `first_block` and later, which a reorg replaced,
+    /// returning the keys of their logs.
+    pub(crate) fn remove_from(&mut self, first_block: u64) -> Vec<LogKey> {
+        self.pending
+            .split_off(&first_block)
+            .into_values()
+            .flat_map(|swaps| swaps.into_iter().map(|(key, _)| key))
+            .collect()
+    }
+
+    /// Take every buffered swap with at least `confirmations` blocks on top of it,
+    /// oldest block first, along with its block number.
+    pub fn drain_confirmed(&mut self, latest_block: u64) -> Vec<(u64, T)> {
+        let Some(max_block) = latest_block.checked_sub(self.confirmations) else {
+            return Vec::new();
+        };
+        let still_pending = self.pending.split_off(&max_block.saturating_add(1));
+        let confirmed = std::mem::replace(&mut self.pending, still_pending);
+
+        confirmed
+            .into_iter()
+            .flat_map(|(block_number, swaps)| swaps.into_iter().map(move |(_, data)| (block_number, data)))
+            .collect()
+    }
+}
+
+
+/// SeenLogs The logs already handed to the writer, to drop the ones delivered twice,
+/// e.g. by a provider resending the logs around a reconnect.
+///
+/// Only the `capacity` most recently seen logs are remembered, so that a long-running
+/// watch doesn't grow without bound. The default covers a deep reorg plus the overlap of
+/// a backfill with the live stream on a busy pool. The trade-off: a log seen longer ago
+/// than that is taken as new if it somehow reappears, and stored again. Logs without a
+/// transaction hash or log index can't be told apart and always count as new.
+pub struct SeenLogs {
+    seen: LruCache<(H256, U256), ()>,
+}
+
+impl SeenLogs {
+    /// The default number of logs remembered.
+    pub const DEFAULT_CAPACITY: usize = 65_536;
+
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        SeenLogs { seen: LruCache::new(capacity) }
+    }
+
+    /// Remember `log`, returning false when it was already seen.
+    pub fn insert(&mut self, log: &Log) -> bool {
+        let (Some(transaction_hash), Some(log_index)) = (log.transaction_hash, log.log_index) else { return true };
+        self.seen.put((transaction_hash, log_index), ()).is_none()
+    }
+
+    /// Forget `log`, which a reorg removed, so it is taken as new if it's included again.
+    pub fn forget(&mut self, log: &Log) {
+        self.forget_key((log.transaction_hash, log.log_index));
+    }
+
+    pub(crate) fn forget_key(&mut self, key: LogKey) {
+        if let (Some(transaction_hash), Some(log_index)) = key {
+            self.seen.pop(&(transaction_hash, log_index));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+
+/// Reorg Blocks replaced by a reorg, from `first_block` to the latest one seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reorg {
+    pub first_block: u64,
+    /// How many of the blocks seen were replaced.
+    pub depth: u64,
+}
+
+/// BlockHashes The hashes of the latest blocks seen, to notice reorgs the provider
+/// doesn't flag with `removed`: a block number seen again with another hash was replaced,
+/// along with every block after it.
+///
+/// Only the `capacity` latest blocks are remembered, a reorg deeper than that goes
+/// unnoticed.
+pub struct BlockHashes {
+    hashes: BTreeMap<u64, H256>,
+    capacity: usize,
+}
+
+impl BlockHashes {
+    /// The default number of blocks remembered, far deeper than mainnet reorgs go.
+    pub const DEFAULT_CAPACITY: usize = 128;
+
+    pub fn new(capacity: usize) -> Self {
+        BlockHashes { hashes: BTreeMap::new(), capacity: capacity.max(1) }
+    }
+
+    /// Note block `number` has hash `hash`. When another hash was noted for it, the
+    /// blocks from `number` on are forgotten and returned as the reorg.
+    pub fn insert(&mut self, number: u64, hash: H256) -> Option<Reorg> {
+        let reorg = match self.hashes.get(&number) {
+            Some(seen) if *seen != hash => {
+                let latest = self.latest().unwrap_or(number);
+                self.forget_from(number);
+                Some(Reorg { first_block: number, depth: latest - number + 1 })
+            }
+            _ => None,
+        };
+        self.hashes.insert(number, hash);
+        while self.hashes.len() > self.capacity {
+            self.hashes.pop_first();
+        }
+        reorg
+    }
+
+    /// The hash noted for block `number`, if it's still remembered.
+    pub fn get(&self, number: u64) -> Option<H256> {
+        self.hashes.get(&number).copied()
+    }
+
+    /// The highest block number noted.
+    pub fn latest(&self) -> Option<u64> {
+        self.hashes.keys().next_back().copied()
+    }
+
+    /// Forget the blocks from `first_block` on.
+    pub fn forget_from(&mut self, first_block: u64) {
+        self.hashes.split_off(&first_block);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use ethers::types::U64;
+
+    fn log(block_number: u64, tx: u8) -> Log {
+        Log {
+            block_number: Some(U64::from(block_number)),
+            transaction_hash: Some(H256::repeat_byte(tx)),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_drain_confirmed() {
+        let mut buffer = PendingBuffer::new(3);
+        buffer.push(&log(100, 1), test_swap("a", 0, 0));
+        buffer.push(&log(101, 2), test_swap("b", 0, 0));
+        buffer.push(&log(102, 3), test_swap("c", 0, 0));
+
+        assert!(buffer.drain_confirmed(102).is_empty());
+
+        let confirmed = buffer.drain_confirmed(104);
+        let hashes: Vec<_> = confirmed.iter().map(|(_, d)| d.transaction_hash.as_str()).collect();
+        assert_eq!(hashes, vec!["a", "b"]);
+        assert_eq!(buffer.drain_confirmed(u64::MAX).len(), 1);
+    }
+
+    #[test]
+    fn test_removed_log_is_dropped() {
+        let mut buffer = PendingBuffer::new(1);
+        buffer.push(&log(100, 1), test_swap("a", 0, 0));
+        buffer.push(&log(100, 2), test_swap("b", 0, 0));
+
+        assert!(buffer.remove(&log(100, 1)));
+        assert!(!buffer.remove(&log(100, 9)));
+
+        let confirmed = buffer.drain_confirmed(101);
+        assert_eq!(confirmed.len(), 1);
+        assert_eq!(confirmed[0].0, 100);
+        assert_eq!(confirmed[0].1.transaction_hash, "b");
+    }
+
+    #[test]
+    fn test_remove_from() {
+        let mut buffer = PendingBuffer::new(5);
+        buffer.push(&log(100, 1), test_swap("a", 0, 0));
+        buffer.push(&log(101, 2), test_swap("b", 0, 0));
+        buffer.push(&log(102, 3), test_swap("c", 0, 0));
+
+        let key = |tx: u8| (Some(H256::repeat_byte(tx)), Some(U256::zero()));
+        assert_eq!(buffer.remove_from(101), vec![key(2), key(3)]);
+        let confirmed = buffer.drain_confirmed(u64::MAX);
+        assert_eq!(confirmed.len(), 1);
+        assert_eq!(confirmed[0].1.transaction_hash, "a");
+    }
+
+    #[test]
+    fn test_block_hashes_detect_reorgs() {
+        let mut hashes = BlockHashes::new(3);
+        for number in 100..=104 {
+            assert_eq!(hashes.insert(number, H256::repeat_byte(number as u8)), None);
+        }
+        assert_eq!((hashes.get(101), hashes.latest()), (None, Some(104)));
+        assert_eq!(hashes.insert(104, H256::repeat_byte(104)), None);
+
+        // Block 103 came back with another hash, replacing 103 and 104
+        assert_eq!(hashes.insert(103, H256::repeat_byte(0xff)), Some(Reorg { first_block: 103, depth: 2 }));
+        assert_eq!((hashes.get(104), hashes.latest()), (None, Some(103)));
+    }
+
+    #[test]
+    fn test_seen_logs_stay_within_capacity() {
+        let mut seen = SeenLogs::new(3);
+        for tx in 1..=10 {
+            assert!(seen.insert(&log(100, tx)));
+            assert!(seen.len() <= 3);
+        }
+        // Seen again while remembered, and forgotten once evicted
+        assert!(!seen.insert(&log(100, 10)));
+        assert!(seen.insert(&log(100, 1)));
+
+        seen.forget(&log(100, 1));
+        assert!(seen.insert(&log(100, 1)));
+        assert!(seen.insert(&Log::default()));
+        assert!(seen.insert(&Log::default()));
+    }
+}
@@ -1,62 +1,723 @@
-use std::io::{self, Write};
-use std::path::Path;
-use serde_json;
-use chrono::{Utc, NaiveDateTime, Datelike};
+//! Storage of the decoded swaps.
+//!
+//! Every backend implements `DataStore` and is compiled in only when its cargo
+//! feature is enabled, so users pay only for the dependencies they use:
+//!
+//! | Feature          | Backend                        | Store       |
+//! |------------------|--------------------------------|-------------|
+//! | `json` (default) | Newline-delimited JSON files   | `JsonStore` |
+//! | `postgres`       | `swaps` table of a Postgres DB | `PostgresStore` |
+//! | `kafka`          | JSON messages on a Kafka topic | `KafkaStore` |
+//! | `parquet`        | Parquet files by day and pool  | `ParquetStore` |
+//!
+//! Files written by `JsonStore` are read back with `load_decoded_data`, or streamed
+//! record by record with `read_decoded_data`.
+//!
+//! `JsonStore` can write a subset of the fields only, picked with a `FieldProjection`.
+//! Such files no longer load as `DecodedData`. Its field names are snake_case, or
+//! another `FieldNaming`, which all load back. Its files hold JSON lines, or one JSON
+//! array per file, optionally pretty-printed, see `JsonLayout`.
+//!
+//! The other decoded events, e.g. token transfers, are stored as `EventRecord`s with
+//! `DataStore::store_event`, by the JSON and in-memory stores.
+//!
+//! `InMemoryStore` has no dependencies and is always available, for tests and embedders,
+//! as is `DryRunStore`, which only prints and counts what would be stored.
+//!
+//! The `--store` flag selects the backends at runtime and fails with a clear error
+//! when a feature wasn't compiled in. Given several, `FanOutStore` writes every swap to
+//! each of them.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Utc};
 use serde::{Serialize, Deserialize};
-use crate::utils;
+use crate::error::TaskError;
+use ethers::abi::RawLog;
+use ethers::types::{Log, H256, U256};
+use crate::log_processing::{to_checksum_address, to_hex};
+
+mod dry_run;
+pub use dry_run::DryRunStore;
+
+mod fan_out;
+pub use fan_out::FanOutStore;
+
+mod memory;
+pub use memory::InMemoryStore;
+
+mod retry;
+pub use retry::{RetryPolicy, RetryingStore};
 
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+pub use json::{load_decoded_data, read_decoded_data, validate_date_format, DecodedDataReader, JsonStore};
+#[cfg(feature = "json")]
+pub(crate) use json::is_json_array;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaStore;
+
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "parquet")]
+pub use self::parquet::{load_parquet_swaps, ParquetStore};
+
+
+/// DecodedData A decoded Uniswap V3 swap.
+///
+/// The amounts are the pool's balance changes, as in the `Swap` event: positive when the
+/// token flowed into the pool, from the sender, and negative when it flowed out, to the
+/// recipient. `zero_for_one` spells the direction out, see
+/// https://docs.uniswap.org/contracts/v3/reference/core/interfaces/pool/IUniswapV3PoolEvents#swap
+///
+/// The amounts, `sqrtPriceX96` and `liquidity` are serialized as decimal strings, since
+/// JSON consumers such as JavaScript parse numbers as doubles and lose precision above
+/// 2^53. Integers are still accepted when deserializing, for files written before.
+/// `sqrtPriceX96` is a uint160, so it's kept in a `U256` rather than a `u128`.
+///
+/// Every field is serialized in snake_case, `sqrtPriceX96` as `sqrt_price_x96`. The
+/// camelCase names of `FieldNaming::Camel`, and `sqrtPriceX96` as written before, are
+/// accepted when deserializing.
+// `sqrtPriceX96` is named after the event parameter
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DecodedData {
+    #[serde(alias = "transactionHash")]
     pub transaction_hash: String,
     pub sender: String,
     pub recipient: String,
+    #[serde(with = "decimal_string")]
     pub amount0: i128,
+    #[serde(with = "decimal_string")]
     pub amount1: i128,
-    pub sqrtPriceX96: u128,
+    #[serde(rename = "sqrt_price_x96", alias = "sqrtPriceX96", with = "decimal_u256")]
+    pub sqrtPriceX96: U256,
+    #[serde(with = "decimal_string")]
     pub liquidity: u128,
     pub tick: i32,
+    /// True when token0 was sold for token1: token0 went into the pool and token1 out,
+    /// moving the price down. Derived from the amounts by `is_zero_for_one`. Files
+    /// written before it existed lack it and get it rederived when read.
+    #[serde(default, alias = "zeroForOne")]
+    pub zero_for_one: bool,
+    /// The absolute `amount0`, the size of the swap in token0 whichever way it went, so
+    /// consumers don't have to rederive it. Derived when decoding, and rederived when
+    /// reading files written before it existed, like `zero_for_one`.
+    #[serde(default, alias = "amount0Abs", with = "decimal_string")]
+    pub amount0_abs: u128,
+    /// The absolute `amount1`, like `amount0_abs`.
+    #[serde(default, alias = "amount1Abs", with = "decimal_string")]
+    pub amount1_abs: u128,
+    /// Every parameter of the decoded event stringified by `token_to_string`, keyed by
+    /// its ABI name, for fields the struct doesn't model. Only set when enabled with
+    /// `DecoderRegistry::set_raw_params`.
+    #[serde(default, alias = "rawParams", skip_serializing_if = "Option::is_none")]
+    pub raw_params: Option<HashMap<String, String>>,
+    /// The log the swap was decoded from, to decode it again later with another ABI.
+    /// Only set when enabled with `DecoderRegistry::set_keep_raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawLogHex>,
+    /// Gas used by the swap's transaction, shared by every swap in it. Only set when
+    /// enabled with `--gas`, which looks the transaction receipt up.
+    #[serde(default, alias = "gasUsed", skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<u64>,
+    /// The price the swap's transaction paid per unit of gas, in wei, set like `gas_used`.
+    #[serde(default, alias = "effectiveGasPrice", skip_serializing_if = "Option::is_none")]
+    pub effective_gas_price: Option<u64>,
+    /// The address of the pool that emitted the swap, taken from the log, which the stores
+    /// file the swap under. Lowercase hex unless addresses are checksummed, like `sender`.
+    /// Empty in files written before it existed.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pool: String,
+    /// The index of the log within its block, which tells the swaps of one transaction
+    /// apart. None in files written before it existed.
+    #[serde(default, alias = "logIndex", skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<u64>,
 }
 
+impl DecodedData {
+    /// The names of the serialized fields, in order.
+    pub const FIELDS: [&'static str; 17] = [
+        "transaction_hash",
+        "sender",
+        "recipient",
+        "amount0",
+        "amount1",
+        "sqrt_price_x96",
+        "liquidity",
+        "tick",
+        "zero_for_one",
+        "amount0_abs",
+        "amount1_abs",
+        "raw_params",
+        "raw",
+        "gas_used",
+        "effective_gas_price",
+        "pool",
+        "log_index",
+    ];
 
-pub fn store_decoded_data(address: &str, data: &DecodedData) -> Result<(), io::Error> {
-    let root_directory = match utils::root_dir() {
-        Some(dir) => dir,
-        None => {
-            eprintln!("Error: Root directory not found");
-            return Err(io::Error::new(io::ErrorKind::Other, "Root directory not found"));
+    /// Whether a swap with these amounts sold token0 for token1. A positive `amount0`
+    /// was paid into the pool. When rounding leaves `amount0` at 0, the token1 paid out
+    /// tells the direction.
+    pub fn is_zero_for_one(amount0: i128, amount1: i128) -> bool {
+        if amount0 != 0 {
+            amount0 > 0
+        } else {
+            amount1 < 0
         }
-    };
+    }
 
-    // Construct the full path to the data directory using the root directory
-    let data_dir = format!("{}/data", root_directory);
+    /// Derive `zero_for_one`, `amount0_abs` and `amount1_abs` again from the amounts, for
+    /// swaps read from files written before they existed.
+    pub fn derive_direction(&mut self) {
+        self.zero_for_one = DecodedData::is_zero_for_one(self.amount0, self.amount1);
+        self.amount0_abs = self.amount0.unsigned_abs();
+        self.amount1_abs = self.amount1.unsigned_abs();
+    }
 
-    // Check if the directory exists, and create it if it doesn't
-    if !std::path::Path::new(&data_dir).exists() {
-        if let Err(err) = std::fs::create_dir_all(&data_dir) {
-            eprintln!("Error: Failed to create data directory: {}", err);
-            return Err(err);
+    /// Rewrite `sender`, `recipient` and `pool` in their EIP-55 checksummed form.
+    /// Addresses that aren't valid hex are left untouched.
+    pub fn checksum_addresses(&mut self) {
+        for address in [&mut self.sender, &mut self.recipient, &mut self.pool] {
+            if let Ok(bytes) = hex::decode(address.trim_start_matches("0x")) {
+                *address = to_checksum_address(&bytes);
+            }
         }
     }
+}
+
+/// test_swap A swap of `tx` with these amounts, its direction and absolute amounts derived
+/// from them as when decoding, for the tests to build on with struct update syntax.
+#[cfg(test)]
+pub(crate) fn test_swap(tx: &str, amount0: i128, amount1: i128) -> DecodedData {
+    DecodedData {
+        transaction_hash: tx.to_string(),
+        amount0,
+        amount1,
+        zero_for_one: DecodedData::is_zero_for_one(amount0, amount1),
+        amount0_abs: amount0.unsigned_abs(),
+        amount1_abs: amount1.unsigned_abs(),
+        ..DecodedData::default()
+    }
+}
+
+/// test_priced_swap A `test_swap` at the price of `sqrt_price_x96`, for the candle and
+/// block summary tests.
+#[cfg(test)]
+pub(crate) fn test_priced_swap(amount0: i128, amount1: i128, sqrt_price_x96: u128) -> DecodedData {
+    DecodedData { sqrtPriceX96: U256::from(sqrt_price_x96), ..test_swap("", amount0, amount1) }
+}
+
+
+/// EventRecord A decoded event other than a Uniswap V3 swap, as stored: the name of the
+/// event, the contract that emitted it and its decoded fields, e.g. a token transfer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub event: String,
+    pub address: String,
+    pub fields: serde_json::Value,
+}
+
+
+/// RawLogHex The topics and data of a log as 0x-prefixed hex strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawLogHex {
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+impl RawLogHex {
+    /// The topics and data of `log`.
+    pub fn of(log: &Log) -> Self {
+        RawLogHex { topics: log.topics.iter().map(|topic| to_hex(topic.as_bytes())).collect(), data: to_hex(&log.data) }
+    }
+
+    /// The log as `Event::parse_log` takes it, to decode it again.
+    pub fn to_raw_log(&self) -> Result<RawLog, hex::FromHexError> {
+        let decode = |value: &str| hex::decode(value.trim_start_matches("0x"));
+        let topics = self
+            .topics
+            .iter()
+            .map(|topic| match decode(topic)? {
+                bytes if bytes.len() == 32 => Ok(H256::from_slice(&bytes)),
+                _ => Err(hex::FromHexError::InvalidStringLength),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RawLog { topics, data: decode(&self.data)? })
+    }
+}
+
+
+/// FieldNaming The convention of the field names `JsonStore` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldNaming {
+    /// `transaction_hash`, `sqrt_price_x96`, as `DecodedData` serializes.
+    #[default]
+    Snake,
+    /// `transactionHash`, `sqrtPriceX96`.
+    Camel,
+    /// The names written before they were made consistent: snake_case but for `sqrtPriceX96`.
+    Raw,
+}
 
-    // Get the current date and format it as yyyy_mm_dd
-    let now = Utc::now().naive_utc();
-    let formatted_date = format!("{}_{}_{}", now.year(), now.month(), now.day());
+impl FieldNaming {
+    /// The name of the serialized `field`, one of `DecodedData::FIELDS`, in this convention.
+    pub fn name(&self, field: &str) -> String {
+        match self {
+            FieldNaming::Snake => field.to_string(),
+            FieldNaming::Camel => to_camel_case(field),
+            FieldNaming::Raw if field == "sqrt_price_x96" => "sqrtPriceX96".to_string(),
+            FieldNaming::Raw => field.to_string(),
+        }
+    }
+}
 
-    // Create the filename using the address and date
-    let filename = format!("{}/{}_{}_decoded_swaps.json", data_dir, address, formatted_date);
+// `sqrt_price_x96` as `sqrtPriceX96`.
+fn to_camel_case(field: &str) -> String {
+    let mut words = field.split('_');
+    let first = words.next().unwrap_or_default().to_string();
+    words.fold(first, |mut name, word| {
+        let mut chars = word.chars();
+        if let Some(initial) = chars.next() {
+            name.extend(initial.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+        name
+    })
+}
 
-    // Serialize the data to JSON
-    let json = serde_json::to_string(&data)?;
+impl FromStr for FieldNaming {
+    type Err = String;
 
-    // Check if the file exists. If it does, append a newline before the new JSON entry.
-    // If not, just write the JSON entry to the new file.
-    if Path::new(&filename).exists() {
-        let mut file = std::fs::OpenOptions::new().append(true).open(filename)?;
-        writeln!(file, "\n{}", json)?;
-    } else {
-        std::fs::write(&filename, json)?;
+    fn from_str(naming: &str) -> Result<Self, Self::Err> {
+        match naming.trim().to_lowercase().as_str() {
+            "snake" | "snake_case" => Ok(FieldNaming::Snake),
+            "camel" | "camelcase" => Ok(FieldNaming::Camel),
+            "raw" => Ok(FieldNaming::Raw),
+            _ => Err(format!("Invalid field naming {:?}, expected snake, camel or raw", naming)),
+        }
     }
+}
 
-    Ok(())
+impl fmt::Display for FieldNaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FieldNaming::Snake => "snake",
+            FieldNaming::Camel => "camel",
+            FieldNaming::Raw => "raw",
+        })
+    }
+}
+
+
+/// FieldProjection The `DecodedData` fields to keep in the output, in the order given.
+///
+/// Built from the `--fields` list, and fails on a name `DecodedData` doesn't serialize so
+/// that a typo is reported at startup instead of silently dropping the column. Names are
+/// taken in any `FieldNaming`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldProjection {
+    fields: Vec<&'static str>,
+}
+
+impl FieldProjection {
+    /// The projection on every field, to rename them.
+    pub fn all() -> Self {
+        FieldProjection { fields: DecodedData::FIELDS.to_vec() }
+    }
+
+    /// The projection on `names`, each one of `DecodedData::FIELDS`.
+    pub fn new<S: AsRef<str>>(names: &[S]) -> Result<Self, TaskError> {
+        if names.is_empty() {
+            return Err(TaskError::InvalidConfig("--fields needs at least one field".to_string()));
+        }
+        let namings = [FieldNaming::Snake, FieldNaming::Camel, FieldNaming::Raw];
+        let fields = names
+            .iter()
+            .map(|name| {
+                let name = name.as_ref().trim();
+                let is_named = |field: &str| namings.iter().any(|naming| naming.name(field) == name);
+                DecodedData::FIELDS.iter().copied().find(|field| is_named(field)).ok_or_else(|| {
+                    TaskError::InvalidConfig(format!(
+                        "unknown field `{}` in --fields, expected one of {}",
+                        name,
+                        DecodedData::FIELDS.join(", ")
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(FieldProjection { fields })
+    }
+
+    /// The JSON object of the projected fields of `data`, in the projection's order.
+    /// Fields skipped when serializing, such as an unset `raw_params`, are left out.
+    pub fn to_json(&self, data: &DecodedData) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.project(data, FieldNaming::Snake)?)
+    }
+
+    // The projected fields of `data` named in `naming`, to be serialized in any JSON style.
+    pub(crate) fn project(&self, data: &DecodedData, naming: FieldNaming) -> Result<Projected, serde_json::Error> {
+        let mut full = match serde_json::to_value(data)? {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!("DecodedData serializes to an object"),
+        };
+        let values: Vec<_> = self
+            .fields
+            .iter()
+            .filter_map(|field| full.remove(*field).map(|value| (naming.name(field), value)))
+            .collect();
+        Ok(Projected(values))
+    }
+}
+
+// Serializes as an object keeping the order of its entries, which a `serde_json::Map`
+// sorts by key.
+pub(crate) struct Projected(Vec<(String, serde_json::Value)>);
+
+impl Serialize for Projected {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (field, value) in &self.0 {
+            map.serialize_entry(field, value)?;
+        }
+        map.end()
+    }
+}
+
+
+/// JsonLayout How `JsonStore` lays out the records of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonLayout {
+    /// One compact JSON record per line (NDJSON), for streaming and appending.
+    #[default]
+    Lines,
+    /// One JSON array per file, closed again after every write. Can be pretty-printed
+    /// to be read by eye.
+    Array,
+}
+
+impl FromStr for JsonLayout {
+    type Err = String;
+
+    fn from_str(layout: &str) -> Result<Self, Self::Err> {
+        match layout.trim().to_lowercase().as_str() {
+            "lines" | "ndjson" => Ok(JsonLayout::Lines),
+            "array" => Ok(JsonLayout::Array),
+            _ => Err(format!("Invalid JSON layout {:?}, expected lines or array", layout)),
+        }
+    }
+}
+
+impl fmt::Display for JsonLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JsonLayout::Lines => "lines",
+            JsonLayout::Array => "array",
+        })
+    }
+}
+
+
+/// PartitionTimezone The timezone whose days the JSON files are split by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartitionTimezone {
+    #[default]
+    Utc,
+    /// The timezone of the machine, following its daylight saving changes.
+    Local,
+    /// A fixed offset from UTC, e.g. `+02:00`.
+    Offset(FixedOffset),
+}
+
+impl PartitionTimezone {
+    /// The day `time` falls on in this timezone.
+    pub fn date_of(&self, time: DateTime<Utc>) -> NaiveDate {
+        match self {
+            PartitionTimezone::Utc => time.date_naive(),
+            PartitionTimezone::Local => time.with_timezone(&Local).date_naive(),
+            PartitionTimezone::Offset(offset) => time.with_timezone(offset).date_naive(),
+        }
+    }
+}
+
+/// Parses `utc`, `local` or an offset such as `+02:00` or `-0530`.
+impl FromStr for PartitionTimezone {
+    type Err = String;
+
+    fn from_str(timezone: &str) -> Result<Self, Self::Err> {
+        match timezone.trim().to_lowercase().as_str() {
+            "utc" | "z" => Ok(PartitionTimezone::Utc),
+            "local" => Ok(PartitionTimezone::Local),
+            offset => offset
+                .parse::<FixedOffset>()
+                .map(PartitionTimezone::Offset)
+                .map_err(|_| format!("Invalid timezone {:?}, expected utc, local or an offset such as +02:00", timezone)),
+        }
+    }
+}
+
+impl fmt::Display for PartitionTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionTimezone::Utc => f.write_str("utc"),
+            PartitionTimezone::Local => f.write_str("local"),
+            PartitionTimezone::Offset(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+
+// (De)serialize 128-bit integers as decimal strings, accepting plain integers too.
+pub(crate) mod decimal_string {
+    use serde::de::{self, MapAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt::{self, Display};
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr + TryFrom<i128> + TryFrom<u128>,
+        <T as FromStr>::Err: Display,
+        <T as TryFrom<i128>>::Error: Display,
+        <T as TryFrom<u128>>::Error: Display,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DecimalVisitor(PhantomData))
+    }
+
+    // The decimal text of a JSON number handed over as a map. ethers turns on serde_json's
+    // `arbitrary_precision`, which does so for the integers beyond a u64.
+    pub(super) fn number_text<'de, A: MapAccess<'de>>(mut map: A) -> Result<String, A::Error> {
+        match map.next_entry::<String, String>()? {
+            Some((_, text)) => Ok(text),
+            None => Err(de::Error::invalid_length(0, &"a JSON number")),
+        }
+    }
+
+    struct DecimalVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de> for DecimalVisitor<T>
+    where
+        T: FromStr + TryFrom<i128> + TryFrom<u128>,
+        <T as FromStr>::Err: Display,
+        <T as TryFrom<i128>>::Error: Display,
+        <T as TryFrom<u128>>::Error: Display,
+    {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an integer or a decimal string")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+            value.parse().map_err(E::custom)
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+            self.visit_i128(value.into())
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+            self.visit_u128(value.into())
+        }
+
+        fn visit_i128<E: de::Error>(self, value: i128) -> Result<T, E> {
+            T::try_from(value).map_err(E::custom)
+        }
+
+        fn visit_u128<E: de::Error>(self, value: u128) -> Result<T, E> {
+            T::try_from(value).map_err(E::custom)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+            number_text(map)?.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+// (De)serialize a `U256` as a decimal string like `decimal_string`. Its `FromStr` parses
+// hex, so strings go through `from_dec_str` instead.
+pub(crate) mod decimal_u256 {
+    use ethers::types::U256;
+    use serde::de::{self, MapAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+    use super::decimal_string::number_text;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+
+    struct DecimalVisitor;
+
+    impl<'de> Visitor<'de> for DecimalVisitor {
+        type Value = U256;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an unsigned integer or a decimal string")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<U256, E> {
+            U256::from_dec_str(value).map_err(E::custom)
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<U256, E> {
+            u64::try_from(value).map(U256::from).map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<U256, E> {
+            Ok(U256::from(value))
+        }
+
+        fn visit_u128<E: de::Error>(self, value: u128) -> Result<U256, E> {
+            Ok(U256::from(value))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<U256, A::Error> {
+            U256::from_dec_str(&number_text(map)?).map_err(de::Error::custom)
+        }
+    }
+}
+
+
+/// DataStore A destination for decoded swaps.
+///
+/// Stores are awaited from the stream loop, so implementations must do their I/O
+/// asynchronously instead of blocking the tokio worker.
+#[async_trait]
+pub trait DataStore: Send + Sync {
+    /// Store one decoded swap emitted by the contract at `address`.
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Store one decoded event other than a swap. Stores without a place for them leave
+    /// them out.
+    async fn store_event(&self, _record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Write out the swaps buffered by `store`. Called once the stream ends; stores that
+    /// write every swap right away don't need to implement it.
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_large_integers_round_trip_as_strings() {
+        let data = DecodedData {
+            transaction_hash: "0x1".to_string(),
+            amount0: -(1i128 << 60),
+            amount1: i128::MAX,
+            // The largest uint160, well above what a u128 holds
+            sqrtPriceX96: (U256::one() << 160) - 1,
+            liquidity: (1u128 << 53) + 1,
+            tick: 202702,
+            amount0_abs: 1 << 60,
+            amount1_abs: i128::MAX.unsigned_abs(),
+            ..DecodedData::default()
+        };
+
+        let json = serde_json::to_value(&data).unwrap();
+        assert_eq!(json["amount0"], "-1152921504606846976");
+        assert_eq!(json["sqrt_price_x96"], "1461501637330902918203684832716283019655932542975");
+        assert_eq!(json["liquidity"], "9007199254740993");
+        assert_eq!((&json["amount0_abs"], &json["amount1_abs"]), (&"1152921504606846976".into(), &i128::MAX.to_string().into()));
+
+        let parsed: DecodedData = serde_json::from_value(json).unwrap();
+        assert_eq!((parsed.amount0, parsed.amount1), (data.amount0, data.amount1));
+        assert_eq!((parsed.sqrtPriceX96, parsed.liquidity), (data.sqrtPriceX96, data.liquidity));
+
+        // Records written with plain numbers still load
+        let old = r#"{"transaction_hash":"0x1","sender":"","recipient":"","amount0":-5,"amount1":7,"sqrtPriceX96":0,"liquidity":9007199254740993,"tick":0}"#;
+        let parsed: DecodedData = serde_json::from_str(old).unwrap();
+        assert_eq!((parsed.amount0, parsed.liquidity, parsed.sqrtPriceX96), (-5, 9007199254740993, U256::zero()));
+        assert!(!parsed.zero_for_one);
+
+        // Integers beyond a u64 too, as in data/
+        let old = r#"{"transaction_hash":"0x1","sender":"","recipient":"","amount0":-292327288806,"amount1":185647918319068726716,"sqrtPriceX96":1996363771059938071917602930411991,"liquidity":27418491632900498175,"tick":202700}"#;
+        let parsed: DecodedData = serde_json::from_str(old).unwrap();
+        assert_eq!((parsed.amount0, parsed.amount1), (-292327288806, 185647918319068726716));
+        assert_eq!(parsed.sqrtPriceX96, U256::from_dec_str("1996363771059938071917602930411991").unwrap());
+        assert_eq!(parsed.liquidity, 27418491632900498175);
+    }
+
+    #[test]
+    fn test_field_projection() {
+        let data = DecodedData {
+            transaction_hash: "0x1".to_string(),
+            sender: "0xsender".to_string(),
+            recipient: "0xrecipient".to_string(),
+            amount0: -5,
+            amount1: 7,
+            sqrtPriceX96: U256::one() << 96,
+            liquidity: 1_000,
+            tick: -3,
+            amount0_abs: 5,
+            amount1_abs: 7,
+            ..DecodedData::default()
+        };
+
+        let projection = FieldProjection::new(&["transaction_hash", "amount0", "amount1", "raw_params"]).unwrap();
+        assert_eq!(projection.to_json(&data).unwrap(), r#"{"transaction_hash":"0x1","amount0":"-5","amount1":"7"}"#);
+
+        let reordered = FieldProjection::new(&["tick", "sender"]).unwrap();
+        assert_eq!(reordered.to_json(&data).unwrap(), r#"{"tick":-3,"sender":"0xsender"}"#);
+
+        // Fields are named in any convention, and written in the one asked for
+        let camel = FieldProjection::new(&["transactionHash", "sqrtPriceX96", "zero_for_one"]).unwrap();
+        let json = |naming| serde_json::to_string(&camel.project(&data, naming).unwrap()).unwrap();
+        assert_eq!(json(FieldNaming::Camel), r#"{"transactionHash":"0x1","sqrtPriceX96":"79228162514264337593543950336","zeroForOne":false}"#);
+        assert_eq!(json(FieldNaming::Raw), r#"{"transaction_hash":"0x1","sqrtPriceX96":"79228162514264337593543950336","zero_for_one":false}"#);
+        let parsed: DecodedData = serde_json::from_str(&serde_json::to_string(&FieldProjection::all().project(&data, FieldNaming::Camel).unwrap()).unwrap()).unwrap();
+        assert_eq!((parsed.transaction_hash.as_str(), parsed.sqrtPriceX96), ("0x1", U256::one() << 96));
+
+        let err = FieldProjection::new(&["amount0", "price"]).unwrap_err();
+        assert!(err.to_string().contains("unknown field `price`"));
+        assert!(FieldProjection::new::<&str>(&[]).is_err());
+    }
+
+    #[test]
+    fn test_partition_timezone() {
+        // 23:30 UTC on March 5th is already March 6th two hours east
+        let time = Utc.with_ymd_and_hms(2024, 3, 5, 23, 30, 0).unwrap();
+        let utc: PartitionTimezone = "utc".parse().unwrap();
+        let east: PartitionTimezone = "+02:00".parse().unwrap();
+        let west: PartitionTimezone = "-0530".parse().unwrap();
+
+        assert_eq!(utc.date_of(time), NaiveDate::from_ymd_opt(2024, 3, 5).unwrap());
+        assert_eq!(east.date_of(time), NaiveDate::from_ymd_opt(2024, 3, 6).unwrap());
+        assert_eq!(west.date_of(time), NaiveDate::from_ymd_opt(2024, 3, 5).unwrap());
+        assert_eq!(east.to_string(), "+02:00");
+        assert_eq!("Local".parse(), Ok(PartitionTimezone::Local));
+        assert!("Europe/Paris".parse::<PartitionTimezone>().is_err());
+    }
 }
@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::block_summaries::{BlockSummary, BlockSummaryStore};
+use crate::candles::{Candle, CandleStore};
+use crate::data_store::{DataStore, DecodedData, EventRecord};
+use crate::numeraire::{NumeraireVolume, VolumeStore};
+
+
+/// DryRunStore Prints what would be stored to stdout and counts it, writing nothing.
+///
+/// Stands in for every storage sink with `--dry-run`, so a new ABI or pool can be checked
+/// end to end without leaving files or rows behind. Clones share the counts, which
+/// `Display` reports. A `quiet` store only counts, for `--count-only`.
+#[derive(Debug, Clone, Default)]
+pub struct DryRunStore {
+    swaps: Arc<AtomicU64>,
+    events: Arc<AtomicU64>,
+    candles: Arc<AtomicU64>,
+    volumes: Arc<AtomicU64>,
+    blocks: Arc<AtomicU64>,
+    quiet: bool,
+}
+
+impl DryRunStore {
+    pub fn new() -> Self {
+        DryRunStore::default()
+    }
+
+    /// A store counting what would be stored without printing it.
+    pub fn quiet() -> Self {
+        DryRunStore { quiet: true, ..DryRunStore::default() }
+    }
+
+    /// How many swaps would have been stored.
+    pub fn swaps(&self) -> u64 {
+        self.swaps.load(Ordering::Relaxed)
+    }
+
+    /// How many events other than swaps would have been stored.
+    pub fn events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    /// How many candles would have been stored.
+    pub fn candles(&self) -> u64 {
+        self.candles.load(Ordering::Relaxed)
+    }
+
+    /// How many numeraire volumes would have been stored.
+    pub fn volumes(&self) -> u64 {
+        self.volumes.load(Ordering::Relaxed)
+    }
+
+    /// How many block summaries would have been stored.
+    pub fn blocks(&self) -> u64 {
+        self.blocks.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for DryRunStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dry run: {} swaps, {} other events, {} candles, {} volumes and {} block summaries would have been stored",
+            self.swaps(),
+            self.events(),
+            self.candles(),
+            self.volumes(),
+            self.blocks()
+        )
+    }
+}
+
+#[async_trait]
+impl DataStore for DryRunStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.quiet {
+            println!("Would store swap of {}: {}", address, serde_json::to_string(data)?);
+        }
+        self.swaps.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn store_event(&self, record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.quiet {
+            println!("Would store {} event of {}: {}", record.event, record.address, serde_json::to_string(&record.fields)?);
+        }
+        self.events.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CandleStore for DryRunStore {
+    async fn store_candle(&self, address: &str, candle: &Candle) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.quiet {
+            println!("Would store candle of {}: {}", address, serde_json::to_string(candle)?);
+        }
+        self.candles.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VolumeStore for DryRunStore {
+    async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.quiet {
+            println!("Would store volume of {}: {}", address, serde_json::to_string(volume)?);
+        }
+        self.volumes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockSummaryStore for DryRunStore {
+    async fn store_block_summary(&self, address: &str, summary: &BlockSummary) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.quiet {
+            println!("Would store block summary of {}: {}", address, serde_json::to_string(summary)?);
+        }
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+
+    #[tokio::test]
+    async fn test_counts_what_would_be_stored() {
+        let dry_run = DryRunStore::new();
+        let store: Box<dyn DataStore> = Box::new(dry_run.clone());
+        let swap = test_swap("0x1", 1, -1);
+        store.store("0xpool", &swap).await.unwrap();
+        store.store("0xpool", &swap).await.unwrap();
+
+        assert_eq!((dry_run.swaps(), dry_run.candles(), dry_run.blocks()), (2, 0, 0));
+        assert_eq!(dry_run.to_string(), "Dry run: 2 swaps, 0 other events, 0 candles, 0 volumes and 0 block summaries would have been stored");
+    }
+}
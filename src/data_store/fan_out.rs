@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use crate::data_store::{DataStore, DecodedData, EventRecord};
+
+
+/// FanOutStore Stores every swap in each of several sinks, e.g. JSON files, Kafka and
+/// Postgres at once.
+///
+/// Every sink has its own queue, written to the sink in order by its own spawned task,
+/// like `WebhookStore` does. `store` only queues, so a slow or failing sink, e.g. one
+/// backing off in a `RetryingStore`, never holds up the writer or the other sinks. Once
+/// `QUEUE_CAPACITY` records wait for a sink, it misses the next ones, which fails the
+/// call naming it. A sink failing to write a record logs it and goes on; `flush` waits
+/// for every queue to drain and returns these failures, each under the name of its sink.
+pub struct FanOutStore {
+    sinks: Vec<Sink>,
+}
+
+// A record queued for the sinks.
+#[allow(clippy::large_enum_variant)]
+enum Record {
+    Swap(String, DecodedData),
+    Event(EventRecord),
+}
+
+// One sink, its queue, None once flushed, and the task writing the queue to it.
+struct Sink {
+    name: String,
+    store: Arc<dyn DataStore>,
+    queue: Mutex<Option<mpsc::Sender<Record>>>,
+    writer: Mutex<Option<JoinHandle<SinkFailures>>>,
+}
+
+// The records a sink failed to write, and the last error.
+#[derive(Default)]
+struct SinkFailures {
+    count: usize,
+    last: Option<String>,
+}
+
+impl FanOutStore {
+    /// How many records wait for one sink at most.
+    pub const QUEUE_CAPACITY: usize = 1024;
+
+    /// Fan out to `sinks`, each named for its errors, written from tasks spawned on the
+    /// current runtime.
+    pub fn new(sinks: Vec<(String, Box<dyn DataStore>)>) -> Self {
+        let sinks = sinks
+            .into_iter()
+            .map(|(name, store)| {
+                let store: Arc<dyn DataStore> = Arc::from(store);
+                let (queue, records) = mpsc::channel(Self::QUEUE_CAPACITY);
+                let writer = tokio::spawn(write_records(name.clone(), Arc::clone(&store), records));
+                Sink { name, store, queue: Mutex::new(Some(queue)), writer: Mutex::new(Some(writer)) }
+            })
+            .collect();
+        FanOutStore { sinks }
+    }
+
+    // Queue `record` for every sink, naming the sinks it couldn't be queued for.
+    fn queue(&self, record: impl Fn() -> Record) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let results = self.sinks.iter().map(|sink| {
+            let queue = sink.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+            let Some(queue) = queue else {
+                return Err("already flushed".to_string());
+            };
+            match queue.try_send(record()) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(format!("{} records are waiting, dropping this one", Self::QUEUE_CAPACITY)),
+                Err(TrySendError::Closed(_)) => Err("its writer has stopped".to_string()),
+            }
+        });
+        self.failures(results.collect())
+    }
+
+    // Name the failed sinks and their errors, Ok when they all succeeded.
+    fn failures(&self, results: Vec<Result<(), String>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let errors: Vec<String> = self.sinks
+            .iter()
+            .zip(results)
+            .filter_map(|(sink, result)| result.err().map(|e| format!("{}: {}", sink.name, e)))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} of {} sinks failed: {}", errors.len(), self.sinks.len(), errors.join("; ")).into())
+        }
+    }
+}
+
+// Write the records queued for the sink `name` in order until the queue is closed,
+// logging and counting the ones it fails to store.
+async fn write_records(name: String, store: Arc<dyn DataStore>, mut records: mpsc::Receiver<Record>) -> SinkFailures {
+    let mut failures = SinkFailures::default();
+    while let Some(record) = records.recv().await {
+        let (result, what) = match &record {
+            Record::Swap(address, data) => (store.store(address, data).await, format!("swap {}", data.transaction_hash)),
+            Record::Event(record) => (store.store_event(record).await, format!("{} event", record.event)),
+        };
+        if let Err(e) = result {
+            eprintln!("Error storing {} in {}: {}", what, name, e);
+            failures.count += 1;
+            failures.last = Some(e.to_string());
+        }
+    }
+    failures
+}
+
+#[async_trait]
+impl DataStore for FanOutStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.queue(|| Record::Swap(address.to_string(), data.clone()))
+    }
+
+    async fn store_event(&self, record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.queue(|| Record::Event(record.clone()))
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Closing the queues ends the writers once they have written what is left
+        let drained = join_all(self.sinks.iter().map(|sink| async move {
+            drop(sink.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take());
+            let writer = sink.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+            let failures = match writer {
+                Some(writer) => writer.await.map_err(|e| e.to_string())?,
+                None => SinkFailures::default(),
+            };
+            sink.store.flush().await.map_err(|e| e.to_string())?;
+            match failures.last {
+                Some(last) => Err(format!("{} record(s) failed, the last with: {}", failures.count, last)),
+                None => Ok(()),
+            }
+        }))
+        .await;
+        self.failures(drained)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::data_store::{test_swap, InMemoryStore};
+
+    // Stores in a shared InMemoryStore, so the test can look at it once boxed
+    struct SharedStore(Arc<InMemoryStore>);
+
+    #[async_trait]
+    impl DataStore for SharedStore {
+        async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.0.store(address, data).await
+        }
+    }
+
+    struct FailingStore;
+
+    #[async_trait]
+    impl DataStore for FailingStore {
+        async fn store(&self, _address: &str, _data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err("broker unreachable".into())
+        }
+    }
+
+    // Stalls every write until released, like a sink backing off
+    struct StalledStore(Arc<tokio::sync::Semaphore>, Arc<InMemoryStore>);
+
+    #[async_trait]
+    impl DataStore for StalledStore {
+        async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            let _permit = self.0.acquire().await?;
+            self.1.store(address, data).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_failing_sink_doesnt_block_the_others() {
+        let (first, last) = (Arc::new(InMemoryStore::new()), Arc::new(InMemoryStore::new()));
+        let store = FanOutStore::new(vec![
+            ("json".to_string(), Box::new(SharedStore(Arc::clone(&first)))),
+            ("kafka".to_string(), Box::new(FailingStore)),
+            ("postgres".to_string(), Box::new(SharedStore(Arc::clone(&last)))),
+        ]);
+
+        store.store("0xpool", &test_swap("0x11", 1, -1)).await.unwrap();
+        let error = store.flush().await.unwrap_err();
+        assert_eq!(error.to_string(), "1 of 3 sinks failed: kafka: 1 record(s) failed, the last with: broker unreachable");
+        assert_eq!((first.len(), last.len()), (1, 1));
+        assert!(store.store("0xpool", &test_swap("0x11", 1, -1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_a_stalled_sink_doesnt_hold_up_the_others() {
+        let (stalled, other) = (Arc::new(InMemoryStore::new()), Arc::new(InMemoryStore::new()));
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let store = FanOutStore::new(vec![
+            ("kafka".to_string(), Box::new(StalledStore(Arc::clone(&release), Arc::clone(&stalled)))),
+            ("json".to_string(), Box::new(SharedStore(Arc::clone(&other)))),
+        ]);
+
+        for _ in 0..3 {
+            store.store("0xpool", &test_swap("0x11", 1, -1)).await.unwrap();
+        }
+        while other.len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(stalled.len(), 0);
+
+        release.add_permits(3);
+        store.flush().await.unwrap();
+        assert_eq!(stalled.len(), 3);
+    }
+}
@@ -0,0 +1,811 @@
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use serde_json;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use fs2::FileExt;
+use std::collections::HashMap;
+use std::fs as std_fs;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tokio::fs;
+use crate::block_summaries::{BlockSummary, BlockSummaryStore};
+use crate::candles::{Candle, CandleStore};
+use crate::data_store::{DataStore, DecodedData, EventRecord, FieldNaming, FieldProjection, JsonLayout, PartitionTimezone};
+use crate::error::TaskError;
+use crate::numeraire::{NumeraireVolume, VolumeStore};
+use crate::utils;
+
+
+/// JsonStore Appends decoded swaps as JSON lines to one file per contract address and day.
+///
+/// By default files are named `<output_dir>/<address>_<date>_decoded_swaps.json`. With
+/// `partition_by_pool` set they are written to `<output_dir>/<address>/<date>.json`
+/// instead, which keeps directories small when many pools are watched.
+///
+/// `<date>` is formatted with `date_format`, ISO `2024-03-05` by default, which sorts
+/// in date order. `LEGACY_DATE_FORMAT` gives the unpadded `2024_3_5` of older versions.
+///
+/// The day a swap is filed under is the day it was stored on in `timezone`, UTC by
+/// default. When a pool's swaps move on to the next day's file the switch is logged, so
+/// a long-running watch shows where each file ends.
+///
+/// With `fields` set only those fields of each swap are written, see `FieldProjection`.
+/// They are named in snake_case unless `naming` is set to another `FieldNaming`.
+///
+/// With `gzip` set the files are gzip-compressed and named `.json.gz`. Swaps are then
+/// buffered and written as one gzip member every `GZIP_MEMBER_RECORDS` swaps, when the
+/// day rotates, and on `flush`, so the file is a valid gzip stream between members and
+/// compresses better than one member per swap. `read_decoded_data` decompresses it.
+///
+/// With `layout` set to `JsonLayout::Array` a file holds one JSON array, rewritten
+/// through a temporary file on every write, instead of one record per line. Only then can
+/// `pretty` be set, to indent the records for reading by eye. Arrays aren't compressed,
+/// and are only read back whole by `load_decoded_data`.
+pub struct JsonStore {
+    pub output_dir: PathBuf,
+    pub partition_by_pool: bool,
+    pub fields: Option<FieldProjection>,
+    pub naming: FieldNaming,
+    pub timezone: PartitionTimezone,
+    pub date_format: String,
+    pub gzip: bool,
+    pub layout: JsonLayout,
+    pub pretty: bool,
+    // The records of each gzip file not written yet
+    gzip_buffers: Mutex<HashMap<PathBuf, Vec<String>>>,
+    // The date of the file each pool's swaps were last written to
+    active_dates: Mutex<HashMap<String, String>>,
+}
+
+impl JsonStore {
+    /// The `chrono` format of the dates in the file names, e.g. `2024-03-05`.
+    pub const DEFAULT_DATE_FORMAT: &'static str = "%Y-%m-%d";
+    /// The unpadded format written before, e.g. `2024_3_5`.
+    pub const LEGACY_DATE_FORMAT: &'static str = "%Y_%-m_%-d";
+    /// How many swaps a gzip member holds at most.
+    pub const GZIP_MEMBER_RECORDS: usize = 256;
+
+    pub fn new(output_dir: impl Into<PathBuf>, partition_by_pool: bool) -> Self {
+        JsonStore {
+            output_dir: output_dir.into(),
+            partition_by_pool,
+            fields: None,
+            naming: FieldNaming::Snake,
+            timezone: PartitionTimezone::Utc,
+            date_format: JsonStore::DEFAULT_DATE_FORMAT.to_string(),
+            gzip: false,
+            layout: JsonLayout::Lines,
+            pretty: false,
+            gzip_buffers: Mutex::new(HashMap::new()),
+            active_dates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A store writing to the `data` directory of the repository.
+    pub fn in_root_dir(partition_by_pool: bool) -> Result<Self, io::Error> {
+        let root_directory = match utils::root_dir() {
+            Some(dir) => dir,
+            None => {
+                eprintln!("Error: Root directory not found");
+                return Err(io::Error::other("Root directory not found"));
+            }
+        };
+
+        // Construct the full path to the data directory using the root directory
+        Ok(JsonStore::new(Path::new(&root_directory).join("data"), partition_by_pool))
+    }
+
+    /// The file that records of `address` emitted on `date` are written to.
+    pub fn output_path(&self, address: &str, date: &str) -> PathBuf {
+        if self.partition_by_pool {
+            self.output_dir.join(address).join(format!("{}.{}", date, self.extension()))
+        } else {
+            self.output_dir.join(format!("{}_{}_decoded_swaps.{}", address, date, self.extension()))
+        }
+    }
+
+    /// The file that the `interval` second candles of `address` opened on `date` are written to.
+    pub fn candles_path(&self, address: &str, date: &str, interval: u64) -> PathBuf {
+        if self.partition_by_pool {
+            self.output_dir.join(address).join(format!("{}_candles_{}s.{}", date, interval, self.extension()))
+        } else {
+            self.output_dir.join(format!("{}_{}_candles_{}s.{}", address, date, interval, self.extension()))
+        }
+    }
+
+    /// The file that the `interval` second numeraire volumes of `address` opened on `date`
+    /// are written to.
+    pub fn volumes_path(&self, address: &str, date: &str, interval: u64) -> PathBuf {
+        if self.partition_by_pool {
+            self.output_dir.join(address).join(format!("{}_volumes_{}s.{}", date, interval, self.extension()))
+        } else {
+            self.output_dir.join(format!("{}_{}_volumes_{}s.{}", address, date, interval, self.extension()))
+        }
+    }
+
+    // A record in the style the store writes, pretty or compact.
+    fn to_json<T: serde::Serialize>(&self, record: &T) -> Result<String, serde_json::Error> {
+        if self.pretty {
+            serde_json::to_string_pretty(record)
+        } else {
+            serde_json::to_string(record)
+        }
+    }
+
+    /// The file that the block summaries of `address` stored on `date` are written to.
+    pub fn blocks_path(&self, address: &str, date: &str) -> PathBuf {
+        if self.partition_by_pool {
+            self.output_dir.join(address).join(format!("{}_blocks.{}", date, self.extension()))
+        } else {
+            self.output_dir.join(format!("{}_{}_blocks.{}", address, date, self.extension()))
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        if self.gzip {
+            "json.gz"
+        } else {
+            "json"
+        }
+    }
+
+    /// The file that the other events of `address` stored on `date` are written to, e.g.
+    /// its token transfers.
+    pub fn events_path(&self, address: &str, date: &str) -> PathBuf {
+        if self.partition_by_pool {
+            self.output_dir.join(address).join(format!("{}_events.{}", date, self.extension()))
+        } else {
+            self.output_dir.join(format!("{}_{}_events.{}", address, date, self.extension()))
+        }
+    }
+
+    // Note the date the swaps of `address` are now filed under, logging the rotation when
+    // it moves on from an earlier one. Returns whether it did.
+    fn rotate(&self, address: &str, date: &str) -> bool {
+        let mut active_dates = self.active_dates.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match active_dates.get_mut(address) {
+            Some(active) if active.as_str() == date => false,
+            Some(active) => {
+                tracing::info!(pool = address, from = %active, to = date, "Rotating the swaps of the pool to a new file");
+                *active = date.to_string();
+                true
+            }
+            None => {
+                active_dates.insert(address.to_string(), date.to_string());
+                false
+            }
+        }
+    }
+
+    fn gzip_buffers(&self) -> MutexGuard<'_, HashMap<PathBuf, Vec<String>>> {
+        self.gzip_buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Buffer a record of the gzip file `filename`, returning the buffer once it holds a
+    // full member.
+    fn buffer_gzip(&self, filename: &Path, json: String) -> Option<Vec<String>> {
+        let mut buffers = self.gzip_buffers();
+        let buffer = buffers.entry(filename.to_path_buf()).or_default();
+        buffer.push(json);
+        (buffer.len() >= JsonStore::GZIP_MEMBER_RECORDS).then(|| std::mem::take(buffer))
+    }
+
+    // Write out the buffered gzip records of every file but `keep`, or of every file.
+    async fn flush_gzip(&self, keep: Option<&Path>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let buffers: Vec<_> = {
+            let mut buffers = self.gzip_buffers();
+            let filenames: Vec<_> = buffers.keys().filter(|filename| Some(filename.as_path()) != keep).cloned().collect();
+            filenames.into_iter().filter_map(|filename| buffers.remove(&filename).map(|records| (filename, records))).collect()
+        };
+        for (filename, records) in buffers {
+            if !records.is_empty() {
+                self.append_records(&filename, &records).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Append one JSON record to `filename`, creating the file and its directory if needed.
+    async fn append_json(&self, filename: &Path, json: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.append_records(filename, &[json.to_string()]).await
+    }
+
+    // Append JSON records to `filename`, as one gzip member when compressing.
+    async fn append_records(&self, filename: &Path, records: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Check if the directory exists, and create it if it doesn't
+        let data_dir = filename.parent().unwrap_or(&self.output_dir);
+        if !fs::try_exists(data_dir).await? {
+            if let Err(err) = fs::create_dir_all(data_dir).await {
+                eprintln!("Error: Failed to create data directory: {}", err);
+                return Err(err.into());
+            }
+        }
+
+        let filename = filename.to_path_buf();
+        if self.layout == JsonLayout::Array && !self.gzip {
+            let elements = records.join(",\n").into_bytes();
+            tokio::task::spawn_blocking(move || append_to_array_locked(&filename, &elements)).await??;
+            return Ok(());
+        }
+
+        // Gzip members are appended as they are, lines each end with a newline
+        let contents = if self.gzip {
+            gzip_member(records)?
+        } else {
+            records.iter().flat_map(|record| [record.as_str(), "\n"]).collect::<String>().into_bytes()
+        };
+
+        // File locks are blocking calls, keep them off the runtime threads
+        let lines = !self.gzip;
+        tokio::task::spawn_blocking(move || append_locked(&filename, &contents, lines)).await??;
+        Ok(())
+    }
+}
+
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+// The records as one gzip member, one record per line. Gzip readers decompress
+// concatenated members as one stream.
+fn gzip_member(records: &[String]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for record in records {
+        encoder.write_all(record.as_bytes())?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()
+}
+
+// Write `contents` to the end of `filename` so that no reader or concurrent writer ever
+// sees a partial record. A new file is written to a temporary file and linked into place,
+// which fails if another writer created it first. An existing file is appended to in one
+// write while holding an exclusive advisory lock, so concurrent writers (in this process
+// or another) take turns. With `lines`, a file whose last line isn't terminated, as older
+// versions left the last record, gets its newline first.
+fn append_locked(filename: &Path, contents: &[u8], lines: bool) -> io::Result<()> {
+    if !filename.exists() && create_new_file(filename, contents)? {
+        return Ok(());
+    }
+
+    let mut file = std_fs::OpenOptions::new().read(true).append(true).open(filename)?;
+    file.lock_exclusive()?;
+    let result = (|| {
+        let len = file.metadata()?.len();
+        if lines && len > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                return file.write_all(&[b"\n".as_slice(), contents].concat());
+            }
+        }
+        file.write_all(contents)
+    })();
+    FileExt::unlock(&file)?;
+    result
+}
+
+// Write `contents` as the new file `filename`, through a temporary file linked into
+// place. Returns false, leaving the file alone, when another writer created it first.
+fn create_new_file(filename: &Path, contents: &[u8]) -> io::Result<bool> {
+    let temp_path = temp_path(filename);
+    std_fs::write(&temp_path, contents)?;
+    let linked = std_fs::hard_link(&temp_path, filename);
+    std_fs::remove_file(&temp_path)?;
+    match linked {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// A temporary file next to `filename`, unique per process and write, so concurrent
+// writers never share one.
+fn temp_path(filename: &Path) -> PathBuf {
+    let write_id = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    filename.with_extension(format!("json.{}-{}.tmp", std::process::id(), write_id))
+}
+
+// Add `elements`, comma-separated JSON values, to the end of the JSON array in
+// `filename`, creating the file if needed. The whole array is written to a temporary
+// file renamed over `filename` while holding an exclusive lock on it, so a crash never
+// leaves half an array behind.
+fn append_to_array_locked(filename: &Path, elements: &[u8]) -> io::Result<()> {
+    let new_file = [b"[\n".as_slice(), elements, b"\n]\n"].concat();
+    if !filename.exists() && create_new_file(filename, &new_file)? {
+        return Ok(());
+    }
+
+    let mut file = lock_current(filename)?;
+    let result = extend_array(&mut file, filename, &new_file, elements);
+    FileExt::unlock(&file)?;
+    result
+}
+
+// Open and exclusively lock `filename`. A writer that renamed a new array over it while
+// the lock was awaited leaves the old file locked, so lock the one in place instead.
+fn lock_current(filename: &Path) -> io::Result<std_fs::File> {
+    loop {
+        let file = std_fs::File::open(filename)?;
+        file.lock_exclusive()?;
+        if is_current(&file, filename)? {
+            return Ok(file);
+        }
+        FileExt::unlock(&file)?;
+    }
+}
+
+#[cfg(unix)]
+fn is_current(file: &std_fs::File, filename: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (locked, current) = (file.metadata()?, std_fs::metadata(filename)?);
+    Ok((locked.dev(), locked.ino()) == (current.dev(), current.ino()))
+}
+
+// Windows doesn't rename over a file open elsewhere, the locked file is the current one
+#[cfg(not(unix))]
+fn is_current(_file: &std_fs::File, _filename: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+// Rewrite the array of `file`, at `filename`, with `elements` before its closing bracket.
+fn extend_array(file: &mut std_fs::File, filename: &Path, new_file: &[u8], elements: &[u8]) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return replace_file(filename, new_file);
+    }
+
+    // Only whitespace follows the bracket, and the last element ends right before it
+    let tail_start = len.saturating_sub(64);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_to_end(&mut tail)?;
+    let not_array = || io::Error::new(io::ErrorKind::InvalidData, "the file doesn't end with a JSON array");
+    let bracket = tail.iter().rposition(|byte| !byte.is_ascii_whitespace()).filter(|&i| tail[i] == b']').ok_or_else(not_array)?;
+    let last = tail[..bracket].iter().rposition(|byte| !byte.is_ascii_whitespace()).ok_or_else(not_array)?;
+    let separator: &[u8] = if tail[last] == b'[' { b"\n" } else { b",\n" };
+
+    let end = tail_start + last as u64 + 1;
+    let mut contents = Vec::with_capacity(end as usize + separator.len() + elements.len() + 3);
+    file.seek(SeekFrom::Start(0))?;
+    file.take(end).read_to_end(&mut contents)?;
+    contents.extend_from_slice(&[separator, elements, b"\n]\n"].concat());
+    replace_file(filename, &contents)
+}
+
+// Write `contents` to a temporary file and rename it over `filename`, so readers see the
+// old file or the new one, never a partial write.
+fn replace_file(filename: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(filename);
+    let written = std_fs::File::create(&temp_path).and_then(|mut temp| {
+        temp.write_all(contents)?;
+        temp.sync_all()
+    });
+    match written.and_then(|()| std_fs::rename(&temp_path, filename)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = std_fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+#[async_trait]
+impl DataStore for JsonStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Get the current date in the partition timezone and format it
+        let formatted_date = self.timezone.date_of(Utc::now()).format(&self.date_format).to_string();
+        let rotated = self.rotate(address, &formatted_date);
+
+        // Create the filename using the address and date
+        let filename = self.output_path(address, &formatted_date);
+
+        // Serialize the data to JSON, or the fields asked for only
+        let json = match &self.fields {
+            Some(fields) => self.to_json(&fields.project(data, self.naming)?)?,
+            None if self.naming == FieldNaming::Snake => self.to_json(data)?,
+            None => self.to_json(&FieldProjection::all().project(data, self.naming)?)?,
+        };
+
+        if !self.gzip {
+            return self.append_json(&filename, &json).await;
+        }
+        // Close the member of the previous day's file before moving on
+        if rotated {
+            self.flush_gzip(Some(&filename)).await?;
+        }
+        match self.buffer_gzip(&filename, json) {
+            Some(records) => self.append_records(&filename, &records).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn store_event(&self, record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Filed like the swaps, under the date they are stored on
+        let formatted_date = self.timezone.date_of(Utc::now()).format(&self.date_format).to_string();
+        let filename = self.events_path(&record.address, &formatted_date);
+        let json = self.to_json(record)?;
+
+        self.append_json(&filename, &json).await
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.flush_gzip(None).await
+    }
+}
+
+#[async_trait]
+impl CandleStore for JsonStore {
+    async fn store_candle(&self, address: &str, candle: &Candle) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Candles are filed under the date they opened on
+        let open_time = Utc.timestamp_opt(candle.open_time as i64, 0).single().ok_or("Invalid candle open time")?;
+        let formatted_date = self.timezone.date_of(open_time).format(&self.date_format).to_string();
+
+        let filename = self.candles_path(address, &formatted_date, candle.interval);
+        let json = self.to_json(candle)?;
+
+        self.append_json(&filename, &json).await
+    }
+}
+
+#[async_trait]
+impl VolumeStore for JsonStore {
+    async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Filed like the candles, under the date they opened on
+        let open_time = Utc.timestamp_opt(volume.open_time as i64, 0).single().ok_or("Invalid volume open time")?;
+        let formatted_date = self.timezone.date_of(open_time).format(&self.date_format).to_string();
+
+        let filename = self.volumes_path(address, &formatted_date, volume.interval);
+        let json = self.to_json(volume)?;
+
+        self.append_json(&filename, &json).await
+    }
+}
+
+#[async_trait]
+impl BlockSummaryStore for JsonStore {
+    async fn store_block_summary(&self, address: &str, summary: &BlockSummary) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Filed like the swaps, under the date they are stored on
+        let formatted_date = self.timezone.date_of(Utc::now()).format(&self.date_format).to_string();
+        let filename = self.blocks_path(address, &formatted_date);
+        let json = self.to_json(summary)?;
+
+        self.append_json(&filename, &json).await
+    }
+}
+
+
+/// validate_date_format Checks that `format` is a `chrono` date format fit for a file name.
+pub fn validate_date_format(format: &str) -> Result<(), TaskError> {
+    let invalid = |reason: &str| TaskError::InvalidConfig(format!("date format {:?} {}", format, reason));
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(invalid("isn't a valid chrono format"));
+    }
+    if format.contains('/') || format.contains('\\') {
+        return Err(invalid("would put a path separator in the file names"));
+    }
+    if format.is_empty() {
+        return Err(invalid("is empty"));
+    }
+    Ok(())
+}
+
+
+/// load_decoded_data Reads back every swap stored in a `JsonStore` file.
+///
+/// Blank lines, which separate the records, are skipped. A line that isn't a valid
+/// `DecodedData` fails the whole load with an `InvalidData` error naming the line.
+/// Files written with `JsonLayout::Array` are parsed as one array. Either way the fields
+/// derived from the amounts are derived again, see `DecodedData::derive_direction`.
+pub fn load_decoded_data(path: impl AsRef<Path>) -> io::Result<Vec<DecodedData>> {
+    let path = path.as_ref();
+    if is_json_array(path)? {
+        let reader = BufReader::new(std_fs::File::open(path)?);
+        let mut swaps: Vec<DecodedData> =
+            serde_json::from_reader(reader).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        swaps.iter_mut().for_each(DecodedData::derive_direction);
+        return Ok(swaps);
+    }
+    read_decoded_data(path)?.collect()
+}
+
+// Whether the uncompressed file at `path` starts with a JSON array.
+pub(crate) fn is_json_array(path: &Path) -> io::Result<bool> {
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        return Ok(false);
+    }
+    let mut start = Vec::new();
+    std_fs::File::open(path)?.take(64).read_to_end(&mut start)?;
+    Ok(start.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'['))
+}
+
+/// read_decoded_data Streams the swaps stored in a `JsonStore` file one line at a time,
+/// for files too large to load at once. Errors like `load_decoded_data`. Files ending in
+/// `.gz` are decompressed.
+pub fn read_decoded_data(path: impl AsRef<Path>) -> io::Result<DecodedDataReader<Box<dyn BufRead + Send>>> {
+    let path = path.as_ref();
+    let file = std_fs::File::open(path)?;
+    let reader: Box<dyn BufRead + Send> = if path.extension().is_some_and(|extension| extension == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(DecodedDataReader::new(reader))
+}
+
+/// DecodedDataReader Iterates over the swaps of newline-delimited JSON read from `reader`.
+pub struct DecodedDataReader<R> {
+    lines: io::Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> DecodedDataReader<R> {
+    pub fn new(reader: R) -> Self {
+        DecodedDataReader { lines: reader.lines(), line_number: 0 }
+    }
+}
+
+impl<R: BufRead> Iterator for DecodedDataReader<R> {
+    type Item = io::Result<DecodedData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = serde_json::from_str::<DecodedData>(&line).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", self.line_number, err))
+            });
+            // Older files lack the direction and the absolute amounts, they only depend on the amounts
+            return Some(parsed.map(|mut data| {
+                data.derive_direction();
+                data
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use ethers::types::U256;
+
+    #[test]
+    fn test_output_path() {
+        let address = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+        let flat = JsonStore::new("out", false);
+        assert_eq!(
+            flat.output_path(address, "2023_10_11"),
+            PathBuf::from("out/0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640_2023_10_11_decoded_swaps.json")
+        );
+        assert_eq!(
+            flat.events_path(address, "2023_10_11"),
+            PathBuf::from("out/0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640_2023_10_11_events.json")
+        );
+
+        let partitioned = JsonStore::new("out", true);
+        assert_eq!(
+            partitioned.output_path(address, "2023_10_11"),
+            PathBuf::from("out/0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640/2023_10_11.json")
+        );
+        assert_eq!(
+            partitioned.candles_path(address, "2023_10_11", 60),
+            PathBuf::from("out/0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640/2023_10_11_candles_60s.json")
+        );
+        assert_eq!(
+            flat.volumes_path(address, "2023_10_11", 3600),
+            PathBuf::from("out/0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640_2023_10_11_volumes_3600s.json")
+        );
+    }
+
+    #[test]
+    fn test_date_formats() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        assert_eq!(date.format(JsonStore::DEFAULT_DATE_FORMAT).to_string(), "2024-03-05");
+        assert_eq!(date.format(JsonStore::LEGACY_DATE_FORMAT).to_string(), "2024_3_5");
+
+        assert!(validate_date_format(JsonStore::DEFAULT_DATE_FORMAT).is_ok());
+        assert!(validate_date_format(JsonStore::LEGACY_DATE_FORMAT).is_ok());
+        assert!(validate_date_format("%Y/%m/%d").is_err());
+        assert!(validate_date_format("%Q").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_never_interleave() {
+        let output_dir = std::env::temp_dir().join(format!("json_store_{}", std::process::id()));
+        let swap = DecodedData {
+            sender: "0xsender".to_string(),
+            recipient: "0xrecipient".to_string(),
+            sqrtPriceX96: U256::one() << 96,
+            liquidity: 1_000,
+            tick: -200_000,
+            ..test_swap(&format!("0x{}", "ab".repeat(32)), -1_000_000_000, 2_000_000_000_000_000_000)
+        };
+
+        // Two stores standing in for two processes writing the same daily file
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let store = JsonStore::new(&output_dir, false);
+                let swap = swap.clone();
+                tokio::spawn(async move {
+                    for _ in 0..200 {
+                        store.store("0xpool", &swap).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        let mut files = std::fs::read_dir(&output_dir).unwrap();
+        let contents = std::fs::read_to_string(files.next().unwrap().unwrap().path()).unwrap();
+        assert!(files.next().is_none(), "temporary files were left behind");
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        let records: Vec<DecodedData> = contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_each_record_is_one_line() {
+        let output_dir = std::env::temp_dir().join(format!("json_store_lines_{}", std::process::id()));
+        let store = JsonStore::new(&output_dir, true);
+        let swap = |n: i128| test_swap(&format!("0x{:064x}", n), n, -n);
+        for n in 1..=3 {
+            store.store("0xpool", &swap(n)).await.unwrap();
+        }
+        let path = std::fs::read_dir(output_dir.join("0xpool")).unwrap().next().unwrap().unwrap().path();
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        // A file left by an older version, its last record without a newline, is appended to on a new line
+        let legacy = output_dir.join("legacy.json");
+        std::fs::write(&legacy, serde_json::to_string(&swap(1)).unwrap()).unwrap();
+        store.append_json(&legacy, &serde_json::to_string(&swap(2)).unwrap()).await.unwrap();
+        let legacy_contents = std::fs::read_to_string(&legacy).unwrap();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        for (contents, records) in [(contents, 3), (legacy_contents, 2)] {
+            assert!(contents.ends_with('\n') && !contents.starts_with('\n'));
+            let lines: Vec<&str> = contents.lines().collect();
+            assert!(lines.iter().all(|line| !line.is_empty()), "blank line in {:?}", contents);
+            assert!(lines.iter().all(|line| serde_json::from_str::<DecodedData>(line).is_ok()));
+            assert_eq!(lines.len(), records);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_decoded_data() {
+        let output_dir = std::env::temp_dir().join(format!("json_store_load_{}", std::process::id()));
+        let store = JsonStore::new(&output_dir, true);
+        let swaps: Vec<_> = (1..=3)
+            .map(|n| DecodedData {
+                sender: "0xsender".to_string(),
+                recipient: "0xrecipient".to_string(),
+                sqrtPriceX96: U256::one() << 96,
+                liquidity: 1_000,
+                tick: n as i32,
+                ..test_swap(&format!("0x{:064x}", n), -n, n * 1_000)
+            })
+            .collect();
+        for swap in &swaps {
+            store.store("0xpool", swap).await.unwrap();
+        }
+
+        let path = std::fs::read_dir(output_dir.join("0xpool")).unwrap().next().unwrap().unwrap().path();
+        let loaded = load_decoded_data(&path).unwrap();
+        let streamed: Vec<_> = read_decoded_data(&path).unwrap().map(Result::unwrap).collect();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        let hashes = |records: &[DecodedData]| records.iter().map(|r| r.transaction_hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(&loaded), hashes(&swaps));
+        assert_eq!(hashes(&streamed), hashes(&swaps));
+        assert_eq!(loaded[2].amount1, 3_000);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_files_read_back() {
+        let output_dir = std::env::temp_dir().join(format!("json_store_gzip_{}", std::process::id()));
+        let mut store = JsonStore::new(&output_dir, false);
+        store.gzip = true;
+        let swap = |n: i128| DecodedData {
+            sender: "0xsender".to_string(),
+            recipient: "0xrecipient".to_string(),
+            sqrtPriceX96: U256::one() << 96,
+            liquidity: 1_000,
+            ..test_swap(&format!("0x{:064x}", n), n, -n)
+        };
+
+        // Two flushes append two gzip members to the same file
+        for n in 1..=3 {
+            store.store("0xpool", &swap(n)).await.unwrap();
+        }
+        assert!(!output_dir.exists(), "swaps are buffered until a member is full");
+        store.flush().await.unwrap();
+        for n in 4..=5 {
+            store.store("0xpool", &swap(n)).await.unwrap();
+        }
+        store.flush().await.unwrap();
+
+        let path = std::fs::read_dir(&output_dir).unwrap().next().unwrap().unwrap().path();
+        let loaded = load_decoded_data(&path).unwrap();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        assert!(path.to_string_lossy().ends_with("_decoded_swaps.json.gz"));
+        assert_eq!(loaded.iter().map(|swap| swap.amount0).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_pretty_array_files_read_back() {
+        let output_dir = std::env::temp_dir().join(format!("json_store_array_{}", std::process::id()));
+        let mut store = JsonStore::new(&output_dir, false);
+        store.layout = JsonLayout::Array;
+        store.pretty = true;
+        let swap = |n: i128| DecodedData {
+            sender: "0xsender".to_string(),
+            recipient: "0xrecipient".to_string(),
+            sqrtPriceX96: U256::one() << 96,
+            liquidity: 1_000,
+            ..test_swap(&format!("0x{:064x}", n), n, -n)
+        };
+        for n in 1..=3 {
+            store.store("0xpool", &swap(n)).await.unwrap();
+        }
+
+        // Each rewrite's temporary file was renamed into place
+        let files: Vec<_> = std::fs::read_dir(&output_dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        let path = files[0].clone();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let loaded = load_decoded_data(&path).unwrap();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(contents.starts_with("[\n{\n  \"transaction_hash\""));
+        assert!(contents.ends_with("}\n]\n"));
+        assert_eq!(loaded.iter().map(|swap| swap.amount0).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_older_array_files_get_derived_fields() {
+        let path = std::env::temp_dir().join(format!("json_store_older_array_{}.json", std::process::id()));
+        let contents = "[\n{\"transaction_hash\":\"0x1\",\"sender\":\"\",\"recipient\":\"\",\"amount0\":-2,\"amount1\":3,\"sqrtPriceX96\":0,\"liquidity\":0,\"tick\":0}\n]\n";
+        std::fs::write(&path, contents).unwrap();
+        let loaded = load_decoded_data(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!((loaded[0].zero_for_one, loaded[0].amount0_abs, loaded[0].amount1_abs), (false, 2, 3));
+    }
+
+    #[test]
+    fn test_read_decoded_data_reports_bad_lines() {
+        let contents = "\n{\"transaction_hash\":\"0x1\",\"sender\":\"\",\"recipient\":\"\",\"amount0\":1,\"amount1\":-1,\"sqrtPriceX96\":0,\"liquidity\":0,\"tick\":0}\n\nnot json\n";
+        let mut reader = DecodedDataReader::new(contents.as_bytes());
+
+        // An older record, without the derived fields
+        let data = reader.next().unwrap().unwrap();
+        assert_eq!(data.transaction_hash, "0x1");
+        assert_eq!((data.zero_for_one, data.amount0_abs, data.amount1_abs), (true, 1, 1));
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 4:"));
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::time::Duration;
+use async_trait::async_trait;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use crate::block_summaries::{BlockSummary, BlockSummaryStore};
+use crate::candles::{Candle, CandleStore};
+use crate::data_store::{DataStore, DecodedData};
+use crate::numeraire::{NumeraireVolume, VolumeStore};
+
+
+/// KafkaStore Publishes decoded swaps as JSON to a Kafka topic, candles to
+/// `<topic>-candles`, numeraire volumes to `<topic>-volumes` and block summaries to
+/// `<topic>-blocks`.
+///
+/// Messages are keyed by the pool address, so the swaps of one pool land on one
+/// partition and stay in order. A delivery that fails is retried up to `retries` times
+/// with an exponential backoff, after which the error is returned for the writer to log
+/// and the stream carries on. Clones share the producer.
+#[derive(Clone)]
+pub struct KafkaStore {
+    producer: FutureProducer,
+    topic: String,
+    candles_topic: String,
+    volumes_topic: String,
+    blocks_topic: String,
+    retries: u32,
+}
+
+impl KafkaStore {
+    pub const DEFAULT_RETRIES: u32 = 3;
+    /// How long a message may wait in the producer queue for delivery.
+    pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A producer for the comma-separated `brokers`, e.g. `localhost:9092`.
+    pub fn new(brokers: &str, topic: impl Into<String>, retries: u32) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", KafkaStore::DELIVERY_TIMEOUT.as_millis().to_string())
+            .create()?;
+        let topic = topic.into();
+        Ok(KafkaStore {
+            producer,
+            candles_topic: format!("{}-candles", topic),
+            volumes_topic: format!("{}-volumes", topic),
+            blocks_topic: format!("{}-blocks", topic),
+            topic,
+            retries,
+        })
+    }
+
+    // Publish `payload` to `topic` keyed by `key`, retrying failed deliveries.
+    async fn publish(&self, topic: &str, key: &str, payload: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut attempt = 0;
+        loop {
+            let record = FutureRecord::to(topic).key(key).payload(payload);
+            match self.producer.send(record, Timeout::After(KafkaStore::DELIVERY_TIMEOUT)).await {
+                Ok(_) => return Ok(()),
+                Err((e, _)) if attempt < self.retries => {
+                    eprintln!("Warning: Kafka delivery to {} failed, retrying: {}", topic, e);
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err((e, _)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+// Wait 200ms before the first retry, doubling for every following one.
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(200).saturating_mul(2u32.saturating_pow(attempt))
+}
+
+#[async_trait]
+impl DataStore for KafkaStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let json = serde_json::to_string(data)?;
+        self.publish(&self.topic, address, &json).await
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Deliveries are awaited one by one, so only the producer's own queue is left
+        use rdkafka::producer::Producer;
+        self.producer.flush(Timeout::After(KafkaStore::DELIVERY_TIMEOUT))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CandleStore for KafkaStore {
+    async fn store_candle(&self, address: &str, candle: &Candle) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let json = serde_json::to_string(candle)?;
+        self.publish(&self.candles_topic, address, &json).await
+    }
+}
+
+#[async_trait]
+impl VolumeStore for KafkaStore {
+    async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let json = serde_json::to_string(volume)?;
+        self.publish(&self.volumes_topic, address, &json).await
+    }
+}
+
+#[async_trait]
+impl BlockSummaryStore for KafkaStore {
+    async fn store_block_summary(&self, address: &str, summary: &BlockSummary) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let json = serde_json::to_string(summary)?;
+        self.publish(&self.blocks_topic, address, &json).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(200));
+        assert_eq!(retry_delay(2), Duration::from_millis(800));
+    }
+}
@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::data_store::{DataStore, DecodedData, EventRecord};
+
+
+/// InMemoryStore Keeps the stored swaps in memory instead of writing them anywhere.
+///
+/// Useful in tests, to assert on what the decode path stored without touching the
+/// filesystem, and for embedders that want to handle storage themselves and
+/// periodically `drain` the buffered swaps.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    records: Mutex<Vec<DecodedData>>,
+    events: Mutex<Vec<EventRecord>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        InMemoryStore::default()
+    }
+
+    /// A copy of the stored swaps, in storage order.
+    pub fn records(&self) -> Vec<DecodedData> {
+        self.lock().clone()
+    }
+
+    /// Take the stored swaps, in storage order, leaving the store empty.
+    pub fn drain(&self) -> Vec<DecodedData> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    /// A copy of the stored events other than swaps, in storage order.
+    pub fn events(&self) -> Vec<EventRecord> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    // A panic while holding the lock can't leave the Vec half-updated, so ignore poisoning.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<DecodedData>> {
+        self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl DataStore for InMemoryStore {
+    async fn store(&self, _address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.lock().push(data.clone());
+        Ok(())
+    }
+
+    async fn store_event(&self, record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record.clone());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+
+    #[tokio::test]
+    async fn test_store_and_drain() {
+        let store = InMemoryStore::new();
+        let data_store: &dyn DataStore = &store;
+        data_store.store("0xpool", &test_swap("a", 0, 0)).await.unwrap();
+        data_store.store("0xpool", &test_swap("b", 0, 0)).await.unwrap();
+
+        assert_eq!(store.records().len(), 2);
+        let hashes: Vec<_> = store.drain().into_iter().map(|d| d.transaction_hash).collect();
+        assert_eq!(hashes, vec!["a", "b"]);
+        assert!(store.is_empty());
+    }
+}
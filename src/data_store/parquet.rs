@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use arrow::array::{Array, ArrayRef, BooleanArray, Float64Array, Int32Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use ethers::types::U256;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use crate::block_summaries::{BlockSummary, BlockSummaryStore};
+use crate::candles::{Candle, CandleStore};
+use crate::data_store::{DataStore, DecodedData, PartitionTimezone};
+use crate::numeraire::{NumeraireVolume, VolumeStore};
+
+
+/// ParquetStore Writes decoded swaps, candles, volumes and block summaries as Parquet files, for
+/// loading into DuckDB or pandas.
+///
+/// Each table is partitioned Hive-style by day and pool:
+/// `<output_dir>/<table>/date=<date>/pool=<address>/part-<run>-<n>.parquet`, where the
+/// table is `swaps`, `candles`, `volumes` or `blocks`. `<run>` is the Unix time the store was
+/// opened at, so a restart adds files next to the previous ones instead of overwriting
+/// them. The day is the day of storage in `timezone`, the open day for candles and volumes.
+///
+/// Rows are buffered and written as one row group every `row_group_size` rows. A file is
+/// only readable once closed, with its footer written: when its day is over and on
+/// `flush`, which the writer calls when the stream ends. A process killed before leaves
+/// the open files unreadable.
+///
+/// Amounts, `sqrtPriceX96` and `liquidity` are stored as decimal strings, like in JSON:
+/// they span the full 128-bit range, beyond the 38 digits of a `decimal(38, 0)`, and a
+/// string never loses precision. Cast them in the query, e.g.
+/// `CAST(amount0 AS HUGEINT)` in DuckDB. `raw_params` and `raw` are JSON strings.
+#[derive(Clone)]
+pub struct ParquetStore {
+    output_dir: PathBuf,
+    timezone: PartitionTimezone,
+    row_group_size: usize,
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl ParquetStore {
+    /// How many rows a row group holds by default.
+    pub const DEFAULT_ROW_GROUP_SIZE: usize = 10_000;
+
+    pub fn new(output_dir: impl Into<PathBuf>, timezone: PartitionTimezone, row_group_size: usize) -> Self {
+        ParquetStore {
+            output_dir: output_dir.into(),
+            timezone,
+            row_group_size: row_group_size.max(1),
+            tables: Arc::new(Mutex::new(Tables {
+                names: FileNames { run: Utc::now().timestamp(), opened: 0 },
+                swaps: Table::new("swaps"),
+                candles: Table::new("candles"),
+                volumes: Table::new("volumes"),
+                blocks: Table::new("blocks"),
+            })),
+        }
+    }
+
+    // Add a row to a table on the blocking pool, Parquet encoding and file writes are synchronous.
+    async fn push<T: ParquetRow + Send + 'static>(
+        &self,
+        table: fn(&mut Tables) -> (&mut Table<T>, &mut FileNames),
+        address: &str,
+        date: String,
+        row: T,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (store, address) = (self.clone(), address.to_string());
+        tokio::task::spawn_blocking(move || {
+            let mut tables = store.lock();
+            let (table, names) = table(&mut tables);
+            table.push(&store.output_dir, names, store.row_group_size, &address, &date, row)
+        })
+        .await?
+    }
+
+    // The lock is only taken on the blocking pool, never across an await.
+    fn lock(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl DataStore for ParquetStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let date = self.timezone.date_of(Utc::now()).format("%Y-%m-%d").to_string();
+        self.push(Tables::swaps, address, date, data.clone()).await
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut tables = store.lock();
+            tables.swaps.close_all()?;
+            tables.candles.close_all()?;
+            tables.volumes.close_all()?;
+            tables.blocks.close_all()
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl CandleStore for ParquetStore {
+    async fn store_candle(&self, address: &str, candle: &Candle) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let open_time = Utc.timestamp_opt(candle.open_time as i64, 0).single().unwrap_or_else(Utc::now);
+        let date = self.timezone.date_of(open_time).format("%Y-%m-%d").to_string();
+        self.push(Tables::candles, address, date, candle.clone()).await
+    }
+}
+
+#[async_trait]
+impl VolumeStore for ParquetStore {
+    async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let open_time = Utc.timestamp_opt(volume.open_time as i64, 0).single().unwrap_or_else(Utc::now);
+        let date = self.timezone.date_of(open_time).format("%Y-%m-%d").to_string();
+        self.push(Tables::volumes, address, date, volume.clone()).await
+    }
+}
+
+#[async_trait]
+impl BlockSummaryStore for ParquetStore {
+    async fn store_block_summary(&self, address: &str, summary: &BlockSummary) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let date = self.timezone.date_of(Utc::now()).format("%Y-%m-%d").to_string();
+        self.push(Tables::blocks, address, date, summary.clone()).await
+    }
+}
+
+
+// The open files of every table.
+struct Tables {
+    names: FileNames,
+    swaps: Table<DecodedData>,
+    candles: Table<Candle>,
+    volumes: Table<NumeraireVolume>,
+    blocks: Table<BlockSummary>,
+}
+
+impl Tables {
+    fn swaps(&mut self) -> (&mut Table<DecodedData>, &mut FileNames) {
+        (&mut self.swaps, &mut self.names)
+    }
+
+    fn candles(&mut self) -> (&mut Table<Candle>, &mut FileNames) {
+        (&mut self.candles, &mut self.names)
+    }
+
+    fn volumes(&mut self) -> (&mut Table<NumeraireVolume>, &mut FileNames) {
+        (&mut self.volumes, &mut self.names)
+    }
+
+    fn blocks(&mut self) -> (&mut Table<BlockSummary>, &mut FileNames) {
+        (&mut self.blocks, &mut self.names)
+    }
+}
+
+// Hands out the names of new files, unique within the run.
+struct FileNames {
+    run: i64,
+    opened: u64,
+}
+
+impl FileNames {
+    fn next(&mut self) -> String {
+        self.opened += 1;
+        format!("part-{}-{}.parquet", self.run, self.opened)
+    }
+}
+
+
+// The open files of one table, keyed by pool and day.
+struct Table<T> {
+    name: &'static str,
+    partitions: HashMap<(String, String), Partition<T>>,
+}
+
+// An open file and the rows of its next row group.
+struct Partition<T> {
+    writer: ArrowWriter<File>,
+    rows: Vec<T>,
+}
+
+impl<T: ParquetRow> Table<T> {
+    fn new(name: &'static str) -> Self {
+        Table { name, partitions: HashMap::new() }
+    }
+
+    fn push(
+        &mut self,
+        output_dir: &Path,
+        names: &mut FileNames,
+        row_group_size: usize,
+        address: &str,
+        date: &str,
+        row: T,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Days only move forward, the files of the previous ones are complete
+        let ended: Vec<_> = self.partitions.keys().filter(|(_, day)| day != date).cloned().collect();
+        for key in ended {
+            if let Some(partition) = self.partitions.remove(&key) {
+                partition.close()?;
+            }
+        }
+
+        let key = (address.to_string(), date.to_string());
+        if !self.partitions.contains_key(&key) {
+            let dir = output_dir.join(self.name).join(format!("date={}", date)).join(format!("pool={}", address));
+            fs::create_dir_all(&dir)?;
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_max_row_group_size(row_group_size)
+                .build();
+            let writer = ArrowWriter::try_new(File::create(dir.join(names.next()))?, T::schema(), Some(properties))?;
+            self.partitions.insert(key.clone(), Partition { writer, rows: Vec::new() });
+        }
+
+        let partition = self.partitions.get_mut(&key).expect("the partition was just opened");
+        partition.rows.push(row);
+        if partition.rows.len() >= row_group_size {
+            partition.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn close_all(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for (_, partition) in self.partitions.drain() {
+            partition.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ParquetRow> Partition<T> {
+    fn write_row_group(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        self.writer.write(&T::to_batch(&self.rows)?)?;
+        self.writer.flush()?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    // Write the buffered rows and the footer, which makes the file readable.
+    fn close(mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.write_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+
+// A record type stored as the rows of a Parquet table.
+trait ParquetRow: Sized {
+    fn schema() -> SchemaRef;
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, arrow::error::ArrowError>;
+}
+
+// A column of decimal strings, for integers wider than Parquet's 64 bits.
+fn decimal_column<T: ToString>(values: impl Iterator<Item = T>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(values.map(|value| value.to_string())))
+}
+
+impl ParquetRow for DecodedData {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("transaction_hash", DataType::Utf8, false),
+            Field::new("sender", DataType::Utf8, false),
+            Field::new("recipient", DataType::Utf8, false),
+            Field::new("amount0", DataType::Utf8, false),
+            Field::new("amount1", DataType::Utf8, false),
+            Field::new("sqrtPriceX96", DataType::Utf8, false),
+            Field::new("liquidity", DataType::Utf8, false),
+            Field::new("tick", DataType::Int32, false),
+            Field::new("zero_for_one", DataType::Boolean, false),
+            Field::new("amount0_abs", DataType::Utf8, false),
+            Field::new("amount1_abs", DataType::Utf8, false),
+            Field::new("raw_params", DataType::Utf8, true),
+            Field::new("raw", DataType::Utf8, true),
+            Field::new("gas_used", DataType::UInt64, true),
+            Field::new("effective_gas_price", DataType::UInt64, true),
+            Field::new("log_index", DataType::UInt64, true),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, arrow::error::ArrowError> {
+        RecordBatch::try_new(DecodedData::schema(), vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|row| &row.transaction_hash))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|row| &row.sender))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|row| &row.recipient))),
+            decimal_column(rows.iter().map(|row| row.amount0)),
+            decimal_column(rows.iter().map(|row| row.amount1)),
+            decimal_column(rows.iter().map(|row| row.sqrtPriceX96)),
+            decimal_column(rows.iter().map(|row| row.liquidity)),
+            Arc::new(Int32Array::from_iter_values(rows.iter().map(|row| row.tick))),
+            Arc::new(BooleanArray::from(rows.iter().map(|row| row.zero_for_one).collect::<Vec<_>>())),
+            decimal_column(rows.iter().map(|row| row.amount0_abs)),
+            decimal_column(rows.iter().map(|row| row.amount1_abs)),
+            Arc::new(StringArray::from(rows.iter().map(|row| row.raw_params.as_ref().and_then(|params| serde_json::to_string(params).ok())).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|row| row.raw.as_ref().and_then(|raw| serde_json::to_string(raw).ok())).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|row| row.gas_used).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|row| row.effective_gas_price).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|row| row.log_index).collect::<Vec<_>>())),
+        ])
+    }
+}
+
+impl ParquetRow for Candle {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("open_time", DataType::UInt64, false),
+            Field::new("interval", DataType::UInt64, false),
+            Field::new("open", DataType::Float64, false),
+            Field::new("high", DataType::Float64, false),
+            Field::new("low", DataType::Float64, false),
+            Field::new("close", DataType::Float64, false),
+            Field::new("volume0", DataType::Utf8, false),
+            Field::new("volume1", DataType::Utf8, false),
+            Field::new("swaps", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, arrow::error::ArrowError> {
+        let float = |value: fn(&Candle) -> f64| -> ArrayRef { Arc::new(Float64Array::from_iter_values(rows.iter().map(value))) };
+        RecordBatch::try_new(Candle::schema(), vec![
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.open_time))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.interval))),
+            float(|row| row.open),
+            float(|row| row.high),
+            float(|row| row.low),
+            float(|row| row.close),
+            decimal_column(rows.iter().map(|row| row.volume0)),
+            decimal_column(rows.iter().map(|row| row.volume1)),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.swaps))),
+        ])
+    }
+}
+
+impl ParquetRow for NumeraireVolume {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("open_time", DataType::UInt64, false),
+            Field::new("interval", DataType::UInt64, false),
+            Field::new("numeraire", DataType::Utf8, false),
+            Field::new("volume", DataType::Float64, false),
+            Field::new("swaps", DataType::UInt64, false),
+            Field::new("unpriced", DataType::UInt64, false),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, arrow::error::ArrowError> {
+        RecordBatch::try_new(NumeraireVolume::schema(), vec![
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.open_time))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.interval))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.numeraire.to_string()))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|row| row.volume))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.swaps))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.unpriced))),
+        ])
+    }
+}
+
+impl ParquetRow for BlockSummary {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("block_number", DataType::UInt64, false),
+            Field::new("swaps", DataType::UInt64, false),
+            Field::new("net_amount0", DataType::Utf8, false),
+            Field::new("net_amount1", DataType::Utf8, false),
+            Field::new("close", DataType::Float64, false),
+        ]))
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, arrow::error::ArrowError> {
+        RecordBatch::try_new(BlockSummary::schema(), vec![
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.block_number))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.swaps))),
+            decimal_column(rows.iter().map(|row| row.net_amount0)),
+            decimal_column(rows.iter().map(|row| row.net_amount1)),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|row| row.close))),
+        ])
+    }
+}
+
+
+/// load_parquet_swaps Reads back the swaps of a file written by `ParquetStore`. Their
+/// pool is taken from the `pool=` directory the file is in.
+pub fn load_parquet_swaps(path: &Path) -> Result<Vec<DecodedData>, Box<dyn Error + Send + Sync>> {
+    let pool = path
+        .ancestors()
+        .find_map(|dir| dir.file_name()?.to_str()?.strip_prefix("pool="))
+        .unwrap_or_default();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+    let mut swaps = Vec::new();
+    for batch in reader {
+        let batch = batch?;
+        let strings = |name| column::<StringArray>(&batch, name);
+        let (hashes, senders, recipients) = (strings("transaction_hash")?, strings("sender")?, strings("recipient")?);
+        let (amount0, amount1) = (strings("amount0")?, strings("amount1")?);
+        let (sqrt_prices, liquidities) = (strings("sqrtPriceX96")?, strings("liquidity")?);
+        let (raw_params, raw) = (strings("raw_params")?, strings("raw")?);
+        let ticks = column::<Int32Array>(&batch, "tick")?;
+        let directions = column::<BooleanArray>(&batch, "zero_for_one")?;
+        let (gas_used, gas_prices) = (column::<UInt64Array>(&batch, "gas_used")?, column::<UInt64Array>(&batch, "effective_gas_price")?);
+        // Files written before the log index was stored lack its column
+        let log_indexes = column::<UInt64Array>(&batch, "log_index").ok();
+        let optional = |array: &UInt64Array, row: usize| array.is_valid(row).then(|| array.value(row));
+        let json_or_none = |array: &StringArray, row: usize| array.is_valid(row).then(|| array.value(row).to_string());
+
+        for row in 0..batch.num_rows() {
+            let (row_amount0, row_amount1): (i128, i128) = (amount0.value(row).parse()?, amount1.value(row).parse()?);
+            swaps.push(DecodedData {
+                transaction_hash: hashes.value(row).to_string(),
+                sender: senders.value(row).to_string(),
+                recipient: recipients.value(row).to_string(),
+                amount0: row_amount0,
+                amount1: row_amount1,
+                sqrtPriceX96: U256::from_dec_str(sqrt_prices.value(row))?,
+                liquidity: liquidities.value(row).parse()?,
+                tick: ticks.value(row),
+                zero_for_one: directions.value(row),
+                // Derived from the amounts, files written before they existed lack them
+                amount0_abs: row_amount0.unsigned_abs(),
+                amount1_abs: row_amount1.unsigned_abs(),
+                raw_params: json_or_none(raw_params, row).map(|json| serde_json::from_str(&json)).transpose()?,
+                raw: json_or_none(raw, row).map(|json| serde_json::from_str(&json)).transpose()?,
+                gas_used: optional(gas_used, row),
+                effective_gas_price: optional(gas_prices, row),
+                pool: pool.to_string(),
+                log_index: log_indexes.and_then(|log_indexes| optional(log_indexes, row)),
+            });
+        }
+    }
+    Ok(swaps)
+}
+
+// The column `name` of `batch`, of array type `A`.
+fn column<'a, A: Array + 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a A, Box<dyn Error + Send + Sync>> {
+    let column = batch.column_by_name(name).ok_or_else(|| format!("no {} column", name))?;
+    column.as_any().downcast_ref::<A>().ok_or_else(|| format!("the {} column has type {}", name, column.data_type()).into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+
+    #[tokio::test]
+    async fn test_big_integers_round_trip() {
+        let output_dir = std::env::temp_dir().join(format!("parquet_store_{}", std::process::id()));
+        let store = ParquetStore::new(&output_dir, PartitionTimezone::Utc, 2);
+        let swaps: Vec<_> = [i128::MIN, -1, i128::MAX]
+            .into_iter()
+            .map(|amount| DecodedData {
+                sender: "0xsender".to_string(),
+                recipient: "0xrecipient".to_string(),
+                sqrtPriceX96: (U256::one() << 160) - 1,
+                liquidity: 1 << 100,
+                tick: -887272,
+                gas_used: (amount > 0).then_some(21_000),
+                ..test_swap(&format!("0x{}", amount), amount, -(amount / 2))
+            })
+            .collect();
+        for swap in &swaps {
+            store.store("0xpool", swap).await.unwrap();
+        }
+        store.flush().await.unwrap();
+
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let dir = output_dir.join("swaps").join(format!("date={}", date)).join("pool=0xpool");
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let read = load_parquet_swaps(&files[0]).unwrap();
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(read.len(), 3);
+        for (read, written) in read.iter().zip(&swaps) {
+            assert_eq!((read.amount0, read.amount1, read.sqrtPriceX96, read.liquidity), (written.amount0, written.amount1, written.sqrtPriceX96, written.liquidity));
+            assert_eq!((read.tick, read.zero_for_one, read.gas_used), (written.tick, written.zero_for_one, written.gas_used));
+            assert_eq!(read.pool, "0xpool");
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use crate::data_store::{DataStore, DecodedData, EventRecord};
+use crate::dead_letters::DeadLetters;
+use crate::error::{classify_storage, ErrorClass};
+
+
+/// RetryPolicy How many times a failed write is retried, and how long to wait in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 to never retry.
+    pub retries: u32,
+    /// Milliseconds waited before the first retry, doubling for every following one.
+    pub backoff_base_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { retries: 3, backoff_base_ms: 500 }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt`, counting from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.backoff_base_ms).saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+
+/// RetryingStore Wraps a store, retrying the swaps it fails to store with a transient
+/// error, e.g. a briefly full disk or a reset database connection, as set by its policy.
+///
+/// Errors `classify_storage` finds fatal, such as a swap that can't be serialized, are
+/// not retried. A swap that still couldn't be stored is appended to the dead letters, when
+/// set, so it isn't lost, and the error is returned either way.
+pub struct RetryingStore {
+    inner: Box<dyn DataStore>,
+    policy: RetryPolicy,
+    dead_letters: Option<Arc<DeadLetters>>,
+}
+
+impl RetryingStore {
+    pub fn new(inner: Box<dyn DataStore>, policy: RetryPolicy) -> Self {
+        RetryingStore { inner, policy, dead_letters: None }
+    }
+
+    /// Append the swaps given up on to `dead_letters`.
+    pub fn with_dead_letters(self, dead_letters: Option<Arc<DeadLetters>>) -> Self {
+        RetryingStore { dead_letters, ..self }
+    }
+}
+
+#[async_trait]
+impl DataStore for RetryingStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut attempt = 0;
+        let error = loop {
+            match self.inner.store(address, data).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.policy.retries && classify_storage(e.as_ref()) == ErrorClass::Transient => {
+                    eprintln!("Warning: storing swap {} failed, retrying: {}", data.transaction_hash, e);
+                    tokio::time::sleep(self.policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => break e,
+            }
+        };
+
+        let Some(dead_letters) = &self.dead_letters else { return Err(error) };
+        let reason = format!("{} (attempts: {})", error, attempt + 1);
+        match dead_letters.append_swap(address, data, &reason).await {
+            Ok(()) => Err(format!("{}, added swap {} to the dead letters", error, data.transaction_hash).into()),
+            Err(e) => Err(format!("{}, and adding swap {} to the dead letters failed: {}", error, data.transaction_hash, e).into()),
+        }
+    }
+
+    async fn store_event(&self, record: &EventRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.store_event(record).await {
+                Err(e) if attempt < self.policy.retries && classify_storage(e.as_ref()) == ErrorClass::Transient => {
+                    eprintln!("Warning: storing {} event failed, retrying: {}", record.event, e);
+                    tokio::time::sleep(self.policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.flush().await {
+                Err(e) if attempt < self.policy.retries && classify_storage(e.as_ref()) == ErrorClass::Transient => {
+                    eprintln!("Warning: flushing the store failed, retrying: {}", e);
+                    tokio::time::sleep(self.policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::data_store::{test_swap, InMemoryStore};
+
+    // Fails the first `failures` stores with `kind`, then stores in memory
+    struct FlakyStore {
+        failures: AtomicU32,
+        kind: io::ErrorKind,
+        stored: Arc<InMemoryStore>,
+    }
+
+    #[async_trait]
+    impl DataStore for FlakyStore {
+        async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            if self.failures.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)).is_ok() {
+                return Err(io::Error::from(self.kind).into());
+            }
+            self.stored.store(address, data).await
+        }
+    }
+
+    fn flaky(failures: u32, kind: io::ErrorKind) -> (Box<dyn DataStore>, Arc<InMemoryStore>) {
+        let stored = Arc::new(InMemoryStore::new());
+        (Box::new(FlakyStore { failures: AtomicU32::new(failures), kind, stored: Arc::clone(&stored) }), stored)
+    }
+
+    const POLICY: RetryPolicy = RetryPolicy { retries: 2, backoff_base_ms: 1 };
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let (inner, stored) = flaky(2, io::ErrorKind::ConnectionReset);
+        RetryingStore::new(inner, POLICY).store("0xpool", &test_swap("0x11", 1, -1)).await.unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_to_the_dead_letters() {
+        let path = std::env::temp_dir().join(format!("retry_dead_letters_{}.json", std::process::id()));
+        let dead_letters = Some(Arc::new(DeadLetters::new(&path)));
+
+        // Still failing after the retries
+        let (inner, _) = flaky(3, io::ErrorKind::TimedOut);
+        let store = RetryingStore::new(inner, POLICY).with_dead_letters(dead_letters.clone());
+        assert!(store.store("0xpool", &test_swap("0x11", 1, -1)).await.is_err());
+
+        // Fatal, given up on right away
+        let (inner, stored) = flaky(1, io::ErrorKind::PermissionDenied);
+        let store = RetryingStore::new(inner, POLICY).with_dead_letters(dead_letters);
+        assert!(store.store("0xpool", &test_swap("0x11", 1, -1)).await.is_err());
+        store.store("0xpool", &test_swap("0x11", 1, -1)).await.unwrap();
+        assert_eq!(stored.len(), 1);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0]["reason"].as_str().unwrap().ends_with("(attempts: 3)"));
+        assert!(records[1]["reason"].as_str().unwrap().ends_with("(attempts: 1)"));
+        assert_eq!(records[1]["pool"], "0xpool");
+        assert_eq!(records[1]["swap"]["transaction_hash"], "0x11");
+    }
+}
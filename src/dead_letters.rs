@@ -0,0 +1,74 @@
+use ethers::types::Log;
+use serde_json::json;
+use std::error::Error;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::data_store::DecodedData;
+
+
+/// DeadLetters Appends the raw logs that failed to decode to a file, one JSON object
+/// per line holding the failure `reason` and the `log`, for later inspection. Swaps that
+/// couldn't be stored go to the same file, under `swap` with their `pool`.
+pub struct DeadLetters {
+    path: PathBuf,
+    // Serializes the appends of the concurrent decode workers
+    lock: Mutex<()>,
+}
+
+impl DeadLetters {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DeadLetters { path: path.into(), lock: Mutex::new(()) }
+    }
+
+    /// Append `log` and the reason it failed to decode.
+    pub async fn append(&self, log: &Log, reason: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.append_line(json!({ "reason": reason, "log": log })).await
+    }
+
+    /// Append the swap `data` of `pool` and the reason it couldn't be stored.
+    pub async fn append_swap(&self, pool: &str, data: &DecodedData, reason: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.append_line(json!({ "reason": reason, "pool": pool, "swap": serde_json::to_value(data)? })).await
+    }
+
+    async fn append_line(&self, record: serde_json::Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line = format!("{}\n", record);
+
+        let _guard = self.lock.lock().await;
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(line.as_bytes()).await?;
+        // A tokio file writes in the background, wait for the line to reach the file
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::H256;
+
+    #[tokio::test]
+    async fn test_append() {
+        let path = std::env::temp_dir().join(format!("dead_letters_{}.json", std::process::id()));
+        let dead_letters = DeadLetters::new(&path);
+        let log = Log { transaction_hash: Some(H256::repeat_byte(0x11)), ..Default::default() };
+
+        dead_letters.append(&log, "first").await.unwrap();
+        dead_letters.append(&log, "second").await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let reasons: Vec<_> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["reason"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(reasons, vec!["first", "second"]);
+    }
+}
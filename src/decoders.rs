@@ -0,0 +1,899 @@
+use ethers::{
+    abi::{ethabi, Abi, Event, Token},
+    types::{Address, Log, U256},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::data_store::{decimal_string, decimal_u256, DecodedData, EventRecord};
+use crate::error::TaskError;
+use crate::log_processing::{parse_address, parse_decoded_log, to_checksum_address, to_hex, topic_address};
+use crate::schemas::{SchemaDecoder, SchemaRegistry};
+
+
+/// The result of decoding a single log with an `EventDecoder`.
+///
+/// Swaps keep their typed `DecodedData` so they can go straight to storage,
+/// every other event is carried as the JSON object produced by its decoder.
+#[derive(Debug)]
+pub enum DecodedEvent {
+    Swap(DecodedData),
+    Transfer(TransferData),
+    DexSwap(DexSwapData),
+    Liquidity(LiquidityData),
+    Other { event_name: String, fields: Value },
+}
+
+impl DecodedEvent {
+    /// The record stored for an event other than a Uniswap V3 swap decoded from `log`,
+    /// filed under the contract that emitted it. None for a swap, which is stored as its
+    /// `DecodedData`.
+    pub fn event_record(&self, log: &Log) -> Option<EventRecord> {
+        let (event, fields) = match self {
+            DecodedEvent::Swap(_) => return None,
+            DecodedEvent::Transfer(data) => ("Transfer".to_string(), serde_json::to_value(data).ok()?),
+            DecodedEvent::DexSwap(data) => ("DexSwap".to_string(), serde_json::to_value(data).ok()?),
+            DecodedEvent::Liquidity(data) => (format!("{:?}", data.kind), serde_json::to_value(data).ok()?),
+            DecodedEvent::Other { event_name, fields } => (event_name.clone(), fields.clone()),
+        };
+        Some(EventRecord { event, address: to_checksum_address(log.address.as_bytes()), fields })
+    }
+}
+
+/// EventDecoder Turns an ABI-parsed log of one specific event into output data.
+///
+/// Implementations are registered by event name in a `DecoderRegistry`, and
+/// `process_log` dispatches to them once the log's topic0 has been matched
+/// against the ABI, so supporting a new event only needs a new impl.
+pub trait EventDecoder: Send + Sync {
+    /// The ABI event name this decoder handles, e.g. "Swap".
+    fn event_name(&self) -> &str;
+
+    /// Decode the parsed log into a JSON object, or None if it can't be decoded.
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value>;
+
+    /// Decode the parsed log into a `DecodedEvent`. The default wraps the JSON
+    /// output of `decode`; decoders with a typed record override it.
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        self.decode(decoded, log).map(|fields| DecodedEvent::Other {
+            event_name: self.event_name().to_string(),
+            fields,
+        })
+    }
+}
+
+
+/// Uniswap V3 `Swap(address,address,int256,int256,uint160,uint128,int24)`.
+pub struct SwapDecoder;
+
+impl EventDecoder for SwapDecoder {
+    fn event_name(&self) -> &str {
+        "Swap"
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        // Large integers are emitted as decimal strings since they don't fit a JSON number.
+        let data = parse_decoded_log(decoded, log)?;
+        Some(json!({
+            "transaction_hash": data.transaction_hash,
+            "sender": data.sender,
+            "recipient": data.recipient,
+            "amount0": data.amount0.to_string(),
+            "amount1": data.amount1.to_string(),
+            "sqrt_price_x96": data.sqrtPriceX96.to_string(),
+            "liquidity": data.liquidity.to_string(),
+            "tick": data.tick,
+        }))
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        parse_decoded_log(decoded, log).map(DecodedEvent::Swap)
+    }
+}
+
+
+/// TransferData An ERC-20 token transfer of `value` raw units from `from` to `to`.
+///
+/// `token` is the address of the token contract that emitted the event. Mints and
+/// burns show up as transfers from and to the zero address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferData {
+    pub transaction_hash: String,
+    pub token: String,
+    pub from: String,
+    pub to: String,
+    /// Decimal string, since 256-bit values don't fit a JSON number.
+    #[serde(with = "decimal_u256")]
+    pub value: U256,
+}
+
+impl TransferData {
+    /// Rewrite `token`, `from` and `to` in their EIP-55 checksummed form.
+    pub fn checksum_addresses(&mut self) {
+        for address in [&mut self.token, &mut self.from, &mut self.to] {
+            if let Ok(bytes) = hex::decode(address.trim_start_matches("0x")) {
+                *address = to_checksum_address(&bytes);
+            }
+        }
+    }
+}
+
+
+/// ERC-20 `Transfer(address,address,uint256)`.
+///
+/// The event isn't part of the pool ABI, add it with `TransferDecoder::add_event` to the
+/// ABI the event map is built from when registering this decoder.
+pub struct TransferDecoder;
+
+impl TransferDecoder {
+    pub const EVENT_NAME: &'static str = "Transfer";
+
+    /// Add `transfer_event()` to `abi` unless it defines it already, for the event map to
+    /// match the transfer logs.
+    pub fn add_event(abi: &mut Abi) {
+        let event = transfer_event();
+        let known = abi.events.entry(Self::EVENT_NAME.to_string()).or_default();
+        if !known.contains(&event) {
+            known.push(event);
+        }
+    }
+
+    fn parse(decoded: &ethabi::Log, log: &Log) -> Option<TransferData> {
+        let value = decoded.params.iter().find(|param| param.name == "value")?;
+        let Token::Uint(value) = value.value else { return None };
+        Some(TransferData {
+            transaction_hash: to_hex(&log.transaction_hash?.0),
+            token: to_hex(&log.address.0),
+            from: topic_address(log.topics.get(1)?),
+            to: topic_address(log.topics.get(2)?),
+            value,
+        })
+    }
+}
+
+impl EventDecoder for TransferDecoder {
+    fn event_name(&self) -> &str {
+        Self::EVENT_NAME
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        serde_json::to_value(TransferDecoder::parse(&decoded, log)?).ok()
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        TransferDecoder::parse(&decoded, log).map(DecodedEvent::Transfer)
+    }
+}
+
+/// transfer_event The ERC-20 `Transfer` event definition, for ABIs that don't include it.
+pub fn transfer_event() -> Event {
+    let abi = ethers::abi::parse_abi(&["event Transfer(address indexed from, address indexed to, uint256 value)"])
+        .expect("valid Transfer signature");
+    abi.event("Transfer").expect("Transfer event defined").clone()
+}
+
+
+/// DexProtocol A DEX besides Uniswap whose swap events can be decoded into `DexSwapData`,
+/// given as `curve` or `balancer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DexProtocol {
+    Curve,
+    Balancer,
+}
+
+impl DexProtocol {
+    /// The swap events of the protocol, under the name its decoder is registered for.
+    /// Balancer's is registered as `BalancerSwap`, so it doesn't clash with Uniswap's `Swap`.
+    pub fn events(&self) -> (&'static str, Vec<Event>) {
+        let (name, signatures): (&str, &[&str]) = match self {
+            // StableSwap pools index the coins with int128, the crypto pools with uint256
+            DexProtocol::Curve => (CurveExchangeDecoder::EVENT_NAME, &[
+                "event TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)",
+                "event TokenExchange(address indexed buyer, uint256 sold_id, uint256 tokens_sold, uint256 bought_id, uint256 tokens_bought)",
+            ]),
+            DexProtocol::Balancer => (BalancerSwapDecoder::EVENT_NAME, &[
+                "event Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut, uint256 amountIn, uint256 amountOut)",
+            ]),
+        };
+        let abi = ethers::abi::parse_abi(signatures).expect("valid swap event signatures");
+        (name, abi.events().cloned().collect())
+    }
+
+    /// Add the swap events of the protocol to `abi`, for the event map to match their logs.
+    pub fn add_events(&self, abi: &mut Abi) {
+        let (name, events) = self.events();
+        let known = abi.events.entry(name.to_string()).or_default();
+        for event in events {
+            if !known.contains(&event) {
+                known.push(event);
+            }
+        }
+    }
+
+    pub fn decoder(&self) -> Box<dyn EventDecoder> {
+        match self {
+            DexProtocol::Curve => Box::new(CurveExchangeDecoder),
+            DexProtocol::Balancer => Box::new(BalancerSwapDecoder),
+        }
+    }
+}
+
+impl FromStr for DexProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "curve" => Ok(DexProtocol::Curve),
+            "balancer" => Ok(DexProtocol::Balancer),
+            _ => Err(format!("Unknown protocol {:?}, expected curve or balancer", s)),
+        }
+    }
+}
+
+
+/// DexSwapData A swap of another DEX than Uniswap, normalized to `amount_in` raw units of
+/// `token_in` traded for `amount_out` raw units of `token_out` in `pool`.
+///
+/// | Field | Curve `TokenExchange` | Balancer `Swap` |
+/// |---|---|---|
+/// | `pool` | the emitting pool | the first 20 bytes of `poolId`, the pool's address |
+/// | `trader` | `buyer` | none, the Vault doesn't log who swapped |
+/// | `token_in` / `token_out` | `sold_id` / `bought_id`, the index of the coin in the pool's `coins` | `tokenIn` / `tokenOut` |
+/// | `amount_in` / `amount_out` | `tokens_sold` / `tokens_bought` | `amountIn` / `amountOut` |
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DexSwapData {
+    pub protocol: DexProtocol,
+    pub transaction_hash: String,
+    pub pool: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trader: Option<String>,
+    pub token_in: String,
+    pub token_out: String,
+    #[serde(with = "decimal_u256")]
+    pub amount_in: U256,
+    #[serde(with = "decimal_u256")]
+    pub amount_out: U256,
+}
+
+impl DexSwapData {
+    /// Rewrite the addresses in their EIP-55 checksummed form. Curve's coin indexes are kept.
+    pub fn checksum_addresses(&mut self) {
+        let addresses = [Some(&mut self.pool), self.trader.as_mut(), Some(&mut self.token_in), Some(&mut self.token_out)];
+        for address in addresses.into_iter().flatten() {
+            if let Ok(bytes) = hex::decode(address.trim_start_matches("0x")) {
+                if bytes.len() == 20 {
+                    *address = to_checksum_address(&bytes);
+                }
+            }
+        }
+    }
+}
+
+// The value of the parameter `name` of a parsed log.
+fn param<'a>(decoded: &'a ethabi::Log, name: &str) -> Option<&'a Token> {
+    decoded.params.iter().find(|param| param.name == name).map(|param| &param.value)
+}
+
+// The value of the `uint` parameter `name` of a parsed log.
+fn uint_param(decoded: &ethabi::Log, name: &str) -> Option<U256> {
+    match param(decoded, name)? {
+        Token::Uint(value) => Some(*value),
+        _ => None,
+    }
+}
+
+
+/// Curve `TokenExchange(address,int128,uint256,int128,uint256)`, and its `uint256` coin
+/// index variant, normalized into a `DexSwapData`.
+pub struct CurveExchangeDecoder;
+
+impl CurveExchangeDecoder {
+    pub const EVENT_NAME: &'static str = "TokenExchange";
+
+    fn parse(decoded: &ethabi::Log, log: &Log) -> Option<DexSwapData> {
+        Some(DexSwapData {
+            protocol: DexProtocol::Curve,
+            transaction_hash: to_hex(&log.transaction_hash?.0),
+            pool: to_hex(&log.address.0),
+            trader: Some(topic_address(log.topics.get(1)?)),
+            token_in: token_to_string(param(decoded, "sold_id")?),
+            token_out: token_to_string(param(decoded, "bought_id")?),
+            amount_in: uint_param(decoded, "tokens_sold")?,
+            amount_out: uint_param(decoded, "tokens_bought")?,
+        })
+    }
+}
+
+impl EventDecoder for CurveExchangeDecoder {
+    fn event_name(&self) -> &str {
+        Self::EVENT_NAME
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        serde_json::to_value(CurveExchangeDecoder::parse(&decoded, log)?).ok()
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        CurveExchangeDecoder::parse(&decoded, log).map(DecodedEvent::DexSwap)
+    }
+}
+
+
+/// Balancer V2 Vault `Swap(bytes32,address,address,uint256,uint256)`, normalized into a
+/// `DexSwapData`. Every Balancer swap is emitted by the Vault, subscribe to it rather
+/// than to the pools.
+pub struct BalancerSwapDecoder;
+
+impl BalancerSwapDecoder {
+    pub const EVENT_NAME: &'static str = "BalancerSwap";
+
+    fn parse(decoded: &ethabi::Log, log: &Log) -> Option<DexSwapData> {
+        let Token::FixedBytes(pool_id) = param(decoded, "poolId")? else { return None };
+        Some(DexSwapData {
+            protocol: DexProtocol::Balancer,
+            transaction_hash: to_hex(&log.transaction_hash?.0),
+            pool: to_hex(pool_id.get(..20)?),
+            trader: None,
+            token_in: topic_address(log.topics.get(2)?),
+            token_out: topic_address(log.topics.get(3)?),
+            amount_in: uint_param(decoded, "amountIn")?,
+            amount_out: uint_param(decoded, "amountOut")?,
+        })
+    }
+}
+
+impl EventDecoder for BalancerSwapDecoder {
+    fn event_name(&self) -> &str {
+        Self::EVENT_NAME
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        serde_json::to_value(BalancerSwapDecoder::parse(&decoded, log)?).ok()
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        BalancerSwapDecoder::parse(&decoded, log).map(DecodedEvent::DexSwap)
+    }
+}
+
+
+/// LiquidityKind Whether a position added liquidity to its range or removed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityKind {
+    Mint,
+    Burn,
+}
+
+/// LiquidityData A Uniswap V3 `Mint` or `Burn`: `amount` liquidity added to or removed
+/// from the `tick_lower`..`tick_upper` range of `pool` by the position of `owner`, for
+/// `amount0` and `amount1` raw token units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiquidityData {
+    pub kind: LiquidityKind,
+    pub transaction_hash: String,
+    pub pool: String,
+    pub owner: String,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    #[serde(with = "decimal_string")]
+    pub amount: u128,
+    #[serde(with = "decimal_u256")]
+    pub amount0: U256,
+    #[serde(with = "decimal_u256")]
+    pub amount1: U256,
+}
+
+impl LiquidityData {
+    /// The change of the pool's liquidity: `amount` for a mint, minus it for a burn.
+    pub fn delta(&self) -> i128 {
+        let amount = i128::try_from(self.amount).unwrap_or(i128::MAX);
+        match self.kind {
+            LiquidityKind::Mint => amount,
+            LiquidityKind::Burn => -amount,
+        }
+    }
+
+    /// Rewrite `pool` and `owner` in their EIP-55 checksummed form.
+    pub fn checksum_addresses(&mut self) {
+        for address in [&mut self.pool, &mut self.owner] {
+            if let Ok(bytes) = hex::decode(address.trim_start_matches("0x")) {
+                *address = to_checksum_address(&bytes);
+            }
+        }
+    }
+
+    // Mint and Burn name their parameters alike, Mint has a `sender` on top.
+    fn parse(kind: LiquidityKind, decoded: &ethabi::Log, log: &Log) -> Option<Self> {
+        let tick = |name: &str| match param(decoded, name)? {
+            Token::Int(value) => i32::try_from(ethers::types::I256::from_raw(*value)).ok(),
+            _ => None,
+        };
+        let Token::Address(owner) = param(decoded, "owner")? else { return None };
+        Some(LiquidityData {
+            kind,
+            transaction_hash: to_hex(&log.transaction_hash?.0),
+            pool: to_hex(&log.address.0),
+            owner: to_hex(&owner.0),
+            tick_lower: tick("tickLower")?,
+            tick_upper: tick("tickUpper")?,
+            amount: u128::try_from(uint_param(decoded, "amount")?).ok()?,
+            amount0: uint_param(decoded, "amount0")?,
+            amount1: uint_param(decoded, "amount1")?,
+        })
+    }
+}
+
+
+/// Uniswap V3 `Mint(address,address,int24,int24,uint128,uint256,uint256)`.
+pub struct MintDecoder;
+
+impl EventDecoder for MintDecoder {
+    fn event_name(&self) -> &str {
+        "Mint"
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        decode_params(&decoded, log)
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        LiquidityData::parse(LiquidityKind::Mint, &decoded, log).map(DecodedEvent::Liquidity)
+    }
+}
+
+
+/// Uniswap V3 `Burn(address,int24,int24,uint128,uint256,uint256)`.
+pub struct BurnDecoder;
+
+impl EventDecoder for BurnDecoder {
+    fn event_name(&self) -> &str {
+        "Burn"
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        decode_params(&decoded, log)
+    }
+
+    fn decode_event(&self, decoded: ethabi::Log, log: &Log) -> Option<DecodedEvent> {
+        LiquidityData::parse(LiquidityKind::Burn, &decoded, log).map(DecodedEvent::Liquidity)
+    }
+}
+
+
+// Build a JSON object holding the transaction hash and every parameter of the
+// parsed log, keyed by the ABI parameter name.
+pub(crate) fn decode_params(decoded: &ethabi::Log, log: &Log) -> Option<Value> {
+    let transaction_hash = to_hex(&log.transaction_hash?.0);
+
+    let mut fields = Map::new();
+    fields.insert("transaction_hash".to_string(), Value::String(transaction_hash));
+    for param in &decoded.params {
+        fields.insert(param.name.clone(), token_to_json(&param.value));
+    }
+    Some(Value::Object(fields))
+}
+
+// Convert an ABI token into JSON. Numbers are emitted as decimal strings so
+// 256-bit values survive, and signed ints are recovered from two's complement.
+fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Bool(value) => Value::Bool(*value),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_to_json).collect())
+        }
+        _ => Value::String(token_to_string(token)),
+    }
+}
+
+/// token_to_string Stringifies an ABI token: addresses and bytes as 0x-prefixed hex,
+/// numbers in decimal with signed ints recovered from two's complement, and arrays and
+/// tuples as their comma-separated elements in brackets.
+pub fn token_to_string(token: &Token) -> String {
+    match token {
+        Token::Address(address) => to_hex(&address.0),
+        Token::Int(value) => ethers::types::I256::from_raw(*value).to_string(),
+        Token::Uint(value) => value.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => value.clone(),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => to_hex(bytes),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            format!("[{}]", tokens.iter().map(token_to_string).collect::<Vec<_>>().join(","))
+        }
+    }
+}
+
+/// raw_params Every parameter of a parsed log stringified by `token_to_string`, keyed by name.
+pub fn raw_params(decoded: &ethabi::Log) -> HashMap<String, String> {
+    decoded.params.iter().map(|param| (param.name.clone(), token_to_string(&param.value))).collect()
+}
+
+
+/// AnonymousEvent Maps the logs of `address` with `topics` topics to the ABI event
+/// `event_name`, given as `<address>:<topic count>:<event name>`.
+///
+/// Anonymous events have no signature hash in topic0 to be recognized by, every topic
+/// is an indexed parameter. They are only decoded where mapped explicitly, and only
+/// when topic0 isn't the signature of an ABI event, so regular events are never taken
+/// for them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnonymousEvent {
+    pub address: Address,
+    pub topics: usize,
+    pub event_name: String,
+}
+
+impl FromStr for AnonymousEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid anonymous event {:?}, expected <address>:<topic count>:<event name>", s);
+        let mut parts = s.split(':').map(str::trim);
+        let (Some(address), Some(topics), Some(event_name), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let topics: usize = topics.parse().map_err(|_| invalid())?;
+        if event_name.is_empty() || topics > 4 {
+            return Err(invalid());
+        }
+        Ok(AnonymousEvent { address: parse_address(address).map_err(|e| e.to_string())?, topics, event_name: event_name.to_string() })
+    }
+}
+
+
+/// DecoderRegistry Maps event names to the decoder responsible for them.
+///
+/// Only events with a registered decoder are decoded by `process_log`,
+/// everything else the ABI knows about is skipped.
+pub struct DecoderRegistry {
+    decoders: HashMap<String, Box<dyn EventDecoder>>,
+    checksum_addresses: bool,
+    raw_params: bool,
+    keep_raw: bool,
+    sample_rate: u64,
+    // Logs matched per pool address, to pick every `sample_rate`th one
+    sample_counts: Mutex<HashMap<Address, u64>>,
+    // The anonymous event definitions keyed by emitting address and topic count
+    anonymous_events: HashMap<(Address, usize), Event>,
+}
+
+impl DecoderRegistry {
+    /// An empty registry; nothing will be decoded until decoders are registered.
+    pub fn new() -> Self {
+        DecoderRegistry {
+            decoders: HashMap::new(),
+            checksum_addresses: false,
+            raw_params: false,
+            keep_raw: false,
+            sample_rate: 1,
+            sample_counts: Mutex::new(HashMap::new()),
+            anonymous_events: HashMap::new(),
+        }
+    }
+
+    /// Register a decoder, replacing any existing decoder for the same event name.
+    pub fn register(&mut self, decoder: Box<dyn EventDecoder>) {
+        self.decoders.insert(decoder.event_name().to_string(), decoder);
+    }
+
+    /// Register the decoder of `protocol`'s swap events. Its events must be in the ABI the
+    /// event map is built from, see `DexProtocol::add_events`.
+    pub fn register_protocol(&mut self, protocol: DexProtocol) {
+        self.register(protocol.decoder());
+    }
+
+    /// Decode the events of `schemas` with a `SchemaDecoder` each, replacing their decoders,
+    /// except the events the registry leaves to a typed decoder. Their events must be in
+    /// the ABI the event map is built from, see `SchemaRegistry::check`.
+    pub fn register_schemas(&mut self, schemas: &SchemaRegistry) {
+        for (event_name, fields) in schemas.schemas() {
+            if !schemas.is_typed(event_name) {
+                self.register(Box::new(SchemaDecoder::new(event_name, fields)));
+            }
+        }
+    }
+
+    pub fn get(&self, event_name: &str) -> Option<&dyn EventDecoder> {
+        self.decoders.get(event_name).map(|decoder| decoder.as_ref())
+    }
+
+    pub fn event_names(&self) -> impl Iterator<Item = &str> {
+        self.decoders.keys().map(|name| name.as_str())
+    }
+
+    /// Only keep the decoders of `event_names`. Fails when one of them has no decoder.
+    pub fn retain_events<S: AsRef<str>>(&mut self, event_names: &[S]) -> Result<(), TaskError> {
+        if let Some(unknown) = event_names.iter().map(|name| name.as_ref()).find(|name| !self.decoders.contains_key(*name)) {
+            let mut known: Vec<&str> = self.event_names().collect();
+            known.sort();
+            return Err(TaskError::InvalidConfig(format!("no decoder for the {} event, expected one of {}", unknown, known.join(", "))));
+        }
+        self.decoders.retain(|name, _| event_names.iter().any(|event_name| event_name.as_ref() == name));
+        Ok(())
+    }
+
+    /// Emit the sender, recipient and pool of decoded swaps as EIP-55 checksummed addresses
+    /// instead of lowercase hex. Off by default.
+    pub fn set_checksum_addresses(&mut self, enabled: bool) {
+        self.checksum_addresses = enabled;
+    }
+
+    pub fn checksum_addresses(&self) -> bool {
+        self.checksum_addresses
+    }
+
+    /// Attach every event parameter to decoded swaps as `raw_params`. Off by default.
+    pub fn set_raw_params(&mut self, enabled: bool) {
+        self.raw_params = enabled;
+    }
+
+    pub fn raw_params(&self) -> bool {
+        self.raw_params
+    }
+
+    /// Attach the topics and data of the log to decoded swaps as `raw`. Off by default.
+    pub fn set_keep_raw(&mut self, enabled: bool) {
+        self.keep_raw = enabled;
+    }
+
+    pub fn keep_raw(&self) -> bool {
+        self.keep_raw
+    }
+
+    /// Only decode every `rate`th log matching a registered decoder, counted per pool,
+    /// starting with the first. 0 and 1 decode every log, the default.
+    ///
+    /// Sampled output is for rough monitoring only: volumes, candles and metrics built
+    /// from it cover a fraction of the swaps and must not be read as complete.
+    pub fn set_sample_rate(&mut self, rate: u64) {
+        self.sample_rate = rate.max(1);
+    }
+
+    /// Whether the next matching log emitted by `pool` is to be decoded under the sample rate.
+    pub fn sample(&self, pool: &Address) -> bool {
+        if self.sample_rate == 1 {
+            return true;
+        }
+        let mut counts = self.sample_counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = counts.entry(*pool).or_insert(0);
+        let sampled = *count % self.sample_rate == 0;
+        *count += 1;
+        sampled
+    }
+
+    /// Decode the logs mapped by `mapping` as its event of `abi`, whatever their topic0.
+    /// Fails when the ABI has no such event, or when the event doesn't index as many
+    /// parameters as the mapped logs have topics.
+    pub fn register_anonymous(&mut self, mapping: &AnonymousEvent, abi: &Abi) -> Result<(), TaskError> {
+        let mut event = abi
+            .event(&mapping.event_name)
+            .map_err(|_| TaskError::InvalidConfig(format!("the anonymous event {} isn't in the ABI", mapping.event_name)))?
+            .clone();
+        let indexed = event.inputs.iter().filter(|param| param.indexed).count();
+        if indexed != mapping.topics {
+            return Err(TaskError::InvalidConfig(format!(
+                "the anonymous event {} indexes {} parameters, so its logs can't have {} topics",
+                mapping.event_name, indexed, mapping.topics
+            )));
+        }
+        event.anonymous = true;
+        self.anonymous_events.insert((mapping.address, mapping.topics), event);
+        Ok(())
+    }
+
+    /// The anonymous event the logs of `address` with `topics` topics are mapped to, if any.
+    pub fn anonymous_event(&self, address: &Address, topics: usize) -> Option<&Event> {
+        self.anonymous_events.get(&(*address, topics))
+    }
+
+    /// The addresses with anonymous events mapped.
+    pub fn anonymous_addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.anonymous_events.keys().map(|(address, _)| *address).collect();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+}
+
+impl Default for DecoderRegistry {
+    /// The registry used by the binary: Swap, Mint and Burn.
+    fn default() -> Self {
+        let mut registry = DecoderRegistry::new();
+        registry.register(Box::new(SwapDecoder));
+        registry.register(Box::new(MintDecoder));
+        registry.register(Box::new(BurnDecoder));
+        registry
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{Abi, RawLog};
+    use ethers::types::{H256, I256, U256};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_retain_events() {
+        let mut registry = DecoderRegistry::default();
+        registry.retain_events(&["Swap"]).unwrap();
+        assert_eq!(registry.event_names().collect::<Vec<_>>(), vec!["Swap"]);
+
+        let err = DecoderRegistry::default().retain_events(&["Swap", "Flash"]).unwrap_err();
+        assert!(err.to_string().contains("no decoder for the Flash event, expected one of Burn, Mint, Swap"));
+    }
+
+    #[test]
+    fn test_burn_decoder() {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event = abi.event("Burn").unwrap();
+
+        let owner = H256::from_str("0x000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe88").unwrap();
+        let tick_lower = H256::from_slice(&ethers::abi::encode(&[Token::Int(I256::from(-887220).into_raw())]));
+        let tick_upper = H256::from_slice(&ethers::abi::encode(&[Token::Int(I256::from(887220).into_raw())]));
+        let data = ethers::abi::encode(&[
+            Token::Uint(U256::from(1_000_000u64)),
+            Token::Uint(U256::from(5u64)),
+            Token::Uint(U256::from(7u64)),
+        ]);
+        let log = Log {
+            topics: vec![event.signature(), owner, tick_lower, tick_upper],
+            data: data.clone().into(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+        let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data }).unwrap();
+
+        let registry = DecoderRegistry::default();
+        let decoder = registry.get("Burn").unwrap();
+        let Some(DecodedEvent::Liquidity(burn)) = decoder.decode_event(decoded.clone(), &log) else {
+            panic!("Expected a Liquidity event");
+        };
+        assert_eq!((burn.kind, burn.tick_lower, burn.tick_upper, burn.amount), (LiquidityKind::Burn, -887220, 887220, 1_000_000));
+        assert_eq!(burn.owner, "0xc36442b4a4522e871399cd717abdd847ab11fe88");
+        assert_eq!(burn.delta(), -1_000_000);
+
+        let fields = decoder.decode(decoded, &log).unwrap();
+
+        assert_eq!(fields["transaction_hash"], to_hex(&[0x11; 32]));
+        assert_eq!(fields["owner"], "0xc36442b4a4522e871399cd717abdd847ab11fe88");
+        assert_eq!(fields["tickLower"], "-887220");
+        assert_eq!(fields["tickUpper"], "887220");
+        assert_eq!(fields["amount"], "1000000");
+        assert_eq!(fields["amount0"], "5");
+        assert_eq!(fields["amount1"], "7");
+    }
+
+    #[test]
+    fn test_transfer_decoder() {
+        let event = transfer_event();
+        // keccak256("Transfer(address,address,uint256)")
+        assert_eq!(
+            event.signature(),
+            H256::from_str("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").unwrap()
+        );
+
+        let from = H256::from_str("0x000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe88").unwrap();
+        let to = H256::from_str("0x00000000000000000000000088e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+        let data = ethers::abi::encode(&[Token::Uint(U256::exp10(30))]);
+        let log = Log {
+            address: "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".parse().unwrap(),
+            topics: vec![event.signature(), from, to],
+            data: data.clone().into(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+        let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data }).unwrap();
+
+        let mut registry = DecoderRegistry::new();
+        registry.register(Box::new(TransferDecoder));
+        let decoder = registry.get("Transfer").unwrap();
+
+        let fields = decoder.decode(decoded.clone(), &log).unwrap();
+        assert_eq!(fields["value"], "1000000000000000000000000000000");
+
+        let Some(DecodedEvent::Transfer(transfer)) = decoder.decode_event(decoded, &log) else { panic!("not a transfer") };
+        assert_eq!(transfer.token, "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+        assert_eq!(transfer.from, "0xc36442b4a4522e871399cd717abdd847ab11fe88");
+        assert_eq!(transfer.to, "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640");
+        assert_eq!(transfer.value, U256::exp10(30));
+    }
+
+    #[test]
+    fn test_curve_exchange_decoder() {
+        let (name, events) = DexProtocol::Curve.events();
+        let buyer = H256::from_str("0x000000000000000000000000c36442b4a4522e871399cd717abdd847ab11fe88").unwrap();
+        let registry = {
+            let mut registry = DecoderRegistry::new();
+            registry.register_protocol("curve".parse().unwrap());
+            registry
+        };
+        // Both coin index types are decoded alike
+        for (event, sold_id, bought_id) in [
+            (&events[0], Token::Int(U256::from(2)), Token::Int(U256::from(1))),
+            (&events[1], Token::Uint(U256::from(2)), Token::Uint(U256::from(1))),
+        ] {
+            let data = ethers::abi::encode(&[sold_id, Token::Uint(U256::exp10(20)), bought_id, Token::Uint(U256::from(99_000_000u64))]);
+            let log = Log {
+                address: "0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7".parse().unwrap(),
+                topics: vec![event.signature(), buyer],
+                data: data.clone().into(),
+                transaction_hash: Some(H256::repeat_byte(0x11)),
+                ..Default::default()
+            };
+            let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data }).unwrap();
+
+            let Some(DecodedEvent::DexSwap(swap)) = registry.get(name).unwrap().decode_event(decoded, &log) else { panic!("not a swap") };
+            assert_eq!(swap.protocol, DexProtocol::Curve);
+            assert_eq!(swap.pool, "0xbebc44782c7db0a1a60cb6fe97d0b483032ff1c7");
+            assert_eq!(swap.trader.as_deref(), Some("0xc36442b4a4522e871399cd717abdd847ab11fe88"));
+            assert_eq!((swap.token_in.as_str(), swap.token_out.as_str()), ("2", "1"));
+            assert_eq!((swap.amount_in, swap.amount_out), (U256::exp10(20), U256::from(99_000_000u64)));
+        }
+    }
+
+    #[test]
+    fn test_balancer_swap_decoder() {
+        let mut abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        DexProtocol::Balancer.add_events(&mut abi);
+        // Registered next to Uniswap's Swap rather than in its place
+        assert_eq!(abi.events["BalancerSwap"][0].name, "Swap");
+        assert_ne!(abi.events["BalancerSwap"][0].signature(), abi.event("Swap").unwrap().signature());
+        let event = &abi.events["BalancerSwap"][0];
+
+        let pool_id = H256::from_str("0x5c6ee304399dbdb9c8ef030ab642b10820db8f56000200000000000000000014").unwrap();
+        let token_in = H256::from_str("0x000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap();
+        let token_out = H256::from_str("0x000000000000000000000000ba100000625a3754423978a60c9317c58a424e3d").unwrap();
+        let data = ethers::abi::encode(&[Token::Uint(U256::exp10(18)), Token::Uint(U256::from(7u64))]);
+        let log = Log {
+            address: "0xBA12222222228d8Ba445958a75a0704d566BF2C8".parse().unwrap(),
+            topics: vec![event.signature(), pool_id, token_in, token_out],
+            data: data.clone().into(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+        let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data }).unwrap();
+
+        let Some(DecodedEvent::DexSwap(mut swap)) = BalancerSwapDecoder.decode_event(decoded, &log) else { panic!("not a swap") };
+        assert_eq!(swap.pool, "0x5c6ee304399dbdb9c8ef030ab642b10820db8f56");
+        assert_eq!(swap.trader, None);
+        assert_eq!(swap.token_in, "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+        assert_eq!(swap.token_out, "0xba100000625a3754423978a60c9317c58a424e3d");
+        assert_eq!((swap.amount_in, swap.amount_out), (U256::exp10(18), U256::from(7u64)));
+
+        // Stored under the Vault that emitted it
+        let record = DecodedEvent::DexSwap(swap.clone()).event_record(&log).unwrap();
+        assert_eq!((record.event.as_str(), record.address.as_str()), ("DexSwap", "0xBA12222222228d8Ba445958a75a0704d566BF2C8"));
+        assert_eq!(record.fields["amount_in"], "1000000000000000000");
+
+        swap.checksum_addresses();
+        assert_eq!(swap.token_in, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+    }
+
+    #[test]
+    fn test_token_to_string() {
+        assert_eq!(token_to_string(&Token::Address(Address::repeat_byte(0xab))), format!("0x{}", "ab".repeat(20)));
+        assert_eq!(token_to_string(&Token::Int(I256::from(-42).into_raw())), "-42");
+        assert_eq!(token_to_string(&Token::Uint(U256::exp10(30))), "1000000000000000000000000000000");
+        assert_eq!(token_to_string(&Token::FixedBytes(vec![0x01, 0xff])), "0x01ff");
+        assert_eq!(token_to_string(&Token::Array(vec![Token::Bool(true), Token::Uint(7.into())])), "[true,7]");
+    }
+
+    #[test]
+    fn test_sample_rate_is_per_pool() {
+        let mut registry = DecoderRegistry::default();
+        registry.set_sample_rate(3);
+        let (pool_a, pool_b) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xbb));
+
+        let sampled_a: Vec<_> = (0..7).map(|_| registry.sample(&pool_a)).collect();
+        assert_eq!(sampled_a, vec![true, false, false, true, false, false, true]);
+        // A busy pool doesn't use up the samples of another
+        assert!(registry.sample(&pool_b));
+        assert!(!registry.sample(&pool_b));
+    }
+}
@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use ethers::providers::{HttpClientError, ProviderError, WsClientError};
+
+use crate::api_keys::is_rate_limit_message;
+
+
+/// TaskError The errors `main` reports to the user.
+///
+/// Configuration problems (a missing env var, an unreadable ABI file) get their own
+/// variant with a message telling the user how to fix them; anything that goes wrong
+/// while fetching or decoding is carried as `Runtime`.
+pub enum TaskError {
+    /// A required environment variable isn't set, e.g. `INFURA_API_KEY`.
+    MissingEnvVar(&'static str),
+    /// No `.git` directory was found above the current directory to default `--output-dir` to.
+    RootDirNotFound,
+    /// The env file given with `--env-file`, or selected with `APP_ENV`, couldn't be loaded.
+    EnvFile { path: String, source: dotenv::Error },
+    /// The ABI JSON file given with `--abi` couldn't be read.
+    ReadAbi { path: String, source: io::Error },
+    /// The ABI JSON file isn't a valid ABI.
+    ParseAbi(serde_json::Error),
+    /// Events asked for aren't defined by the ABI. Holds the missing names and the ones it defines.
+    MissingEvents { missing: Vec<String>, available: Vec<String> },
+    /// Events of the ABI share a signature hash, so logs of all but one can't be decoded.
+    /// Holds each group of colliding event definitions.
+    AmbiguousAbi(Vec<Vec<String>>),
+    /// A setting is out of its valid range.
+    InvalidConfig(String),
+    /// The storage backend selected with `--store` wasn't compiled in, holds its cargo feature.
+    BackendNotCompiled(&'static str),
+    /// A provider subscription ended, which only happens when the connection drops.
+    /// Holds what was subscribed to.
+    Disconnected(&'static str),
+    /// An ABI source kept answering with a rate limit through every retry. Holds the
+    /// source, the number of requests made and its last answer.
+    AbiSource { name: String, attempts: u32, message: String },
+    /// Any other error raised while running a command. Sendable, so it can be returned
+    /// from a spawned task.
+    Runtime(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::MissingEnvVar(name) => write!(f, "{} not set — add it to .env", name),
+            TaskError::RootDirNotFound => write!(f, "Root directory not found, run from inside the repository or pass --output-dir"),
+            TaskError::EnvFile { path, source } => write!(f, "Failed to load the env file {}: {}", path, source),
+            TaskError::ReadAbi { path, source } => write!(f, "Failed to read ABI JSON file {}: {}", path, source),
+            TaskError::ParseAbi(err) => write!(f, "Failed to parse ABI JSON file: {}", err),
+            TaskError::MissingEvents { missing, available } => write!(
+                f,
+                "The ABI has no {} event, check the --abi file. It defines: {}",
+                missing.join(", "),
+                if available.is_empty() { "no events".to_string() } else { available.join(", ") }
+            ),
+            TaskError::AmbiguousAbi(collisions) => {
+                write!(f, "Events of the ABI share a signature hash, so their logs can't be told apart:")?;
+                for events in collisions {
+                    write!(f, "\n  {}", events.join(" / "))?;
+                }
+                Ok(())
+            }
+            TaskError::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            TaskError::BackendNotCompiled(feature) => write!(
+                f,
+                "The {} storage backend isn't compiled in — rebuild with `--features {}`",
+                feature, feature
+            ),
+            TaskError::Disconnected(subscription) => write!(f, "The {} subscription ended, the connection dropped", subscription),
+            TaskError::AbiSource { name, attempts, message } => write!(
+                f,
+                "{} was still rate limiting the ABI requests after {} attempts: {}",
+                name, attempts, message
+            ),
+            TaskError::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// `main` prints the Debug representation of the error it returns, so show the
+// friendly message there instead of the variant structure.
+impl fmt::Debug for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for TaskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TaskError::EnvFile { source, .. } => Some(source),
+            TaskError::ReadAbi { source, .. } => Some(source),
+            TaskError::ParseAbi(err) => Some(err),
+            TaskError::Runtime(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for TaskError {
+    fn from(err: Box<dyn Error>) -> Self {
+        // Unwrap configuration errors raised deep in a command, e.g. by `connect_provider`.
+        // Others may not be sendable, only their message is kept.
+        match err.downcast::<TaskError>() {
+            Ok(err) => *err,
+            Err(err) => TaskError::Runtime(err.to_string().into()),
+        }
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for TaskError {
+    fn from(err: Box<dyn Error + Send + Sync>) -> Self {
+        match err.downcast::<TaskError>() {
+            Ok(err) => *err,
+            Err(err) => TaskError::Runtime(err),
+        }
+    }
+}
+
+
+/// ErrorClass Whether an error is worth reconnecting over, or retrying a write over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The connection or the provider failed, e.g. a dropped WebSocket, a timeout or a
+    /// rate limit. Reconnecting is expected to help.
+    Transient,
+    /// A configuration or request problem, e.g. a missing key, a bad address or an
+    /// error answered by the node. Reconnecting would fail the same way.
+    Fatal,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorClass::Transient => "transient",
+            ErrorClass::Fatal => "fatal",
+        })
+    }
+}
+
+/// classify Sorts an error raised while watching the logs into an `ErrorClass`.
+///
+/// Transport failures are transient, as are rate limits and JSON-RPC errors reporting
+/// one. Configuration errors, other errors answered by the node and anything not
+/// recognized are fatal, so an unknown failure is reported instead of retried forever.
+pub fn classify(err: &(dyn Error + 'static)) -> ErrorClass {
+    if let Some(err) = err.downcast_ref::<TaskError>() {
+        return match err {
+            TaskError::Disconnected(_) => ErrorClass::Transient,
+            TaskError::Runtime(err) => classify(err.as_ref()),
+            _ => ErrorClass::Fatal,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<ProviderError>() {
+        return match err {
+            ProviderError::JsonRpcClientError(err) => match err.as_error_response() {
+                Some(response) if response.code == 429 || response.code == -32005 || is_rate_limit_message(&response.message) => {
+                    ErrorClass::Transient
+                }
+                Some(_) => ErrorClass::Fatal,
+                None => ErrorClass::Transient,
+            },
+            ProviderError::HTTPError(_) => ErrorClass::Transient,
+            _ => ErrorClass::Fatal,
+        };
+    }
+    if err.is::<WsClientError>() || err.is::<HttpClientError>() || err.is::<io::Error>() {
+        return ErrorClass::Transient;
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        if err.is_connect() || err.is_timeout() {
+            return ErrorClass::Transient;
+        }
+    }
+    ErrorClass::Fatal
+}
+
+
+/// classify_storage Sorts an error raised while storing a swap into an `ErrorClass`.
+///
+/// I/O errors of a dropped connection, a timeout or a full disk anywhere in the error's
+/// source chain are transient, e.g. a reset Postgres connection. Anything else, such as a
+/// swap that can't be serialized or a missing permission, is fatal and not retried.
+pub fn classify_storage(err: &(dyn Error + 'static)) -> ErrorClass {
+    // ENOSPC, the same on Linux and macOS
+    const STORAGE_FULL: i32 = 28;
+    let mut next = Some(err);
+    while let Some(err) = next {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            let transient = matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            ) || err.raw_os_error() == Some(STORAGE_FULL);
+            if transient {
+                return ErrorClass::Transient;
+            }
+        }
+        next = err.source();
+    }
+    ErrorClass::Fatal
+}
+
+
+/// load_env Loads the variables of `env_file`, or else of `.env.<APP_ENV>` in the current
+/// directory when `APP_ENV` is set, e.g. `.env.prod` for `APP_ENV=prod`, and of `.env`.
+///
+/// Variables already set win over the files, and the profile file over `.env`. A file
+/// asked for explicitly must load, while a missing `.env` is fine.
+pub fn load_env(env_file: Option<&Path>) -> Result<(), TaskError> {
+    let explicit = match env_file {
+        Some(path) => Some(path.to_path_buf()),
+        None => std::env::var("APP_ENV")
+            .ok()
+            .filter(|profile| !profile.is_empty())
+            .map(|profile| PathBuf::from(format!(".env.{}", profile))),
+    };
+    if let Some(path) = explicit {
+        dotenv::from_path(&path).map_err(|source| TaskError::EnvFile { path: path.display().to_string(), source })?;
+    }
+    if env_file.is_none() {
+        dotenv::dotenv().ok();
+    }
+    Ok(())
+}
+
+
+/// env_var Reads a required environment variable, as a `TaskError::MissingEnvVar` if unset.
+pub fn env_var(name: &'static str) -> Result<String, TaskError> {
+    std::env::var(name).map_err(|_| TaskError::MissingEnvVar(name))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_errors_survive_boxing() {
+        let boxed: Box<dyn Error> = Box::new(TaskError::MissingEnvVar("INFURA_API_KEY"));
+        let err = TaskError::from(boxed);
+
+        assert!(matches!(err, TaskError::MissingEnvVar("INFURA_API_KEY")));
+        assert_eq!(format!("{:?}", err), "INFURA_API_KEY not set — add it to .env");
+    }
+
+    #[test]
+    fn test_explicit_env_file_must_exist() {
+        let err = load_env(Some(Path::new("does/not/exist.env"))).unwrap_err();
+        assert!(matches!(err, TaskError::EnvFile { ref path, .. } if path == "does/not/exist.env"));
+
+        let path = std::env::temp_dir().join(format!("load_env_{}.env", std::process::id()));
+        std::fs::write(&path, "LOAD_ENV_TEST_VARIABLE=from_file\n").unwrap();
+        let loaded = load_env(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+        assert_eq!(std::env::var("LOAD_ENV_TEST_VARIABLE").unwrap(), "from_file");
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&TaskError::Disconnected("logs")), ErrorClass::Transient);
+        assert_eq!(classify(&TaskError::MissingEnvVar("INFURA_API_KEY")), ErrorClass::Fatal);
+        assert_eq!(classify(&TaskError::Runtime(Box::new(io::Error::from(io::ErrorKind::ConnectionReset)))), ErrorClass::Transient);
+
+        let boxed: Box<dyn Error> = Box::new(TaskError::Disconnected("blocks"));
+        assert_eq!(classify(boxed.as_ref()), ErrorClass::Transient);
+
+        let ws: Box<dyn Error> = Box::new(ProviderError::from(WsClientError::UnexpectedClose));
+        assert_eq!(classify(ws.as_ref()), ErrorClass::Transient);
+        assert_eq!(classify(&ProviderError::CustomError("bad filter".to_string())), ErrorClass::Fatal);
+        assert_eq!(classify(&fmt::Error), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn test_classify_storage() {
+        assert_eq!(classify_storage(&io::Error::from(io::ErrorKind::ConnectionReset)), ErrorClass::Transient);
+        assert_eq!(classify_storage(&io::Error::from_raw_os_error(28)), ErrorClass::Transient);
+        assert_eq!(classify_storage(&io::Error::from(io::ErrorKind::PermissionDenied)), ErrorClass::Fatal);
+        // Found behind the error wrapping it
+        let wrapped = TaskError::Runtime(Box::new(io::Error::from(io::ErrorKind::TimedOut)));
+        assert_eq!(classify_storage(&wrapped), ErrorClass::Transient);
+
+        let serialization = serde_json::from_str::<u64>("\"a\"").unwrap_err();
+        assert_eq!(classify_storage(&serialization), ErrorClass::Fatal);
+    }
+}
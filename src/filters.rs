@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use crate::data_store::DecodedData;
+
+
+/// AmountThresholds Minimum swap sizes for `amount0` and `amount1`, in raw token units.
+///
+/// Swap amounts are signed from the pool's point of view (positive is paid into the
+/// pool, negative is paid out), so thresholds are compared against the absolute value:
+/// a threshold of 1000 is reached by both `1000` and `-1000`. An unset threshold
+/// places no constraint on its token.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AmountThresholds {
+    pub amount0: Option<u128>,
+    pub amount1: Option<u128>,
+}
+
+impl AmountThresholds {
+    /// Whether the swap reaches every threshold that is set. True when none is set.
+    pub fn all_reached(&self, data: &DecodedData) -> bool {
+        self.reached(data).all(|reached| reached)
+    }
+
+    /// Whether the swap reaches at least one of the thresholds that are set. False when none is set.
+    pub fn any_reached(&self, data: &DecodedData) -> bool {
+        self.reached(data).any(|reached| reached)
+    }
+
+    // Compare each set threshold against the absolute amount of its token.
+    fn reached<'a>(&'a self, data: &'a DecodedData) -> impl Iterator<Item = bool> + 'a {
+        [(self.amount0, data.amount0_abs), (self.amount1, data.amount1_abs)]
+            .into_iter()
+            .filter_map(|(threshold, amount)| threshold.map(|threshold| amount >= threshold))
+    }
+}
+
+
+/// AddressList Allowed and blocked addresses, compared case-insensitively so checksummed
+/// and lowercase forms match.
+///
+/// The block list takes precedence: an address on both lists is rejected. When the allow
+/// list isn't empty only its addresses are accepted, and two empty lists accept everything.
+#[derive(Debug, Clone, Default)]
+pub struct AddressList {
+    allow: HashSet<String>,
+    block: HashSet<String>,
+}
+
+impl AddressList {
+    pub fn new<S: AsRef<str>>(allow: &[S], block: &[S]) -> Self {
+        let normalize = |addresses: &[S]| addresses.iter().map(|address| address.as_ref().trim().to_lowercase()).collect();
+        AddressList { allow: normalize(allow), block: normalize(block) }
+    }
+
+    /// Whether `address` passes the lists.
+    pub fn accepts(&self, address: &str) -> bool {
+        if self.allow.is_empty() && self.block.is_empty() {
+            return true;
+        }
+        let address = address.to_lowercase();
+        !self.block.contains(&address) && (self.allow.is_empty() || self.allow.contains(&address))
+    }
+}
+
+
+/// TickBand The ticks a swap may leave the pool at, bounds included. An unset bound places
+/// no constraint on its side.
+///
+/// The tick is the signed `int24` of the Swap event, negative below a price of 1 in raw
+/// units, so `min: Some(-100)` keeps the swaps at tick -100 and above.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TickBand {
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+}
+
+impl TickBand {
+    /// Whether `tick` is within the band. True when no bound is set.
+    pub fn contains(&self, tick: i32) -> bool {
+        self.min.is_none_or(|min| tick >= min) && self.max.is_none_or(|max| tick <= max)
+    }
+}
+
+
+/// WhaleAlert Calls back for every swap reaching one of its thresholds.
+pub struct WhaleAlert {
+    thresholds: AmountThresholds,
+    callback: Box<dyn Fn(&DecodedData) + Send + Sync>,
+}
+
+impl WhaleAlert {
+    pub fn new(thresholds: AmountThresholds, callback: Box<dyn Fn(&DecodedData) + Send + Sync>) -> Self {
+        WhaleAlert { thresholds, callback }
+    }
+
+    /// Fire the callback if `data` is a whale swap. Returns whether it fired.
+    pub fn check(&self, data: &DecodedData) -> bool {
+        let is_whale = self.thresholds.any_reached(data);
+        if is_whale {
+            (self.callback)(data);
+        }
+        is_whale
+    }
+}
+
+
+/// SwapFilter Decides which decoded swaps are stored, and reports the whale swaps.
+///
+/// It runs after decoding and before storage: swaps below `min_amounts`, swaps whose tick
+/// is outside `ticks`, and swaps whose decoded sender or recipient isn't accepted by
+/// `senders` or `recipients`, are dropped.
+/// The `whale` callback fires for the kept swaps reaching its thresholds.
+#[derive(Default)]
+pub struct SwapFilter {
+    pub min_amounts: AmountThresholds,
+    pub ticks: TickBand,
+    pub senders: AddressList,
+    pub recipients: AddressList,
+    pub whale: Option<WhaleAlert>,
+}
+
+impl SwapFilter {
+    /// Whether `data` should be stored, firing the whale callback if it is a whale swap.
+    pub fn apply(&self, data: &DecodedData) -> bool {
+        if !self.min_amounts.all_reached(data) || !self.ticks.contains(data.tick) {
+            return false;
+        }
+        if !self.senders.accepts(&data.sender) || !self.recipients.accepts(&data.recipient) {
+            return false;
+        }
+        if let Some(whale) = &self.whale {
+            whale.check(data);
+        }
+        true
+    }
+}
+
+
+/// webhook_callback A whale callback POSTing the swap as JSON to `url`.
+///
+/// The request is sent from a spawned task so a slow endpoint doesn't hold up storage,
+/// failures are only logged.
+pub fn webhook_callback(url: String) -> Box<dyn Fn(&DecodedData) + Send + Sync> {
+    let client = reqwest::Client::new();
+    Box::new(move |data: &DecodedData| {
+        let body = match serde_json::to_value(data) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Error serializing whale swap: {}", e);
+                return;
+            }
+        };
+        let request = client.post(&url).json(&body);
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+                eprintln!("Error posting whale swap to webhook: {}", e);
+            }
+        });
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_thresholds_use_absolute_amounts() {
+        let thresholds = AmountThresholds { amount0: Some(1_000), amount1: None };
+        assert!(thresholds.all_reached(&test_swap("", 1_000, 0)));
+        assert!(thresholds.all_reached(&test_swap("", -1_000, 0)));
+        assert!(!thresholds.all_reached(&test_swap("", -999, i128::MIN)));
+
+        assert!(AmountThresholds::default().all_reached(&test_swap("", 0, 0)));
+        assert!(!AmountThresholds::default().any_reached(&test_swap("", i128::MAX, i128::MAX)));
+    }
+
+    #[test]
+    fn test_swap_filter() {
+        let whales = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&whales);
+        let filter = SwapFilter {
+            min_amounts: AmountThresholds { amount0: Some(10), amount1: Some(10) },
+            whale: Some(WhaleAlert::new(
+                AmountThresholds { amount0: Some(1_000), amount1: Some(1_000) },
+                Box::new(move |_| { counter.fetch_add(1, Ordering::SeqCst); }),
+            )),
+            ..SwapFilter::default()
+        };
+
+        assert!(!filter.apply(&test_swap("", 5, -5_000)));
+        assert!(filter.apply(&test_swap("", 50, -50)));
+        assert!(filter.apply(&test_swap("", 50, -5_000)));
+        assert_eq!(whales.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_tick_band() {
+        let band = TickBand { min: Some(-200), max: Some(-100) };
+        assert!(band.contains(-200) && band.contains(-150) && band.contains(-100));
+        assert!(!band.contains(-201) && !band.contains(-99) && !band.contains(150));
+        assert!(TickBand { min: None, max: Some(-887_272) }.contains(-887_272));
+        assert!(TickBand::default().contains(i32::MIN));
+
+        let filter = SwapFilter { ticks: TickBand { min: Some(202_000), max: None }, ..SwapFilter::default() };
+        let mut data = test_swap("", 1, -1);
+        data.tick = 202_700;
+        assert!(filter.apply(&data));
+        data.tick = -202_700;
+        assert!(!filter.apply(&data));
+    }
+
+    #[test]
+    fn test_address_lists() {
+        let trader = "0xD7f3fbe8c72a961a5515203eada59750437fa762";
+        let other = "0x1c09a10047fcc944efde9226e259eddfde2c1cf0";
+
+        assert!(AddressList::default().accepts(trader));
+
+        let allowed = AddressList::new(&[trader.to_lowercase()], &[]);
+        assert!(allowed.accepts(trader));
+        assert!(!allowed.accepts(other));
+
+        let blocked = AddressList::new(&[], &[other]);
+        assert!(blocked.accepts(trader));
+        assert!(!blocked.accepts(&other.to_uppercase().replace("0X", "0x")));
+
+        // The block list wins over the allow list
+        let both = AddressList::new(&[trader, other], &[other]);
+        assert!(both.accepts(trader));
+        assert!(!both.accepts(other));
+
+        let filter = SwapFilter { recipients: AddressList::new(&[other], &[]), ..SwapFilter::default() };
+        let mut data = test_swap("", 1, -1);
+        data.recipient = other.to_string();
+        assert!(filter.apply(&data));
+        data.recipient = trader.to_string();
+        assert!(!filter.apply(&data));
+    }
+}
@@ -0,0 +1,37 @@
+//! Fetches Ethereum logs for a contract, decodes them with the contract's ABI
+//! and stores the decoded data.
+//!
+//! The binary in `main.rs` wires these modules into a CLI; they are exposed as a
+//! library so benchmarks and integration tests can drive the decode path directly.
+
+pub mod abi;
+pub mod abi_sources;
+pub mod api_keys;
+pub mod block_summaries;
+pub mod candles;
+pub mod confirmations;
+pub mod data_store;
+pub mod dead_letters;
+pub mod decoders;
+pub mod error;
+pub mod etherscan;
+pub mod filters;
+pub mod liquidity;
+pub mod log_processing;
+pub mod log_sources;
+pub mod metrics;
+pub mod numeraire;
+pub mod pipeline;
+pub mod price_bands;
+pub mod rate_limit;
+pub mod recent;
+#[cfg(feature = "json")]
+pub mod replay;
+pub mod sandwich;
+pub mod schemas;
+#[cfg(feature = "sse")]
+pub mod sse;
+pub mod test_sig_match;
+pub mod token_decimals;
+pub mod utils;
+pub mod webhook;
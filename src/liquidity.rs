@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::decoders::{LiquidityData, LiquidityKind};
+
+
+/// LiquidityAggregator The net liquidity added to each pool over the run, summed from its
+/// `Mint` (positive) and `Burn` (negative) events, in total and per tick range.
+///
+/// Build it up with `push` as the events are decoded. Its `Display` prints a line per
+/// pool, for the periodic metrics summary.
+#[derive(Debug, Clone, Default)]
+pub struct LiquidityAggregator {
+    pools: HashMap<String, PoolLiquidity>,
+}
+
+/// PoolLiquidity The liquidity events of one pool.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoolLiquidity {
+    pub mints: u64,
+    pub burns: u64,
+    /// Liquidity minted minus liquidity burned.
+    pub net_delta: i128,
+    /// The net delta of each `(tick_lower, tick_upper)` range with events.
+    pub ranges: BTreeMap<(i32, i32), i128>,
+}
+
+impl LiquidityAggregator {
+    pub fn new() -> Self {
+        LiquidityAggregator::default()
+    }
+
+    /// Add a mint or burn of the pool at `pool`.
+    pub fn push(&mut self, pool: &str, data: &LiquidityData) {
+        let liquidity = self.pools.entry(pool.to_string()).or_default();
+        match data.kind {
+            LiquidityKind::Mint => liquidity.mints += 1,
+            LiquidityKind::Burn => liquidity.burns += 1,
+        }
+        let delta = data.delta();
+        liquidity.net_delta = liquidity.net_delta.saturating_add(delta);
+        let range = liquidity.ranges.entry((data.tick_lower, data.tick_upper)).or_default();
+        *range = range.saturating_add(delta);
+    }
+
+    pub fn pool(&self, pool: &str) -> Option<&PoolLiquidity> {
+        self.pools.get(pool)
+    }
+
+    pub fn pools(&self) -> &HashMap<String, PoolLiquidity> {
+        &self.pools
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
+
+impl fmt::Display for PoolLiquidity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "net liquidity {:+} ({} mints, {} burns, {} ranges)", self.net_delta, self.mints, self.burns, self.ranges.len())
+    }
+}
+
+impl fmt::Display for LiquidityAggregator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut pools: Vec<_> = self.pools.iter().collect();
+        pools.sort_by(|a, b| a.0.cmp(b.0));
+        for (i, (pool, liquidity)) in pools.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", pool, liquidity)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U256;
+
+    fn event(kind: LiquidityKind, tick_lower: i32, tick_upper: i32, amount: u128) -> LiquidityData {
+        LiquidityData {
+            kind,
+            transaction_hash: "0x11".to_string(),
+            pool: "0xpool".to_string(),
+            owner: "0xowner".to_string(),
+            tick_lower,
+            tick_upper,
+            amount,
+            amount0: U256::zero(),
+            amount1: U256::zero(),
+        }
+    }
+
+    #[test]
+    fn test_net_liquidity_per_pool_and_range() {
+        let mut liquidity = LiquidityAggregator::new();
+        liquidity.push("0xpool", &event(LiquidityKind::Mint, -10, 10, 1_000));
+        liquidity.push("0xpool", &event(LiquidityKind::Mint, 0, 60, 500));
+        liquidity.push("0xpool", &event(LiquidityKind::Burn, -10, 10, 400));
+        liquidity.push("0xother", &event(LiquidityKind::Burn, 0, 60, 7));
+
+        let pool = liquidity.pool("0xpool").unwrap();
+        assert_eq!((pool.mints, pool.burns, pool.net_delta), (2, 1, 1_100));
+        assert_eq!(pool.ranges, BTreeMap::from([((-10, 10), 600), ((0, 60), 500)]));
+        assert_eq!(liquidity.pool("0xother").unwrap().net_delta, -7);
+
+        assert_eq!(
+            liquidity.to_string(),
+            "0xother: net liquidity -7 (0 mints, 1 burns, 1 ranges)\n0xpool: net liquidity +1100 (2 mints, 1 burns, 2 ranges)"
+        );
+    }
+}
@@ -1,24 +1,20 @@
 use ethers::{
-    core::types::{Filter, Log, H160, U256},
-    providers::{Provider, Ws},
+    core::types::{Log, H160, U256},
     prelude::*,
-    abi::{Abi, RawLog, EventExt, Detokenize, Token, ethabi, Event},
+    abi::{Abi, RawLog, EventExt, Token, ethabi, Event},
     utils::keccak256,
 };
 use ethers::types::Log as EthersLog;
 use eyre::Result;
-use dotenv::dotenv;
-use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::io;
-use std::path::Path;
-use chrono::{Utc, NaiveDate, Datelike};
-use std::io::Write;
-use hex::FromHex;
 
 
-use crate::data_store::DecodedData;
-use crate::data_store::store_decoded_data;
+use crate::candles::{price_from_sqrt_price_x96, price_from_tick, tick_matches_price};
+use crate::data_store::{DecodedData, RawLogHex};
+use crate::decoders::{decode_params, raw_params, DecodedEvent, DecoderRegistry, EventDecoder, SwapDecoder};
+use crate::error::TaskError;
+use crate::metrics::{EventOutcome, Metrics};
+use crate::utils::event_signature_hash;
 
 
 // Convert a slice of u8 into a hexadecimal string representation.
@@ -26,33 +22,96 @@ pub fn to_hex(slice: &[u8]) -> String {
     format!("0x{}", hex::encode(slice))
 }
 
-pub fn parse_decoded_log(decoded: ethabi::Log, log: &EthersLog) -> Option<DecodedData> {
+/// to_checksum_address Formats a 20 byte address as an EIP-55 mixed-case checksummed hex string.
+///
+/// A hex letter is uppercased when the matching nibble of the Keccak256 hash of the
+/// lowercase hex address is 8 or more, see https://eips.ethereum.org/EIPS/eip-55.
+pub fn to_checksum_address(bytes: &[u8]) -> String {
+    let lowercase = hex::encode(bytes);
+    let hash = keccak256(lowercase.as_bytes());
+
+    let checksummed: String = lowercase
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// parse_address Parses a hex address given by the user, with or without its `0x` prefix.
+///
+/// An address must have 40 hex digits. All lowercase or all uppercase ones are taken as
+/// they are, a mixed-case one must be EIP-55 checksummed, which catches most typos. The
+/// error is a `TaskError::InvalidConfig` quoting the input.
+pub fn parse_address(input: &str) -> Result<H160, TaskError> {
+    let invalid = |problem: &str| TaskError::InvalidConfig(format!("invalid address {:?}: {}", input, problem));
+    let trimmed = input.trim();
+    let digits = trimmed.strip_prefix("0x").or_else(|| trimmed.strip_prefix("0X")).unwrap_or(trimmed);
+    if digits.len() != 40 {
+        return Err(invalid(&format!("expected 40 hex digits, got {}", digits.chars().count())));
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(invalid(&format!("{:?} isn't a hex digit", c)));
+    }
+    let bytes = hex::decode(digits).map_err(|e| invalid(&e.to_string()))?;
+    let mixed_case = digits.chars().any(|c| c.is_ascii_lowercase()) && digits.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && to_checksum_address(&bytes)[2..] != *digits {
+        return Err(invalid(&format!("wrong EIP-55 checksum, expected {}", to_checksum_address(&bytes))));
+    }
+    Ok(H160::from_slice(&bytes))
+}
+
+/// topic_address The address held by an indexed `address` topic, as lowercase hex.
+pub fn topic_address(topic: &H256) -> String {
     // Extract the last 20 bytes of the topic, representing the Ethereum address,
     // because Ethereum addresses are 20 bytes long and topics are zero-padded.
-    // Convert topics to Ethereum addresses.
-    let sender = to_hex(&log.topics[1][12..]);
-    let recipient = to_hex(&log.topics[2][12..]);
+    to_hex(&topic[12..])
+}
+
+/// amount_from_token The swap amount held by an `int` or `uint` token, None for other tokens.
+///
+/// V3 pools declare the amounts `int256`, two's complement encoded, and `Int`s are read as
+/// such, saturating at `i128::MIN` and `i128::MAX`. Pools declaring them `uint256`, like
+/// V2, only have positive ones, and `Uint`s are taken as they are, saturating at `i128::MAX`.
+pub fn amount_from_token(token: &Token) -> Option<i128> {
+    match token {
+        Token::Int(value) => {
+            let value = I256::from_raw(*value);
+            let saturated = if value.is_negative() { i128::MIN } else { i128::MAX };
+            Some(i128::try_from(value).unwrap_or(saturated))
+        }
+        Token::Uint(value) if *value > U256::from(i128::MAX) => Some(i128::MAX),
+        Token::Uint(value) => Some(value.low_u128() as i128),
+        _ => None,
+    }
+}
+
+/// parse_decoded_log The swap held by a parsed `Swap` log, None when the log lacks the
+/// sender or the recipient, or a transaction hash, as pending logs do.
+///
+/// The sender and recipient are read from the parsed params, which hold them whether the
+/// event indexes them, in the topics, or not, in the data. Params parsed without them fall
+/// back to the topics of the Uniswap V3 layout, `topics[1]` and `topics[2]`.
+pub fn parse_decoded_log(decoded: ethabi::Log, log: &EthersLog) -> Option<DecodedData> {
+    let sender = address_param(&decoded, "sender").or_else(|| log.topics.get(1).map(topic_address))?;
+    let recipient = address_param(&decoded, "recipient").or_else(|| log.topics.get(2).map(topic_address))?;
 
     // Convert transaction hash to its full hexadecimal string representation.
-    let transaction_hash = to_hex(&log.transaction_hash.unwrap().0);
+    let transaction_hash = to_hex(&log.transaction_hash?.0);
 
     let mut amount0: i128 = 0;
     let mut amount1: i128 = 0;
-    let mut sqrtPriceX96: u128 = 0;
+    let mut sqrt_price_x96 = U256::zero();
     let mut liquidity: u128 = 0;
     let mut tick: i32 = 0;
 
     for param in &decoded.params {
         match param.name.as_str() {
             "amount0" | "amount1" => {
-                if let Token::Int(value) = &param.value {
-                    let converted_value = if *value > U256::from(i128::MAX as u128) {
-                        let neg_value = (U256::max_value() - *value + U256::one()).low_u128();
-                        -(neg_value as i128)
-                    } else {
-                        value.low_u128() as i128
-                    };
-
+                if let Some(converted_value) = amount_from_token(&param.value) {
                     if param.name.as_str() == "amount0" {
                         amount0 = converted_value;
                     } else {
@@ -62,10 +121,11 @@ pub fn parse_decoded_log(decoded: ethabi::Log, log: &EthersLog) -> Option<Decode
             }
             "sqrtPriceX96" => {
                 if let Token::Uint(value) = &param.value {
-                    sqrtPriceX96 = value.low_u128();
+                    sqrt_price_x96 = *value;
                 }
             }
             "liquidity" => {
+                // A uint128, which the low 128 bits hold whole
                 if let Token::Uint(value) = &param.value {
                     liquidity = value.low_u128();
                 }
@@ -85,62 +145,368 @@ pub fn parse_decoded_log(decoded: ethabi::Log, log: &EthersLog) -> Option<Decode
         recipient,
         amount0,
         amount1,
-        sqrtPriceX96,
+        sqrtPriceX96: sqrt_price_x96,
         liquidity,
         tick,
+        zero_for_one: DecodedData::is_zero_for_one(amount0, amount1),
+        amount0_abs: amount0.unsigned_abs(),
+        amount1_abs: amount1.unsigned_abs(),
+        raw_params: None,
+        raw: None,
+        gas_used: None,
+        effective_gas_price: None,
+        pool: to_hex(log.address.as_bytes()),
+        log_index: log.log_index.map(|index| index.low_u64()),
     })
 }
 
 
+// The address param `name` of a parsed log as lowercase hex, None without one.
+fn address_param(decoded: &ethabi::Log, name: &str) -> Option<String> {
+    match decoded.params.iter().find(|param| param.name == name).map(|param| &param.value) {
+        Some(Token::Address(address)) => Some(to_hex(address.as_bytes())),
+        _ => None,
+    }
+}
+
+
+/// build_event_map Builds the lookup table used to match logs against the ABI events.
+///
+/// The map is keyed by the Keccak256 hash of each event's ABI signature, which is what
+/// appears as topic0 of the emitted logs, and holds the event name and definition.
+///
+/// # Arguments
+///
+/// * `abi` - The ABI containing event definitions.
+///
+/// # Returns
+///
+/// The map, or `TaskError::AmbiguousAbi` listing the events that share a signature hash
+/// but differ, e.g. in which parameters are indexed, since only one of them could be
+/// decoded. Events listed twice with the same definition are not an error.
+pub fn build_event_map(abi: &Abi) -> Result<HashMap<[u8; 32], (String, Event)>, TaskError> {
+    let mut event_map: HashMap<[u8; 32], (String, Event)> = HashMap::new();
+    let mut collisions: HashMap<[u8; 32], Vec<String>> = HashMap::new();
+    // /!\ We use event.abi_signature() instead of event.signature() here.
+    // The reason is that `event.signature()` provides a human-readable format,
+    // while `event.abi_signature()` provides the human-readable ABI signature
+    // format suitable for hashing to match Ethereum's log signature standard.
+    // https://docs.rs/ethers/latest/ethers/abi/struct.Event.html
+    for (event_name, events) in &abi.events {
+        for event in events {
+            let event_signature_hash = event_signature_hash(&event.abi_signature());
+            match event_map.get(&event_signature_hash) {
+                Some((_, existing)) if existing == event => {}
+                Some((_, existing)) => collisions
+                    .entry(event_signature_hash)
+                    .or_insert_with(|| vec![describe_event(existing)])
+                    .push(describe_event(event)),
+                None => {
+                    event_map.insert(event_signature_hash, (event_name.clone(), event.clone()));
+                }
+            }
+        }
+    }
+
+    if collisions.is_empty() {
+        return Ok(event_map);
+    }
+    let mut collisions: Vec<Vec<String>> = collisions.into_values().collect();
+    collisions.sort();
+    Err(TaskError::AmbiguousAbi(collisions))
+}
+
+// Format an event with its parameter types, indexing and names,
+// e.g. `Transfer(address indexed from, address indexed to, uint256 value)`.
+fn describe_event(event: &Event) -> String {
+    let params: Vec<String> = event.inputs
+        .iter()
+        .map(|param| format!("{}{} {}", param.kind, if param.indexed { " indexed" } else { "" }, param.name))
+        .collect();
+    format!("{}({})", event.name, params.join(", "))
+}
+
+
+/// EventListing An event of an ABI, as listed by the `events` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventListing {
+    pub name: String,
+    /// The parameter types and names, marking the indexed ones, e.g.
+    /// `Transfer(address indexed from, address indexed to, uint256 value)`.
+    pub signature: String,
+    /// The Keccak256 hash of the ABI signature, topic0 of the event's logs.
+    pub topic0: H256,
+    /// How many parameters are in the topics rather than the data.
+    pub indexed: usize,
+    /// The other events of the ABI with the same topic0, of which only one can be decoded.
+    pub collisions: Vec<String>,
+}
+
+/// list_events The events of `abi` keyed by topic0 like `build_event_map` does, in name
+/// order. An event defined twice the same way is listed once, and events sharing a
+/// topic0 but differing are listed with their collisions rather than failing.
+pub fn list_events(abi: &Abi) -> Vec<EventListing> {
+    let mut listings: Vec<EventListing> = Vec::new();
+    for (event_name, events) in &abi.events {
+        for event in events {
+            let signature = describe_event(event);
+            if listings.iter().any(|listing| listing.signature == signature) {
+                continue;
+            }
+            listings.push(EventListing {
+                name: event_name.clone(),
+                signature,
+                topic0: H256(event_signature_hash(&event.abi_signature())),
+                indexed: event.inputs.iter().filter(|param| param.indexed).count(),
+                collisions: Vec::new(),
+            });
+        }
+    }
+
+    let topics: Vec<(H256, String)> = listings.iter().map(|listing| (listing.topic0, listing.signature.clone())).collect();
+    for listing in &mut listings {
+        listing.collisions = topics
+            .iter()
+            .filter(|(topic0, signature)| *topic0 == listing.topic0 && *signature != listing.signature)
+            .map(|(_, signature)| signature.clone())
+            .collect();
+    }
+    listings
+}
+
+impl std::fmt::Display for EventListing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n  signature: {}\n  topic0: {:?}\n  indexed params: {}", self.name, self.signature, self.topic0, self.indexed)?;
+        for collision in &self.collisions {
+            write!(f, "\n  COLLISION: same topic0 as {}, only one of them can be decoded", collision)?;
+        }
+        Ok(())
+    }
+}
+
 
 /// process_log Processes a given Ethereum log entry using the provided ABI.
 ///
 /// This function attempts to decode the log entry based on known event signatures
-/// from the ABI. If successful, it prints out the relevant event parameters.
+/// from the ABI, using the decoder registered for the matched event name.
 ///
 /// # Arguments
 ///
 /// * `log` - The Ethereum log entry to be processed.
-/// * `abi` - The ABI containing event definitions to decode the log.
+/// * `event_map` - The ABI event definitions keyed by their signature hash.
+/// * `decoders` - The decoders for the events we want to decode, keyed by event name.
+/// * `metrics` - The counters updated with the outcome of the processing.
 ///
 /// # Returns
 ///
-/// A Result indicating the success or failure of the processing.
-pub async fn process_log(log: Log, event_map: &HashMap<[u8; 32], (String, Event)>) -> Result<Option<DecodedData>, Box<dyn std::error::Error>> {
+/// The decoded event, None if no registered decoder handles the log, or a `DecodeError`
+/// if the log matched an event but couldn't be decoded, e.g. because the ABI is stale.
+pub async fn process_log(log: Log, event_map: &HashMap<[u8; 32], (String, Event)>, decoders: &DecoderRegistry, metrics: &Metrics) -> Result<Option<DecodedEvent>, Box<dyn std::error::Error>> {
+    process_log_sync(log, event_map, decoders, metrics)
+}
+
+
+/// process_log_sync The work of `process_log`, which is all CPU, without an async runtime.
+/// The decode workers call it directly.
+pub fn process_log_sync(log: Log, event_map: &HashMap<[u8; 32], (String, Event)>, decoders: &DecoderRegistry, metrics: &Metrics) -> Result<Option<DecodedEvent>, Box<dyn std::error::Error>> {
+    metrics.record_seen();
+    if let Some(block_number) = log.block_number {
+        metrics.record_block(block_number.as_u64());
+    }
+
+    // Pending logs aren't in a transaction yet, they are sent again once mined
+    if log.transaction_hash.is_none() {
+        metrics.record_skipped();
+        tracing::debug!(block = ?log.block_number, "Skipping pending log without a transaction hash");
+        return Ok(None);
+    }
+
     let raw_log = RawLog {
         topics: log.topics.clone(),
         data: (*log.data.clone()).to_vec(),
     };
 
-    let log_topic: H256 = log.topics[0];
+    // Anonymous events have no signature to match, they are only decoded where mapped
+    // explicitly, and never when topic0 is the signature of an ABI event
+    let signed = log.topics.first().is_some_and(|topic0| event_map.contains_key(topic0.as_fixed_bytes()));
+    if !signed {
+        if let Some(event) = decoders.anonymous_event(&log.address, log.topics.len()) {
+            return decode_anonymous(&log, raw_log, event, metrics).map(Some).map_err(Into::into);
+        }
+    }
 
-    let mut successfully_decoded = false;
+    let Some(&log_topic) = log.topics.first() else {
+        metrics.record_skipped();
+        return Ok(None);
+    };
 
-    // Iterate over each event signature hash in our map.
-    for (hash, (event_name, event)) in event_map {
-        // check if the event_name is equal to Swap
-        if event_name != "Swap" {
-            continue;
-        }
-        // Check if the first topic of the log (which is the event signature) matches the current hash.
-        if log_topic.as_bytes() == *hash {
-            // If the log's topic matches an event's signature, attempt to parse the raw log using the event's ABI details.
-            // If the parsing fails, it might be due to reasons like a mismatched or outdated ABI, corrupted log data,
-            // non-standard encoding, or other discrepancies between the log and the ABI definition.
-            let result = event.parse_log(raw_log.clone()).map_err(|e| eyre::eyre!("Failed to decode event: {:?}", e));
+    // The map is keyed by topic0, the log's event is found with a single lookup. Logs of an
+    // ABI event without a decoder are counted under the event, others only in total.
+    // Traced rather than printed, RUST_LOG=tasks=trace shows what is being ignored.
+    let Some((event_name, event)) = event_map.get(log_topic.as_fixed_bytes()) else {
+        let skipped = metrics.record_skipped();
+        tracing::trace!(topic0 = %to_hex(log_topic.as_bytes()), skipped, "Skipping log of an event not in the ABI");
+        return Ok(None);
+    };
+    // Only events with a registered decoder are decoded
+    let Some(decoder) = decoders.get(event_name) else {
+        metrics.record_skipped();
+        let event_skipped = metrics.record_event(event_name, EventOutcome::Skipped);
+        tracing::trace!(event = %event_name, skipped = event_skipped, "Skipping event without a decoder");
+        return Ok(None);
+    };
+    // Logs left out by sampling are dropped before the costly ABI decoding
+    if !decoders.sample(&log.address) {
+        metrics.record_sampled_out();
+        metrics.record_event(event_name, EventOutcome::SampledOut);
+        return Ok(None);
+    }
 
-            let mut decoded_data = None;
+    let decoded_event = match decode_with(&log, raw_log, event, decoder, decoders) {
+        Ok(decoded_event) => decoded_event,
+        Err(reason) => return Err(decode_failure(&log, event_name, metrics, reason).into()),
+    };
 
-            if let Ok(decoded) = result {
-                decoded_data = parse_decoded_log(decoded, &log);
-                if let Some(ref data) = decoded_data {
-                    println!("{:?}", data);
-                }
+    match decoded_event {
+        Some(DecodedEvent::Swap(ref data)) => {
+            if !tick_matches_price(data.tick, data.sqrtPriceX96) {
+                tracing::warn!(
+                    pool = %data.pool,
+                    tx_hash = %data.transaction_hash,
+                    tick = data.tick,
+                    tick_price = price_from_tick(data.tick, 0, 0),
+                    sqrt_price = price_from_sqrt_price_x96(data.sqrtPriceX96, 0, 0),
+                    "Tick and sqrtPriceX96 of swap encode different prices, check the decoding"
+                );
             }
-            return Ok(decoded_data);
+            metrics.record_decoded();
+            metrics.record_event(event_name, EventOutcome::Decoded);
+            if let Some((price, band)) = metrics.record_swap(&data.pool, data) {
+                tracing::warn!(
+                    pool = %data.pool,
+                    tx_hash = %data.transaction_hash,
+                    price,
+                    band = %band,
+                    "Swap price is outside the price band of its pool, check the decoding"
+                );
+            }
+        }
+        Some(DecodedEvent::Liquidity(ref data)) => {
+            metrics.record_decoded();
+            metrics.record_event(event_name, EventOutcome::Decoded);
+            metrics.record_liquidity(&data.pool, data);
+        }
+        Some(_) => {
+            metrics.record_decoded();
+            metrics.record_event(event_name, EventOutcome::Decoded);
+        }
+        None => {
+            let reason = format!("The {} decoder couldn't decode the log", event_name);
+            return Err(decode_failure(&log, event_name, metrics, reason).into());
         }
     }
-    Ok(None)
+    Ok(decoded_event)
+}
+
+
+/// decode_log The Uniswap V3 swap held by `log`, None when it isn't the `Swap` event of
+/// `event_map` or doesn't decode.
+///
+/// Pure: no metrics, no printing and no I/O, for tests and fuzzing. `process_log` decodes
+/// the same way, adding the decoder registry, the counters and the warnings on top.
+pub fn decode_log(log: &Log, event_map: &HashMap<[u8; 32], (String, Event)>) -> Option<DecodedData> {
+    let (event_name, event) = event_map.get(log.topics.first()?.as_fixed_bytes())?;
+    if event_name != "Swap" {
+        return None;
+    }
+    let raw_log = RawLog { topics: log.topics.clone(), data: log.data.to_vec() };
+    match decode_with(log, raw_log, event, &SwapDecoder, &DecoderRegistry::new()) {
+        Ok(Some(DecodedEvent::Swap(data))) => Some(data),
+        _ => None,
+    }
+}
+
+// Decode `log` as `event` with `decoder`, adding the raw params, the raw log and the
+// checksummed addresses when `decoders` is set to. Pure, the error is the reason the log
+// didn't parse.
+fn decode_with(log: &Log, raw_log: RawLog, event: &Event, decoder: &dyn EventDecoder, decoders: &DecoderRegistry) -> Result<Option<DecodedEvent>, String> {
+    // Attempt to parse the raw log using the event's ABI details.
+    // If the parsing fails, it might be due to reasons like a mismatched or outdated ABI, corrupted log data,
+    // non-standard encoding, or other discrepancies between the log and the ABI definition.
+    let decoded = event.parse_log(raw_log).map_err(|e| format!("Failed to decode event: {:?}", e))?;
+
+    let params = decoders.raw_params().then(|| raw_params(&decoded));
+    let mut decoded_event = decoder.decode_event(decoded, log);
+    if let Some(DecodedEvent::Swap(ref mut data)) = decoded_event {
+        data.raw_params = params;
+        data.raw = decoders.keep_raw().then(|| RawLogHex::of(log));
+    }
+    if decoders.checksum_addresses() {
+        match decoded_event {
+            Some(DecodedEvent::Swap(ref mut data)) => data.checksum_addresses(),
+            Some(DecodedEvent::Transfer(ref mut data)) => data.checksum_addresses(),
+            Some(DecodedEvent::DexSwap(ref mut data)) => data.checksum_addresses(),
+            Some(DecodedEvent::Liquidity(ref mut data)) => data.checksum_addresses(),
+            _ => {}
+        }
+    }
+    Ok(decoded_event)
+}
+
+
+// Decode a log mapped to an anonymous event. Every parameter is output, like the Mint
+// and Burn decoders do, since the typed decoders expect the signature in topic0.
+fn decode_anonymous(log: &Log, raw_log: RawLog, event: &Event, metrics: &Metrics) -> Result<DecodedEvent, DecodeError> {
+    let decoded = event.parse_log(raw_log).map_err(|e| decode_failure(log, &event.name, metrics, e.to_string()))?;
+    let Some(fields) = decode_params(&decoded, log) else {
+        let reason = format!("The anonymous {} log has no transaction hash", event.name);
+        return Err(decode_failure(log, &event.name, metrics, reason));
+    };
+    metrics.record_decoded();
+    metrics.record_event(&event.name, EventOutcome::Decoded);
+    Ok(DecodedEvent::Other { event_name: event.name.clone(), fields })
+}
+
+
+/// DecodeError A log matched an ABI event with a registered decoder but couldn't be decoded.
+#[derive(Debug)]
+pub struct DecodeError {
+    pub transaction_hash: Option<String>,
+    pub topic0: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to decode log with topic0 {} in tx {}: {}",
+            self.topic0,
+            self.transaction_hash.as_deref().unwrap_or("<unknown>"),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Count and log at warn a log that couldn't be decoded, so a stale ABI or malformed logs
+// don't go unnoticed.
+fn decode_failure(log: &Log, event_name: &str, metrics: &Metrics, reason: String) -> DecodeError {
+    metrics.record_error();
+    metrics.record_event(event_name, EventOutcome::Error);
+    let error = DecodeError {
+        transaction_hash: log.transaction_hash.map(|hash| to_hex(&hash.0)),
+        topic0: log.topics.first().map(|topic0| to_hex(&topic0.0)).unwrap_or_default(),
+        reason,
+    };
+    tracing::warn!(
+        tx_hash = error.transaction_hash.as_deref().unwrap_or("<unknown>"),
+        topic0 = %error.topic0,
+        reason = %error.reason,
+        "Failed to decode log"
+    );
+    error
 }
 
 
@@ -150,6 +516,37 @@ mod tests {
     use ethers::core::types::{H256};
     use std::str::FromStr;
 
+    // A log of the Swap `event` in transaction 0x11.., from sender 0xaa.. to recipient
+    // 0xbb.., paying 5 of token0 out for 7 of token1. Its params are put in the topics or
+    // the data as `event` indexes them.
+    fn swap_log(event: &Event, sqrt_price_x96: U256, liquidity: u128, tick: i32) -> Log {
+        let mut topics = vec![event.signature()];
+        let mut data = Vec::new();
+        for input in &event.inputs {
+            let token = match input.name.as_str() {
+                "sender" => Token::Address(H160::repeat_byte(0xaa)),
+                "recipient" => Token::Address(H160::repeat_byte(0xbb)),
+                "amount0" => Token::Int(I256::from(-5).into_raw()),
+                "amount1" => Token::Int(I256::from(7).into_raw()),
+                "sqrtPriceX96" => Token::Uint(sqrt_price_x96),
+                "liquidity" => Token::Uint(U256::from(liquidity)),
+                "tick" => Token::Int(I256::from(tick).into_raw()),
+                other => panic!("Unexpected Swap param {}", other),
+            };
+            if input.indexed {
+                topics.push(H256::from_slice(&ethers::abi::encode(&[token])));
+            } else {
+                data.push(token);
+            }
+        }
+        Log {
+            topics,
+            data: ethers::abi::encode(&data).into(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_process_log() {
         // 1. Set up a log to be processed. From log printed with pretty_print_log trait
@@ -160,7 +557,7 @@ mod tests {
                 H256::from_str("0x000000000000000000000000d7f3fbe8c72a961a5515203eada59750437fa762").unwrap(),
                 H256::from_str("0x0000000000000000000000001c09a10047fcc944efde9226e259eddfde2c1cf0").unwrap()
             ],
-            data: Bytes::from_hex("0x0000000000000000000000000000000000000000000000000000000d92cae287fffffffffffffffffffffffffffffffffffffffffffffffdfe6d04e32064349f0000000000000000000000000000000000006270c87ad64fc69a7baa1492b4f20000000000000000000000000000000000000000000000017c7599806e23275900000000000000000000000000000000000000000000000000000000000317ce").unwrap(),
+            data: Bytes::from(hex::decode("0000000000000000000000000000000000000000000000000000000d92cae287fffffffffffffffffffffffffffffffffffffffffffffffdfe6d04e32064349f0000000000000000000000000000000000006270c87ad64fc69a7baa1492b4f20000000000000000000000000000000000000000000000017c7599806e23275900000000000000000000000000000000000000000000000000000000000317ce").unwrap()),
             block_hash: Some(H256::from_str("0x1a65b8bb49fe739ae92ed688ab765cafe4dbcdd2b6c442e48a682ce2c0e451ee").unwrap()),
             block_number: Some(U64::from(18326572)),
             transaction_hash: Some(H256::from_str("0x13f84c56285e67f705bca6cb865610deda492752c0face651e0b3cb7893500f3").unwrap()),
@@ -174,31 +571,380 @@ mod tests {
         // 2. Set up the event map
         let wrapped_json = std::fs::read_to_string("src/abi.json").unwrap();
         let abi: ethers::abi::Abi = serde_json::from_str(&wrapped_json).unwrap();
-        let mut event_map = HashMap::new();
-        for (event_name, events) in &abi.events {
-            for event in events {
-                let event_signature_hash = keccak256(event.abi_signature().as_bytes());
-                event_map.insert(event_signature_hash, (event_name.clone(), event.clone()));
-            }
-        }
+        let event_map = build_event_map(&abi).unwrap();
 
         // 3. Call the process_log function
-        let result = tokio_test::block_on(process_log(log, &event_map));
+        let decoders = DecoderRegistry::default();
+        let metrics = Metrics::default();
+        let result = tokio_test::block_on(process_log(log, &event_map, &decoders, &metrics));
 
         // 4. Check the result
         assert!(result.is_ok());
-        let decoded_data = result.unwrap();
-        assert!(decoded_data.is_some());
+        let decoded_event = result.unwrap();
+        assert!(decoded_event.is_some());
 
-        let data = decoded_data.unwrap();
+        let data = match decoded_event.unwrap() {
+            DecodedEvent::Swap(data) => data,
+            other => panic!("Expected a Swap, got {:?}", other),
+        };
         // https://etherscan.io/tx/0x13f84c56285e67f705bca6cb865610deda492752c0face651e0b3cb7893500f3#eventlog
         assert_eq!(data.transaction_hash, "0x13f84c56285e67f705bca6cb865610deda492752c0face651e0b3cb7893500f3");
         assert_eq!(data.sender, "0xd7f3fbe8c72a961a5515203eada59750437fa762");
         assert_eq!(data.recipient, "0x1c09a10047fcc944efde9226e259eddfde2c1cf0");
         assert_eq!(data.amount0, 58297344647);
         assert_eq!(data.amount1, -37006917189485972321);
-        assert_eq!(data.sqrtPriceX96, 1996611740862433600358475292128498);
+        assert_eq!(data.sqrtPriceX96, U256::from(1996611740862433600358475292128498u128));
         assert_eq!(data.liquidity, 27414987083570423641);
         assert_eq!(data.tick, 202702);
+        assert_eq!(data.pool, "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640");
+        // USDC was paid in and WETH paid out, pushing the price down
+        assert!(data.zero_for_one);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.logs_seen, snapshot.decoded), (1, 1));
+        assert_eq!(snapshot.pools["0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"].swaps, 1);
+    }
+
+    #[test]
+    fn test_process_log_checksum_addresses() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let mut decoders = DecoderRegistry::default();
+        decoders.set_checksum_addresses(true);
+        let metrics = Metrics::default();
+
+        let log = Log {
+            address: H160::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap(),
+            ..swap_log(abi.event("Swap").unwrap(), U256::from(1u128 << 96), 1_000, 0)
+        };
+        let Some(DecodedEvent::Swap(data)) = process_log_sync(log, &event_map, &decoders, &metrics).unwrap() else { panic!("Expected a Swap") };
+
+        // The pool follows the other addresses, and the metrics are keyed on the same form
+        assert_eq!(data.pool, "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+        assert_eq!(metrics.snapshot().pools["0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"].swaps, 1);
+    }
+
+    #[test]
+    fn test_process_log_raw_params() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let mut decoders = DecoderRegistry::default();
+        decoders.set_raw_params(true);
+
+        let log = swap_log(abi.event("Swap").unwrap(), U256::from(1u128 << 96), 1_000, -3);
+
+        let Some(DecodedEvent::Swap(data)) = tokio_test::block_on(process_log(log, &event_map, &decoders, &Metrics::default())).unwrap() else {
+            panic!("Expected a Swap");
+        };
+        let raw_params = data.raw_params.unwrap();
+        assert_eq!(raw_params["sender"], format!("0x{}", "aa".repeat(20)));
+        assert_eq!(raw_params["amount0"], "-5");
+        assert_eq!(raw_params["sqrtPriceX96"], (1u128 << 96).to_string());
+        assert_eq!(raw_params["tick"], "-3");
+        assert!(data.raw.is_none());
+    }
+
+    #[test]
+    fn test_process_log_keeps_sqrt_price_above_u128() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+
+        // The largest uint160 sqrtPriceX96 a pool can report
+        let sqrt_price_x96 = (U256::one() << 160) - 1;
+        let log = swap_log(abi.event("Swap").unwrap(), sqrt_price_x96, u128::MAX, 887271);
+        let Some(DecodedEvent::Swap(data)) = process_log_sync(log, &event_map, &DecoderRegistry::default(), &Metrics::default()).unwrap() else {
+            panic!("Expected a Swap");
+        };
+        assert_eq!((data.sqrtPriceX96, data.liquidity), (sqrt_price_x96, u128::MAX));
+
+        // And it survives a round trip through the stored JSON
+        let stored: DecodedData = serde_json::from_str(&serde_json::to_string(&data).unwrap()).unwrap();
+        assert_eq!(stored.sqrtPriceX96, sqrt_price_x96);
+    }
+
+    #[test]
+    fn test_process_log_keep_raw() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let mut decoders = DecoderRegistry::default();
+        decoders.set_keep_raw(true);
+
+        let swap = abi.event("Swap").unwrap();
+        let log = swap_log(swap, U256::from(1u128 << 96), 1_000, -3);
+
+        let Some(DecodedEvent::Swap(data)) = tokio_test::block_on(process_log(log.clone(), &event_map, &decoders, &Metrics::default())).unwrap() else {
+            panic!("Expected a Swap");
+        };
+        let raw = data.raw.unwrap();
+        assert_eq!(raw.topics[1], format!("0x{}{}", "00".repeat(12), "aa".repeat(20)));
+
+        // The kept log decodes again to the same swap
+        let decoded = swap.parse_log(raw.to_raw_log().unwrap()).unwrap();
+        assert_eq!(decoded.params[2].value, Token::Int(I256::from(-5).into_raw()));
+    }
+
+    #[test]
+    fn test_decode_log() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let log = swap_log(abi.event("Swap").unwrap(), U256::from(1u128 << 96), 1_000, -3);
+
+        let data = decode_log(&log, &event_map).unwrap();
+        assert_eq!((data.amount0, data.amount1, data.tick), (-5, 7, -3));
+        assert!(data.raw_params.is_none());
+
+        // Other events, truncated data and pending logs aren't swaps
+        let burn = Log { topics: vec![abi.event("Burn").unwrap().signature()], ..log.clone() };
+        assert!(decode_log(&burn, &event_map).is_none());
+        assert!(decode_log(&Log { data: log.data[..64].to_vec().into(), ..log.clone() }, &event_map).is_none());
+        assert!(decode_log(&Log { transaction_hash: None, ..log }, &event_map).is_none());
+    }
+
+    #[test]
+    fn test_process_log_skips_pending_logs() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let swap = abi.event("Swap").unwrap();
+        let log = Log { transaction_hash: None, ..swap_log(swap, U256::from(1u128 << 96), 1_000, -3) };
+
+        let metrics = Metrics::default();
+        let result = tokio_test::block_on(process_log(log.clone(), &event_map, &DecoderRegistry::default(), &metrics));
+        assert!(matches!(result, Ok(None)));
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.skipped, snapshot.errors), (1, 0));
+
+        // Nor does the decoder itself panic on one
+        let decoded = swap.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() }).unwrap();
+        assert!(parse_decoded_log(decoded, &log).is_none());
+    }
+
+    #[test]
+    fn test_build_event_map_detects_ambiguous_events() {
+        // ERC-20 and ERC-721 Transfer share a signature but index different parameters
+        let abi: ethers::abi::Abi = ethers::abi::parse_abi(&[
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+            "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+        ]).unwrap();
+
+        match build_event_map(&abi) {
+            Err(TaskError::AmbiguousAbi(collisions)) => assert_eq!(
+                collisions,
+                vec![vec![
+                    "Transfer(address indexed from, address indexed to, uint256 value)".to_string(),
+                    "Transfer(address indexed from, address indexed to, uint256 indexed tokenId)".to_string(),
+                ]]
+            ),
+            other => panic!("Expected an ambiguous ABI, got {:?}", other.map(|map| map.len())),
+        }
+    }
+
+    #[test]
+    fn test_list_events() {
+        let abi: ethers::abi::Abi = ethers::abi::parse_abi(&[
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+            "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+        ]).unwrap();
+
+        let listings = list_events(&abi);
+        let signatures: Vec<&str> = listings.iter().map(|listing| listing.signature.as_str()).collect();
+        assert_eq!(
+            signatures,
+            [
+                "Approval(address indexed owner, address indexed spender, uint256 value)",
+                "Transfer(address indexed from, address indexed to, uint256 value)",
+                "Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+            ]
+        );
+        assert_eq!(listings.iter().map(|listing| listing.indexed).collect::<Vec<_>>(), [2, 2, 3]);
+        // keccak256("Transfer(address,address,uint256)")
+        let transfer = H256::from_str("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").unwrap();
+        assert_eq!((listings[1].topic0, listings[2].topic0), (transfer, transfer));
+        assert!(listings[0].collisions.is_empty());
+        assert_eq!(listings[1].collisions, [signatures[2]]);
+        assert!(listings[2].to_string().contains("COLLISION: same topic0 as Transfer(address indexed from, address indexed to, uint256 value)"));
+    }
+
+    #[test]
+    fn test_process_log_decode_failure() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let decoders = DecoderRegistry::default();
+        let metrics = Metrics::default();
+
+        // A Swap log whose data is too short to hold the non-indexed parameters
+        let swap_topic = H256::from_str("0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67").unwrap();
+        let log = Log {
+            topics: vec![swap_topic, H256::zero(), H256::zero()],
+            data: Bytes::from(vec![0u8; 8]),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+
+        let err = tokio_test::block_on(process_log(log, &event_map, &decoders, &metrics)).unwrap_err();
+        let err = err.downcast::<DecodeError>().unwrap();
+        assert_eq!(err.topic0, "0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67");
+        assert_eq!(err.transaction_hash, Some(format!("0x{}", "11".repeat(32))));
+        assert_eq!(metrics.snapshot().errors, 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_process_log_anonymous_event() {
+        let abi = ethers::abi::parse_abi(&["event Deposit(address indexed account, uint256 amount)"]).unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let vault = H160::repeat_byte(0x42);
+        let mut decoders = DecoderRegistry::default();
+        decoders.register_anonymous(&format!("{:?}:1:Deposit", vault).parse().unwrap(), &abi).unwrap();
+        let metrics = Metrics::default();
+
+        // The only topic is the indexed account, there is no signature hash
+        let log = |address: H160| Log {
+            address,
+            topics: vec![H256::from(H160::repeat_byte(0xaa))],
+            data: ethers::abi::encode(&[Token::Uint(U256::from(5))]).into(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+
+        let decoded = tokio_test::block_on(process_log(log(vault), &event_map, &decoders, &metrics)).unwrap();
+        match decoded {
+            Some(DecodedEvent::Other { event_name, fields }) => {
+                assert_eq!(event_name, "Deposit");
+                assert_eq!(fields["account"], format!("0x{}", "aa".repeat(20)));
+                assert_eq!(fields["amount"], "5");
+            }
+            other => panic!("Expected a Deposit, got {:?}", other),
+        }
+
+        // The same layout from an address that isn't mapped is left alone
+        let unmapped = tokio_test::block_on(process_log(log(H160::repeat_byte(0x43)), &event_map, &decoders, &metrics)).unwrap();
+        assert!(unmapped.is_none());
+        assert_eq!((metrics.snapshot().decoded, metrics.snapshot().skipped), (1, 1));
+
+        // A mapping must match the indexed parameters of the event
+        assert!(decoders.register_anonymous(&format!("{:?}:2:Deposit", vault).parse().unwrap(), &abi).is_err());
+    }
+
+    #[test]
+    fn test_amount_from_int_and_uint_tokens() {
+        // int256 -5, two's complement
+        let minus_five = U256::max_value() - U256::from(4);
+        assert_eq!(amount_from_token(&Token::Int(minus_five)), Some(-5));
+        assert_eq!(amount_from_token(&Token::Int(U256::from(7))), Some(7));
+        // A uint is never negative, however high its bit
+        assert_eq!(amount_from_token(&Token::Uint(U256::from(7))), Some(7));
+        assert_eq!(amount_from_token(&Token::Uint(U256::from(u128::MAX))), Some(i128::MAX));
+        // Beyond 128 bits, where the low bits alone would read as a small amount
+        assert_eq!(amount_from_token(&Token::Uint(U256::from(u128::MAX) + 1)), Some(i128::MAX));
+        assert_eq!(amount_from_token(&Token::Uint(U256::MAX)), Some(i128::MAX));
+        assert_eq!(amount_from_token(&Token::Bool(true)), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn fuzz_amount_from_int_round_trips(amount: i128) {
+            let token = Token::Int(I256::from(amount).into_raw());
+            proptest::prop_assert_eq!(amount_from_token(&token), Some(amount));
+        }
+
+        #[test]
+        fn fuzz_amount_from_any_int256(bytes: [u8; 32]) {
+            let value = I256::from_raw(U256::from_big_endian(&bytes));
+            let expected = match i128::try_from(value) {
+                Ok(amount) => amount,
+                Err(_) if value.is_negative() => i128::MIN,
+                Err(_) => i128::MAX,
+            };
+            proptest::prop_assert_eq!(amount_from_token(&Token::Int(value.into_raw())), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_amount_from_int_boundaries() {
+        for amount in [i128::MIN, i128::MIN + 1, -1, 0, 1, i128::MAX - 1, i128::MAX] {
+            assert_eq!(amount_from_token(&Token::Int(I256::from(amount).into_raw())), Some(amount));
+        }
+        // Beyond i128 the amount saturates instead of wrapping
+        let beyond = I256::from(i128::MAX) + I256::one();
+        assert_eq!(amount_from_token(&Token::Int(beyond.into_raw())), Some(i128::MAX));
+        assert_eq!(amount_from_token(&Token::Int((-beyond - I256::one()).into_raw())), Some(i128::MIN));
+        assert_eq!(amount_from_token(&Token::Int(I256::MIN.into_raw())), Some(i128::MIN));
+    }
+
+    #[test]
+    fn test_parse_decoded_log_uint_amounts() {
+        let log = Log {
+            topics: vec![H256::zero(), H256::repeat_byte(0xaa), H256::repeat_byte(0xbb)],
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Log::default()
+        };
+        let param = |name: &str, value: Token| ethabi::LogParam { name: name.to_string(), value };
+        let decoded = ethabi::Log {
+            params: vec![
+                param("amount0", Token::Uint(U256::from(1_000))),
+                param("amount1", Token::Int(U256::max_value())),
+            ],
+        };
+
+        let data = parse_decoded_log(decoded, &log).unwrap();
+        assert_eq!((data.amount0, data.amount1), (1_000, -1));
+        assert_eq!((data.amount0_abs, data.amount1_abs), (1_000, 1));
+        assert!(data.zero_for_one);
+    }
+
+    #[test]
+    fn test_decode_log_with_non_indexed_sender() {
+        // The sender is in the data and the recipient is the first indexed topic
+        let abi = ethers::abi::parse_abi(&[
+            "event Swap(address sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)",
+        ])
+        .unwrap();
+        let event_map = build_event_map(&abi).unwrap();
+        let log = swap_log(abi.event("Swap").unwrap(), U256::from(1u128 << 96), 1_000, -3);
+        assert_eq!(log.topics.len(), 2);
+
+        let data = decode_log(&log, &event_map).unwrap();
+        assert_eq!(data.sender, format!("0x{}", "aa".repeat(20)));
+        assert_eq!(data.recipient, format!("0x{}", "bb".repeat(20)));
+        assert_eq!((data.amount0, data.amount1, data.liquidity, data.tick), (-5, 7, 1_000, -3));
+    }
+
+    #[test]
+    fn test_to_checksum_address() {
+        // Test vectors from https://eips.ethereum.org/EIPS/eip-55
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let bytes = hex::decode(&address[2..]).unwrap();
+            assert_eq!(to_checksum_address(&bytes), address);
+        }
+
+        // The Uniswap V3 USDC/ETH pool the binary watches
+        let pool = hex::decode("88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap();
+        assert_eq!(to_checksum_address(&pool), "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let pool = H160::from_slice(&hex::decode("88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap());
+        for input in [
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640",
+            "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640",
+            " 88E6A0C2DDD26FEEB64F039A2C41296FCB3F5640 ",
+        ] {
+            assert_eq!(parse_address(input).unwrap(), pool);
+        }
+
+        let error = |input: &str| parse_address(input).unwrap_err().to_string();
+        assert_eq!(error("0x88e6"), "Invalid configuration: invalid address \"0x88e6\": expected 40 hex digits, got 4");
+        assert!(error("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f564g").ends_with("'g' isn't a hex digit"));
+        // One letter's case flipped
+        assert!(error("0x88e6a0c2dDD26FEEb64F039a2c41296FcB3f5640").contains("wrong EIP-55 checksum, expected 0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"));
+    }
+}
@@ -0,0 +1,1428 @@
+use async_trait::async_trait;
+use ethers::{
+    core::types::{BlockNumber, Filter, Log, H256, U256},
+    providers::{JsonRpcClient, Middleware, Provider, ProviderError, PubsubClient, Ws},
+};
+use futures_util::stream::{Stream, StreamExt};
+use lru::LruCache;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+use crate::api_keys::RotatingHttp;
+use crate::confirmations::BlockHashes;
+use crate::error::TaskError;
+use crate::log_processing::parse_address;
+use crate::pipeline::{WorkerInput, WriterMessage};
+use crate::rate_limit::{RateLimited, RateLimiter};
+
+
+/// LogSource How `fetch_eth_logs` receives the contract's logs.
+#[derive(Debug, Clone, Copy)]
+pub enum LogSource {
+    /// Subscribe to logs and new blocks over a WebSocket connection.
+    Ws,
+    /// Poll `eth_getLogs` over HTTP, for providers without WebSocket support.
+    Http(FetchConfig),
+}
+
+
+/// Subscription One set of pools watched for one set of events, e.g. the swaps of some
+/// pools and the mints of others.
+///
+/// Each subscription gets its own filter and its own stream of logs, all received over
+/// the same connection and merged into one pipeline. Parsed from
+/// `<address>[,<address>...][:<event>[,<event>...]]`, no events meaning every decoded one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub addresses: Vec<String>,
+    pub events: Vec<String>,
+}
+
+impl Subscription {
+    /// Every decoded event of the pools at `addresses`.
+    pub fn all_events<S: Into<String>>(addresses: impl IntoIterator<Item = S>) -> Self {
+        Subscription { addresses: addresses.into_iter().map(Into::into).collect(), events: Vec::new() }
+    }
+}
+
+impl FromStr for Subscription {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (addresses, events) = spec.split_once(':').unwrap_or((spec, ""));
+        let split = |list: &str| -> Vec<String> {
+            list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+        };
+        let subscription = Subscription { addresses: split(addresses), events: split(events) };
+        if subscription.addresses.is_empty() {
+            return Err(format!("The subscription {:?} has no address, expected <address>[,<address>...][:<event>,...]", spec));
+        }
+        for address in &subscription.addresses {
+            parse_address(address).map_err(|e| e.to_string())?;
+        }
+        Ok(subscription)
+    }
+}
+
+
+/// FetchConfig How `track_head_http` and `poll_logs` pace and size their requests.
+///
+/// Providers differ in how wide a block range they serve and how fast they throttle,
+/// so these are tunable instead of hard-coded. Build it with `new` to have the values
+/// checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FetchConfig {
+    /// Milliseconds between two polls for new blocks.
+    pub poll_interval_ms: u64,
+    /// The most blocks requested in one `eth_getLogs` call, wider ranges are fetched in chunks.
+    pub max_block_range: u64,
+    /// How many times a failed RPC request is retried before giving up.
+    pub max_retries: u32,
+    /// Milliseconds waited before the first retry, doubling for every following one.
+    pub backoff_base_ms: u64,
+    /// How the logs of a range holding more than the provider returns at once are fetched.
+    pub pagination: Pagination,
+}
+
+impl Default for FetchConfig {
+    /// A poll per mainnet block, ranges Infura and Alchemy both serve, and 3 retries.
+    fn default() -> Self {
+        FetchConfig { poll_interval_ms: 12_000, max_block_range: 2_000, max_retries: 3, backoff_base_ms: 500, pagination: Pagination::Bisect }
+    }
+}
+
+impl FetchConfig {
+    /// Polls shorter than this would hammer the provider faster than blocks are built.
+    pub const MIN_POLL_INTERVAL_MS: u64 = 100;
+
+    pub fn new(poll_interval_ms: u64, max_block_range: u64, max_retries: u32, backoff_base_ms: u64) -> Result<Self, TaskError> {
+        if poll_interval_ms < FetchConfig::MIN_POLL_INTERVAL_MS {
+            return Err(TaskError::InvalidConfig(format!(
+                "poll interval must be at least {}ms, got {}ms",
+                FetchConfig::MIN_POLL_INTERVAL_MS, poll_interval_ms
+            )));
+        }
+        if max_block_range == 0 {
+            return Err(TaskError::InvalidConfig("max block range must be at least 1 block".to_string()));
+        }
+        Ok(FetchConfig { poll_interval_ms, max_block_range, max_retries, backoff_base_ms, pagination: Pagination::Bisect })
+    }
+
+    /// The config fetching the ranges over the provider's limits as `pagination` says.
+    pub fn with_pagination(self, pagination: Pagination) -> Self {
+        FetchConfig { pagination, ..self }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.backoff_base_ms).saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+
+/// Pagination How `get_logs_paginated` fetches a block range holding more logs than the
+/// provider returns to one `eth_getLogs` request, given as `bisect`, `hint` or
+/// `cursor:<page size>`.
+///
+/// Providers handle such ranges differently: most reject the request, some suggesting
+/// a narrower range to ask for instead, and others return a capped page of the first
+/// logs, leaving the caller to continue after them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pagination {
+    /// A rejected range is split in half, and each half requested on its own.
+    #[default]
+    Bisect,
+    /// A rejected range is continued from the range the error suggests, e.g. Infura's
+    /// "Try with this block range [0x10, 0x1f]", up to the end of the range. Errors
+    /// without a suggestion are bisected.
+    Hint,
+    /// The provider returns at most `page_size` logs without an error. A full page is
+    /// cut before its last block, which may be incomplete, and the range continued from
+    /// that block like a cursor. Rejected ranges are bisected. A page filled by a single
+    /// block can't be continued and fails the request rather than miss logs.
+    Cursor { page_size: usize },
+}
+
+impl FromStr for Pagination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pagination {:?}, expected bisect, hint or cursor:<page size>", s);
+        match s.trim().to_lowercase().as_str() {
+            "bisect" => Ok(Pagination::Bisect),
+            "hint" => Ok(Pagination::Hint),
+            other => match other.strip_prefix("cursor:").map(|page_size| page_size.trim().parse()) {
+                Some(Ok(page_size)) if page_size > 0 => Ok(Pagination::Cursor { page_size }),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+
+/// Connection The provider connected for a `LogSource`.
+///
+/// Both transports share the processing: `watch_logs`, `poll_logs` and the head trackers
+/// are generic over the provider's client, and the connection looks blocks and receipts
+/// up over whichever it holds.
+pub enum Connection {
+    Ws(Provider<RateLimited<Ws>>),
+    Http(Provider<RateLimited<RotatingHttp>>),
+}
+
+impl Connection {
+    /// Connect to `url`, over a WebSocket for a `ws://` or `wss://` URL, over HTTP for an
+    /// `http://` or `https://` one. Requests take a token of `limiter` first, when set.
+    pub async fn connect(url: &str, limiter: Option<Arc<RateLimiter>>) -> Result<Self, Box<dyn Error>> {
+        match url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase()).as_deref() {
+            Some("ws" | "wss") => Ok(Connection::Ws(Provider::new(RateLimited::new(Ws::connect(url).await?, limiter)))),
+            Some("http" | "https") => Ok(Connection::Http(Provider::new(RateLimited::new(RotatingHttp::single(url)?, limiter)))),
+            // The URL often holds an API key, leave it out of the error
+            _ => Err(TaskError::InvalidConfig("the RPC URL must start with ws://, wss://, http:// or https://".to_string()).into()),
+        }
+    }
+
+    /// The log source streaming over this connection: subscriptions over a WebSocket,
+    /// polling with `config` over HTTP.
+    pub fn log_source(&self, config: FetchConfig) -> LogSource {
+        match self {
+            Connection::Ws(_) => LogSource::Ws,
+            Connection::Http(_) => LogSource::Http(config),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockTimestamps for Connection {
+    async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        let block = match self {
+            Connection::Ws(provider) => provider.get_block(block_number).await?,
+            Connection::Http(provider) => provider.get_block(block_number).await?,
+        };
+        Ok(block.map(|block| block.timestamp.as_u64()))
+    }
+}
+
+#[async_trait]
+impl TransactionGas for Connection {
+    async fn transaction_gas(&self, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+        match self {
+            Connection::Ws(provider) => provider_transaction_gas(provider, hash).await,
+            Connection::Http(provider) => provider_transaction_gas(provider, hash).await,
+        }
+    }
+}
+
+// The gas cost of transaction `hash` from its receipt. Receipts of nodes that leave
+// `effectiveGasPrice` out fall back to the transaction's gas price, what pre-London
+// transactions paid.
+async fn provider_transaction_gas<P: JsonRpcClient>(provider: &Provider<P>, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+    let Some(receipt) = provider.get_transaction_receipt(hash).await? else {
+        return Ok(None);
+    };
+    let Some(gas_used) = receipt.gas_used else {
+        return Ok(None);
+    };
+    let effective_gas_price = match receipt.effective_gas_price {
+        Some(price) => price,
+        None => match provider.get_transaction(hash).await?.and_then(|tx| tx.gas_price) {
+            Some(price) => price,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(GasCost { gas_used: gas_used.low_u64(), effective_gas_price: effective_gas_price.low_u64() }))
+}
+
+
+/// SharedConnection The connection of a watch, swapped for a new one on reconnect.
+///
+/// Block timestamps are looked up through whichever connection is current, so the
+/// writer's `CachedTimestamps` keeps working across reconnects.
+pub struct SharedConnection {
+    current: RwLock<Arc<Connection>>,
+}
+
+impl SharedConnection {
+    pub fn new(connection: Connection) -> Self {
+        SharedConnection { current: RwLock::new(Arc::new(connection)) }
+    }
+
+    pub fn current(&self) -> Arc<Connection> {
+        Arc::clone(&self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    /// Use `connection` from now on. Requests already sent finish on the old one.
+    pub fn replace(&self, connection: Connection) {
+        *self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(connection);
+    }
+}
+
+#[async_trait]
+impl BlockTimestamps for SharedConnection {
+    async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        self.current().block_timestamp(block_number).await
+    }
+}
+
+#[async_trait]
+impl TransactionGas for SharedConnection {
+    async fn transaction_gas(&self, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+        self.current().transaction_gas(hash).await
+    }
+}
+
+
+/// BlockTimestamps Looks up when blocks were mined.
+#[async_trait]
+pub trait BlockTimestamps: Send + Sync {
+    /// The Unix timestamp of block `block_number`, or None if the block isn't known.
+    async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>>;
+}
+
+
+/// CachedTimestamps Remembers the timestamps looked up through another `BlockTimestamps`.
+///
+/// Many logs share a block, so every block is only fetched once while it stays among
+/// the `capacity` most recently used ones. Older blocks are evicted, which bounds the
+/// memory used during long backfills. Unknown blocks aren't cached, so they are asked
+/// for again once the provider has them.
+pub struct CachedTimestamps<'a> {
+    inner: &'a dyn BlockTimestamps,
+    cache: Mutex<LruCache<u64, u64>>,
+}
+
+impl<'a> CachedTimestamps<'a> {
+    /// The default number of blocks remembered, over a day of mainnet blocks.
+    pub const DEFAULT_CAPACITY: usize = 8_192;
+
+    pub fn new(inner: &'a dyn BlockTimestamps, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        CachedTimestamps { inner, cache: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    /// Remember the timestamp of a block learned some other way, e.g. with the chain head.
+    pub fn insert(&self, block_number: u64, timestamp: u64) {
+        self.cache().put(block_number, timestamp);
+    }
+
+    // The lock is never held across an await, a panic can't leave the cache inconsistent.
+    fn cache(&self) -> MutexGuard<'_, LruCache<u64, u64>> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl BlockTimestamps for CachedTimestamps<'_> {
+    async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        if let Some(timestamp) = self.cache().get(&block_number) {
+            return Ok(Some(*timestamp));
+        }
+        let timestamp = self.inner.block_timestamp(block_number).await?;
+        if let Some(timestamp) = timestamp {
+            self.cache().put(block_number, timestamp);
+        }
+        Ok(timestamp)
+    }
+}
+
+
+/// GasCost What a transaction paid for gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasCost {
+    pub gas_used: u64,
+    /// The price paid per unit of gas, in wei.
+    pub effective_gas_price: u64,
+}
+
+/// TransactionGas Looks up what transactions paid for gas.
+#[async_trait]
+pub trait TransactionGas: Send + Sync {
+    /// The gas cost of transaction `hash`, or None if its receipt isn't known yet.
+    async fn transaction_gas(&self, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>>;
+}
+
+
+/// CachedGas Remembers the gas costs looked up through another `TransactionGas`.
+///
+/// The swaps of a transaction share its receipt, e.g. a multi-hop route through the
+/// pool, so each transaction is only fetched once while it stays among the `capacity`
+/// most recently used ones. Unknown transactions aren't cached, like `CachedTimestamps`.
+pub struct CachedGas<'a> {
+    inner: &'a dyn TransactionGas,
+    cache: Mutex<LruCache<H256, GasCost>>,
+}
+
+impl<'a> CachedGas<'a> {
+    /// The default number of transactions remembered.
+    pub const DEFAULT_CAPACITY: usize = 4_096;
+
+    pub fn new(inner: &'a dyn TransactionGas, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        CachedGas { inner, cache: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    // The lock is never held across an await, a panic can't leave the cache inconsistent.
+    fn cache(&self) -> MutexGuard<'_, LruCache<H256, GasCost>> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl TransactionGas for CachedGas<'_> {
+    async fn transaction_gas(&self, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+        if let Some(cost) = self.cache().get(&hash) {
+            return Ok(Some(*cost));
+        }
+        let cost = self.inner.transaction_gas(hash).await?;
+        if let Some(cost) = cost {
+            self.cache().put(hash, cost);
+        }
+        Ok(cost)
+    }
+}
+
+
+/// SourceEvent What a log source yields: a log of the contract, or a new chain head.
+// Logs far outnumber heads, boxing them would cost an allocation each
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum SourceEvent {
+    Log(Log),
+    Block(u64),
+}
+
+
+/// ChainHead The latest block of the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainHead {
+    pub number: u64,
+    /// Unix timestamp the block was mined at.
+    pub timestamp: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+}
+
+
+/// track_head_ws Publishes every new chain head on `head`, from a WebSocket block subscription.
+///
+/// This is the one place the head is fetched: the confirmation depth, the HTTP log
+/// polling and the timestamp cache all read it from the `watch` channel.
+///
+/// # Returns
+///
+/// Once nobody reads `head` anymore, an error if subscribing fails, or
+/// `TaskError::Disconnected` when the subscription ends.
+pub async fn track_head_ws<P: PubsubClient>(provider: &Provider<P>, head: &watch::Sender<Option<ChainHead>>) -> Result<(), Box<dyn Error>> {
+    let mut blocks = provider.subscribe_blocks().await?;
+    while let Some(block) = blocks.next().await {
+        let (Some(number), Some(hash)) = (block.number, block.hash) else { continue };
+        let chain_head = ChainHead { number: number.as_u64(), timestamp: block.timestamp.as_u64(), hash, parent_hash: block.parent_hash };
+        if head.send(Some(chain_head)).is_err() {
+            return Ok(());
+        }
+    }
+    Err(TaskError::Disconnected("block").into())
+}
+
+/// track_head_http Publishes the chain head on `head` by polling the latest block over
+/// HTTP every `config.poll_interval_ms`, retrying failed requests as set by `config`.
+/// A head is only published again once a new block is built.
+///
+/// # Returns
+///
+/// Once nobody reads `head` anymore, or an RPC error that persisted through the retries.
+pub async fn track_head_http<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    config: &FetchConfig,
+    head: &watch::Sender<Option<ChainHead>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut interval = tokio::time::interval(config.poll_interval());
+    loop {
+        interval.tick().await;
+        if head.is_closed() {
+            return Ok(());
+        }
+
+        let block = with_retries(config, || async { provider.get_block(BlockNumber::Latest).await }).await?;
+        let Some(chain_head) = block.and_then(|block| {
+            Some(ChainHead { number: block.number?.as_u64(), timestamp: block.timestamp.as_u64(), hash: block.hash?, parent_hash: block.parent_hash })
+        }) else { continue };
+        head.send_if_modified(|current| {
+            // A block replacing the head at the same height is new too
+            let is_new = current.is_none_or(|current| current.number < chain_head.number || current.hash != chain_head.hash);
+            if is_new {
+                *current = Some(chain_head);
+            }
+            is_new
+        });
+    }
+}
+
+
+/// watch_logs Streams the logs matching any of `filters` from the node's log filters,
+/// handing them to `forward_events`. Used over a WebSocket connection, any transport works.
+///
+/// Each filter is watched with its own stream, and the streams are merged, so pools
+/// with different events share the connection and the pipeline. The blocks replaced by a
+/// reorg are fetched again with `eth_getLogs`, as `config` says.
+///
+/// # Returns
+///
+/// Once the pipeline ends, an error if subscribing fails, or `TaskError::Disconnected`
+/// when the subscription ends: a WebSocket subscription only ends when the connection drops.
+pub async fn watch_logs<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filters: &[Filter],
+    config: &FetchConfig,
+    logs: mpsc::Sender<WorkerInput>,
+) -> Result<(), Box<dyn Error>> {
+    let mut streams = Vec::with_capacity(filters.len());
+    for filter in filters {
+        streams.push(provider.watch(filter).await?);
+    }
+    let logs_stream = futures_util::stream::select_all(streams).map(SourceEvent::Log);
+    let refetch = FilterLogs { provider, filters, config };
+    match forward_events(logs_stream, &refetch, logs).await {
+        SourceEnd::PipelineClosed => Ok(()),
+        SourceEnd::Exhausted => Err(TaskError::Disconnected("log").into()),
+    }
+}
+
+
+/// SourceEnd Why `forward_events` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEnd {
+    /// The source has no more events, e.g. its connection dropped.
+    Exhausted,
+    /// The workers or the writer have gone away.
+    PipelineClosed,
+}
+
+
+/// forward_events Feeds the events of a log source into the decode pipeline.
+///
+/// Logs are pushed to `logs` for decoding. Reorged out logs and the number of every new
+/// block are pushed behind them as writer messages, which the workers pass on to the
+/// writer after the swaps of the logs before them. The writer holds swaps back until they
+/// are deep enough, and drops the swap of a removed log whatever the confirmation depth.
+/// Returns once `events` ends, e.g. on a disconnect, or the pipeline has gone away,
+/// telling which.
+///
+/// A log whose block was seen before with another hash means the chain reorged from
+/// that block on: the reorg is logged and pushed as `WriterMessage::Reorg` ahead of
+/// the log, so the swaps still pending from the replaced blocks are dropped. The logs of
+/// the replaced blocks are then fetched again from `refetch` and pushed in their place,
+/// and the source's own copies of them are skipped. When they can't be fetched, the
+/// source's logs are relied on.
+pub async fn forward_events(
+    events: impl Stream<Item = SourceEvent>,
+    refetch: &dyn LogRange,
+    logs: mpsc::Sender<WorkerInput>,
+) -> SourceEnd {
+    futures_util::pin_mut!(events);
+    // The hash of the blocks the logs came from
+    let mut block_hashes = BlockHashes::new(BlockHashes::DEFAULT_CAPACITY);
+    // The logs fetched again after a reorg, up to the last replaced block
+    let mut refetched = RefetchedLogs::default();
+
+    while let Some(event) = events.next().await {
+        let sent = match event {
+            SourceEvent::Log(log) => {
+                // println!("Mock Log:\n{:?}", pretty_print_log(&log));  // used for unit test creation
+
+                // A removed log was reorged out, make sure its swap never gets stored
+                if log.removed == Some(true) {
+                    logs.send(WriterMessage::Removed(log).into()).await.is_ok()
+                } else {
+                    if let (Some(number), Some(hash)) = (log.block_number, log.block_hash) {
+                        if let Some(reorg) = block_hashes.insert(number.as_u64(), hash) {
+                            tracing::warn!(depth = reorg.depth, first_block = reorg.first_block, "Chain reorg");
+                            if logs.send(WriterMessage::Reorg(reorg.first_block).into()).await.is_err() {
+                                return SourceEnd::PipelineClosed;
+                            }
+                            let last_block = reorg.first_block + reorg.depth - 1;
+                            match refetch.logs_in_range(reorg.first_block, last_block).await {
+                                Ok(new_logs) => {
+                                    refetched = RefetchedLogs::new(last_block, &new_logs);
+                                    for new_log in new_logs {
+                                        if let (Some(number), Some(hash)) = (new_log.block_number, new_log.block_hash) {
+                                            block_hashes.insert(number.as_u64(), hash);
+                                        }
+                                        if logs.send(new_log.into()).await.is_err() {
+                                            return SourceEnd::PipelineClosed;
+                                        }
+                                    }
+                                }
+                                Err(e) => tracing::warn!(
+                                    first_block = reorg.first_block,
+                                    last_block,
+                                    error = %e,
+                                    "Couldn't fetch the blocks again after the reorg, relying on the subscription"
+                                ),
+                            }
+                        }
+                    }
+                    if refetched.contains(&log) {
+                        continue;
+                    }
+                    // Waits for room when the workers are behind
+                    logs.send(log.into()).await.is_ok()
+                }
+            }
+            SourceEvent::Block(latest_block) => logs.send(WriterMessage::Block(latest_block).into()).await.is_ok(),
+        };
+        if !sent {
+            return SourceEnd::PipelineClosed;
+        }
+    }
+    SourceEnd::Exhausted
+}
+
+// The logs `forward_events` fetched again after a reorg, to skip the source's copies of
+// them. Forgotten once the source moves past the last replaced block.
+#[derive(Default)]
+struct RefetchedLogs {
+    last_block: u64,
+    logs: Vec<(H256, U256)>,
+}
+
+impl RefetchedLogs {
+    fn new(last_block: u64, logs: &[Log]) -> Self {
+        let logs = logs.iter().filter_map(|log| Some((log.block_hash?, log.log_index?))).collect();
+        RefetchedLogs { last_block, logs }
+    }
+
+    fn contains(&mut self, log: &Log) -> bool {
+        if log.block_number.is_some_and(|number| number.as_u64() > self.last_block) {
+            self.logs.clear();
+        }
+        matches!((log.block_hash, log.log_index), (Some(hash), Some(index)) if self.logs.contains(&(hash, index)))
+    }
+}
+
+
+/// LogRange Fetches the logs of a range of blocks again, for `forward_events` to replace
+/// the logs of the blocks a reorg replaced.
+#[async_trait]
+pub trait LogRange: Send + Sync {
+    /// The logs of blocks `from_block..=to_block`, in chain order.
+    async fn logs_in_range(&self, from_block: u64, to_block: u64) -> Result<Vec<Log>, Box<dyn Error + Send + Sync>>;
+}
+
+/// FilterLogs The logs matching any of `filters`, fetched with `eth_getLogs` as `config`
+/// says, like `poll_logs` fetches them.
+pub struct FilterLogs<'a, P> {
+    pub provider: &'a Provider<P>,
+    pub filters: &'a [Filter],
+    pub config: &'a FetchConfig,
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> LogRange for FilterLogs<'_, P> {
+    async fn logs_in_range(&self, from_block: u64, to_block: u64) -> Result<Vec<Log>, Box<dyn Error + Send + Sync>> {
+        Ok(fetch_window(self.provider, self.filters, self.config, from_block, to_block).await?)
+    }
+}
+
+
+/// read_log_file The logs recorded in the JSON file at `path`: an array of logs as ethers
+/// serializes `Log`, which is also what a node answers `eth_getLogs` with.
+pub fn read_log_file(path: &Path) -> Result<Vec<Log>, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read the logs file {}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&contents).map_err(|e| format!("Failed to parse the logs file {}: {}", path.display(), e))?)
+}
+
+/// feed_logs Feeds recorded logs into the decode pipeline like a live source does, through
+/// `forward_events`, without any network.
+///
+/// The logs are taken as final: once they are all sent, the head is moved `confirmations`
+/// blocks past the last of them, so every swap is stored. The head goes through the
+/// workers behind the logs, so it reaches the writer after their swaps.
+pub async fn feed_logs(recorded: Vec<Log>, confirmations: u64, logs: mpsc::Sender<WorkerInput>) -> SourceEnd {
+    let last_block = recorded.iter().filter_map(|log| log.block_number).max().map(|number| number.as_u64());
+    let head = last_block.map(|last_block| SourceEvent::Block(last_block.saturating_add(confirmations)));
+    let events = futures_util::stream::iter(recorded.into_iter().map(SourceEvent::Log).chain(head));
+    forward_events(events, &OfflineLookups, logs).await
+}
+
+
+/// OfflineLookups The block timestamps, transaction receipts and logs of recorded logs
+/// read without a node: every block and transaction is unknown, and no log is fetched again.
+pub struct OfflineLookups;
+
+#[async_trait]
+impl BlockTimestamps for OfflineLookups {
+    async fn block_timestamp(&self, _block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl TransactionGas for OfflineLookups {
+    async fn transaction_gas(&self, _hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl LogRange for OfflineLookups {
+    async fn logs_in_range(&self, _from_block: u64, _to_block: u64) -> Result<Vec<Log>, Box<dyn Error + Send + Sync>> {
+        Ok(Vec::new())
+    }
+}
+
+
+/// poll_logs Fetches the logs matching any of `filters` by polling `eth_getLogs` over HTTP.
+///
+/// Every filter is requested for the same block range, and the logs of a range are
+/// delivered in chain order whichever filter matched them.
+/// Whenever a new chain head is published on `head`, the logs of the blocks not seen
+/// yet are requested, from `next_block` on, or starting at the first head when None.
+/// `next_block` is kept up to date, so a later call resumes where this one stopped.
+/// The block ranges never overlap, so no log is delivered twice and none is skipped
+/// between heads, unless the chain reorgs.
+///
+/// The hash of every head is noted, and a head whose parent isn't the block noted at its
+/// height, or replacing the head at the same height, means a reorg. The canonical blocks
+/// are then fetched back to the last one still matching, the reorg is logged and pushed to
+/// `logs` as `WriterMessage::Reorg`, and the logs are requested again from the first
+/// replaced block. Only reorgs of blocks seen as a head are noticed. After falling behind the
+/// missed blocks are requested in chunks of at most `config.max_block_range` blocks,
+/// and a chunk still holding too many logs for one request is paged through by
+/// `get_logs_paginated`. Failed requests are retried as set by `config`.
+///
+/// # Returns
+///
+/// Once the pipeline or the head tracker ends, or an RPC error that persisted through the retries.
+pub async fn poll_logs<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filters: &[Filter],
+    config: &FetchConfig,
+    mut head: watch::Receiver<Option<ChainHead>>,
+    logs: mpsc::Sender<WorkerInput>,
+    next_block: &mut Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let mut block_hashes = BlockHashes::new(BlockHashes::DEFAULT_CAPACITY);
+    while head.changed().await.is_ok() {
+        let Some(latest) = *head.borrow_and_update() else { continue };
+        // The highest block the head shows was replaced
+        let replaced = match (block_hashes.get(latest.number), latest.number.checked_sub(1)) {
+            (Some(seen), _) if seen != latest.hash => Some(latest.number),
+            (_, Some(parent)) => block_hashes.get(parent).filter(|seen| *seen != latest.parent_hash).map(|_| parent),
+            _ => None,
+        };
+        if let Some(replaced) = replaced {
+            let first_block = find_fork(provider, config, &block_hashes, replaced).await?;
+            let depth = block_hashes.latest().unwrap_or(replaced) - first_block + 1;
+            block_hashes.forget_from(first_block);
+            tracing::warn!(depth, first_block, "Chain reorg");
+            if logs.send(WriterMessage::Reorg(first_block).into()).await.is_err() {
+                return Ok(());
+            }
+            *next_block = Some(next_block.map_or(first_block, |next| next.min(first_block)));
+        }
+        block_hashes.insert(latest.number, latest.hash);
+        while let Some((from_block, to_block)) = block_range(*next_block, latest.number, config.max_block_range) {
+            if send_window(provider, filters, config, from_block, to_block, &logs).await?.is_none() {
+                return Ok(());
+            }
+            *next_block = Some(to_block + 1);
+        }
+    }
+    Ok(())
+}
+
+
+/// fetch_range Fetches the logs matching any of `filters` in blocks `from_block..=to_block`,
+/// for a backfill of past blocks rather than following the head.
+///
+/// The blocks are requested in chunks of at most `config.max_block_range` blocks, like
+/// `poll_logs` catches up, and the logs of a chunk are delivered in chain order. Failed
+/// requests are retried as set by `config`. Reorgs aren't looked for, the range is
+/// expected to be settled. After every chunk `progress` is called with how far the
+/// backfill got. A head sent to `logs` once this returns reaches the writer after the
+/// swaps of every log fetched.
+///
+/// # Returns
+///
+/// Once every block of the range was fetched or the pipeline ended, or an RPC error
+/// that persisted through the retries.
+pub async fn fetch_range<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filters: &[Filter],
+    config: &FetchConfig,
+    from_block: u64,
+    to_block: u64,
+    logs: mpsc::Sender<WorkerInput>,
+    mut progress: impl FnMut(&BackfillProgress),
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut found = 0;
+    let mut next_block = Some(from_block);
+    while let Some((from, to)) = block_range(next_block, to_block, config.max_block_range) {
+        let Some(sent) = send_window(provider, filters, config, from, to, &logs).await? else {
+            return Ok(());
+        };
+        found += sent as u64;
+        progress(&BackfillProgress { from_block, to_block, current_block: to, logs: found, elapsed: started.elapsed() });
+        next_block = Some(to + 1);
+    }
+    Ok(())
+}
+
+// Fetch the logs of every filter in blocks `from_block..=to_block` and send them to `logs`
+// in chain order, returning how many there were. None once the pipeline has ended.
+async fn send_window<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filters: &[Filter],
+    config: &FetchConfig,
+    from_block: u64,
+    to_block: u64,
+    logs: &mpsc::Sender<WorkerInput>,
+) -> Result<Option<usize>, ProviderError> {
+    let window_logs = fetch_window(provider, filters, config, from_block, to_block).await?;
+    let count = window_logs.len();
+    for log in window_logs {
+        if logs.send(log.into()).await.is_err() {
+            return Ok(None);
+        }
+    }
+    Ok(Some(count))
+}
+
+// The logs of every filter in blocks `from_block..=to_block`, in chain order.
+async fn fetch_window<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filters: &[Filter],
+    config: &FetchConfig,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, ProviderError> {
+    let mut window_logs = Vec::new();
+    for filter in filters {
+        window_logs.extend(with_retries(config, || get_logs_paginated(provider, filter, from_block, to_block, config.pagination)).await?);
+    }
+    window_logs.sort_by_key(|log| (log.block_number, log.log_index));
+    Ok(window_logs)
+}
+
+
+/// BackfillProgress How far `fetch_range` got through the blocks `from_block..=to_block`,
+/// reported after every chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillProgress {
+    pub from_block: u64,
+    pub to_block: u64,
+    /// The last block fetched.
+    pub current_block: u64,
+    /// The logs found so far.
+    pub logs: u64,
+    /// The time since the backfill started.
+    pub elapsed: Duration,
+}
+
+impl BackfillProgress {
+    /// The blocks fetched so far.
+    pub fn blocks_done(&self) -> u64 {
+        self.current_block.saturating_sub(self.from_block) + 1
+    }
+
+    /// All the blocks of the range.
+    pub fn total_blocks(&self) -> u64 {
+        self.to_block.saturating_sub(self.from_block) + 1
+    }
+
+    pub fn percent(&self) -> f64 {
+        100.0 * self.blocks_done() as f64 / self.total_blocks() as f64
+    }
+
+    /// The time left at the throughput so far, in blocks per second.
+    pub fn eta(&self) -> Duration {
+        let remaining = self.total_blocks().saturating_sub(self.blocks_done());
+        self.elapsed.mul_f64(remaining as f64 / self.blocks_done() as f64)
+    }
+}
+
+impl fmt::Display for BackfillProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let eta = self.eta().as_secs();
+        write!(
+            f,
+            "block {} of {}..={} ({:.1}%), {} logs found, ETA {}h{:02}m{:02}s",
+            self.current_block, self.from_block, self.to_block, self.percent(), self.logs, eta / 3600, eta / 60 % 60, eta % 60
+        )
+    }
+}
+
+
+/// The seconds between two mainnet blocks since the merge, used to guess where a
+/// timestamp falls before searching for its block.
+const BLOCK_TIME_SECS: u64 = 12;
+
+/// first_block_at Finds the first block built at or after `timestamp`, in seconds since the epoch.
+///
+/// The block is guessed from the latest one assuming a block every 12 seconds, then the
+/// guess is widened with a doubling step until it straddles the timestamp and bisected
+/// down to the block, each step one `get_block` request retried as set by `config`. A
+/// range of a few days takes a few dozen requests per end.
+///
+/// # Returns
+///
+/// The block number, the one after the latest block when no block was built at or after
+/// `timestamp` yet, or an RPC error that persisted through the retries.
+pub async fn first_block_at<P: JsonRpcClient>(provider: &Provider<P>, config: &FetchConfig, timestamp: u64) -> Result<u64, Box<dyn Error>> {
+    let latest = with_retries(config, || provider.get_block_number()).await?.as_u64();
+    let latest_time = block_time(provider, config, latest).await?;
+    if latest_time < timestamp {
+        return Ok(latest + 1);
+    }
+
+    // Widen from the guess until block `before` is older than `timestamp` and block `after` isn't
+    let guess = latest.saturating_sub((latest_time - timestamp) / BLOCK_TIME_SECS);
+    let mut step = 64;
+    let (mut before, mut after) = if block_time(provider, config, guess).await? >= timestamp {
+        let mut after = guess;
+        loop {
+            if after == 0 {
+                return Ok(0);
+            }
+            let candidate = after.saturating_sub(step);
+            if block_time(provider, config, candidate).await? < timestamp {
+                break (candidate, after);
+            }
+            after = candidate;
+            step *= 2;
+        }
+    } else {
+        // The latest block isn't older, the search ends there at the latest
+        let mut before = guess;
+        loop {
+            let candidate = before.saturating_add(step).min(latest);
+            if block_time(provider, config, candidate).await? >= timestamp {
+                break (before, candidate);
+            }
+            before = candidate;
+            step *= 2;
+        }
+    };
+
+    while after - before > 1 {
+        let middle = before + (after - before) / 2;
+        if block_time(provider, config, middle).await? >= timestamp {
+            after = middle;
+        } else {
+            before = middle;
+        }
+    }
+    Ok(after)
+}
+
+// The timestamp of block `block_number`, in seconds since the epoch.
+async fn block_time<P: JsonRpcClient>(provider: &Provider<P>, config: &FetchConfig, block_number: u64) -> Result<u64, Box<dyn Error>> {
+    let block = with_retries(config, || provider.get_block(block_number)).await?;
+    Ok(block.ok_or_else(|| format!("block {} not found", block_number))?.timestamp.as_u64())
+}
+
+// The first block replaced by a reorg, given that block `replaced` was: walk back while the
+// canonical block differs from the hash noted for it, as far as hashes were noted.
+async fn find_fork<P: JsonRpcClient>(provider: &Provider<P>, config: &FetchConfig, block_hashes: &BlockHashes, replaced: u64) -> Result<u64, ProviderError> {
+    let mut first_block = replaced;
+    while let Some(seen) = first_block.checked_sub(1).and_then(|parent| block_hashes.get(parent)) {
+        let block = with_retries(config, || provider.get_block(first_block - 1)).await?;
+        if block.and_then(|block| block.hash) == Some(seen) {
+            break;
+        }
+        first_block -= 1;
+    }
+    Ok(first_block)
+}
+
+// Run an RPC request, retrying it with the backoff of `config` when it fails.
+async fn with_retries<T, F, Fut>(config: &FetchConfig, mut request: F) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ProviderError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < config.max_retries => {
+                tracing::warn!(attempt, error = %e, "RPC request failed, retrying");
+                tokio::time::sleep(config.backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+/// get_logs_bisecting Fetches the logs matching `filter` in blocks `from_block..=to_block`.
+///
+/// Providers reject a request whose result set is too large, e.g. Infura's "query
+/// returned more than 10000 results". The range is then split in half and each half
+/// requested on its own, splitting further until every window succeeds or is down to a
+/// single block. The logs are returned in block order.
+pub async fn get_logs_bisecting<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, ProviderError> {
+    get_logs_paginated(provider, filter, from_block, to_block, Pagination::Bisect).await
+}
+
+/// get_logs_paginated Fetches the logs matching `filter` in blocks `from_block..=to_block`,
+/// paging through a range holding more logs than one request returns as `pagination`
+/// says, until the whole range is fetched. Whatever the pagination, a rejected range
+/// without a usable suggestion is bisected like `get_logs_bisecting` does. The logs are
+/// returned in block order.
+pub async fn get_logs_paginated<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+    pagination: Pagination,
+) -> Result<Vec<Log>, ProviderError> {
+    let mut logs = Vec::new();
+    // The windows still to fetch, the next one on top
+    let mut windows = vec![(from_block, to_block)];
+
+    while let Some((from, to)) = windows.pop() {
+        match provider.get_logs(&filter.clone().from_block(from).to_block(to)).await {
+            Ok(mut window_logs) => {
+                if let Pagination::Cursor { page_size } = pagination {
+                    if window_logs.len() >= page_size {
+                        if let Some(next) = cursor_block(&window_logs, from, to) {
+                            // The last block of the page may be cut, it's fetched again
+                            window_logs.retain(|log| log.block_number.is_none_or(|number| number.as_u64() < next));
+                            windows.push((next, to));
+                        } else {
+                            // Skipping the rest of the block would lose logs without a trace
+                            tracing::warn!(block = from, page_size, "Block fills a whole page of logs, it can't be paged through");
+                            return Err(ProviderError::CustomError(format!(
+                                "block {} holds at least {} logs, a full page, which can't be paged through by block; narrow the filter or use another pagination",
+                                from, page_size
+                            )));
+                        }
+                    }
+                }
+                logs.extend(window_logs);
+            }
+            Err(e) if from < to && is_too_many_results(&e.to_string()) => {
+                let hint = (pagination == Pagination::Hint).then(|| hinted_range(&e.to_string())).flatten();
+                match hint.filter(|&(hint_from, hint_to)| hint_from == from && hint_to < to) {
+                    Some((_, hint_to)) => {
+                        windows.push((hint_to + 1, to));
+                        windows.push((from, hint_to));
+                    }
+                    None => {
+                        let middle = from + (to - from) / 2;
+                        tracing::debug!(from, to, middle, "Too many logs in the blocks, splitting the range");
+                        windows.push((middle + 1, to));
+                        windows.push((from, middle));
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(logs)
+}
+
+// The block a capped page of the logs of `from..=to` continues from: the block of its
+// last log, which may be cut. None when the page is all from `from`, so it can't advance.
+fn cursor_block(page: &[Log], from: u64, to: u64) -> Option<u64> {
+    let last = page.iter().filter_map(|log| log.block_number).map(|number| number.as_u64()).max()?;
+    (last > from && last <= to).then_some(last)
+}
+
+// The block range a "too many results" error suggests asking for instead, e.g. the
+// `[0x10, 0x1f]` of "query returned more than 10000 results. Try with this block range
+// [0x10, 0x1f].", in hex or decimal.
+fn hinted_range(message: &str) -> Option<(u64, u64)> {
+    let start = message.rfind('[')?;
+    let end = start + message[start..].find(']')?;
+    let (from, to) = message[start + 1..end].split_once(',')?;
+    let block = |block: &str| {
+        let block = block.trim().trim_matches('"');
+        match block.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => block.parse().ok(),
+        }
+    };
+    let (from, to) = (block(from)?, block(to)?);
+    (from <= to).then_some((from, to))
+}
+
+// Whether a get_logs error means the range matched too many logs to return at once.
+fn is_too_many_results(message: &str) -> bool {
+    message.contains("query returned more than") || message.contains("Log response size exceeded")
+}
+
+// The blocks to request logs for next, given the first block not fetched yet (None
+// before the first poll), the latest block and the widest range to request at once.
+// None when every block up to the latest has been fetched.
+fn block_range(next_block: Option<u64>, latest_block: u64, max_block_range: u64) -> Option<(u64, u64)> {
+    let from_block = next_block.unwrap_or(latest_block);
+    let to_block = latest_block.min(from_block.saturating_add(max_block_range.max(1) - 1));
+    (from_block <= latest_block).then_some((from_block, to_block))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{HttpClientError, JsonRpcError};
+    use serde::{de::DeserializeOwned, Serialize};
+
+    // A node answering eth_getLogs with one log per block, rejecting ranges wider than `max_blocks`.
+    #[derive(Debug)]
+    struct LimitedNode {
+        max_blocks: u64,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for LimitedNode {
+        type Error = HttpClientError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: std::fmt::Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            assert_eq!(method, "eth_getLogs");
+            let params = serde_json::to_value(params).unwrap();
+            let block = |key: &str| u64::from_str_radix(params[0][key].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+            let (from, to) = (block("fromBlock"), block("toBlock"));
+
+            if to - from + 1 > self.max_blocks {
+                return Err(HttpClientError::JsonRpcError(JsonRpcError {
+                    code: -32005,
+                    message: "query returned more than 10000 results".to_string(),
+                    data: None,
+                }));
+            }
+            let logs: Vec<Log> = (from..=to).map(|n| Log { block_number: Some(n.into()), ..Default::default() }).collect();
+            Ok(serde_json::from_value(serde_json::to_value(logs).unwrap()).unwrap())
+        }
+    }
+
+    // Block timestamps counting the lookups, block n was mined at second n.
+    #[derive(Default)]
+    struct CountingTimestamps {
+        lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BlockTimestamps for CountingTimestamps {
+        async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+            self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Some(block_number))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_timestamps() {
+        let inner = CountingTimestamps::default();
+        let cached = CachedTimestamps::new(&inner, 2);
+
+        for block in [1, 1, 2, 1, 2] {
+            assert_eq!(cached.block_timestamp(block).await.unwrap(), Some(block));
+        }
+        assert_eq!(inner.lookups.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Block 3 evicts block 2, the least recently used
+        cached.block_timestamp(1).await.unwrap();
+        cached.block_timestamp(3).await.unwrap();
+        cached.block_timestamp(2).await.unwrap();
+        assert_eq!(inner.lookups.load(std::sync::atomic::Ordering::SeqCst), 4);
+    }
+
+    // Gas costs counting the lookups, unknown for the zero hash.
+    #[derive(Default)]
+    struct CountingGas {
+        lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TransactionGas for CountingGas {
+        async fn transaction_gas(&self, hash: H256) -> Result<Option<GasCost>, Box<dyn Error + Send + Sync>> {
+            self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok((!hash.is_zero()).then_some(GasCost { gas_used: 21_000, effective_gas_price: 30_000_000_000 }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_gas_is_fetched_once_per_transaction() {
+        let inner = CountingGas::default();
+        let cached = CachedGas::new(&inner, 16);
+
+        for _ in 0..3 {
+            let cost = cached.transaction_gas(H256::repeat_byte(1)).await.unwrap().unwrap();
+            assert_eq!(cost.gas_used, 21_000);
+        }
+        assert_eq!(inner.lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A receipt that isn't known yet is asked for again
+        assert_eq!(cached.transaction_gas(H256::zero()).await.unwrap(), None);
+        assert_eq!(cached.transaction_gas(H256::zero()).await.unwrap(), None);
+        assert_eq!(inner.lookups.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_bisecting() {
+        let provider = Provider::new(LimitedNode { max_blocks: 3 });
+
+        let logs = get_logs_bisecting(&provider, &Filter::new(), 100, 119).await.unwrap();
+        let blocks: Vec<u64> = logs.iter().map(|log| log.block_number.unwrap().as_u64()).collect();
+        assert_eq!(blocks, (100..=119).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_bisecting_stops_at_one_block() {
+        let provider = Provider::new(LimitedNode { max_blocks: 0 });
+
+        let err = get_logs_bisecting(&provider, &Filter::new(), 100, 103).await.unwrap_err();
+        assert!(is_too_many_results(&err.to_string()));
+    }
+
+    // A node with two logs per block, rejecting ranges wider than `max_blocks` with a
+    // suggested range when `hint` is set, and returning at most `page_size` logs.
+    #[derive(Debug, Default)]
+    struct PagingNode {
+        max_blocks: u64,
+        hint: bool,
+        page_size: Option<usize>,
+        requests: Mutex<Vec<(u64, u64)>>,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for PagingNode {
+        type Error = HttpClientError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: std::fmt::Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            assert_eq!(method, "eth_getLogs");
+            let params = serde_json::to_value(params).unwrap();
+            let block = |key: &str| u64::from_str_radix(params[0][key].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+            let (from, to) = (block("fromBlock"), block("toBlock"));
+            self.requests.lock().unwrap().push((from, to));
+
+            if to - from + 1 > self.max_blocks {
+                let hint = if self.hint { format!(". Try with this block range [{:#x}, {:#x}].", from, from + self.max_blocks - 1) } else { String::new() };
+                return Err(HttpClientError::JsonRpcError(JsonRpcError {
+                    code: -32005,
+                    message: format!("query returned more than 10000 results{}", hint),
+                    data: None,
+                }));
+            }
+            let logs: Vec<Log> = (from..=to)
+                .flat_map(|n| (0..2u64).map(move |index| Log { block_number: Some(n.into()), log_index: Some(index.into()), ..Default::default() }))
+                .take(self.page_size.unwrap_or(usize::MAX))
+                .collect();
+            Ok(serde_json::from_value(serde_json::to_value(logs).unwrap()).unwrap())
+        }
+    }
+
+    fn block_logs(logs: &[Log]) -> Vec<(u64, u64)> {
+        logs.iter().map(|log| (log.block_number.unwrap().as_u64(), log.log_index.unwrap().as_u64())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_following_hints() {
+        let provider = Provider::new(PagingNode { max_blocks: 4, hint: true, ..PagingNode::default() });
+        let logs = get_logs_paginated(&provider, &Filter::new(), 100, 109, Pagination::Hint).await.unwrap();
+        assert_eq!(block_logs(&logs), (100..=109).flat_map(|n| [(n, 0), (n, 1)]).collect::<Vec<_>>());
+        // Each rejection is followed by the suggested range, then the rest of the range
+        assert_eq!(
+            *provider.as_ref().requests.lock().unwrap(),
+            [(100, 109), (100, 103), (104, 109), (104, 107), (108, 109)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_with_a_cursor() {
+        let provider = Provider::new(PagingNode { max_blocks: u64::MAX, page_size: Some(5), ..PagingNode::default() });
+        let logs = get_logs_paginated(&provider, &Filter::new(), 100, 104, Pagination::Cursor { page_size: 5 }).await.unwrap();
+        // Every log once, though the pages cut blocks in two
+        assert_eq!(block_logs(&logs), (100..=104).flat_map(|n| [(n, 0), (n, 1)]).collect::<Vec<_>>());
+        assert_eq!(*provider.as_ref().requests.lock().unwrap(), [(100, 104), (102, 104), (104, 104)]);
+
+        // A page filled by a single block can't be continued, the fetch fails rather than skip the rest of the block
+        let provider = Provider::new(PagingNode { max_blocks: u64::MAX, page_size: Some(2), ..PagingNode::default() });
+        let err = get_logs_paginated(&provider, &Filter::new(), 100, 101, Pagination::Cursor { page_size: 2 }).await.unwrap_err();
+        assert!(err.to_string().contains("block 100 holds at least 2 logs"), "{}", err);
+        assert_eq!(*provider.as_ref().requests.lock().unwrap(), [(100, 101)]);
+    }
+
+    #[test]
+    fn test_parse_pagination() {
+        assert_eq!("bisect".parse::<Pagination>(), Ok(Pagination::Bisect));
+        assert_eq!("Hint".parse::<Pagination>(), Ok(Pagination::Hint));
+        assert_eq!("cursor:1000".parse::<Pagination>(), Ok(Pagination::Cursor { page_size: 1000 }));
+        for invalid in ["cursor", "cursor:0", "cursor:x", "pages"] {
+            assert!(invalid.parse::<Pagination>().is_err(), "{}", invalid);
+        }
+        assert_eq!(hinted_range("Try with this block range [0x10, 0x1F]."), Some((16, 31)));
+        assert_eq!(hinted_range("this block range should work: [100, 200]"), Some((100, 200)));
+        assert_eq!(hinted_range("query returned more than 10000 results"), None);
+    }
+
+    // A node of blocks 0..=latest, block n built at second 1_000 + 12n plus a few seconds of jitter.
+    #[derive(Debug)]
+    struct TimedNode {
+        latest: u64,
+    }
+
+    impl TimedNode {
+        fn timestamp(n: u64) -> u64 {
+            1_000 + 12 * n + n % 5
+        }
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for TimedNode {
+        type Error = HttpClientError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+        where
+            T: std::fmt::Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            let result = match method {
+                "eth_blockNumber" => serde_json::to_value(ethers::types::U64::from(self.latest)).unwrap(),
+                "eth_getBlockByNumber" => {
+                    let params = serde_json::to_value(params).unwrap();
+                    let n = u64::from_str_radix(params[0].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+                    let block = (n <= self.latest).then(|| ethers::types::Block::<H256> {
+                        number: Some(n.into()),
+                        timestamp: TimedNode::timestamp(n).into(),
+                        ..Default::default()
+                    });
+                    serde_json::to_value(block).unwrap()
+                }
+                _ => panic!("unexpected request {}", method),
+            };
+            Ok(serde_json::from_value(result).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_block_at() {
+        let provider = Provider::new(TimedNode { latest: 10_000 });
+        let config = FetchConfig::default();
+
+        for timestamp in [0, 1_000, 1_001, 1_013, 50_000, 50_003, 120_000, TimedNode::timestamp(10_000)] {
+            let expected = (0..=10_000).find(|n| TimedNode::timestamp(*n) >= timestamp).unwrap();
+            assert_eq!(first_block_at(&provider, &config, timestamp).await.unwrap(), expected, "timestamp {}", timestamp);
+        }
+        // No block built yet
+        assert_eq!(first_block_at(&provider, &config, TimedNode::timestamp(10_000) + 1).await.unwrap(), 10_001);
+    }
+
+    #[test]
+    fn test_backfill_progress() {
+        let progress = BackfillProgress { from_block: 100, to_block: 499, current_block: 199, logs: 42, elapsed: Duration::from_secs(30) };
+        assert_eq!((progress.blocks_done(), progress.total_blocks()), (100, 400));
+        assert_eq!(progress.eta(), Duration::from_secs(90));
+        assert_eq!(progress.to_string(), "block 199 of 100..=499 (25.0%), 42 logs found, ETA 0h01m30s");
+
+        let done = BackfillProgress { current_block: 499, ..progress };
+        assert_eq!((done.percent(), done.eta()), (100.0, Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_feed_logs_from_a_file() {
+        let log = |block: u64, index: u64| Log {
+            block_number: Some(block.into()),
+            block_hash: Some(H256::from_low_u64_be(block)),
+            log_index: Some(index.into()),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("feed_logs_{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_string(&vec![log(100, 0), log(100, 1), log(102, 0)]).unwrap()).unwrap();
+        let recorded = read_log_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recorded.len(), 3);
+
+        let (log_tx, mut log_rx) = mpsc::channel(8);
+        assert_eq!(feed_logs(recorded, 2, log_tx).await, SourceEnd::Exhausted);
+        let mut blocks = Vec::new();
+        let mut heads = Vec::new();
+        while let Some(input) = log_rx.recv().await {
+            match input {
+                WorkerInput::Log(log) => blocks.push(log.block_number.unwrap().as_u64()),
+                WorkerInput::Writer(WriterMessage::Block(head)) => heads.push((blocks.len(), head)),
+                other => panic!("Expected a log or a head, got {:?}", other),
+            }
+        }
+        assert_eq!(blocks, [100, 100, 102]);
+        // Confirmed past the last log, behind it
+        assert_eq!(heads, [(3, 104)]);
+
+        assert!(read_log_file(&path).unwrap_err().to_string().contains("Failed to read the logs file"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_by_url_scheme() {
+        // An HTTP connection is only made on the first request
+        assert!(matches!(Connection::connect("http://localhost:8545", None).await, Ok(Connection::Http(_))));
+        assert!(matches!(Connection::connect("HTTPS://mainnet.example.com/v3/key", None).await, Ok(Connection::Http(_))));
+        let err = Connection::connect("ftp://localhost:8545", None).await.err().unwrap();
+        assert!(err.to_string().contains("must start with ws://, wss://, http:// or https://"));
+        assert!(Connection::connect("localhost:8545", None).await.is_err());
+    }
+
+    #[test]
+    fn test_block_range() {
+        // The first poll starts at the head
+        assert_eq!(block_range(None, 100, 2_000), Some((100, 100)));
+        // Following polls pick up right after the last fetched block
+        assert_eq!(block_range(Some(101), 104, 2_000), Some((101, 104)));
+        // Nothing new since the last poll
+        assert_eq!(block_range(Some(105), 104, 2_000), None);
+        // Falling behind is caught up in chunks
+        assert_eq!(block_range(Some(101), 104, 3), Some((101, 103)));
+        assert_eq!(block_range(Some(104), 104, 3), Some((104, 104)));
+    }
+
+    #[test]
+    fn test_parse_subscription() {
+        let (a, b, c) = ("0x".to_string() + &"a".repeat(40), "0x".to_string() + &"b".repeat(40), "0x".to_string() + &"c".repeat(40));
+        let subscription: Subscription = format!("{}, {}:Swap,Mint", a, b).parse().unwrap();
+        assert_eq!(subscription.addresses, vec![a.clone(), b]);
+        assert_eq!(subscription.events, vec!["Swap", "Mint"]);
+
+        assert_eq!(c.parse(), Ok(Subscription::all_events([c.as_str()])));
+        assert_eq!(format!("{}:", c).parse(), Ok(Subscription::all_events([c.as_str()])));
+        assert!(":Swap".parse::<Subscription>().is_err());
+        // Every address of the list is checked
+        let error = format!("{},0xbbb:Swap", a).parse::<Subscription>().unwrap_err();
+        assert!(error.contains("invalid address \"0xbbb\""));
+    }
+
+    #[test]
+    fn test_fetch_config_validation() {
+        assert_eq!(FetchConfig::new(12_000, 2_000, 3, 500).unwrap(), FetchConfig::default());
+        assert!(matches!(FetchConfig::new(12_000, 0, 3, 500), Err(TaskError::InvalidConfig(_))));
+        assert!(matches!(FetchConfig::new(10, 2_000, 3, 500), Err(TaskError::InvalidConfig(_))));
+        assert_eq!(FetchConfig::default().backoff(2), Duration::from_secs(2));
+    }
+}
@@ -1,25 +1,56 @@
 use ethers::{
     core::types::{Filter},
-    providers::{Provider, Ws},
+    providers::{JsonRpcClient, Provider, Ws},
     prelude::*,
-    abi::{Abi, EventExt, Detokenize},
-    utils::keccak256,
+    abi::{Abi, EventExt},
 };
 use eyre::Result;
-use dotenv::dotenv;
-use serde::{Serialize, Deserialize};
-use chrono::{Utc, NaiveDate, Datelike};
-use std::io::Write;
-use std::collections::HashMap;
-
-mod etherscan;
-mod test_sig_match;
-mod data_store;
-mod log_processing;
-mod utils;
-use crate::data_store::store_decoded_data;
-use crate::log_processing::process_log;
-use crate::utils::pretty_print_log;
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+mod cli;
+use clap::Parser;
+
+use crate::cli::{Cli, Command, StorageBackend, Transport};
+use tasks::abi::{check_events, AbiOrigin};
+use tasks::abi_sources::{fetch_abi, mainnet_sources, AbiSourceKind};
+use tasks::api_keys::{is_rate_limit_message, ApiKeys, RotatingHttp, THROTTLE_DURATION};
+use tasks::data_store::{DataStore, DecodedData, DryRunStore, EventRecord, FanOutStore, FieldNaming, FieldProjection, JsonLayout, PartitionTimezone, RetryPolicy, RetryingStore};
+#[cfg(feature = "json")]
+use tasks::data_store::{validate_date_format, JsonStore};
+#[cfg(feature = "json")]
+use tasks::replay::replay;
+#[cfg(feature = "postgres")]
+use tasks::data_store::PostgresStore;
+#[cfg(feature = "kafka")]
+use tasks::data_store::KafkaStore;
+#[cfg(feature = "parquet")]
+use tasks::data_store::ParquetStore;
+use tasks::dead_letters::DeadLetters;
+use tasks::decoders::{AnonymousEvent, DecodedEvent, DecoderRegistry, DexProtocol, TransferDecoder};
+use tasks::error::{classify, env_var, load_env, ErrorClass, TaskError};
+use tasks::log_processing::{build_event_map, list_events, parse_address, process_log};
+use tasks::block_summaries::BlockSummaryStore;
+use tasks::candles::{CandleStore, PriceQuote};
+use tasks::filters::{webhook_callback, AddressList, AmountThresholds, SwapFilter, TickBand, WhaleAlert};
+use tasks::metrics::{serve_metrics, Metrics, RunSummary, SwapRates};
+use tasks::numeraire::{VolumeSettings, VolumeStore};
+#[cfg(feature = "sse")]
+use tasks::sse::serve_sse;
+use tasks::log_sources::{feed_logs, fetch_range, first_block_at, poll_logs, read_log_file, track_head_http, track_head_ws, watch_logs, BackfillProgress, BlockTimestamps, CachedGas, CachedTimestamps, ChainHead, Connection, FetchConfig, LogSource, OfflineLookups, Pagination, SharedConnection, Subscription, TransactionGas};
+use tasks::webhook::WebhookStore;
+use tasks::pipeline::{spawn_workers, PipelineConfig, WorkerInput, Writer, WriterMessage};
+use tasks::price_bands::PriceBands;
+use tasks::rate_limit::{RateLimited, RateLimiter};
+use tasks::schemas::SchemaRegistry;
+use tasks::token_decimals::{fetch_pool_decimals, PriceQuotes};
+use tasks::utils;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
 
 
 // resources:
@@ -29,98 +60,1145 @@ use crate::utils::pretty_print_log;
 // https://www.gakonst.com/ethers-rs/subscriptions/multiple-subscriptions.html
 
 
-/// fetch_eth_logs Fetches Ethereum logs for a given contract address and processes each log.
+/// The Uniswap V3 USDC/WETH 0.05% pool, watched when no `--subscribe` is given.
+const DEFAULT_POOL_ADDRESS: &str = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+
+/// build_filter Builds the log filter of a subscription.
+///
+/// Besides the addresses, topic0 is restricted to the signature hashes of the ABI events
+/// that have a registered decoder, and are among the events of the subscription if it
+/// names any, so the provider only sends logs we are going to decode instead of every
+/// event the contracts emit.
+///
+/// # Arguments
+///
+/// * `subscription` - The contract addresses and events for which logs are to be fetched.
+/// * `abi` - The ABI containing event definitions.
+/// * `decoders` - The decoders for the events we want to receive, keyed by event name.
+///
+/// # Returns
+///
+/// The filter, or an error if an address can't be parsed or an event of the
+/// subscription has no decoder.
+fn build_filter(subscription: &Subscription, abi: &Abi, decoders: &DecoderRegistry) -> Result<Filter, Box<dyn std::error::Error>> {
+    if let Some(event) = subscription.events.iter().find(|event| !decoders.event_names().any(|name| name == event.as_str())) {
+        return Err(TaskError::InvalidConfig(format!(
+            "the subscription of {} asks for the {} event, which isn't decoded",
+            subscription.addresses.join(","),
+            event
+        ))
+        .into());
+    }
+
+    let mut topic0: Vec<H256> = decoders.event_names()
+        .filter(|event_name| subscription.events.is_empty() || subscription.events.iter().any(|event| event == event_name))
+        .filter_map(|event_name| abi.events.get(event_name))
+        .flatten()
+        .map(|event| H256::from(utils::event_signature_hash(&event.abi_signature())))
+        .collect();
+    topic0.sort();
+
+    let addresses = subscription.addresses.iter().map(|address| parse_address(address)).collect::<Result<Vec<_>, _>>()?;
+    let filter = Filter::new().topic0(topic0);
+    Ok(match addresses.as_slice() {
+        [address] => filter.address(*address),
+        _ => filter.address(addresses),
+    })
+}
+
+
+/// RpcEndpoints The node endpoints connections are made to. The URLs, when given, are
+/// used verbatim, e.g. for a self-hosted node, Alchemy or a local Anvil. Without them
+/// the connections go to Infura with the API keys.
+struct RpcEndpoints {
+    /// WebSocket URL, from `--rpc-ws-url` or `RPC_WS_URL`.
+    ws_url: Option<String>,
+    /// HTTP URL, from `--rpc-http-url` or `RPC_HTTP_URL`.
+    http_url: Option<String>,
+    /// The Infura API keys, None when neither `--api-key` nor `INFURA_API_KEY` is set.
+    api_keys: Option<Arc<ApiKeys>>,
+    /// The limiter every request takes a token of, None without `--rpc-rate-limit`.
+    rate_limit: Option<Arc<RateLimiter>>,
+}
+
+impl RpcEndpoints {
+    /// The Infura API keys, for the endpoints without a URL of their own.
+    fn api_keys(&self) -> Result<&Arc<ApiKeys>, TaskError> {
+        self.api_keys.as_ref().ok_or(TaskError::MissingEnvVar("INFURA_API_KEY"))
+    }
+}
+
+
+/// connect_provider Connects over a WebSocket to the configured URL, or else to Ethereum
+/// mainnet through Infura's endpoint with the next of the API keys.
+async fn connect_provider(endpoints: &RpcEndpoints) -> Result<Provider<RateLimited<Ws>>, Box<dyn std::error::Error>> {
+    let rate_limited = |ws| Provider::new(RateLimited::new(ws, endpoints.rate_limit.clone()));
+    if let Some(url) = &endpoints.ws_url {
+        return Ok(rate_limited(Ws::connect(url.as_str()).await?));
+    }
+    let keys = endpoints.api_keys()?;
+    // Each connection takes the next key, moving on when one is rate limited
+    let mut attempts = 0;
+    loop {
+        let (index, api_key) = keys.next_key();
+        let url: String = format!("wss://mainnet.infura.io/ws/v3/{}", api_key);
+
+        match Ws::connect(url).await {
+            Ok(ws) => return Ok(rate_limited(ws)),
+            Err(e) if is_rate_limit_message(&e.to_string()) && attempts + 1 < keys.len() => {
+                eprintln!("Warning: API key {} is rate limited, connecting with the next one", index);
+                keys.mark_throttled(index, THROTTLE_DURATION);
+                attempts += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+
+/// connect_http_provider Connects over HTTP to the configured URL, or else to Ethereum
+/// mainnet through Infura's endpoint, for when WebSocket connections aren't available.
+/// Infura requests rotate over the API keys.
+fn connect_http_provider(endpoints: &RpcEndpoints) -> Result<Provider<RateLimited<RotatingHttp>>, Box<dyn std::error::Error>> {
+    let transport = match &endpoints.http_url {
+        Some(url) => RotatingHttp::single(url)?,
+        None => RotatingHttp::new(Arc::clone(endpoints.api_keys()?), |api_key| format!("https://mainnet.infura.io/v3/{}", api_key))?,
+    };
+    Ok(Provider::new(RateLimited::new(transport, endpoints.rate_limit.clone())))
+}
+
+
+/// connect Opens the connection the logs of `source` are received over.
+async fn connect(source: LogSource, endpoints: &RpcEndpoints) -> Result<Connection, Box<dyn std::error::Error>> {
+    Ok(match source {
+        LogSource::Ws => Connection::Ws(connect_provider(endpoints).await?),
+        LogSource::Http(_) => Connection::Http(connect_http_provider(endpoints)?),
+    })
+}
+
+
+/// The delay before the first reconnect after a transient provider error, doubled on
+/// every failed reconnect in a row up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+
+/// run_session Receives the logs over one connection, running the log source and the
+/// head tracker until either ends. Over a WebSocket the blocks replaced by a reorg are
+/// fetched again as `pagination` says.
+///
+/// # Returns
+///
+/// Once the pipeline ends, or the first error of the source or the tracker, including
+/// `TaskError::Disconnected` when a subscription ends, for the caller to classify.
+#[allow(clippy::too_many_arguments)]
+async fn run_session(
+    connection: &Connection,
+    source: LogSource,
+    filters: &[Filter],
+    pagination: Pagination,
+    head: &watch::Sender<Option<ChainHead>>,
+    log_tx: mpsc::Sender<WorkerInput>,
+    next_block: &mut Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match (connection, source) {
+        (Connection::Http(provider), LogSource::Http(fetch)) => {
+            tokio::select! {
+                result = poll_logs(provider, filters, &fetch, head.subscribe(), log_tx, next_block) => result,
+                result = track_head_http(provider, &fetch, head) => result,
+            }
+        }
+        (Connection::Ws(provider), _) => {
+            let fetch = FetchConfig::default().with_pagination(pagination);
+            tokio::select! {
+                result = watch_logs(provider, filters, &fetch, log_tx) => result,
+                result = track_head_ws(provider, head) => result,
+            }
+        }
+        (Connection::Http(_), LogSource::Ws) => unreachable!("an HTTP connection is only made for LogSource::Http"),
+    }
+}
+
+
+/// resolve_api_keys The Infura API keys given with `--api-key`, or else the comma-separated
+/// list in `INFURA_API_KEY`.
+fn resolve_api_keys(cli: &Cli) -> Result<ApiKeys, TaskError> {
+    match ApiKeys::new(cli.api_keys.clone()) {
+        Some(keys) => Ok(keys),
+        None => ApiKeys::from_env("INFURA_API_KEY"),
+    }
+}
+
+
+/// tick_band The ticks given with `--min-tick` and `--max-tick`. Fails when the band is empty.
+fn tick_band(cli: &Cli) -> Result<TickBand, TaskError> {
+    if let (Some(min), Some(max)) = (cli.min_tick, cli.max_tick) {
+        if min > max {
+            return Err(TaskError::InvalidConfig(format!("--min-tick {} is above --max-tick {}", min, max)));
+        }
+    }
+    Ok(TickBand { min: cli.min_tick, max: cli.max_tick })
+}
+
+
+/// resolve_endpoints The RPC URLs given with `--rpc-ws-url` and `--rpc-http-url`, or else
+/// in `RPC_WS_URL` and `RPC_HTTP_URL`, the Infura API keys for those not given, and the
+/// `--rpc-rate-limit` shared by every connection. Missing keys are only an error once a
+/// connection needs them.
+fn resolve_endpoints(cli: &Cli) -> Result<RpcEndpoints, TaskError> {
+    let url = |flag: &Option<String>, name: &'static str| {
+        flag.clone().or_else(|| env_var(name).ok()).filter(|url| !url.trim().is_empty())
+    };
+    Ok(RpcEndpoints {
+        ws_url: url(&cli.rpc_ws_url, "RPC_WS_URL"),
+        http_url: url(&cli.rpc_http_url, "RPC_HTTP_URL"),
+        api_keys: resolve_api_keys(cli).ok().map(Arc::new),
+        rate_limit: cli.rpc_rate_limit.map(|rate| RateLimiter::new(rate, cli.rpc_burst)).transpose()?.map(Arc::new),
+    })
+}
+
+
+/// WatchOptions The settings of the `watch` command passed to `fetch_eth_logs`.
+struct WatchOptions<'a> {
+    /// How many blocks must be built on top of a log's block before its swap is stored.
+    /// With 0 swaps are stored as soon as they are decoded.
+    confirmations: u64,
+    /// Whether swap addresses are emitted in EIP-55 checksummed form.
+    checksum_addresses: bool,
+    /// Whether every Swap parameter is added to the output as `raw_params`.
+    raw_params: bool,
+    /// Whether the raw log is kept on the swaps as `raw`.
+    keep_raw: bool,
+    /// Decode only every this many swaps of each pool, 1 for all of them.
+    sample_rate: u64,
+    /// The number of decode workers and the capacity of the channels.
+    pipeline: PipelineConfig,
+    /// The events to decode, empty for every event with a decoder.
+    events: Vec<String>,
+    /// The logs decoded as anonymous events of the ABI, whatever their topic0.
+    anonymous_events: Vec<AnonymousEvent>,
+    /// The events decoded by their schema rather than a typed decoder.
+    schemas: SchemaRegistry,
+    /// The DEXes besides Uniswap whose swaps are decoded too.
+    protocols: Vec<DexProtocol>,
+    /// Whether ERC-20 transfers are decoded too, their event already in the ABI.
+    transfers: bool,
+    /// The pools and events each watched with their own filter, over one connection.
+    subscriptions: Vec<Subscription>,
+    /// Whether logs are streamed over a WebSocket or polled over HTTP.
+    source: LogSource,
+    /// How the backfills and the blocks fetched again after a reorg page through ranges
+    /// over the provider's limits, when the logs are streamed over a WebSocket. Polling
+    /// over HTTP has it in its `FetchConfig`.
+    pagination: Pagination,
+    /// Where to store OHLC candles of the stored swaps and their interval in seconds,
+    /// None to not build candles.
+    candles: Option<(&'a dyn CandleStore, u64)>,
+    /// Where to store the per-block rollups of the stored swaps, None to not build them.
+    blocks: Option<&'a dyn BlockSummaryStore>,
+    /// Where to store the numeraire volumes of the stored swaps and how to value them,
+    /// None to not build them.
+    volumes: Option<(&'a dyn VolumeStore, &'a VolumeSettings)>,
+    /// Whether to add the gas used and gas price of their transaction to the swaps.
+    gas: bool,
+    /// Whether to log a `sandwich_suspected` annotation for the likely sandwich attacks.
+    detect_sandwiches: bool,
+    /// Which decoded swaps are stored, and the whale swap callback.
+    filter: SwapFilter,
+    /// A webhook every stored swap is also POSTed to.
+    webhook: Option<&'a dyn DataStore>,
+    /// The counters updated by the decode workers.
+    metrics: Arc<Metrics>,
+    /// Where the logs that fail to decode are written, if anywhere.
+    dead_letters: Option<Arc<DeadLetters>>,
+    /// The endpoints connections are made to.
+    endpoints: &'a RpcEndpoints,
+    /// The time range whose logs are backfilled before exiting, None to follow the chain head.
+    backfill: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// Whether to report how far the backfill got after every chunk of blocks.
+    show_progress: bool,
+    /// The JSON file of recorded logs replayed instead of connecting to a node.
+    log_file: Option<PathBuf>,
+}
+
+
+/// backfill_progress Draws the progress of the backfill of `from_block..=to_block` as a bar.
+#[cfg(feature = "progress")]
+fn backfill_progress(from_block: u64, to_block: u64) -> impl FnMut(&BackfillProgress) {
+    let bar = indicatif::ProgressBar::new(to_block.saturating_sub(from_block) + 1);
+    if let Ok(style) = indicatif::ProgressStyle::with_template("{wide_bar} {percent}% {msg}") {
+        bar.set_style(style);
+    }
+    move |progress| {
+        bar.set_position(progress.blocks_done());
+        bar.set_message(format!("block {}, {} logs, ETA {}s", progress.current_block, progress.logs, progress.eta().as_secs()));
+        if progress.current_block >= progress.to_block {
+            bar.finish();
+        }
+    }
+}
+
+/// backfill_progress Prints the progress of the backfill after every chunk of blocks.
+#[cfg(not(feature = "progress"))]
+fn backfill_progress(_from_block: u64, _to_block: u64) -> impl FnMut(&BackfillProgress) {
+    |progress| println!("Backfilled to {}", progress)
+}
+
+
+/// fetch_eth_logs Fetches Ethereum logs for the subscribed contracts and processes each log.
 ///
 /// The function connects to the Ethereum network using a provider and creates
-/// a filter to fetch logs for the given contract address. Each log is then processed
-/// using the provided ABI.
+/// a filter per subscription, whose logs are merged and either streamed over a
+/// WebSocket or polled over HTTP. The log source only pushes the raw logs into a bounded
+/// channel; a pool of workers decodes them using the provided ABI and hands the swaps
+/// to a single writer, which stores them. When storage falls behind the channels fill up
+/// and the log source waits instead of growing memory.
+///
+/// Errors of the connection are sorted with `classify`: after a transient one, such as
+/// a dropped WebSocket, the source reconnects with a growing delay while the workers and
+/// the writer carry on. A fatal one, such as a rejected filter, ends the watch.
 ///
 /// # Arguments
 ///
-/// * `address` - The Ethereum contract address for which logs are to be fetched.
 /// * `abi` - The ABI containing event definitions to decode the logs.
+/// * `store` - Where the decoded swaps are stored.
+/// * `options` - How the logs are received, decoded and stored.
 ///
 /// # Returns
 ///
 /// A Result indicating the success or failure of the fetching and processing.
-async fn fetch_eth_logs(address: &str, abi: &Abi) -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    let api_key: String = std::env::var("INFURA_API_KEY").expect("INFURA_API_KEY not set");
-    let url: String = format!("wss://mainnet.infura.io/ws/v3/{}", api_key);
-
-    let provider = Provider::<Ws>::connect(url).await?;
+async fn fetch_eth_logs(abi: &Abi, store: &dyn DataStore, options: WatchOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let WatchOptions { confirmations, checksum_addresses, raw_params, keep_raw, sample_rate, pipeline, events, anonymous_events, schemas, protocols, transfers, subscriptions, source, pagination, candles, blocks, volumes, gas, detect_sandwiches, filter: swap_filter, webhook, metrics, dead_letters, endpoints, backfill, show_progress, log_file } = options;
 
-    // Specify the filter
-    let filter = Filter {
-        address: Some(vec![address.parse()?].into()),
-        ..Default::default()
+    // Recorded logs are replayed without a node, their timestamps and receipts are unknown
+    let connection = match log_file {
+        Some(_) => None,
+        None => Some(SharedConnection::new(connect(source, endpoints).await?)),
+    };
+    let (block_timestamps, transaction_gas): (&dyn BlockTimestamps, &dyn TransactionGas) = match &connection {
+        Some(connection) => (connection, connection),
+        None => (&OfflineLookups, &OfflineLookups),
     };
 
-    // Get the logs specifically for the given address
-    let mut logs_stream = provider.watch(&filter).await?;
+    let mut decoders = DecoderRegistry::default();
+    decoders.set_checksum_addresses(checksum_addresses);
+    decoders.set_raw_params(raw_params);
+    decoders.set_keep_raw(keep_raw);
+    decoders.set_sample_rate(sample_rate);
+    for protocol in protocols {
+        decoders.register_protocol(protocol);
+    }
+    if transfers {
+        decoders.register(Box::new(TransferDecoder));
+    }
+    schemas.check(abi)?;
+    decoders.register_schemas(&schemas);
+    if !events.is_empty() {
+        decoders.retain_events(&events)?;
+    }
+    for mapping in &anonymous_events {
+        decoders.register_anonymous(mapping, abi)?;
+    }
 
-    // Flag to track if the event_map has been created
-    let mut map_created = false;
-    // Create an empty HashMap to store the Keccak256 hash of event signatures as the key,
-    // and a tuple of event name and the event structure as the value.
-    let mut event_map = HashMap::new();
+    // Specify the filters, only asking for the events we have decoders for
+    let mut filters = subscriptions
+        .iter()
+        .map(|subscription| build_filter(subscription, abi, &decoders))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Anonymous events can't be told apart by topic0, every log of their contracts is asked for
+    for address in decoders.anonymous_addresses() {
+        let subscribed = subscriptions.iter().flat_map(|subscription| &subscription.addresses).any(|subscribed| parse_address(subscribed).is_ok_and(|subscribed| subscribed == address));
+        if subscribed {
+            return Err(TaskError::InvalidConfig(format!("{:?} has anonymous events and can't also be subscribed to, its logs would be received twice", address)).into());
+        }
+        filters.push(Filter::new().address(address));
+    }
 
-    loop {
-        if let Some(log) = logs_stream.next().await {
-            // println!("Mock Log:\n{:?}", pretty_print_log(&log));  // used for unit test creation
-            if !map_created {
-                // /!\ We use event.abi_signature() instead of event.signature() here.
-                // The reason is that `event.signature()` provides a human-readable format,
-                // while `event.abi_signature()` provides the human-readable ABI signature
-                // format suitable for hashing to match Ethereum's log signature standard.
-                // https://docs.rs/ethers/latest/ethers/abi/struct.Event.html
-                for (event_name, events) in &abi.events {
-                    for event in events {
-                        let event_signature_hash = keccak256(event.abi_signature().as_bytes());
-                        event_map.insert(event_signature_hash, (event_name.clone(), event.clone()));
-                    }
+    // Map the Keccak256 hash of event signatures to the event name and structure,
+    // built once and shared by every worker.
+    let event_map = Arc::new(build_event_map(abi)?);
+
+    // Raw logs flow from the log source to the workers, decoded swaps from the workers to the
+    // writer. The heads and reorgs follow the logs through the workers, to stay in order.
+    let (log_tx, log_rx) = mpsc::channel(pipeline.channel_capacity.max(1));
+    let (writer_tx, writer_rx) = mpsc::channel(pipeline.channel_capacity.max(1));
+    let workers = spawn_workers(pipeline.workers, log_rx, event_map, Arc::new(decoders), Arc::clone(&metrics), dead_letters, writer_tx);
+
+    // One task follows the chain head for the confirmations, the HTTP polling, the timestamps and the block lag
+    let (head_tx, head_rx) = watch::channel::<Option<ChainHead>>(None);
+
+    // Swaps of the same block share its timestamp, only fetch it once. The heads come with theirs.
+    let timestamps = CachedTimestamps::new(block_timestamps, CachedTimestamps::DEFAULT_CAPACITY);
+    // The swaps of a transaction share its receipt, only fetch it once
+    let gas_costs = gas.then(|| CachedGas::new(transaction_gas, CachedGas::DEFAULT_CAPACITY));
+    let mut heads = head_rx.clone();
+    let record_head_timestamps = async {
+        while heads.changed().await.is_ok() {
+            if let Some(head) = *heads.borrow_and_update() {
+                timestamps.insert(head.number, head.timestamp);
+                metrics.record_head(head.number);
+            }
+        }
+    };
+
+    // Get the logs specifically for the given address, reconnecting after transient errors
+    let logs = async {
+        // Owned here, so the workers and the writer see the channels close once the logs end
+        let (log_tx, head_tx) = (log_tx, head_tx);
+        if let Some(path) = &log_file {
+            let recorded = read_log_file(path)?;
+            println!("Replaying {} logs from {}", recorded.len(), path.display());
+            feed_logs(recorded, confirmations, log_tx).await;
+            return Ok(());
+        }
+        let Some(connection) = &connection else {
+            unreachable!("a connection is only skipped for a logs file");
+        };
+        if let Some((from_time, to_time)) = backfill {
+            let fetch = match source {
+                LogSource::Http(fetch) => fetch,
+                LogSource::Ws => FetchConfig::default().with_pagination(pagination),
+            };
+            let provider = connect_http_provider(endpoints)?;
+            let (from_block, to_block) = resolve_block_range(&provider, &fetch, from_time, to_time).await?;
+            println!("Backfilling blocks {}..={} ({} to {})", from_block, to_block, from_time.to_rfc3339(), to_time.to_rfc3339());
+            let mut report = show_progress.then(|| backfill_progress(from_block, to_block));
+            fetch_range(&provider, &filters, &fetch, from_block, to_block, log_tx.clone(), |progress| {
+                if let Some(report) = report.as_mut() {
+                    report(progress);
                 }
-                map_created = true;
+            })
+            .await?;
+            // Every block of the range is final by now, store the swaps still waiting on
+            // confirmations. The head follows the logs through the workers, after their swaps.
+            let latest = provider.get_block_number().await?.as_u64();
+            log_tx.send(WriterMessage::Block(latest).into()).await.ok();
+            return Ok(());
+        }
+        let mut next_block = None;
+        let mut delay = RECONNECT_DELAY;
+        loop {
+            let current = connection.current();
+            let started = Instant::now();
+            let session = run_session(&current, source, &filters, pagination, &head_tx, log_tx.clone(), &mut next_block);
+            let error = match session.await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if classify(error.as_ref()) == ErrorClass::Fatal {
+                eprintln!("Error: fatal provider error, not reconnecting: {}", error);
+                return Err(error);
             }
 
-            let decoded_data = process_log(log, &event_map).await?;
-            if let Some(data) = decoded_data {
-                if let Err(e) = store_decoded_data(address, &data) {
-                    eprintln!("Error storing decoded data: {}", e);
+            // A connection that held up for a while starts over with the shortest delay
+            if started.elapsed() >= MAX_RECONNECT_DELAY {
+                delay = RECONNECT_DELAY;
+            }
+            eprintln!("Warning: transient provider error, reconnecting in {:?}: {}", delay, error);
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+
+            match connect(source, endpoints).await {
+                Ok(new_connection) => connection.replace(new_connection),
+                Err(e) if classify(e.as_ref()) == ErrorClass::Transient => {
+                    eprintln!("Warning: transient error reconnecting, trying again: {}", e);
                 }
+                Err(e) => return Err(e),
+            }
+        }
+    };
+
+    // The timestamps of the heads are recorded as long as the logs are received. Ctrl-C
+    // stops receiving, the logs already received are still decoded and stored.
+    let intake = async {
+        tokio::select! {
+            result = logs => result,
+            _ = record_head_timestamps => Ok(()),
+            _ = tokio::signal::ctrl_c() => {
+                println!("Interrupted, storing the logs already received");
+                Ok(())
             }
         }
+    };
+
+    let writer = Writer {
+        store,
+        confirmations,
+        filter: swap_filter,
+        webhook,
+        candles,
+        blocks,
+        volumes,
+        detect_sandwiches,
+        price_quotes: metrics.price_quotes(),
+        timestamps: &timestamps,
+        gas: gas_costs.as_ref().map(|gas_costs| gas_costs as &dyn TransactionGas),
+        head: Some(head_rx),
+        metrics: Some(metrics.as_ref()),
+    };
+
+    let (intake_result, _) = tokio::join!(intake, writer.run(writer_rx));
+    for worker in workers {
+        worker.await?;
     }
-    Ok(())
+    intake_result
+}
+
+
+/// resolve_block_range The blocks built from `from_time` to `to_time`, both inclusive,
+/// searched for by their timestamps with `first_block_at`.
+async fn resolve_block_range<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    fetch: &FetchConfig,
+    from_time: DateTime<Utc>,
+    to_time: DateTime<Utc>,
+) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let seconds = |time: DateTime<Utc>| u64::try_from(time.timestamp()).unwrap_or(0);
+    let from_block = first_block_at(provider, fetch, seconds(from_time)).await?;
+    // The last block built at or before `to_time` is the one before the first block after it
+    let to_block = first_block_at(provider, fetch, seconds(to_time).saturating_add(1)).await?.checked_sub(1);
+    match to_block {
+        Some(to_block) if from_block <= to_block => Ok((from_block, to_block)),
+        _ => Err(format!("no blocks were built from {} to {}", from_time.to_rfc3339(), to_time.to_rfc3339()).into()),
+    }
+}
+
+
+/// decode_transaction Decodes the swaps and the other events emitted by a single transaction.
+///
+/// Instead of streaming, the transaction receipt is fetched and each of its logs
+/// is run through `process_log`, which makes it easy to compare the decoded output
+/// against Etherscan's event log view of the same transaction.
+///
+/// # Arguments
+///
+/// * `tx_hash` - The hash of the transaction to decode.
+/// * `abi` - The ABI containing event definitions to decode the logs.
+/// * `checksum_addresses` - Whether swap addresses are emitted in EIP-55 checksummed form.
+/// * `raw_params` - Whether every Swap parameter is added to the swaps as `raw_params`.
+/// * `keep_raw` - Whether the raw log is added to the swaps as `raw`.
+/// * `protocols` - The other DEXes whose swaps are decoded too, their events already in `abi`.
+/// * `endpoints` - The endpoints to connect to.
+///
+/// # Returns
+///
+/// The decoded swaps and the records of the other decoded events, e.g. the swaps of
+/// other DEXes, each in log order.
+async fn decode_transaction(tx_hash: &str, abi: &Abi, checksum_addresses: bool, raw_params: bool, keep_raw: bool, protocols: &[DexProtocol], endpoints: &RpcEndpoints) -> Result<(Vec<DecodedData>, Vec<EventRecord>), Box<dyn std::error::Error>> {
+    let provider = connect_provider(endpoints).await?;
+
+    let receipt = provider
+        .get_transaction_receipt(tx_hash.parse::<H256>()?)
+        .await?
+        .ok_or_else(|| format!("Transaction receipt not found for {}", tx_hash))?;
+
+    let event_map = build_event_map(abi)?;
+    let mut decoders = DecoderRegistry::default();
+    decoders.set_checksum_addresses(checksum_addresses);
+    decoders.set_raw_params(raw_params);
+    decoders.set_keep_raw(keep_raw);
+    for protocol in protocols {
+        decoders.register_protocol(*protocol);
+    }
+    let metrics = Metrics::default();
+
+    let mut swaps = Vec::new();
+    let mut events = Vec::new();
+    for log in receipt.logs {
+        // Logs that fail to decode have been warned about by process_log and are skipped
+        match process_log(log.clone(), &event_map, &decoders, &metrics).await {
+            Ok(Some(DecodedEvent::Swap(data))) => swaps.push(data),
+            Ok(Some(event)) => events.extend(event.event_record(&log)),
+            _ => {}
+        }
+    }
+    Ok((swaps, events))
+}
+
+
+/// check_watched_events Fails before connecting when the ABI can't decode what is watched:
+/// when it lacks one of the `--events` or of the events of a subscription, or, without
+/// `--events`, every event with a decoder.
+fn check_watched_events(abi: &Abi, events: &[String], subscriptions: &[Subscription]) -> Result<(), TaskError> {
+    for subscription in subscriptions {
+        check_events(abi, &subscription.events)?;
+    }
+    if !events.is_empty() {
+        return check_events(abi, events);
+    }
+    let decoded: Vec<String> = DecoderRegistry::default().event_names().map(String::from).collect();
+    if decoded.iter().any(|event| abi.events.contains_key(event)) {
+        return Ok(());
+    }
+    check_events(abi, &decoded)
+}
+
+
+/// watches_transfers Whether `--events` or a subscription names the ERC-20 `Transfer`,
+/// which unlike the pool events is only decoded when asked for.
+fn watches_transfers(events: &[String], subscriptions: &[Subscription]) -> bool {
+    events
+        .iter()
+        .chain(subscriptions.iter().flat_map(|subscription| &subscription.events))
+        .any(|event| event == TransferDecoder::EVENT_NAME)
+}
+
+
+/// lookup_pool_decimals Looks the token decimals of the subscribed pools that have
+/// no quote of their own in `quotes` up on chain. A pool whose lookup fails keeps the
+/// default quote.
+async fn lookup_pool_decimals(quotes: &mut PriceQuotes, subscriptions: &[Subscription], endpoints: &RpcEndpoints) {
+    let pools: Vec<&String> = subscriptions
+        .iter()
+        .flat_map(|subscription| &subscription.addresses)
+        .filter(|pool| !quotes.has_pool(pool))
+        .collect();
+    if pools.is_empty() {
+        return;
+    }
+    let provider = match connect_http_provider(endpoints) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Warning: couldn't look the token decimals up, prices are in raw units: {}", e);
+            return;
+        }
+    };
+    for pool in pools {
+        let decimals = match parse_address(pool) {
+            Ok(address) => fetch_pool_decimals(&provider, address).await,
+            Err(e) => Err(e.to_string().into()),
+        };
+        match decimals {
+            Ok(decimals) => quotes.insert(&decimals),
+            Err(e) => eprintln!("Warning: couldn't look the token decimals of {} up, its prices are in raw units: {}", pool, e),
+        }
+    }
+}
+
+
+/// spawn_metrics_summary Prints a summary of the processing counters every `interval`.
+fn spawn_metrics_summary(metrics: Arc<Metrics>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, skip the empty summary
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            println!("Metrics: {}", metrics.snapshot());
+        }
+    });
+}
+
+
+/// spawn_count_stats Prints the swaps per minute, the cumulative volume and the latest
+/// price of each pool every `interval`, for `--count-only`.
+fn spawn_count_stats(metrics: Arc<Metrics>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        let (mut previous, mut since) = (metrics.snapshot(), Instant::now());
+        loop {
+            ticker.tick().await;
+            let current = metrics.snapshot();
+            println!("{}", SwapRates { previous: &previous, current: &current, elapsed: since.elapsed() });
+            (previous, since) = (current, Instant::now());
+        }
+    });
 }
 
 
-#[tokio::main]
-async fn main() {
-    // Get the root directory
-    let root_directory = match utils::root_dir() {
-        Some(dir) => dir,
-        None => {
-            eprintln!("Error: Root directory not found");
+/// probe_finished Completes once `duration` seconds have passed, or once the logs seen
+/// span `blocks` blocks, whichever comes first. Never completes when neither is set.
+async fn probe_finished(duration: Option<u64>, blocks: Option<u64>, metrics: Arc<Metrics>) {
+    if duration.is_none() && blocks.is_none() {
+        return std::future::pending().await;
+    }
+    let deadline = duration.map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return;
+        }
+        let snapshot = metrics.snapshot();
+        if blocks.is_some_and(|blocks| snapshot.first_block > 0 && snapshot.last_block - snapshot.first_block + 1 >= blocks) {
             return;
         }
+    }
+}
+
+
+/// The stores for the decoded swaps, the candles, the numeraire volumes and the block summaries.
+type Stores = (Box<dyn DataStore>, Box<dyn CandleStore>, Box<dyn VolumeStore>, Box<dyn BlockSummaryStore>);
+
+
+/// open_store Opens the storage backend selected with `--store`, as the store for the
+/// decoded swaps, the store for the candles, the store for the numeraire volumes and the
+/// store for the block summaries.
+///
+/// Fails with `TaskError::BackendNotCompiled` when the backend's cargo feature is disabled.
+async fn open_store(backend: StorageBackend, options: StoreOptions) -> Result<Stores, TaskError> {
+    match backend {
+        #[cfg(feature = "json")]
+        StorageBackend::Json => {
+            let output_dir = match options.output_dir {
+                Some(dir) => dir,
+                None => Path::new(&utils::root_dir().ok_or(TaskError::RootDirNotFound)?).join("data"),
+            };
+            validate_date_format(&options.date_format)?;
+            if options.pretty && options.json_layout == JsonLayout::Lines {
+                return Err(TaskError::InvalidConfig("--pretty needs --json-layout array, JSON lines hold one record per line".to_string()));
+            }
+            if options.gzip && options.json_layout == JsonLayout::Array {
+                return Err(TaskError::InvalidConfig("--gzip only applies to --json-layout lines".to_string()));
+            }
+            let mut swaps = JsonStore::new(output_dir.clone(), options.partition_by_pool);
+            swaps.fields = options.fields;
+            swaps.naming = options.field_naming;
+            swaps.timezone = options.timezone;
+            swaps.date_format = options.date_format.clone();
+            swaps.gzip = options.gzip;
+            swaps.layout = options.json_layout;
+            swaps.pretty = options.pretty;
+            // Candles, volumes and block summaries are always written whole
+            let rollups = || {
+                let mut rollups = JsonStore::new(output_dir.clone(), options.partition_by_pool);
+                rollups.gzip = options.gzip;
+                rollups.timezone = options.timezone;
+                rollups.date_format = options.date_format.clone();
+                rollups.layout = options.json_layout;
+                rollups.pretty = options.pretty;
+                rollups
+            };
+            Ok((Box::new(swaps), Box::new(rollups()), Box::new(rollups()), Box::new(rollups())))
+        }
+        #[cfg(feature = "postgres")]
+        StorageBackend::Postgres => {
+            options.check_json_only()?;
+            let dsn = match options.postgres_dsn {
+                Some(dsn) => dsn,
+                None => env_var("DATABASE_URL")?,
+            };
+            let store = PostgresStore::connect(&dsn, options.postgres_batch_size)
+                .await
+                .map_err(|e| TaskError::Runtime(format!("Failed to connect to Postgres: {}", e).into()))?;
+            Ok((Box::new(store.clone()), Box::new(store.clone()), Box::new(store.clone()), Box::new(store)))
+        }
+        #[cfg(feature = "kafka")]
+        StorageBackend::Kafka => {
+            options.check_json_only()?;
+            let brokers = match options.kafka_brokers {
+                Some(brokers) => brokers,
+                None => env_var("KAFKA_BROKERS")?,
+            };
+            let store = KafkaStore::new(&brokers, options.kafka_topic, options.kafka_retries)
+                .map_err(|e| TaskError::Runtime(format!("Failed to create the Kafka producer: {}", e).into()))?;
+            Ok((Box::new(store.clone()), Box::new(store.clone()), Box::new(store.clone()), Box::new(store)))
+        }
+        #[cfg(feature = "parquet")]
+        StorageBackend::Parquet => {
+            options.check_json_only()?;
+            let output_dir = match options.output_dir {
+                Some(dir) => dir,
+                None => Path::new(&utils::root_dir().ok_or(TaskError::RootDirNotFound)?).join("data"),
+            };
+            let store = ParquetStore::new(output_dir, options.timezone, options.parquet_row_group_size);
+            Ok((Box::new(store.clone()), Box::new(store.clone()), Box::new(store.clone()), Box::new(store)))
+        }
+        #[cfg(not(feature = "json"))]
+        StorageBackend::Json => {
+            let _ = options;
+            Err(TaskError::BackendNotCompiled("json"))
+        }
+        #[cfg(not(feature = "postgres"))]
+        StorageBackend::Postgres => {
+            let _ = options;
+            Err(TaskError::BackendNotCompiled("postgres"))
+        }
+        #[cfg(not(feature = "kafka"))]
+        StorageBackend::Kafka => {
+            let _ = options;
+            Err(TaskError::BackendNotCompiled("kafka"))
+        }
+        #[cfg(not(feature = "parquet"))]
+        StorageBackend::Parquet => {
+            let _ = options;
+            Err(TaskError::BackendNotCompiled("parquet"))
+        }
+    }
+}
+
+/// open_sinks Opens every backend of `--store`, each retrying on its own and giving up to
+/// `dead_letters`, as one store writing every swap to all of them. The candles, the
+/// volumes and the block summaries are stored in the first backend only.
+async fn open_sinks(backends: &[StorageBackend], options: StoreOptions, dead_letters: Option<Arc<DeadLetters>>) -> Result<Stores, TaskError> {
+    let retry = options.retry;
+    let mut sinks: Vec<(String, Box<dyn DataStore>)> = Vec::new();
+    let mut rollups = None;
+    for (i, &backend) in backends.iter().enumerate() {
+        if backends[..i].contains(&backend) {
+            return Err(TaskError::InvalidConfig(format!("--store {} is given twice", backend.name())));
+        }
+        let (store, candle_store, volume_store, block_store) = open_store(backend, options.clone()).await?;
+        sinks.push((backend.name().to_string(), Box::new(RetryingStore::new(store, retry).with_dead_letters(dead_letters.clone()))));
+        rollups.get_or_insert((candle_store, volume_store, block_store));
+    }
+    let Some((candle_store, volume_store, block_store)) = rollups else {
+        return Err(TaskError::InvalidConfig("--store needs at least one backend".to_string()));
+    };
+    // A single sink's errors don't need its name
+    let store: Box<dyn DataStore> = match sinks.len() {
+        1 => sinks.remove(0).1,
+        _ => Box::new(FanOutStore::new(sinks)),
+    };
+    Ok((store, candle_store, volume_store, block_store))
+}
+
+/// StoreOptions The command line settings of the storage backends.
+#[derive(Clone)]
+struct StoreOptions {
+    output_dir: Option<PathBuf>,
+    partition_by_pool: bool,
+    fields: Option<FieldProjection>,
+    field_naming: FieldNaming,
+    timezone: PartitionTimezone,
+    date_format: String,
+    gzip: bool,
+    json_layout: JsonLayout,
+    pretty: bool,
+    #[cfg(feature = "postgres")]
+    postgres_dsn: Option<String>,
+    #[cfg(feature = "postgres")]
+    postgres_batch_size: usize,
+    #[cfg(feature = "kafka")]
+    kafka_brokers: Option<String>,
+    #[cfg(feature = "kafka")]
+    kafka_topic: String,
+    #[cfg(feature = "kafka")]
+    kafka_retries: u32,
+    #[cfg(feature = "parquet")]
+    parquet_row_group_size: usize,
+    retry: RetryPolicy,
+}
+
+impl StoreOptions {
+    fn from_cli(cli: &Cli) -> Result<Self, TaskError> {
+        Ok(StoreOptions {
+            output_dir: cli.output_dir.clone(),
+            partition_by_pool: cli.partition_by_pool,
+            fields: (!cli.fields.is_empty()).then(|| FieldProjection::new(&cli.fields)).transpose()?,
+            field_naming: cli.field_naming,
+            timezone: cli.timezone,
+            date_format: cli.date_format.clone(),
+            gzip: cli.gzip,
+            json_layout: cli.json_layout,
+            pretty: cli.pretty,
+            #[cfg(feature = "postgres")]
+            postgres_dsn: cli.postgres_dsn.clone(),
+            #[cfg(feature = "postgres")]
+            postgres_batch_size: cli.postgres_batch_size,
+            #[cfg(feature = "kafka")]
+            kafka_brokers: cli.kafka_brokers.clone(),
+            #[cfg(feature = "kafka")]
+            kafka_topic: cli.kafka_topic.clone(),
+            #[cfg(feature = "kafka")]
+            kafka_retries: cli.kafka_retries,
+            #[cfg(feature = "parquet")]
+            parquet_row_group_size: cli.parquet_row_group_size,
+            retry: RetryPolicy { retries: cli.store_retries, backoff_base_ms: cli.store_backoff_ms },
+        })
+    }
+
+    // Fail on the settings only the JSON files have, when writing elsewhere.
+    #[cfg(any(feature = "postgres", feature = "kafka", feature = "parquet"))]
+    fn check_json_only(&self) -> Result<(), TaskError> {
+        if self.fields.is_some() {
+            return Err(TaskError::InvalidConfig("--fields only applies to --store json".to_string()));
+        }
+        if self.field_naming != FieldNaming::Snake {
+            return Err(TaskError::InvalidConfig("--field-naming only applies to --store json".to_string()));
+        }
+        if self.gzip {
+            return Err(TaskError::InvalidConfig("--gzip only applies to --store json".to_string()));
+        }
+        if self.json_layout != JsonLayout::Lines || self.pretty {
+            return Err(TaskError::InvalidConfig("--json-layout and --pretty only apply to --store json".to_string()));
+        }
+        Ok(())
+    }
+}
+
+
+/// whale_alert Builds the whale alert configured on the command line, if any threshold is set.
+///
+/// Whale swaps are POSTed to `--whale-webhook` when given, and printed otherwise.
+fn whale_alert(cli: &Cli) -> Option<WhaleAlert> {
+    if cli.whale_amount0.is_none() && cli.whale_amount1.is_none() {
+        return None;
+    }
+    let thresholds = AmountThresholds { amount0: cli.whale_amount0, amount1: cli.whale_amount1 };
+    let callback: Box<dyn Fn(&DecodedData) + Send + Sync> = match &cli.whale_webhook {
+        Some(url) => webhook_callback(url.clone()),
+        None => Box::new(|data: &DecodedData| println!("Whale swap: {:?}", data)),
     };
+    Some(WhaleAlert::new(thresholds, callback))
+}
+
+
+/// worker_threads The thread count of the runtime: `--worker-threads`, else the
+/// `WORKER_THREADS` environment variable, else the number of CPUs available.
+fn worker_threads(cli: &Cli) -> Result<usize, TaskError> {
+    let threads = match (cli.worker_threads, std::env::var("WORKER_THREADS")) {
+        (Some(threads), _) => threads,
+        (None, Ok(threads)) => threads
+            .trim()
+            .parse()
+            .map_err(|_| TaskError::InvalidConfig(format!("WORKER_THREADS {:?} is not a number of threads", threads)))?,
+        (None, Err(_)) => std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+    };
+    if threads == 0 {
+        return Err(TaskError::InvalidConfig("the runtime needs at least one worker thread".to_string()));
+    }
+    Ok(threads)
+}
+
+
+/// Configuration problems are returned as a `TaskError`, which prints a short message
+/// and exits with a non-zero status instead of panicking.
+fn main() -> Result<(), TaskError> {
+    let cli = Cli::parse();
+    load_env(cli.env_file.as_deref())?;
+    // Warnings are shown, diagnostics such as the skipped events are off unless enabled with RUST_LOG
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::builder().with_default_directive(LevelFilter::WARN.into()).from_env_lossy())
+        .with_writer(std::io::stderr)
+        .init();
+
+    // Built by hand rather than with #[tokio::main] so the thread count can be capped
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads(&cli)?)
+        .enable_all()
+        .build()
+        .map_err(|e| TaskError::Runtime(e.into()))?;
+    runtime.block_on(run(cli))
+}
+
+/// run Runs the command given on the command line, `watch` unless another is.
+async fn run(mut cli: Cli) -> Result<(), TaskError> {
+
+    // Use the embedded pool ABI unless others are given
+    let abi_origin = AbiOrigin::from_env(&cli.abi);
+    eprintln!("Using {}", abi_origin);
+    let mut abi = abi_origin.load()?;
+    for protocol in &cli.protocol {
+        protocol.add_events(&mut abi);
+    }
+    let transfers = watches_transfers(&cli.events, &cli.subscribe);
+    if transfers {
+        TransferDecoder::add_event(&mut abi);
+    }
+
+    match cli.command.take().unwrap_or(Command::Watch) {
+        Command::Watch => {
+            #[cfg(not(feature = "sse"))]
+            if cli.sse_port.is_some() {
+                return Err(TaskError::InvalidConfig("--sse-port needs the sse feature, rebuild with `--features sse`".to_string()));
+            }
+            let endpoints = resolve_endpoints(&cli)?;
+            let whale = whale_alert(&cli);
+            let ticks = tick_band(&cli)?;
+            let webhook = cli.webhook_url
+                .as_ref()
+                .map(|url| WebhookStore::new(url, Duration::from_secs(cli.webhook_timeout), cli.webhook_retries))
+                .transpose()
+                .map_err(|e| TaskError::Runtime(e.into()))?;
+            check_watched_events(&abi, &cli.events, &cli.subscribe)?;
+            let backfill = cli.from_time.map(|from_time| (from_time, cli.to_time.unwrap_or_else(Utc::now)));
+            if let Some((from_time, to_time)) = backfill.filter(|(from_time, to_time)| from_time >= to_time) {
+                return Err(TaskError::InvalidConfig(format!(
+                    "--from-time {} must be before --to-time {}",
+                    from_time.to_rfc3339(),
+                    to_time.to_rfc3339()
+                )));
+            }
+            let subscriptions = if cli.subscribe.is_empty() {
+                vec![Subscription::all_events([DEFAULT_POOL_ADDRESS])]
+            } else {
+                cli.subscribe.clone()
+            };
 
-    // Construct the full path to abi.json using the root directory
-    let abi_path = format!("{}/src/abi.json", root_directory);
+            // Pools given with --pool-decimals use those, --decimals0/--decimals1 apply to
+            // every other pool, and without them the other pools' decimals are looked up
+            let default_quote = PriceQuote {
+                invert: cli.invert_price,
+                decimals0: cli.decimals0.unwrap_or(0),
+                decimals1: cli.decimals1.unwrap_or(0),
+            };
+            let mut price_quotes = PriceQuotes::new(default_quote, &cli.pool_decimals);
+            // Replaying a logs file never reaches a node
+            if cli.decimals0.is_none() && cli.decimals1.is_none() && cli.source.is_none() {
+                lookup_pool_decimals(&mut price_quotes, &subscriptions, &endpoints).await;
+            }
+            let metrics = Arc::new(
+                Metrics::new(price_quotes)
+                    .with_recent_swaps(cli.recent_swaps)
+                    .with_price_bands(PriceBands::new(&cli.price_band)),
+            );
+            if cli.count_only {
+                spawn_count_stats(Arc::clone(&metrics), Duration::from_secs(cli.metrics_interval.unwrap_or(60).max(1)));
+            } else if let Some(interval) = cli.metrics_interval {
+                spawn_metrics_summary(Arc::clone(&metrics), Duration::from_secs(interval.max(1)));
+            }
 
-    // Check if the file exists
-    if let Ok(abi_json) = std::fs::read_to_string(abi_path) {
-        let abi: ethers::abi::Abi = serde_json::from_str(&abi_json).unwrap();
+            // Continue with fetching Ethereum logs using the ABI
+            let store_options = StoreOptions::from_cli(&cli)?;
+            // A dry run prints what would be stored instead of storing anything, counting only doesn't even print it
+            let dry_run = cli.dry_run.then(DryRunStore::new).or_else(|| cli.count_only.then(DryRunStore::quiet));
+            // The logs that fail to decode and the swaps that fail to store share the file
+            let dead_letters = cli.dead_letters.clone().filter(|_| dry_run.is_none()).map(|path| Arc::new(DeadLetters::new(path)));
+            let volume_settings = cli.volumes
+                .map(|numeraire| VolumeSettings::new(numeraire, cli.volume_interval, cli.volume_max_age, &cli.pool_valuation))
+                .transpose()
+                .map_err(TaskError::InvalidConfig)?;
+            let (store, candle_store, volume_store, block_store): Stores = match &dry_run {
+                Some(dry_run) => (Box::new(dry_run.clone()), Box::new(dry_run.clone()), Box::new(dry_run.clone()), Box::new(dry_run.clone())),
+                None => open_sinks(&cli.store, store_options, dead_letters.clone()).await?,
+            };
+            let options = WatchOptions {
+                confirmations: cli.confirmations,
+                checksum_addresses: cli.checksum_addresses,
+                raw_params: cli.raw_params,
+                keep_raw: cli.keep_raw,
+                sample_rate: cli.sample.unwrap_or(1),
+                pipeline: PipelineConfig { workers: cli.workers, channel_capacity: cli.channel_capacity },
+                events: cli.events,
+                anonymous_events: cli.anonymous_event,
+                schemas: match &cli.schemas {
+                    Some(path) => SchemaRegistry::load(path)?,
+                    None => SchemaRegistry::builtin(),
+                },
+                protocols: cli.protocol,
+                transfers,
+                subscriptions,
+                source: match cli.transport {
+                    Transport::Ws => LogSource::Ws,
+                    Transport::Http => LogSource::Http(FetchConfig::new(
+                        cli.poll_interval.saturating_mul(1_000),
+                        cli.max_block_range,
+                        cli.max_retries,
+                        cli.backoff_base_ms,
+                    )?.with_pagination(cli.pagination)),
+                },
+                pagination: cli.pagination,
+                candles: cli.candles.filter(|_| !cli.count_only).map(|interval| (candle_store.as_ref(), interval)),
+                blocks: (cli.block_summaries && !cli.count_only).then_some(block_store.as_ref()),
+                volumes: volume_settings.as_ref().filter(|_| !cli.count_only).map(|settings| (volume_store.as_ref(), settings)),
+                gas: cli.gas,
+                detect_sandwiches: cli.detect_sandwiches,
+                filter: SwapFilter {
+                    min_amounts: AmountThresholds { amount0: cli.min_amount0, amount1: cli.min_amount1 },
+                    ticks,
+                    senders: AddressList::new(&cli.sender_allow, &cli.sender_block),
+                    recipients: AddressList::new(&cli.recipient_allow, &cli.recipient_block),
+                    whale,
+                },
+                webhook: webhook.as_ref().filter(|_| dry_run.is_none()).map(|webhook| webhook as &dyn DataStore),
+                metrics: Arc::clone(&metrics),
+                dead_letters,
+                endpoints: &endpoints,
+                backfill,
+                show_progress: !cli.no_progress,
+                log_file: cli.source.clone(),
+            };
+            // The metrics and SSE servers shut down along with the watch, however it ends
+            let (shutdown_tx, shutdown_rx) = watch::channel(());
+            let shutdown = || {
+                let mut shutdown_rx = shutdown_rx.clone();
+                async move { shutdown_rx.changed().await.ok(); }
+            };
+            let mut servers = Vec::new();
+            if let Some(port) = cli.metrics_port {
+                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                let (metrics, shutdown) = (Arc::clone(&metrics), shutdown());
+                servers.push(tokio::spawn(async move {
+                    if let Err(e) = serve_metrics(addr, metrics, shutdown).await {
+                        eprintln!("Error serving metrics: {}", e);
+                    }
+                }));
+            }
+            #[cfg(feature = "sse")]
+            if let Some(port) = cli.sse_port {
+                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                let (metrics, shutdown) = (Arc::clone(&metrics), shutdown());
+                servers.push(tokio::spawn(async move {
+                    if let Err(e) = serve_sse(addr, metrics, shutdown).await {
+                        eprintln!("Error serving the swap events: {}", e);
+                    }
+                }));
+            }
 
-        // Continue with fetching Ethereum logs using the ABI
-        let address = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
-        if let Err(err) = fetch_eth_logs(address, &abi).await {
-            eprintln!("Error: {}", err);
+            let result = tokio::select! {
+                result = fetch_eth_logs(&abi, store.as_ref(), options) => result,
+                // Nothing is stored when counting only, the watch can be dropped anywhere
+                _ = probe_finished(cli.duration, cli.blocks, Arc::clone(&metrics)) => Ok(()),
+            };
+            let _ = shutdown_tx.send(());
+            for server in servers {
+                server.await.ok();
+            }
+            if let Some(dry_run) = dry_run.filter(|_| cli.dry_run) {
+                println!("{}", dry_run);
+            }
+            // The writer has drained, however the watch ended
+            let summary = RunSummary::from(&metrics.snapshot());
+            println!("{}", summary);
+            if let Some(path) = &cli.summary_file {
+                if let Err(e) = summary.write_json(path) {
+                    eprintln!("Error writing the run summary to {}: {}", path.display(), e);
+                }
+            }
+            result?;
+        }
+        Command::Decode { tx } => {
+            let endpoints = resolve_endpoints(&cli)?;
+            let (swaps, events) = decode_transaction(&tx, &abi, cli.checksum_addresses, cli.raw_params, cli.keep_raw, &cli.protocol, &endpoints).await?;
+            for data in &swaps {
+                println!("{}", serde_json::to_string(data).map_err(|e| TaskError::Runtime(e.into()))?);
+            }
+            for record in &events {
+                println!("{}", serde_json::to_string(record).map_err(|e| TaskError::Runtime(e.into()))?);
+            }
+            println!("Decoded {} swap(s) and {} other event(s) in {}", swaps.len(), events.len(), tx);
+        }
+        Command::Events { address } => {
+            let abi = match address {
+                // The ABI sources block, keep them off the runtime's threads
+                Some(address) => tokio::task::spawn_blocking(move || {
+                    fetch_abi(&mainnet_sources(&AbiSourceKind::DEFAULT_ORDER), &address).map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| TaskError::Runtime(e.into()))?
+                .map_err(|e| TaskError::Runtime(e.into()))?,
+                None => abi,
+            };
+            for listing in list_events(&abi) {
+                println!("{}", listing);
+            }
         }
-    } else {
-        eprintln!("Error: Failed to read ABI JSON file");
+        #[cfg(feature = "json")]
+        Command::Replay { from, to, pool } => {
+            let store_options = StoreOptions::from_cli(&cli)?;
+            let retry = store_options.retry;
+            let (store, _, _, _) = open_store(to, store_options).await?;
+            let store = RetryingStore::new(store, retry);
+            let replayed = replay(&from, &store, pool.as_deref()).await.map_err(TaskError::Runtime)?;
+            println!("Replayed {} swap(s) from {}", replayed, from.display());
+        }
+        #[cfg(not(feature = "json"))]
+        Command::Replay { .. } => return Err(TaskError::BackendNotCompiled("json")),
     }
+    Ok(())
 }
 
 
@@ -134,12 +1212,12 @@ mod tests {
     use tokio::time::{timeout, Duration};
 
     #[tokio::test]
+    #[ignore = "needs INFURA_API_KEY and network access"]
     async fn test_websocket_connection() -> Result<(), Box<dyn std::error::Error>> {
         dotenv().ok();
-        let api_key: String = std::env::var("INFURA_API_KEY").expect("INFURA_API_KEY not set");
-        let url = format!("wss://mainnet.infura.io/ws/v3/{}", api_key);
+        let endpoints = resolve_endpoints(&Cli::parse_from(["tasks"]))?;
 
-        let provider = Provider::<Ws>::connect(url.clone())
+        let provider = connect_provider(&endpoints)
             .await
             .expect("Failed to connect to WebSocket provider");
 
@@ -159,9 +1237,8 @@ mod tests {
         let timeout_duration = Duration::from_secs(30);
 
         let result = timeout(timeout_duration, async {
-            while let Some(_log) = logs_stream.next().await {
+            if logs_stream.next().await.is_some() {
                 log_received = true;  // Set the flag to true once a log is received
-                break;  // Exit the loop after receiving and processing one log
             }
 
             Ok::<(), ProviderError>(())
@@ -182,5 +1259,63 @@ mod tests {
 
         Ok(())
     }
-}
 
+    #[test]
+    fn test_build_filter_topic0() {
+        let abi_json = std::fs::read_to_string("src/abi.json").unwrap();
+        let abi: Abi = serde_json::from_str(&abi_json).unwrap();
+
+        let mut decoders = DecoderRegistry::new();
+        decoders.register(Box::new(tasks::decoders::SwapDecoder));
+        decoders.register(Box::new(tasks::decoders::BurnDecoder));
+
+        let filter = build_filter(&Subscription::all_events(["0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"]), &abi, &decoders).unwrap();
+
+        // keccak256("Swap(address,address,int256,int256,uint160,uint128,int24)")
+        let swap = H256::from_str("0xc42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67").unwrap();
+        // keccak256("Burn(address,int24,int24,uint128,uint256,uint256)")
+        let burn = H256::from_str("0x0c396cd989a39f4459b5fa1aed6a9a8dcdbc45908acfd67e028cd568da98982c").unwrap();
+
+        let mut expected = vec![swap, burn];
+        expected.sort();
+        assert_eq!(filter.topics[0], Some(ValueOrArray::Array(expected.into_iter().map(Some).collect())));
+        assert_eq!(filter.address, Some(ValueOrArray::Value(H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap())));
+
+        // A subscription narrows the events and may span several pools
+        let subscription: Subscription = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640,0x8ad599c3A0ff1De082011EFDDc58f1908eb6e6D8:Swap".parse().unwrap();
+        let filter = build_filter(&subscription, &abi, &decoders).unwrap();
+        assert_eq!(filter.topics[0], Some(ValueOrArray::Array(vec![Some(swap)])));
+        assert!(matches!(filter.address, Some(ValueOrArray::Array(ref addresses)) if addresses.len() == 2));
+
+        let mints: Subscription = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:Mint".parse().unwrap();
+        assert!(build_filter(&mints, &abi, &decoders).is_err());
+    }
+
+    #[test]
+    fn test_watch_swaps_and_transfers() {
+        let cli = Cli::parse_from([
+            "tasks",
+            "--subscribe", "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:Swap",
+            "--subscribe", "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48:Transfer",
+        ]);
+        assert!(watches_transfers(&cli.events, &cli.subscribe));
+        assert!(!watches_transfers(&["Swap".to_string()], &[]));
+        assert!(watches_transfers(&["Swap".to_string(), "Transfer".to_string()], &[]));
+
+        // The pool ABI gets the ERC-20 event, once however often it is added
+        let mut abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        TransferDecoder::add_event(&mut abi);
+        TransferDecoder::add_event(&mut abi);
+        assert_eq!(abi.events["Transfer"].len(), 1);
+        check_watched_events(&abi, &cli.events, &cli.subscribe).unwrap();
+        assert!(build_event_map(&abi).is_ok());
+
+        let mut decoders = DecoderRegistry::default();
+        decoders.register(Box::new(TransferDecoder));
+        // keccak256("Transfer(address,address,uint256)")
+        let transfer = H256::from_str("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").unwrap();
+        let filter = build_filter(&cli.subscribe[1], &abi, &decoders).unwrap();
+        assert_eq!(filter.topics[0], Some(ValueOrArray::Array(vec![Some(transfer)])));
+        assert!(build_filter(&cli.subscribe[0], &abi, &decoders).is_ok());
+    }
+}
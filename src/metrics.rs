@@ -0,0 +1,779 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::data_store::{decimal_string, DecodedData};
+use crate::decoders::LiquidityData;
+use crate::liquidity::{LiquidityAggregator, PoolLiquidity};
+use crate::price_bands::{PriceBand, PriceBands};
+use crate::recent::RecentSwaps;
+use crate::token_decimals::PriceQuotes;
+
+
+/// Metrics Running counters of the logs processed by `process_log`.
+///
+/// The counters are atomics and the per-pool volumes sit behind a mutex, so the decode
+/// workers can share one `Arc<Metrics>` and update it concurrently. The latest swaps are
+/// kept in a `RecentSwaps` buffer, empty unless sized with `with_recent_swaps`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    logs_seen: AtomicU64,
+    decoded: AtomicU64,
+    skipped: AtomicU64,
+    sampled_out: AtomicU64,
+    errors: AtomicU64,
+    removed_logs: AtomicU64,
+    reorgs: AtomicU64,
+    retracted_swaps: AtomicU64,
+    // 0 until a log with a block number is seen, mainnet logs start well past block 0
+    first_block: AtomicU64,
+    last_block: AtomicU64,
+    last_stored_block: AtomicU64,
+    // 0 until the head tracker publishes a head
+    head_block: AtomicU64,
+    events: Mutex<HashMap<String, EventCounts>>,
+    pools: Mutex<HashMap<String, PoolVolume>>,
+    liquidity: Mutex<LiquidityAggregator>,
+    price_quotes: PriceQuotes,
+    price_bands: PriceBands,
+    recent: RecentSwaps,
+}
+
+// A per-pool Prometheus metric: its name, type, help text and value.
+type PoolMetric = (&'static str, &'static str, &'static str, fn(&PoolVolume) -> String);
+
+/// PoolVolume The swap count and summed absolute swap amounts of one pool, in raw units,
+/// and the price after its latest swap.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoolVolume {
+    pub swaps: u64,
+    pub amount0: u128,
+    pub amount1: u128,
+    pub last_price: f64,
+    /// Swaps priced outside the pool's `PriceBand`, 0 without one.
+    pub out_of_band: u64,
+}
+
+/// EventCounts The outcome of the logs of one event type.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EventCounts {
+    pub decoded: u64,
+    pub skipped: u64,
+    pub sampled_out: u64,
+    pub errors: u64,
+}
+
+impl EventCounts {
+    /// All the logs of the event seen.
+    pub fn seen(&self) -> u64 {
+        self.decoded + self.skipped + self.sampled_out + self.errors
+    }
+}
+
+/// EventOutcome What `process_log` did with a log of a known event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOutcome {
+    Decoded,
+    Skipped,
+    SampledOut,
+    Error,
+}
+
+/// MetricsSnapshot A copy of the counters at one point in time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Logs passed to `process_log`.
+    pub logs_seen: u64,
+    /// Logs decoded by their event's decoder.
+    pub decoded: u64,
+    /// Logs of events without a registered decoder.
+    pub skipped: u64,
+    /// Logs of decoded events left out by the sample rate.
+    pub sampled_out: u64,
+    /// Logs matching a decoded event that couldn't be decoded.
+    pub errors: u64,
+    /// Logs received with `removed` set, reorged out of the chain.
+    pub removed_logs: u64,
+    /// Chain reorgs detected from the block hashes of the logs.
+    pub reorgs: u64,
+    /// Swaps waiting for confirmations that were dropped, never stored, because their log
+    /// was removed or their block reorged out.
+    pub retracted_swaps: u64,
+    /// The lowest block number a log was seen in, 0 if none was.
+    pub first_block: u64,
+    /// The highest block number a log was seen in.
+    pub last_block: u64,
+    /// The highest block number a swap was stored from, 0 if none was.
+    pub last_stored_block: u64,
+    /// The latest chain head, 0 until one was published.
+    pub head_block: u64,
+    /// Log outcomes keyed by the name of their ABI event.
+    pub events: HashMap<String, EventCounts>,
+    /// Swap volume keyed by pool address.
+    pub pools: HashMap<String, PoolVolume>,
+    /// Net liquidity minted keyed by pool address, for the pools with Mint or Burn events.
+    pub liquidity: HashMap<String, PoolLiquidity>,
+}
+
+impl Metrics {
+    /// Counters reporting the latest price of each pool as set by its quote in
+    /// `price_quotes`. The default reports raw token1 per token0 prices.
+    pub fn new(price_quotes: PriceQuotes) -> Self {
+        Metrics { price_quotes, ..Metrics::default() }
+    }
+
+    /// How the prices of each pool are scaled and oriented.
+    pub fn price_quotes(&self) -> &PriceQuotes {
+        &self.price_quotes
+    }
+
+    /// Keep the latest `capacity` swaps, served at `/recent` by `serve_metrics`.
+    pub fn with_recent_swaps(self, capacity: usize) -> Self {
+        Metrics { recent: RecentSwaps::new(capacity), ..self }
+    }
+
+    pub fn recent_swaps(&self) -> &RecentSwaps {
+        &self.recent
+    }
+
+    /// Check the price of every swap of the pools in `price_bands` against their band.
+    pub fn with_price_bands(self, price_bands: PriceBands) -> Self {
+        Metrics { price_bands, ..self }
+    }
+
+    pub fn record_seen(&self) {
+        self.logs_seen.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_decoded(&self) {
+        self.decoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a skipped log, returning how many were skipped so far.
+    pub fn record_skipped(&self) -> u64 {
+        self.skipped.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn record_sampled_out(&self) {
+        self.sampled_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_removed_log(&self) {
+        self.removed_logs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reorg(&self) {
+        self.reorgs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count `count` pending swaps dropped by a removed log or a reorg.
+    pub fn record_retracted(&self, count: u64) {
+        self.retracted_swaps.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_block(&self, block_number: u64) {
+        self.last_block.fetch_max(block_number, Ordering::Relaxed);
+        // Never fails, the closure always returns a value
+        let _ = self.first_block.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |first| {
+            Some(if first == 0 { block_number } else { first.min(block_number) })
+        });
+    }
+
+    /// Count the outcome of a log of the ABI event `event_name`, on top of the totals,
+    /// returning how many of its logs had that outcome so far.
+    pub fn record_event(&self, event_name: &str, outcome: EventOutcome) -> u64 {
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let counts = events.entry(event_name.to_string()).or_default();
+        let count = match outcome {
+            EventOutcome::Decoded => &mut counts.decoded,
+            EventOutcome::Skipped => &mut counts.skipped,
+            EventOutcome::SampledOut => &mut counts.sampled_out,
+            EventOutcome::Error => &mut counts.errors,
+        };
+        *count += 1;
+        *count
+    }
+
+    /// Note the chain head moved to `block_number`. A reorg can move it back.
+    pub fn record_head(&self, block_number: u64) {
+        self.head_block.store(block_number, Ordering::Relaxed);
+    }
+
+    /// Note a swap of block `block_number` was written to the store.
+    pub fn record_stored(&self, block_number: u64) {
+        self.last_stored_block.fetch_max(block_number, Ordering::Relaxed);
+    }
+
+    /// Add the absolute amounts of a swap of the pool at `pool` to its volume.
+    ///
+    /// A swap priced outside the pool's band is counted, and its price and the band it
+    /// fell outside of are returned.
+    pub fn record_swap(&self, pool: &str, data: &DecodedData) -> Option<(f64, PriceBand)> {
+        let price = self.price_quotes.for_pool(pool).price(data.sqrtPriceX96);
+        let out_of_band = self.price_bands.for_pool(pool).filter(|band| !band.contains(price));
+        let mut pools = self.pools.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let volume = pools.entry(pool.to_string()).or_default();
+        volume.swaps += 1;
+        volume.amount0 = volume.amount0.saturating_add(data.amount0.unsigned_abs());
+        volume.amount1 = volume.amount1.saturating_add(data.amount1.unsigned_abs());
+        volume.last_price = price;
+        if out_of_band.is_some() {
+            volume.out_of_band += 1;
+        }
+        drop(pools);
+        self.recent.push(pool, data);
+        out_of_band.map(|band| (price, band))
+    }
+
+    /// Add a mint or burn of the pool at `pool` to its net liquidity.
+    pub fn record_liquidity(&self, pool: &str, data: &LiquidityData) {
+        self.liquidity.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(pool, data);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            logs_seen: self.logs_seen.load(Ordering::Relaxed),
+            decoded: self.decoded.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            sampled_out: self.sampled_out.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            removed_logs: self.removed_logs.load(Ordering::Relaxed),
+            reorgs: self.reorgs.load(Ordering::Relaxed),
+            retracted_swaps: self.retracted_swaps.load(Ordering::Relaxed),
+            first_block: self.first_block.load(Ordering::Relaxed),
+            last_block: self.last_block.load(Ordering::Relaxed),
+            last_stored_block: self.last_stored_block.load(Ordering::Relaxed),
+            head_block: self.head_block.load(Ordering::Relaxed),
+            events: self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+            pools: self.pools.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+            liquidity: self.liquidity.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).pools().clone(),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// How many blocks the last processed log is behind the chain head, None until both
+    /// a head and a log were seen. Growing, it means the pipeline can't keep up, though
+    /// between the swaps of a quiet pool it grows too.
+    pub fn block_lag(&self) -> Option<u64> {
+        (self.head_block > 0 && self.last_block > 0).then(|| self.head_block.saturating_sub(self.last_block))
+    }
+
+    /// Render the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, help, value) in [
+            ("swaps_logs_processed_total", "Logs passed to the decoder.", self.logs_seen),
+            ("swaps_logs_decoded_total", "Logs decoded by their event's decoder.", self.decoded),
+            ("swaps_logs_skipped_total", "Logs of events without a registered decoder.", self.skipped),
+            ("swaps_logs_sampled_out_total", "Logs of decoded events left out by the sample rate.", self.sampled_out),
+            ("swaps_logs_errors_total", "Logs that matched a decoded event but couldn't be decoded.", self.errors),
+            ("swaps_logs_removed_total", "Logs received with removed set, reorged out of the chain.", self.removed_logs),
+            ("swaps_reorgs_total", "Chain reorgs detected from the block hashes of the logs.", self.reorgs),
+            ("swaps_retracted_total", "Pending swaps dropped unstored because their log was removed or reorged out.", self.retracted_swaps),
+        ] {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} counter\n{} {}\n", name, help, name, name, value));
+        }
+        out.push_str(&format!(
+            "# HELP swaps_last_block The highest block number a log was seen in.\n# TYPE swaps_last_block gauge\nswaps_last_block {}\n",
+            self.last_block
+        ));
+        if let Some(lag) = self.block_lag() {
+            out.push_str(&format!(
+                "# HELP swaps_head_block The latest chain head.\n# TYPE swaps_head_block gauge\nswaps_head_block {}\n",
+                self.head_block
+            ));
+            out.push_str(&format!(
+                "# HELP swaps_block_lag Blocks the last processed log is behind the chain head.\n# TYPE swaps_block_lag gauge\nswaps_block_lag {}\n",
+                lag
+            ));
+        }
+
+        let mut pools: Vec<_> = self.pools.iter().collect();
+        pools.sort_by(|a, b| a.0.cmp(b.0));
+        let pool_metrics: [PoolMetric; 5] = [
+            ("swaps_pool_swaps_total", "counter", "Swaps decoded per pool.", |v| v.swaps.to_string()),
+            ("swaps_pool_volume0_total", "counter", "Summed absolute amount0 per pool, in raw units.", |v| v.amount0.to_string()),
+            ("swaps_pool_volume1_total", "counter", "Summed absolute amount1 per pool, in raw units.", |v| v.amount1.to_string()),
+            ("swaps_pool_price", "gauge", "Pool price after the latest swap.", |v| v.last_price.to_string()),
+            ("swaps_pool_price_out_of_band_total", "counter", "Swaps priced outside the pool's sanity band.", |v| v.out_of_band.to_string()),
+        ];
+        for (name, kind, help, value) in pool_metrics {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+            for (pool, volume) in &pools {
+                out.push_str(&format!("{}{{pool=\"{}\"}} {}\n", name, pool, value(volume)));
+            }
+        }
+
+        if !self.liquidity.is_empty() {
+            let name = "swaps_pool_net_liquidity";
+            out.push_str(&format!("# HELP {} Liquidity minted minus burned per pool.\n# TYPE {} gauge\n", name, name));
+            let mut liquidity: Vec<_> = self.liquidity.iter().collect();
+            liquidity.sort_by(|a, b| a.0.cmp(b.0));
+            for (pool, pool_liquidity) in liquidity {
+                out.push_str(&format!("{}{{pool=\"{}\"}} {}\n", name, pool, pool_liquidity.net_delta));
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "logs seen: {}, decoded: {}, skipped: {}, errors: {}",
+            self.logs_seen, self.decoded, self.skipped, self.errors
+        )?;
+        if self.sampled_out > 0 {
+            write!(f, ", sampled out: {}", self.sampled_out)?;
+        }
+        if self.removed_logs > 0 || self.reorgs > 0 {
+            write!(f, ", removed logs: {}, reorgs: {}, retracted swaps: {}", self.removed_logs, self.reorgs, self.retracted_swaps)?;
+        }
+        if let Some(lag) = self.block_lag() {
+            write!(f, ", head: {}, lag: {} blocks", self.head_block, lag)?;
+        }
+        let mut pools: Vec<_> = self.pools.iter().collect();
+        pools.sort_by(|a, b| a.0.cmp(b.0));
+        for (pool, volume) in pools {
+            write!(
+                f,
+                "\n  {}: {} swaps, volume0: {}, volume1: {}",
+                pool, volume.swaps, volume.amount0, volume.amount1
+            )?;
+            if volume.out_of_band > 0 {
+                write!(f, ", price out of band: {}", volume.out_of_band)?;
+            }
+        }
+        let mut liquidity: Vec<_> = self.liquidity.iter().collect();
+        liquidity.sort_by(|a, b| a.0.cmp(b.0));
+        for (pool, pool_liquidity) in liquidity {
+            write!(f, "\n  {}: {}", pool, pool_liquidity)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// SwapRates The swaps of each pool between two snapshots, as swaps per minute, along with
+/// the cumulative volume and the latest price, printed periodically by `--count-only`.
+pub struct SwapRates<'a> {
+    pub previous: &'a MetricsSnapshot,
+    pub current: &'a MetricsSnapshot,
+    /// The time between the two snapshots.
+    pub elapsed: Duration,
+}
+
+impl SwapRates<'_> {
+    /// The swaps of `pool` per minute between the snapshots.
+    pub fn per_minute(&self, pool: &str) -> f64 {
+        let current = self.current.pools.get(pool).map_or(0, |volume| volume.swaps);
+        let previous = self.previous.pools.get(pool).map_or(0, |volume| volume.swaps);
+        let minutes = self.elapsed.as_secs_f64() / 60.0;
+        if minutes > 0.0 {
+            current.saturating_sub(previous) as f64 / minutes
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for SwapRates<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stats: {} logs seen, {} decoded", self.current.logs_seen, self.current.decoded)?;
+        let mut pools: Vec<_> = self.current.pools.iter().collect();
+        pools.sort_by(|a, b| a.0.cmp(b.0));
+        for (pool, volume) in pools {
+            write!(
+                f,
+                "\n  {}: {:.1} swaps/min, {} swaps, volume0: {}, volume1: {}, price: {}",
+                pool, self.per_minute(pool), volume.swaps, volume.amount0, volume.amount1, volume.last_price
+            )?;
+        }
+        Ok(())
+    }
+}
+
+
+/// RunSummary The final report of a run, printed on shutdown and optionally written as JSON.
+///
+/// Built from a `MetricsSnapshot` taken once the writer has drained. Blocks are None
+/// when no log, or no swap for `last_stored_block`, was seen. Volumes are serialized as
+/// decimal strings like the swap amounts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+    /// Blocks from the first to the last one a log was seen in, both included.
+    pub blocks_spanned: u64,
+    pub last_stored_block: Option<u64>,
+    pub head_block: Option<u64>,
+    /// Blocks the last processed log was behind the head when the run ended.
+    pub block_lag: Option<u64>,
+    pub logs_seen: u64,
+    pub decoded: u64,
+    pub skipped: u64,
+    pub sampled_out: u64,
+    pub errors: u64,
+    pub removed_logs: u64,
+    pub reorgs: u64,
+    pub retracted_swaps: u64,
+    pub events: BTreeMap<String, EventCounts>,
+    pub pools: BTreeMap<String, PoolSummary>,
+    /// The net liquidity of the pools with Mint or Burn events.
+    pub liquidity: BTreeMap<String, LiquiditySummary>,
+}
+
+/// PoolSummary The swaps of one pool in a `RunSummary`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolSummary {
+    pub swaps: u64,
+    #[serde(with = "decimal_string")]
+    pub volume0: u128,
+    #[serde(with = "decimal_string")]
+    pub volume1: u128,
+}
+
+/// LiquiditySummary The liquidity events of one pool in a `RunSummary`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiquiditySummary {
+    pub mints: u64,
+    pub burns: u64,
+    #[serde(with = "decimal_string")]
+    pub net_delta: i128,
+}
+
+impl From<&MetricsSnapshot> for RunSummary {
+    fn from(snapshot: &MetricsSnapshot) -> Self {
+        let block = |number: u64| (number > 0).then_some(number);
+        let (first_block, last_block) = (block(snapshot.first_block), block(snapshot.last_block));
+        RunSummary {
+            first_block,
+            last_block,
+            blocks_spanned: match (first_block, last_block) {
+                (Some(first), Some(last)) => last - first + 1,
+                _ => 0,
+            },
+            last_stored_block: block(snapshot.last_stored_block),
+            head_block: block(snapshot.head_block),
+            block_lag: snapshot.block_lag(),
+            logs_seen: snapshot.logs_seen,
+            decoded: snapshot.decoded,
+            skipped: snapshot.skipped,
+            sampled_out: snapshot.sampled_out,
+            errors: snapshot.errors,
+            removed_logs: snapshot.removed_logs,
+            reorgs: snapshot.reorgs,
+            retracted_swaps: snapshot.retracted_swaps,
+            events: snapshot.events.iter().map(|(name, counts)| (name.clone(), counts.clone())).collect(),
+            pools: snapshot.pools
+                .iter()
+                .map(|(pool, volume)| {
+                    (pool.clone(), PoolSummary { swaps: volume.swaps, volume0: volume.amount0, volume1: volume.amount1 })
+                })
+                .collect(),
+            liquidity: snapshot.liquidity
+                .iter()
+                .map(|(pool, liquidity)| {
+                    (pool.clone(), LiquiditySummary { mints: liquidity.mints, burns: liquidity.burns, net_delta: liquidity.net_delta })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RunSummary {
+    /// Write the summary to `path` as pretty-printed JSON, replacing any previous one.
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n")
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run summary:")?;
+        match (self.first_block, self.last_block) {
+            (Some(first), Some(last)) => writeln!(f, "  blocks {} to {} ({} blocks)", first, last, self.blocks_spanned)?,
+            _ => writeln!(f, "  no blocks seen")?,
+        }
+        match self.last_stored_block {
+            Some(block) => writeln!(f, "  last stored block: {}", block)?,
+            None => writeln!(f, "  nothing stored")?,
+        }
+        if let (Some(head), Some(lag)) = (self.head_block, self.block_lag) {
+            writeln!(f, "  chain head: {}, lag: {} blocks", head, lag)?;
+        }
+        write!(
+            f,
+            "  logs seen: {}, decoded: {}, skipped: {}, sampled out: {}, errors: {}",
+            self.logs_seen, self.decoded, self.skipped, self.sampled_out, self.errors
+        )?;
+        if self.removed_logs > 0 || self.reorgs > 0 {
+            write!(f, "\n  removed logs: {}, reorgs: {}, retracted swaps: {}", self.removed_logs, self.reorgs, self.retracted_swaps)?;
+        }
+        for (name, counts) in &self.events {
+            write!(
+                f,
+                "\n  {}: {} seen, {} decoded, {} skipped, {} sampled out, {} errors",
+                name, counts.seen(), counts.decoded, counts.skipped, counts.sampled_out, counts.errors
+            )?;
+        }
+        for (pool, summary) in &self.pools {
+            write!(f, "\n  {}: {} swaps, volume0: {}, volume1: {}", pool, summary.swaps, summary.volume0, summary.volume1)?;
+        }
+        for (pool, summary) in &self.liquidity {
+            write!(f, "\n  {}: net liquidity {:+} ({} mints, {} burns)", pool, summary.net_delta, summary.mints, summary.burns)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// serve_metrics Serves `GET /metrics` in the Prometheus text format on `addr`, and
+/// `GET /recent`, the latest swaps as a JSON array, oldest first.
+///
+/// Spawn it on its own task so scrapes never wait on the log stream. It stops accepting
+/// connections and returns once `shutdown` completes and the open requests are answered.
+pub async fn serve_metrics(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = Arc::clone(&metrics);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = metrics_response(&request, &metrics);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    Server::try_bind(&addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+// Answer a request to the metrics server.
+fn metrics_response(request: &Request<Body>, metrics: &Metrics) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    if request.method() == Method::GET && request.uri().path() == "/metrics" {
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        *response.body_mut() = Body::from(metrics.snapshot().to_prometheus());
+    } else if request.method() == Method::GET && request.uri().path() == "/recent" {
+        match serde_json::to_string(&metrics.recent_swaps().snapshot()) {
+            Ok(json) => {
+                response.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+                *response.body_mut() = Body::from(json);
+            }
+            Err(_) => *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    } else {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+    }
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use ethers::types::U256;
+
+    #[test]
+    fn test_record_swap_volume() {
+        let metrics = Metrics::default();
+        metrics.record_seen();
+        metrics.record_seen();
+        metrics.record_decoded();
+        metrics.record_skipped();
+        metrics.record_swap("0xpool", &test_swap("", 100, -40));
+        metrics.record_swap("0xpool", &test_swap("", -50, 20));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.logs_seen, snapshot.decoded, snapshot.skipped, snapshot.errors), (2, 1, 1, 0));
+        assert_eq!(snapshot.pools["0xpool"], PoolVolume { swaps: 2, amount0: 150, amount1: 60, last_price: 0.0, out_of_band: 0 });
+    }
+
+    #[test]
+    fn test_swap_rates() {
+        let metrics = Metrics::default();
+        metrics.record_swap("0xpool", &test_swap("", 100, -40));
+        let previous = metrics.snapshot();
+        for _ in 0..3 {
+            metrics.record_swap("0xpool", &test_swap("", -50, 20));
+        }
+        let current = metrics.snapshot();
+
+        let rates = SwapRates { previous: &previous, current: &current, elapsed: Duration::from_secs(30) };
+        assert_eq!(rates.per_minute("0xpool"), 6.0);
+        assert_eq!(rates.per_minute("0xother"), 0.0);
+        assert!(rates.to_string().ends_with("0xpool: 6.0 swaps/min, 4 swaps, volume0: 250, volume1: 100, price: 0"));
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let metrics = Metrics::default();
+        for block in [18326572, 18326570, 18326575] {
+            metrics.record_block(block);
+        }
+        metrics.record_event("Swap", EventOutcome::Decoded);
+        metrics.record_event("Swap", EventOutcome::Error);
+        metrics.record_event("Mint", EventOutcome::Skipped);
+        assert_eq!(metrics.record_event("Mint", EventOutcome::Skipped), 2);
+        metrics.record_swap("0xpool", &test_swap("", -5, 7));
+        metrics.record_stored(18326572);
+
+        let summary = RunSummary::from(&metrics.snapshot());
+        assert_eq!((summary.first_block, summary.last_block, summary.blocks_spanned), (Some(18326570), Some(18326575), 6));
+        assert_eq!(summary.last_stored_block, Some(18326572));
+        assert_eq!(summary.events["Swap"], EventCounts { decoded: 1, errors: 1, ..EventCounts::default() });
+        assert_eq!(summary.events["Mint"].seen(), 2);
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["pools"]["0xpool"]["volume1"], "7");
+
+        let empty = RunSummary::from(&Metrics::default().snapshot());
+        assert_eq!((empty.first_block, empty.blocks_spanned, empty.last_stored_block), (None, 0, None));
+        assert_eq!((empty.head_block, empty.block_lag), (None, None));
+    }
+
+    #[test]
+    fn test_block_lag() {
+        let metrics = Metrics::default();
+        metrics.record_head(18326580);
+        // No log processed yet
+        assert_eq!(metrics.snapshot().block_lag(), None);
+
+        metrics.record_block(18326575);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.block_lag(), Some(5));
+        assert!(snapshot.to_string().contains(", head: 18326580, lag: 5 blocks"));
+        assert!(snapshot.to_prometheus().contains("swaps_block_lag 5\n"));
+        assert_eq!(RunSummary::from(&snapshot).block_lag, Some(5));
+
+        // A head reorged back below the last log isn't a negative lag
+        metrics.record_head(18326570);
+        assert_eq!(metrics.snapshot().block_lag(), Some(0));
+    }
+
+    #[test]
+    fn test_liquidity() {
+        use crate::decoders::LiquidityKind;
+        let metrics = Metrics::default();
+        let mint = LiquidityData {
+            kind: LiquidityKind::Mint,
+            transaction_hash: "0x11".to_string(),
+            pool: "0xpool".to_string(),
+            owner: "0xowner".to_string(),
+            tick_lower: -10,
+            tick_upper: 10,
+            amount: 1_000,
+            amount0: U256::zero(),
+            amount1: U256::zero(),
+        };
+        metrics.record_liquidity("0xpool", &mint);
+        metrics.record_liquidity("0xpool", &LiquidityData { kind: LiquidityKind::Burn, amount: 300, ..mint });
+
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.to_string().ends_with("0xpool: net liquidity +700 (1 mints, 1 burns, 1 ranges)"));
+        assert!(snapshot.to_prometheus().contains("swaps_pool_net_liquidity{pool=\"0xpool\"} 700\n"));
+        let json = serde_json::to_value(RunSummary::from(&snapshot)).unwrap();
+        assert_eq!(json["liquidity"]["0xpool"]["net_delta"], "700");
+    }
+
+    #[test]
+    fn test_price_band() {
+        use crate::price_bands::PoolPriceBand;
+        let band = PriceBand { min: 2.0, max: 3.0 };
+        let metrics = Metrics::default().with_price_bands(PriceBands::new(&[PoolPriceBand { pool: "0xpool".to_string(), band }]));
+
+        // Priced at 1, below the band
+        assert_eq!(metrics.record_swap("0xpool", &DecodedData { sqrtPriceX96: U256::one() << 96, ..test_swap("", -5, 7) }), Some((1.0, band)));
+        assert_eq!(metrics.record_swap("0xpool", &DecodedData { sqrtPriceX96: U256::from(3) << 95, ..test_swap("", -5, 7) }), None);
+        // Pools without a band aren't checked
+        assert_eq!(metrics.record_swap("0xother", &DecodedData { sqrtPriceX96: U256::one() << 96, ..test_swap("", -5, 7) }), None);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.pools["0xpool"].out_of_band, snapshot.pools["0xother"].out_of_band), (1, 0));
+        assert!(snapshot.to_string().contains("0xpool: 2 swaps, volume0: 10, volume1: 14, price out of band: 1"));
+        assert!(snapshot.to_prometheus().contains("swaps_pool_price_out_of_band_total{pool=\"0xpool\"} 1\n"));
+    }
+
+    #[test]
+    fn test_reorg_counters() {
+        let metrics = Metrics::default();
+        assert!(!RunSummary::from(&metrics.snapshot()).to_string().contains("reorgs"));
+
+        metrics.record_removed_log();
+        metrics.record_removed_log();
+        metrics.record_reorg();
+        metrics.record_retracted(3);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.removed_logs, snapshot.reorgs, snapshot.retracted_swaps), (2, 1, 3));
+        assert!(snapshot.to_string().contains(", removed logs: 2, reorgs: 1, retracted swaps: 3"));
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("swaps_logs_removed_total 2\n"));
+        assert!(text.contains("swaps_reorgs_total 1\n"));
+        assert!(text.contains("swaps_retracted_total 3\n"));
+        let summary = RunSummary::from(&snapshot);
+        assert!(summary.to_string().contains("\n  removed logs: 2, reorgs: 1, retracted swaps: 3"));
+        assert_eq!(serde_json::to_value(&summary).unwrap()["retracted_swaps"], 3);
+    }
+
+    #[test]
+    fn test_to_prometheus() {
+        let metrics = Metrics::default();
+        metrics.record_seen();
+        metrics.record_block(18326572);
+        metrics.record_block(18326570);
+        metrics.record_swap("0xpool", &DecodedData { sqrtPriceX96: U256::one() << 96, ..test_swap("", -5, 7) });
+
+        let text = metrics.snapshot().to_prometheus();
+        assert!(text.contains("# TYPE swaps_logs_processed_total counter\nswaps_logs_processed_total 1\n"));
+        assert!(text.contains("swaps_last_block 18326572\n"));
+        assert!(text.contains("swaps_pool_volume0_total{pool=\"0xpool\"} 5\n"));
+        assert!(text.contains("swaps_pool_price{pool=\"0xpool\"} 1\n"));
+    }
+
+    #[test]
+    fn test_metrics_response() {
+        let metrics = Metrics::default();
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        assert_eq!(metrics_response(&request, &metrics).status(), StatusCode::OK);
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(metrics_response(&request, &metrics).status(), StatusCode::NOT_FOUND);
+
+        let metrics = Metrics::default().with_recent_swaps(1);
+        metrics.record_swap("0xpool", &test_swap("", 1, -1));
+        let request = Request::get("/recent").body(Body::empty()).unwrap();
+        let response = metrics_response(&request, &metrics);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = tokio_test::block_on(hyper::body::to_bytes(response.into_body())).unwrap();
+        let recent: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(recent[0]["pool"], "0xpool");
+    }
+}
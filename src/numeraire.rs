@@ -0,0 +1,458 @@
+use async_trait::async_trait;
+use ethers::types::U256;
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::candles::PriceQuote;
+use crate::data_store::DecodedData;
+use crate::log_processing::parse_address;
+
+
+/// Numeraire The common unit volumes are expressed in, to compare pools whatever their tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Numeraire {
+    /// Ether, counting WETH as ether.
+    Eth,
+    /// US dollars, usually through a dollar stablecoin such as USDC.
+    Usd,
+}
+
+impl FromStr for Numeraire {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "eth" | "weth" => Ok(Numeraire::Eth),
+            "usd" => Ok(Numeraire::Usd),
+            _ => Err(format!("Invalid numeraire {:?}, expected eth or usd", s)),
+        }
+    }
+}
+
+impl fmt::Display for Numeraire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Numeraire::Eth => "ETH",
+            Numeraire::Usd => "USD",
+        })
+    }
+}
+
+
+/// PoolToken One of the two tokens of a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolToken {
+    Token0,
+    Token1,
+}
+
+
+/// Valuation How the swaps of one pool are converted into the numeraire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Valuation {
+    /// The token is the numeraire itself, e.g. WETH for `Numeraire::Eth` or USDC for
+    /// `Numeraire::Usd`: a swap's volume is its amount of that token. It is the same as
+    /// valuing the other token at the pool's own price after the swap, so no outside
+    /// price is needed, and it is never stale.
+    Native(PoolToken),
+    /// Neither token is the numeraire: a swap's volume is its amount of the token valued
+    /// at the `PriceFeed`'s price for it, e.g. the WETH of a WBTC/WETH pool at the ETH/USD
+    /// price of the USDC/WETH pool.
+    Feed(PoolToken),
+}
+
+
+/// FeedPrice The price of one whole token in the numeraire, as of `timestamp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedPrice {
+    pub price: f64,
+    /// Unix timestamp the price was observed at.
+    pub timestamp: u64,
+}
+
+/// PriceFeed Where the prices of the `Valuation::Feed` tokens come from, e.g. an oracle, an
+/// API, or the swaps of a reference pool.
+pub trait PriceFeed {
+    /// The latest price of `token` of the pool at `pool`, None when unknown.
+    fn price(&self, pool: &str, token: PoolToken) -> Option<FeedPrice>;
+}
+
+/// LatestPrices A `PriceFeed` holding the latest price set for each token, e.g. from the
+/// swaps of the USDC/WETH pool as they are decoded.
+#[derive(Debug, Clone, Default)]
+pub struct LatestPrices {
+    prices: HashMap<(String, PoolToken), FeedPrice>,
+}
+
+impl LatestPrices {
+    pub fn new() -> Self {
+        LatestPrices::default()
+    }
+
+    /// Price `token` of the pool at `pool` at `price`, observed at `timestamp`. An older
+    /// price than the one held is ignored.
+    pub fn set(&mut self, pool: &str, token: PoolToken, price: f64, timestamp: u64) {
+        let latest = self.prices.entry((pool.to_lowercase(), token)).or_insert(FeedPrice { price, timestamp });
+        if timestamp >= latest.timestamp {
+            *latest = FeedPrice { price, timestamp };
+        }
+    }
+}
+
+impl PriceFeed for LatestPrices {
+    fn price(&self, pool: &str, token: PoolToken) -> Option<FeedPrice> {
+        self.prices.get(&(pool.to_lowercase(), token)).copied()
+    }
+}
+
+
+/// NumeraireVolume The volume of one pool's swaps in one interval, in the numeraire.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumeraireVolume {
+    /// Unix timestamp of the start of the interval.
+    pub open_time: u64,
+    /// Length of the interval in seconds.
+    pub interval: u64,
+    pub numeraire: Numeraire,
+    /// The summed volume of the priced swaps, in whole units of the numeraire.
+    pub volume: f64,
+    /// The swaps counted in `volume`.
+    pub swaps: u64,
+    /// The swaps left out of `volume` for lack of a fresh enough price.
+    pub unpriced: u64,
+}
+
+
+/// VolumeStore A destination for completed numeraire volumes, separate from the swap
+/// `DataStore`.
+#[async_trait]
+pub trait VolumeStore: Send + Sync {
+    /// Store one completed interval's volume of the pool at `address`.
+    async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+
+/// PoolValuation How the swaps of one pool are valued, given as `<pool>:<token>` for a
+/// pool whose `token0` or `token1` is the numeraire itself, e.g.
+/// `0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:token0` for USDC/WETH in USD, or as
+/// `<pool>:<token>:<reference pool>` for a pool whose `token` is valued at the price of
+/// the reference pool, e.g. the WETH of a WBTC/WETH pool at the USDC/WETH price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolValuation {
+    /// The pool address, lowercase.
+    pub pool: String,
+    pub valuation: Valuation,
+    /// The pool whose swaps price the token of a `Valuation::Feed`, lowercase. It must be
+    /// valued natively itself, its other token being the one priced.
+    pub reference: Option<String>,
+}
+
+impl FromStr for PoolValuation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pool valuation {:?}, expected <pool>:<token0|token1>[:<reference pool>]", s);
+        let mut parts = s.split(':').map(str::trim);
+        let (Some(pool), Some(token), reference, None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let token = match token.to_lowercase().as_str() {
+            "token0" => PoolToken::Token0,
+            "token1" => PoolToken::Token1,
+            _ => return Err(invalid()),
+        };
+        for address in std::iter::once(pool).chain(reference) {
+            parse_address(address).map_err(|e| e.to_string())?;
+        }
+        Ok(PoolValuation {
+            pool: pool.to_lowercase(),
+            valuation: if reference.is_some() { Valuation::Feed(token) } else { Valuation::Native(token) },
+            reference: reference.map(str::to_lowercase),
+        })
+    }
+}
+
+
+/// VolumeSettings What the numeraire volumes are built of: the numeraire, the interval
+/// and staleness of `VolumeAggregator`, and the valuation of each pool. Pools without a
+/// valuation are left out of the volumes.
+#[derive(Debug, Clone)]
+pub struct VolumeSettings {
+    pub numeraire: Numeraire,
+    /// Length of the intervals in seconds.
+    pub interval: u64,
+    /// How many seconds away from a swap a feed price may have been observed.
+    pub max_age: u64,
+    valuations: HashMap<String, PoolValuation>,
+}
+
+impl VolumeSettings {
+    /// Settings valuing the pools as set by `valuations`. Fails when a pool is given
+    /// twice, or a reference pool isn't valued natively.
+    pub fn new(numeraire: Numeraire, interval: u64, max_age: u64, valuations: &[PoolValuation]) -> Result<Self, String> {
+        let mut pools = HashMap::new();
+        for valuation in valuations {
+            if pools.insert(valuation.pool.clone(), valuation.clone()).is_some() {
+                return Err(format!("Pool {} is valued twice", valuation.pool));
+            }
+        }
+        for valuation in pools.values() {
+            let Some(reference) = &valuation.reference else { continue };
+            if !matches!(pools.get(reference), Some(PoolValuation { valuation: Valuation::Native(_), .. })) {
+                return Err(format!("Reference pool {} of {} must be valued natively, as <pool>:<token>", reference, valuation.pool));
+            }
+        }
+        Ok(VolumeSettings { numeraire, interval, max_age, valuations: pools })
+    }
+
+    /// The valuation of `pool`, in any case, None when it is left out.
+    pub fn valuation(&self, pool: &str) -> Option<&PoolValuation> {
+        self.valuations.get(&pool.to_lowercase())
+    }
+
+    /// The pools valued at the price of the reference pool `pool`.
+    pub fn priced_by<'a>(&'a self, pool: &'a str) -> impl Iterator<Item = &'a PoolValuation> + 'a {
+        self.valuations.values().filter(move |valuation| valuation.reference.as_deref() == Some(pool.to_lowercase().as_str()))
+    }
+}
+
+
+/// VolumeAggregator Folds the swaps of one pool into fixed-interval volumes in a common
+/// `Numeraire`, like `CandleAggregator` folds them into candles.
+///
+/// Each swap is valued as set by the pool's `Valuation`: the amount of its numeraire
+/// token for a native pair, or else the amount of the priced token times the feed's
+/// price for it. The amounts are scaled to whole tokens with the decimals of the pool's
+/// `PriceQuote`, so pools without them are valued in raw units.
+///
+/// A feed price observed more than `max_age` seconds away from the swap's block time is
+/// stale: the swap is counted as unpriced rather than valued at an outdated price, so
+/// a feed that stopped updating shows as a growing `unpriced` count instead of skewed
+/// volumes. Swaps are bucketed on their block timestamp, and the buckets close like the
+/// candles, see `close_before`. Swaps of a bucket that was already closed are dropped.
+pub struct VolumeAggregator {
+    numeraire: Numeraire,
+    valuation: Valuation,
+    quote: PriceQuote,
+    interval: u64,
+    max_age: u64,
+    // The open buckets by open time, with the last block of their swaps
+    open: BTreeMap<u64, (NumeraireVolume, u64)>,
+    // The open time of the latest bucket opened
+    latest: Option<u64>,
+    // The open time of the last bucket closed, later swaps of it or of older buckets are too late
+    last_closed: Option<u64>,
+}
+
+impl VolumeAggregator {
+    /// An aggregator of `interval` seconds, at least one, valuing the swaps of a pool
+    /// with the decimals of `quote` as set by `valuation`.
+    pub fn new(numeraire: Numeraire, valuation: Valuation, quote: PriceQuote, interval: u64, max_age: u64) -> Self {
+        VolumeAggregator {
+            numeraire,
+            valuation,
+            quote,
+            interval: interval.max(1),
+            max_age,
+            open: BTreeMap::new(),
+            latest: None,
+            last_closed: None,
+        }
+    }
+
+    /// The volume of a swap of `pool` mined at `timestamp` in the numeraire, None when
+    /// the feed has no fresh enough price.
+    pub fn value(&self, pool: &str, timestamp: u64, data: &DecodedData, feed: &dyn PriceFeed) -> Option<f64> {
+        let amount = |token| match token {
+            PoolToken::Token0 => data.amount0_abs as f64 / 10f64.powi(i32::from(self.quote.decimals0)),
+            PoolToken::Token1 => data.amount1_abs as f64 / 10f64.powi(i32::from(self.quote.decimals1)),
+        };
+        match self.valuation {
+            Valuation::Native(token) => Some(amount(token)),
+            Valuation::Feed(token) => {
+                let price = feed.price(pool, token)?;
+                (price.timestamp.abs_diff(timestamp) <= self.max_age).then(|| amount(token) * price.price)
+            }
+        }
+    }
+
+    /// The price of the pool's other token in the numeraire after a swap leaving it at
+    /// `sqrt_price_x96`, e.g. of WETH in USD after a USDC/WETH swap, for a `PriceFeed`.
+    /// None unless the pool is valued natively, or for a zero price.
+    pub fn reference_price(&self, sqrt_price_x96: U256) -> Option<f64> {
+        // Whole token1 per whole token0, whatever the orientation of the quote
+        let price = PriceQuote { invert: false, ..self.quote }.price(sqrt_price_x96);
+        match self.valuation {
+            _ if price == 0.0 => None,
+            Valuation::Native(PoolToken::Token0) => Some(1.0 / price),
+            Valuation::Native(PoolToken::Token1) => Some(price),
+            Valuation::Feed(_) => None,
+        }
+    }
+
+    /// Add a swap of `pool` from block `block_number`, mined at `timestamp`.
+    pub fn push(&mut self, pool: &str, timestamp: u64, block_number: u64, data: &DecodedData, feed: &dyn PriceFeed) {
+        let open_time = timestamp - timestamp % self.interval;
+        if self.last_closed.is_some_and(|closed| open_time <= closed) {
+            tracing::warn!(tx_hash = %data.transaction_hash, numeraire = %self.numeraire, "Swap came in after its volume was closed");
+            return;
+        }
+        let value = self.value(pool, timestamp, data, feed);
+        self.latest = self.latest.max(Some(open_time));
+
+        let (current, last_block) = self.open.entry(open_time).or_insert_with(|| {
+            let volume = NumeraireVolume {
+                open_time,
+                interval: self.interval,
+                numeraire: self.numeraire,
+                volume: 0.0,
+                swaps: 0,
+                unpriced: 0,
+            };
+            (volume, block_number)
+        });
+        *last_block = (*last_block).max(block_number);
+        match value {
+            Some(value) => {
+                current.volume += value;
+                current.swaps += 1;
+            }
+            None => current.unpriced += 1,
+        }
+    }
+
+    /// Take the volumes complete once the chain head is at `latest_block`, oldest first,
+    /// like `CandleAggregator::close_before`: those a later bucket was opened after,
+    /// whose last block the head is more than one block past.
+    pub fn close_before(&mut self, latest_block: u64) -> Vec<NumeraireVolume> {
+        let Some(latest) = self.latest else { return Vec::new() };
+        let mut closed = Vec::new();
+        while let Some(entry) = self.open.first_entry() {
+            let (_, last_block) = entry.get();
+            if *entry.key() >= latest || last_block.saturating_add(1) >= latest_block {
+                break;
+            }
+            self.last_closed = Some(*entry.key());
+            closed.push(entry.remove().0);
+        }
+        closed
+    }
+
+    /// Take every open interval's volume, e.g. on shutdown, oldest first.
+    pub fn flush(&mut self) -> Vec<NumeraireVolume> {
+        if let Some(&open_time) = self.open.keys().next_back() {
+            self.last_closed = Some(open_time);
+        }
+        std::mem::take(&mut self.open).into_values().map(|(volume, _)| volume).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+
+    const POOL: &str = "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed";
+
+    #[test]
+    fn test_native_volume() {
+        // USDC/WETH valued in USD through its USDC side, with 1,574.6 USDC for 1 WETH
+        let quote = PriceQuote { invert: false, decimals0: 6, decimals1: 18 };
+        let mut volumes = VolumeAggregator::new(Numeraire::Usd, Valuation::Native(PoolToken::Token0), quote, 60, 0);
+        let feed = LatestPrices::new();
+
+        volumes.push(POOL, 120, 10, &test_swap("", 1_574_600_000, -(10i128.pow(18))), &feed);
+        volumes.push(POOL, 180, 12, &test_swap("", 1_000_000, 0), &feed);
+        // Came in late, the 120 bucket is still open
+        volumes.push(POOL, 150, 11, &test_swap("", 500_000, -(10i128.pow(15))), &feed);
+
+        // The head must be more than one block past block 11
+        assert_eq!(volumes.close_before(12), vec![]);
+        let closed = volumes.close_before(13);
+        assert_eq!(closed.len(), 1);
+        let volume = &closed[0];
+        assert_eq!((volume.open_time, volume.numeraire, volume.swaps, volume.unpriced), (120, Numeraire::Usd, 2, 0));
+        assert!((volume.volume - 1_575.1).abs() < 1e-9);
+
+        // Late, dropped
+        volumes.push(POOL, 60, 9, &test_swap("", 1, -1), &feed);
+        assert_eq!(volumes.flush().iter().map(|volume| volume.volume).collect::<Vec<_>>(), vec![1.0]);
+    }
+
+    #[test]
+    fn test_feed_volume_and_staleness() {
+        // WBTC/WETH valued in USD through its WETH side, at the ETH price of another pool
+        let quote = PriceQuote { invert: false, decimals0: 8, decimals1: 18 };
+        let mut volumes = VolumeAggregator::new(Numeraire::Usd, Valuation::Feed(PoolToken::Token1), quote, 60, 30);
+        let mut feed = LatestPrices::new();
+
+        // No price yet
+        assert_eq!(volumes.value(POOL, 100, &test_swap("", 1, -(10i128.pow(18))), &feed), None);
+        feed.set(POOL, PoolToken::Token1, 2_000.0, 100);
+        // An older price doesn't replace the latest
+        feed.set(&POOL.to_uppercase(), PoolToken::Token1, 1.0, 90);
+        assert_eq!(volumes.value(POOL, 120, &test_swap("", 1, -(10i128.pow(18)) / 2), &feed), Some(1_000.0));
+        // 31 seconds away from the price, stale
+        assert_eq!(volumes.value(POOL, 131, &test_swap("", 1, -(10i128.pow(18))), &feed), None);
+
+        volumes.push(POOL, 120, 10, &test_swap("", 1, -(10i128.pow(18)) / 2), &feed);
+        volumes.push(POOL, 125, 10, &test_swap("", 1, -(10i128.pow(18))), &feed);
+        feed.set(POOL, PoolToken::Token1, 2_000.0, 10_000);
+        volumes.push(POOL, 130, 11, &test_swap("", 1, -(10i128.pow(18))), &feed);
+        let volume = volumes.flush().remove(0);
+        assert_eq!((volume.volume, volume.swaps, volume.unpriced), (3_000.0, 2, 1));
+    }
+
+    #[test]
+    fn test_reference_price() {
+        // sqrtPriceX96 after the Swap in tx 0x13f84c56...00f3 of the USDC/WETH pool
+        let sqrt_price_x96 = U256::from(1996611740862433600358475292128498u128);
+        let quote = PriceQuote { invert: true, decimals0: 6, decimals1: 18 };
+
+        // The pool is valued through USDC, so it prices WETH in USD
+        let usdc_weth = VolumeAggregator::new(Numeraire::Usd, Valuation::Native(PoolToken::Token0), quote, 60, 0);
+        assert!((usdc_weth.reference_price(sqrt_price_x96).unwrap() - 1574.606).abs() < 1e-3);
+        assert_eq!(usdc_weth.reference_price(U256::zero()), None);
+
+        // Through WETH, it prices USDC in ETH
+        let in_eth = VolumeAggregator::new(Numeraire::Eth, Valuation::Native(PoolToken::Token1), quote, 60, 0);
+        assert!((in_eth.reference_price(sqrt_price_x96).unwrap() - 0.000635079).abs() < 1e-9);
+
+        let fed = VolumeAggregator::new(Numeraire::Usd, Valuation::Feed(PoolToken::Token1), quote, 60, 0);
+        assert_eq!(fed.reference_price(sqrt_price_x96), None);
+    }
+
+    #[test]
+    fn test_parse_pool_valuation() {
+        const WBTC_WETH: &str = "0xcbcdf9626bc03e24f779434178a73a0b4bad62ed";
+        const USDC_WETH: &str = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+        let native: PoolValuation = format!("{}:token0", USDC_WETH).parse().unwrap();
+        assert_eq!(native, PoolValuation { pool: USDC_WETH.to_lowercase(), valuation: Valuation::Native(PoolToken::Token0), reference: None });
+        let fed: PoolValuation = format!("{}:Token1:{}", WBTC_WETH, USDC_WETH).parse().unwrap();
+        assert_eq!((fed.valuation, fed.reference.as_deref()), (Valuation::Feed(PoolToken::Token1), Some(USDC_WETH.to_lowercase().as_str())));
+        assert!(format!("{}:token2", USDC_WETH).parse::<PoolValuation>().is_err());
+        assert!("0xpool:token0".parse::<PoolValuation>().is_err());
+
+        let settings = VolumeSettings::new(Numeraire::Usd, 60, 30, &[native.clone(), fed.clone()]).unwrap();
+        assert_eq!(settings.valuation(USDC_WETH), Some(&native));
+        assert_eq!(settings.priced_by(USDC_WETH).collect::<Vec<_>>(), vec![&fed]);
+        // The reference pool must be valued itself, natively
+        assert!(VolumeSettings::new(Numeraire::Usd, 60, 30, std::slice::from_ref(&fed)).is_err());
+        assert!(VolumeSettings::new(Numeraire::Usd, 60, 30, &[native.clone(), native]).is_err());
+    }
+
+    #[test]
+    fn test_parse_numeraire() {
+        assert_eq!("ETH".parse::<Numeraire>(), Ok(Numeraire::Eth));
+        assert_eq!("weth".parse::<Numeraire>(), Ok(Numeraire::Eth));
+        assert_eq!("usd".parse::<Numeraire>(), Ok(Numeraire::Usd));
+        assert!("btc".parse::<Numeraire>().is_err());
+        assert_eq!(serde_json::to_value(Numeraire::Usd).unwrap(), "usd");
+    }
+}
@@ -0,0 +1,1062 @@
+use ethers::abi::Event;
+use ethers::types::{Log, H256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::block_summaries::{BlockAggregator, BlockSummary, BlockSummaryStore};
+use crate::candles::{Candle, CandleAggregator, CandleStore};
+use crate::confirmations::{PendingBuffer, SeenLogs};
+use crate::data_store::{DataStore, DecodedData, EventRecord};
+use crate::dead_letters::DeadLetters;
+use crate::decoders::{DecodedEvent, DecoderRegistry};
+use crate::filters::SwapFilter;
+use crate::log_processing::process_log_sync;
+use crate::log_sources::{BlockTimestamps, ChainHead, TransactionGas};
+use crate::metrics::Metrics;
+use crate::numeraire::{LatestPrices, NumeraireVolume, Valuation, VolumeAggregator, VolumeSettings, VolumeStore};
+use crate::sandwich::{SandwichDetector, SandwichSuspect};
+use crate::token_decimals::PriceQuotes;
+
+
+/// PipelineConfig Sizes the decode pipeline between the log stream and storage.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    /// Number of worker tasks decoding logs concurrently.
+    pub workers: usize,
+    /// Capacity of the bounded channels feeding the workers and the writer. Once full,
+    /// the stream loop waits for room instead of buffering logs without limit.
+    pub channel_capacity: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig { workers: 4, channel_capacity: 1024 }
+    }
+}
+
+
+/// What the workers hand to the writer task: the swaps they decode, and the messages of
+/// the log source passed on in order with them.
+// Swaps far outnumber the other messages, boxing them would cost an allocation each
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum WriterMessage {
+    /// A swap decoded from `log`, ready to be stored (or buffered until confirmed).
+    Swap(Log, DecodedData),
+    /// Another event decoded from `log`, e.g. a token transfer, stored like the swaps
+    /// but without their filters and aggregates.
+    Event(Log, EventRecord),
+    /// `log` was reorged out, its swap must not be stored.
+    Removed(Log),
+    /// The chain head moved to this block number, confirmed swaps can be stored.
+    Block(u64),
+    /// The chain reorged from this block number on, the swaps pending from these blocks
+    /// must not be stored. The new chain's logs are sent again.
+    Reorg(u64),
+}
+
+
+/// What the log source hands to the workers: a raw log to decode, or a message for the
+/// writer. A message is passed on once the logs sent before it are decoded, and before
+/// any log sent after it, so the writer gets it in order with the swaps.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum WorkerInput {
+    Log(Log),
+    Writer(WriterMessage),
+}
+
+impl From<Log> for WorkerInput {
+    fn from(log: Log) -> Self {
+        WorkerInput::Log(log)
+    }
+}
+
+impl From<WriterMessage> for WorkerInput {
+    fn from(message: WriterMessage) -> Self {
+        WorkerInput::Writer(message)
+    }
+}
+
+
+/// spawn_workers Spawns the worker tasks decoding raw logs into swaps.
+///
+/// The workers share `inputs`, so each log is decoded by exactly one of them, and send
+/// every decoded swap, and every other decoded event as an `EventRecord`, to `output`.
+/// The writer messages of `inputs` are sent to `output` in order with the swaps: after
+/// the swaps of every log received before them, before those of any log received after.
+/// The workers exit once `inputs` is closed and drained, or once the writer has gone
+/// away.
+///
+/// # Arguments
+///
+/// * `workers` - How many worker tasks to spawn, at least one is always spawned.
+/// * `inputs` - The raw logs and writer messages pushed by the stream loop.
+/// * `event_map` - The ABI event definitions keyed by their signature hash.
+/// * `decoders` - The decoders for the events we want to decode, keyed by event name.
+/// * `metrics` - The counters shared by the workers.
+/// * `dead_letters` - Where the logs that fail to decode are written, if anywhere.
+/// * `output` - Where the decoded swaps and the writer messages are sent.
+///
+/// # Returns
+///
+/// The handles of the spawned tasks.
+pub fn spawn_workers(
+    workers: usize,
+    inputs: mpsc::Receiver<WorkerInput>,
+    event_map: Arc<HashMap<[u8; 32], (String, Event)>>,
+    decoders: Arc<DecoderRegistry>,
+    metrics: Arc<Metrics>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    output: mpsc::Sender<WriterMessage>,
+) -> Vec<JoinHandle<()>> {
+    let inputs = Arc::new(Mutex::new(inputs));
+    // Held shared while a log is decoded, and exclusively to pass a writer message on
+    // once every log received before it is done
+    let in_flight = Arc::new(RwLock::new(()));
+
+    (0..workers.max(1))
+        .map(|_| {
+            let inputs = Arc::clone(&inputs);
+            let in_flight = Arc::clone(&in_flight);
+            let event_map = Arc::clone(&event_map);
+            let decoders = Arc::clone(&decoders);
+            let metrics = Arc::clone(&metrics);
+            let dead_letters = dead_letters.clone();
+            let output = output.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    // Only hold the lock while waiting for the next input, so decoding runs in parallel
+                    let mut receiver = inputs.lock().await;
+                    let Some(input) = receiver.recv().await else { break };
+                    let log = match input {
+                        WorkerInput::Log(log) => log,
+                        WorkerInput::Writer(message) => {
+                            // No later log is taken until the message is passed on
+                            let _done = in_flight.write().await;
+                            if output.send(message).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+                    let _decoding = in_flight.read().await;
+                    drop(receiver);
+
+                    let decoded_event = process_log_sync(log.clone(), &event_map, &decoders, &metrics).map_err(|e| e.to_string());
+                    match decoded_event {
+                        Ok(Some(DecodedEvent::Swap(data))) => {
+                            if output.send(WriterMessage::Swap(log, data)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Some(event)) => {
+                            let Some(record) = event.event_record(&log) else { continue };
+                            if output.send(WriterMessage::Event(log, record)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => {}
+                        // The failure was already counted and warned about by process_log_sync
+                        Err(reason) => {
+                            if let Some(dead_letters) = &dead_letters {
+                                if let Err(e) = dead_letters.append(&log, &reason).await {
+                                    tracing::error!(error = %e, "Failed to write the dead letter");
+                                }
+                            }
+                        }
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+
+/// Writer The single task storing the swaps handed over by the workers.
+///
+/// Swaps are filtered, held back until `confirmations` blocks are built on top of them,
+/// then written to `store`, POSTed to `webhook` and folded into candles, block summaries
+/// and numeraire volumes, in the order the writer receives them. Each swap is stored
+/// under its own `pool`, and the rollups are built per pool. A recent log received
+/// twice is only stored once, see `SeenLogs`. The other decoded events are held back
+/// the same way, then stored with `DataStore::store_event`.
+pub struct Writer<'a> {
+    /// Where the decoded swaps are stored.
+    pub store: &'a dyn DataStore,
+    /// How many blocks must be built on top of a log's block before its swap is stored.
+    /// With 0 swaps are stored as soon as they are decoded.
+    pub confirmations: u64,
+    /// Which decoded swaps are stored, and the whale swap callback.
+    pub filter: SwapFilter,
+    /// A webhook every stored swap is also queued for, flushed once the messages end.
+    pub webhook: Option<&'a dyn DataStore>,
+    /// Where to store OHLC candles of the stored swaps and their interval in seconds,
+    /// None to not build candles.
+    pub candles: Option<(&'a dyn CandleStore, u64)>,
+    /// Where to store the per-block rollups of the stored swaps, None to not build them.
+    pub blocks: Option<&'a dyn BlockSummaryStore>,
+    /// Where to store the volumes of the stored swaps in a common numeraire and how to
+    /// value them, None to not build them.
+    pub volumes: Option<(&'a dyn VolumeStore, &'a VolumeSettings)>,
+    /// Whether to look for sandwich attacks among the decoded swaps, printing a
+    /// `sandwich_suspected` annotation for each one found.
+    pub detect_sandwiches: bool,
+    /// How the candle and block summary prices of each pool are scaled and oriented.
+    pub price_quotes: &'a PriceQuotes,
+    /// Looks up the block timestamps the candles and volumes are bucketed on.
+    pub timestamps: &'a dyn BlockTimestamps,
+    /// Looks up the gas cost of the swaps' transactions, added to each stored swap.
+    /// None to leave the gas fields out and save the receipt lookups.
+    pub gas: Option<&'a dyn TransactionGas>,
+    /// The chain heads published by the head tracker, which confirm the pending swaps
+    /// like `WriterMessage::Block` does. None when only the messages carry the head.
+    pub head: Option<watch::Receiver<Option<ChainHead>>>,
+    /// The counters noting the last block a swap was stored from, for the run summary.
+    pub metrics: Option<&'a Metrics>,
+}
+
+impl<'a> Writer<'a> {
+    /// Writer::new A writer storing every decoded swap into `store` as soon as it comes
+    /// in, with no other sink. The other fields are set with struct update syntax.
+    pub fn new(store: &'a dyn DataStore, price_quotes: &'a PriceQuotes, timestamps: &'a dyn BlockTimestamps) -> Self {
+        Writer {
+            store,
+            confirmations: 0,
+            filter: SwapFilter::default(),
+            webhook: None,
+            candles: None,
+            blocks: None,
+            volumes: None,
+            detect_sandwiches: false,
+            price_quotes,
+            timestamps,
+            gas: None,
+            head: None,
+            metrics: None,
+        }
+    }
+
+    /// Store the swaps received on `messages` until every sender is gone, i.e. the log
+    /// source ended and the workers are done. Storage errors are logged, not returned.
+    pub async fn run(self, mut messages: mpsc::Receiver<WriterMessage>) {
+        // Swaps waiting for enough confirmations, with their log index, flushed as new blocks come in.
+        let mut pending: PendingBuffer<(u64, DecodedData)> = PendingBuffer::new(self.confirmations);
+        // The other events waiting for enough confirmations.
+        let mut pending_events: PendingBuffer<EventRecord> = PendingBuffer::new(self.confirmations);
+        // The latest logs received, to not store a log delivered twice.
+        let mut seen = SeenLogs::new(SeenLogs::DEFAULT_CAPACITY);
+        // The candles, block summaries and volumes of each pool being built from its stored swaps.
+        let mut rollups = Rollups::default();
+        // The swaps of the latest blocks, when looking for sandwiches.
+        let mut sandwiches = self.detect_sandwiches.then(SandwichDetector::new);
+
+        let mut head = self.head.clone();
+
+        loop {
+            let message = tokio::select! {
+                // Take in the swaps already sent before a newer head confirms past them
+                biased;
+                message = messages.recv() => message,
+                Some(latest_block) = next_head(&mut head) => Some(WriterMessage::Block(latest_block)),
+            };
+            // Apply a head published right before the stream ended
+            let Some(message) = message.or_else(|| unseen_head(&mut head).map(WriterMessage::Block)) else { break };
+            let latest_block = match message {
+                WriterMessage::Block(latest_block) => Some(latest_block),
+                _ => None,
+            };
+
+            let confirmed = match message {
+                WriterMessage::Swap(log, data) => {
+                    if !seen.insert(&log) {
+                        continue;
+                    }
+                    // Sandwiches are looked for among every swap, a filtered out one can be the victim
+                    if let Some(sandwiches) = sandwiches.as_mut() {
+                        sandwiches.push(&log, &data);
+                    }
+                    // Drop the swaps below the thresholds and report the whales
+                    if !self.filter.apply(&data) {
+                        continue;
+                    }
+                    let log_index = log.log_index.map(|index| index.low_u64()).unwrap_or_default();
+                    if self.confirmations > 0 {
+                        pending.push(&log, (log_index, data));
+                        continue;
+                    }
+                    let block_number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+                    vec![(block_number, (log_index, data))]
+                }
+                WriterMessage::Event(log, record) => {
+                    if !seen.insert(&log) {
+                        continue;
+                    }
+                    if self.confirmations > 0 {
+                        pending_events.push(&log, record);
+                    } else {
+                        self.store_event(&record).await;
+                    }
+                    continue;
+                }
+                WriterMessage::Removed(log) => {
+                    let retracted = pending.remove(&log);
+                    pending_events.remove(&log);
+                    if let Some(metrics) = self.metrics {
+                        metrics.record_removed_log();
+                        metrics.record_retracted(u64::from(retracted));
+                    }
+                    seen.forget(&log);
+                    if let Some(sandwiches) = sandwiches.as_mut() {
+                        sandwiches.remove(&log);
+                    }
+                    continue;
+                }
+                WriterMessage::Reorg(first_block) => {
+                    // Forget the dropped swaps too, they come back if the new chain includes them
+                    let retracted = pending.remove_from(first_block);
+                    if let Some(metrics) = self.metrics {
+                        metrics.record_reorg();
+                        metrics.record_retracted(retracted.len() as u64);
+                    }
+                    for key in retracted.into_iter().chain(pending_events.remove_from(first_block)) {
+                        seen.forget_key(key);
+                    }
+                    if let Some(sandwiches) = sandwiches.as_mut() {
+                        sandwiches.remove_from(first_block);
+                    }
+                    if self.confirmations == 0 {
+                        tracing::warn!(first_block, "Swaps from the block on may have been reorged out, but were already stored");
+                    }
+                    continue;
+                }
+                WriterMessage::Block(latest_block) => {
+                    for (_, record) in pending_events.drain_confirmed(latest_block) {
+                        self.store_event(&record).await;
+                    }
+                    pending.drain_confirmed(latest_block)
+                }
+            };
+
+            for (block_number, (log_index, mut data)) in confirmed {
+                self.add_gas(&mut data).await;
+                self.store_swap(block_number, log_index, &data, &mut rollups).await;
+            }
+            // A block well behind the head has all its swaps, and so has an interval a later one was opened after
+            if let Some(latest_block) = latest_block {
+                for (pool, pool_blocks) in rollups.blocks.iter_mut() {
+                    for summary in pool_blocks.close_before(latest_block) {
+                        self.store_block_summary(pool, &summary).await;
+                    }
+                }
+                for (pool, aggregator) in rollups.candles.iter_mut() {
+                    for candle in aggregator.close_before(latest_block) {
+                        self.store_candle(pool, &candle).await;
+                    }
+                }
+                for (pool, aggregator) in rollups.volumes.iter_mut() {
+                    for volume in aggregator.close_before(latest_block) {
+                        self.store_volume(pool, &volume).await;
+                    }
+                }
+            }
+            if let Some((latest_block, sandwiches)) = latest_block.zip(sandwiches.as_mut()) {
+                report_sandwiches(&sandwiches.close_before(latest_block));
+            }
+        }
+
+        if let Some(sandwiches) = sandwiches.as_mut() {
+            report_sandwiches(&sandwiches.flush());
+        }
+
+        // Summarize the last blocks of each pool, however many of their swaps came in
+        for (pool, pool_blocks) in rollups.blocks.iter_mut() {
+            for summary in pool_blocks.flush() {
+                self.store_block_summary(pool, &summary).await;
+            }
+        }
+
+        // Store the candles and volumes that were still open when the log source ended
+        for (pool, aggregator) in rollups.candles.iter_mut() {
+            for candle in aggregator.flush() {
+                self.store_candle(pool, &candle).await;
+            }
+        }
+        for (pool, aggregator) in rollups.volumes.iter_mut() {
+            for volume in aggregator.flush() {
+                self.store_volume(pool, &volume).await;
+            }
+        }
+        if let Err(e) = self.store.flush().await {
+            tracing::error!(error = %e, "Failed to store decoded data");
+        }
+        // Wait for the swaps still queued for the webhook
+        if let Some(webhook) = self.webhook {
+            if let Err(e) = webhook.flush().await {
+                tracing::warn!(error = %e, "Failed to POST decoded data to the webhook");
+            }
+        }
+    }
+
+    // Write one confirmed swap, at `log_index` of its block, to every sink, under its pool.
+    async fn store_swap(&self, block_number: u64, log_index: u64, data: &DecodedData, rollups: &mut Rollups) {
+        match self.store.store(&data.pool, data).await {
+            Ok(()) => {
+                if let Some(metrics) = self.metrics {
+                    metrics.record_stored(block_number);
+                }
+            }
+            Err(e) => tracing::error!(tx_hash = %data.transaction_hash, error = %e, "Failed to store decoded data"),
+        }
+        if let Some(webhook) = self.webhook {
+            if let Err(e) = webhook.store(&data.pool, data).await {
+                tracing::warn!(tx_hash = %data.transaction_hash, error = %e, "Failed to POST decoded data to the webhook");
+            }
+        }
+        if self.blocks.is_some() {
+            let pool_blocks = rollups.blocks.entry(data.pool.clone()).or_insert_with(|| BlockAggregator::new(self.price_quotes.for_pool(&data.pool)));
+            pool_blocks.push(block_number, log_index, data);
+        }
+
+        // Candles and volumes are bucketed on the block timestamp
+        if self.candles.is_none() && self.volumes.is_none() {
+            return;
+        }
+        let timestamp = match self.timestamps.block_timestamp(block_number).await {
+            Ok(Some(timestamp)) => timestamp,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(block = block_number, error = %e, "Failed to fetch the block timestamp");
+                return;
+            }
+        };
+        if let Some((_, interval)) = self.candles {
+            let aggregator = rollups.candles.entry(data.pool.clone()).or_insert_with(|| CandleAggregator::new(interval, self.price_quotes.for_pool(&data.pool)));
+            aggregator.push(timestamp, block_number, log_index, data);
+        }
+        if let Some((_, settings)) = self.volumes {
+            self.add_volume(settings, timestamp, block_number, data, rollups);
+        }
+    }
+
+    // Fold a swap into the volume of its pool, when valued, and price the pools valued
+    // at its pool's price.
+    fn add_volume(&self, settings: &VolumeSettings, timestamp: u64, block_number: u64, data: &DecodedData, rollups: &mut Rollups) {
+        let Some(valuation) = settings.valuation(&data.pool) else { return };
+        let aggregator = rollups.volumes.entry(data.pool.clone()).or_insert_with(|| {
+            VolumeAggregator::new(settings.numeraire, valuation.valuation, self.price_quotes.for_pool(&data.pool), settings.interval, settings.max_age)
+        });
+        if let Some(price) = aggregator.reference_price(data.sqrtPriceX96) {
+            for priced in settings.priced_by(&data.pool) {
+                if let Valuation::Feed(token) = priced.valuation {
+                    rollups.prices.set(&priced.pool, token, price, timestamp);
+                }
+            }
+        }
+        aggregator.push(&data.pool, timestamp, block_number, data, &rollups.prices);
+    }
+
+    // Write one completed candle of `pool` to the candle store.
+    async fn store_candle(&self, pool: &str, candle: &Candle) {
+        let Some((candle_store, _)) = self.candles else { return };
+        if let Err(e) = candle_store.store_candle(pool, candle).await {
+            tracing::error!(pool, error = %e, "Failed to store candle");
+        }
+    }
+
+    // Write one confirmed event other than a swap to the store.
+    async fn store_event(&self, record: &EventRecord) {
+        if let Err(e) = self.store.store_event(record).await {
+            tracing::error!(event = %record.event, error = %e, "Failed to store decoded event");
+        }
+    }
+
+    // Add the gas cost of the swap's transaction, when enabled. A failed lookup leaves it out.
+    async fn add_gas(&self, data: &mut DecodedData) {
+        let Some(gas) = self.gas else { return };
+        let Ok(hash) = data.transaction_hash.parse::<H256>() else { return };
+        match gas.transaction_gas(hash).await {
+            Ok(Some(cost)) => {
+                data.gas_used = Some(cost.gas_used);
+                data.effective_gas_price = Some(cost.effective_gas_price);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(tx_hash = %data.transaction_hash, error = %e, "Failed to fetch the transaction receipt"),
+        }
+    }
+
+    // Write one completed numeraire volume of `pool` to its sink.
+    async fn store_volume(&self, pool: &str, volume: &NumeraireVolume) {
+        let Some((volume_store, _)) = self.volumes else { return };
+        if let Err(e) = volume_store.store_volume(pool, volume).await {
+            tracing::error!(pool, error = %e, "Failed to store volume");
+        }
+    }
+
+    // Write one completed block summary of `pool` to its sink.
+    async fn store_block_summary(&self, pool: &str, summary: &BlockSummary) {
+        let Some(block_store) = self.blocks else { return };
+        if let Err(e) = block_store.store_block_summary(pool, summary).await {
+            tracing::error!(pool, error = %e, "Failed to store block summary");
+        }
+    }
+}
+
+// The rollups being built of the stored swaps of each pool, for the sinks enabled.
+#[derive(Default)]
+struct Rollups {
+    candles: HashMap<String, CandleAggregator>,
+    // The summaries of each pool's open blocks
+    blocks: HashMap<String, BlockAggregator>,
+    volumes: HashMap<String, VolumeAggregator>,
+    // The prices of the tokens valued at a reference pool's price, from its swaps
+    prices: LatestPrices,
+}
+
+// Warn about the sandwiches found, one `sandwich_suspected` annotation each.
+fn report_sandwiches(suspects: &[SandwichSuspect]) {
+    for suspect in suspects {
+        tracing::warn!(
+            annotation = suspect.annotation,
+            block_number = suspect.block_number,
+            pool = %suspect.pool,
+            attacker = %suspect.attacker,
+            front_run = %suspect.front_run,
+            victim = %suspect.victim,
+            back_run = %suspect.back_run,
+            "Sandwich attack suspected"
+        );
+    }
+}
+
+// The number of the next head published on `head`. Never resolves without a head
+// channel, or once the head tracker has ended.
+async fn next_head(head: &mut Option<watch::Receiver<Option<ChainHead>>>) -> Option<u64> {
+    if let Some(receiver) = head {
+        if receiver.changed().await.is_ok() {
+            return receiver.borrow_and_update().map(|head| head.number);
+        }
+    }
+    std::future::pending().await
+}
+
+// The number of the head published on `head` since it was last read, if any.
+fn unseen_head(head: &mut Option<watch::Receiver<Option<ChainHead>>>) -> Option<u64> {
+    let receiver = head.as_mut()?;
+    if !receiver.has_changed().unwrap_or(false) {
+        return None;
+    }
+    receiver.borrow_and_update().map(|head| head.number)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::{test_swap, InMemoryStore};
+    use crate::decoders::TransferDecoder;
+    use crate::log_processing::build_event_map;
+    use crate::schemas::SchemaRegistry;
+    use crate::log_sources::{feed_logs, forward_events, LogRange, OfflineLookups, SourceEnd, SourceEvent};
+    use async_trait::async_trait;
+    use ethers::abi::{Abi, Token};
+    use ethers::types::{H256, I256, U256, U64};
+    use std::error::Error;
+    use std::sync::Mutex as StdMutex;
+
+    // Block timestamps for a harness without a provider: every block is unknown.
+    struct NoTimestamps;
+
+    #[async_trait]
+    impl BlockTimestamps for NoTimestamps {
+        async fn block_timestamp(&self, _block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+            Ok(None)
+        }
+    }
+
+    // Run a scripted sequence of log source events through the whole pipeline, the way
+    // `fetch_eth_logs` does with a live provider, and return what got stored. The script
+    // ending plays the part of the provider disconnecting.
+    async fn run_scripted(events: Vec<SourceEvent>, confirmations: u64) -> InMemoryStore {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        run_decoding(events, confirmations, &abi, DecoderRegistry::default()).await
+    }
+
+    // `run_scripted` with the events of `abi` and the `decoders` given.
+    async fn run_decoding(events: Vec<SourceEvent>, confirmations: u64, abi: &Abi, decoders: DecoderRegistry) -> InMemoryStore {
+        let event_map = Arc::new(build_event_map(abi).unwrap());
+        let store = InMemoryStore::new();
+
+        let (log_tx, log_rx) = mpsc::channel(4);
+        let (writer_tx, writer_rx) = mpsc::channel(4);
+        let workers = spawn_workers(2, log_rx, event_map, Arc::new(decoders), Arc::new(Metrics::default()), None, writer_tx);
+        let quotes = PriceQuotes::default();
+        let writer = Writer { confirmations, ..Writer::new(&store, &quotes, &NoTimestamps) };
+
+        tokio::join!(
+            forward_events(futures_util::stream::iter(events), &OfflineLookups, log_tx),
+            writer.run(writer_rx),
+        );
+        for worker in workers {
+            worker.await.unwrap();
+        }
+        store
+    }
+
+    // Records the pool each swap was stored under.
+    #[derive(Default)]
+    struct PoolRecorder(StdMutex<Vec<(String, String)>>);
+
+    #[async_trait]
+    impl DataStore for PoolRecorder {
+        async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.0.lock().unwrap().push((address.to_string(), data.transaction_hash.clone()));
+            Ok(())
+        }
+    }
+
+    fn log_in_block(tx: u8, block_number: u64) -> Log {
+        Log { block_number: Some(U64::from(block_number)), log_index: Some(U256::zero()), ..swap_log(tx) }
+    }
+
+    fn decoded_swap(tx: u8) -> DecodedData {
+        test_swap(&tx.to_string(), 0, 0)
+    }
+
+    fn swap_log(tx: u8) -> Log {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event = abi.event("Swap").unwrap();
+        let data = ethers::abi::encode(&[
+            Token::Int(I256::from(1000).into_raw()),
+            Token::Int(I256::from(-2000).into_raw()),
+            Token::Uint(U256::from(1u64) << 96),
+            Token::Uint(U256::from(5u64)),
+            Token::Int(I256::from(0).into_raw()),
+        ]);
+        Log {
+            topics: vec![event.signature(), H256::repeat_byte(0xaa), H256::repeat_byte(0xbb)],
+            data: data.into(),
+            transaction_hash: Some(H256::repeat_byte(tx)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_workers_decode_every_log() {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = Arc::new(build_event_map(&abi).unwrap());
+        let decoders = Arc::new(DecoderRegistry::default());
+
+        let (log_tx, log_rx) = mpsc::channel(2);
+        let (swap_tx, mut swap_rx) = mpsc::channel(2);
+        let metrics = Arc::new(Metrics::default());
+        let workers = spawn_workers(3, log_rx, event_map, decoders, Arc::clone(&metrics), None, swap_tx);
+
+        tokio::spawn(async move {
+            for tx in 1..=10u8 {
+                log_tx.send(swap_log(tx).into()).await.unwrap();
+            }
+        });
+
+        let mut hashes = Vec::new();
+        while let Some(message) = swap_rx.recv().await {
+            match message {
+                WriterMessage::Swap(_, data) => hashes.push(data.transaction_hash),
+                other => panic!("Expected a Swap, got {:?}", other),
+            }
+        }
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        hashes.sort();
+        let mut expected: Vec<_> = (1..=10u8).map(|tx| format!("0x{}", hex::encode([tx; 32]))).collect();
+        expected.sort();
+        assert_eq!(hashes, expected);
+        assert_eq!(metrics.snapshot().decoded, 10);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_stream_until_disconnect() {
+        // A log of an event without a decoder is skipped
+        let unknown = Log { topics: vec![H256::repeat_byte(0xff)], ..swap_log(9) };
+        let events = vec![
+            SourceEvent::Log(log_in_block(1, 100)),
+            SourceEvent::Log(unknown),
+            SourceEvent::Log(log_in_block(2, 101)),
+        ];
+
+        let store = run_scripted(events, 0).await;
+
+        let mut hashes: Vec<_> = store.records().into_iter().map(|d| d.transaction_hash).collect();
+        hashes.sort();
+        assert_eq!(hashes, vec![format!("0x{}", hex::encode([1u8; 32])), format!("0x{}", hex::encode([2u8; 32]))]);
+    }
+
+    #[tokio::test]
+    async fn test_swaps_are_stored_under_their_pool() {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = Arc::new(build_event_map(&abi).unwrap());
+        let store = PoolRecorder::default();
+
+        let (log_tx, log_rx) = mpsc::channel(4);
+        let (writer_tx, writer_rx) = mpsc::channel(4);
+        let workers = spawn_workers(2, log_rx, event_map, Arc::new(DecoderRegistry::default()), Arc::new(Metrics::default()), None, writer_tx);
+        let quotes = PriceQuotes::default();
+        let writer = Writer::new(&store, &quotes, &NoTimestamps);
+
+        let usdc_weth = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+        let wbtc_weth = "0xCBCdF9626bC03E24f779434178A73a0B4bad62eD";
+        let send_logs = async move {
+            for (tx, pool) in [(1, usdc_weth), (2, wbtc_weth), (3, usdc_weth)] {
+                let log = Log { address: pool.parse().unwrap(), ..swap_log(tx) };
+                log_tx.send(log.into()).await.unwrap();
+            }
+        };
+        tokio::join!(send_logs, writer.run(writer_rx));
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        let mut stored = store.0.into_inner().unwrap();
+        stored.sort_by(|a, b| a.1.cmp(&b.1));
+        let hash = |tx: u8| format!("0x{}", hex::encode([tx; 32]));
+        let expected = [(usdc_weth, 1), (wbtc_weth, 2), (usdc_weth, 3)];
+        assert_eq!(stored.len(), expected.len());
+        for ((pool, tx_hash), (expected_pool, tx)) in stored.iter().zip(expected) {
+            assert!(pool.eq_ignore_ascii_case(expected_pool), "{} stored under {}", tx_hash, pool);
+            assert_eq!(*tx_hash, hash(tx));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_events_routes_removed_logs_and_blocks() {
+        let removed = Log { removed: Some(true), ..log_in_block(2, 100) };
+        let events = vec![
+            SourceEvent::Log(log_in_block(1, 100)),
+            SourceEvent::Log(removed),
+            SourceEvent::Block(101),
+        ];
+
+        let (log_tx, mut log_rx) = mpsc::channel(4);
+        let end = forward_events(futures_util::stream::iter(events), &OfflineLookups, log_tx).await;
+        assert_eq!(end, SourceEnd::Exhausted);
+
+        // In the order of the events, behind the logs before them
+        assert!(matches!(log_rx.recv().await, Some(WorkerInput::Log(log)) if log.transaction_hash == Some(H256::repeat_byte(1))));
+        assert!(matches!(log_rx.recv().await, Some(WorkerInput::Writer(WriterMessage::Removed(log))) if log.transaction_hash == Some(H256::repeat_byte(2))));
+        assert!(matches!(log_rx.recv().await, Some(WorkerInput::Writer(WriterMessage::Block(101)))));
+        assert!(log_rx.recv().await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retractions_reach_the_writer_behind_their_swaps() {
+        // Every swap is retracted right after its log, by a removed log or by a reorg. Ahead
+        // of its swap, a retraction would find nothing to drop and the swap would be stored.
+        let in_block = |tx: u8, block_number: u64, hash: u8| Log { block_hash: Some(H256::repeat_byte(hash)), ..log_in_block(tx, block_number) };
+        let mut events = Vec::new();
+        for tx in 1..=20u8 {
+            let block_number = 100 + u64::from(tx);
+            events.push(SourceEvent::Log(in_block(tx, block_number, 0xa0)));
+            if tx % 2 == 0 {
+                events.push(SourceEvent::Log(Log { removed: Some(true), ..in_block(tx, block_number, 0xa0) }));
+            } else {
+                // Replaced by a block without swaps
+                events.push(SourceEvent::Log(Log { topics: vec![H256::repeat_byte(0xff)], ..in_block(tx + 100, block_number, 0xb0) }));
+            }
+        }
+        events.push(SourceEvent::Block(200));
+
+        let store = run_scripted(events, 1).await;
+        assert!(store.is_empty(), "{:?}", store.records());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fed_logs_are_all_confirmed_by_the_final_head() {
+        // The head moved past the last log reaches the writer behind every swap, however
+        // the workers interleave
+        let recorded: Vec<_> = (1..=40u8).map(|tx| log_in_block(tx, 100 + u64::from(tx) / 4)).collect();
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let event_map = Arc::new(build_event_map(&abi).unwrap());
+        let store = InMemoryStore::new();
+
+        let (log_tx, log_rx) = mpsc::channel(4);
+        let (writer_tx, writer_rx) = mpsc::channel(4);
+        let workers = spawn_workers(4, log_rx, event_map, Arc::new(DecoderRegistry::default()), Arc::new(Metrics::default()), None, writer_tx);
+        let quotes = PriceQuotes::default();
+        let writer = Writer { confirmations: 3, ..Writer::new(&store, &quotes, &NoTimestamps) };
+        tokio::join!(feed_logs(recorded, 3, log_tx), writer.run(writer_rx));
+        for worker in workers {
+            worker.await.unwrap();
+        }
+        assert_eq!(store.len(), 40);
+    }
+
+    #[tokio::test]
+    async fn test_transfers_are_stored_as_event_records() {
+        let abi = ethers::abi::parse_abi(&["event Transfer(address indexed from, address indexed to, uint256 value)"]).unwrap();
+        let mut decoders = DecoderRegistry::new();
+        decoders.register(Box::new(TransferDecoder));
+        let usdc: ethers::types::Address = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".parse().unwrap();
+        let transfer = Log {
+            address: usdc,
+            topics: vec![abi.event("Transfer").unwrap().signature(), H256::from_low_u64_be(1), H256::from_low_u64_be(2)],
+            data: ethers::abi::encode(&[Token::Uint(U256::from(1_000u64))]).into(),
+            block_number: Some(U64::from(100)),
+            log_index: Some(U256::zero()),
+            transaction_hash: Some(H256::repeat_byte(1)),
+            ..Default::default()
+        };
+
+        let store = run_decoding(vec![SourceEvent::Log(transfer)], 0, &abi, decoders).await;
+
+        assert!(store.is_empty());
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].event.as_str(), events[0].address.as_str()), ("Transfer", "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"));
+        assert_eq!(events[0].fields["value"], "1000");
+        assert_eq!(events[0].fields["transaction_hash"], format!("0x{}", hex::encode([1u8; 32])));
+    }
+
+    #[tokio::test]
+    async fn test_writer_never_stores_reorged_transfers() {
+        let store = InMemoryStore::new();
+        let metrics = Metrics::default();
+        let quotes = PriceQuotes::default();
+        let writer = Writer { confirmations: 1, metrics: Some(&metrics), ..Writer::new(&store, &quotes, &NoTimestamps) };
+
+        let transfer = |tx: u8| EventRecord { event: "Transfer".to_string(), address: "0xtoken".to_string(), fields: serde_json::json!({ "value": tx.to_string() }) };
+        let (writer_tx, writer_rx) = mpsc::channel(8);
+        for message in [
+            WriterMessage::Event(log_in_block(1, 100), transfer(1)),
+            WriterMessage::Event(log_in_block(2, 100), transfer(2)),
+            WriterMessage::Removed(log_in_block(2, 100)),
+            WriterMessage::Event(log_in_block(3, 101), transfer(3)),
+            WriterMessage::Block(101),
+        ] {
+            writer_tx.send(message).await.unwrap();
+        }
+        drop(writer_tx);
+        writer.run(writer_rx).await;
+
+        // Transfer 2 was reorged out and transfer 3 isn't confirmed
+        let values: Vec<_> = store.events().into_iter().map(|record| record.fields["value"].clone()).collect();
+        assert_eq!(values, vec!["1"]);
+    }
+
+    #[tokio::test]
+    async fn test_registry_events_are_stored() {
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let mut decoders = DecoderRegistry::new();
+        let schemas = SchemaRegistry::from_json(r#"{"Flash": [{"param": "sender", "type": "address"}, {"param": "paid0", "field": "fee0", "type": "uint"}]}"#).unwrap();
+        decoders.register_schemas(&schemas);
+        let data = ethers::abi::encode(&[Token::Uint(U256::from(1_000u64)), Token::Uint(U256::zero()), Token::Uint(U256::from(5u64)), Token::Uint(U256::zero())]);
+        let flash = Log {
+            topics: vec![abi.event("Flash").unwrap().signature(), H256::from_low_u64_be(0xaa), H256::from_low_u64_be(0xbb)],
+            data: data.into(),
+            ..log_in_block(1, 100)
+        };
+
+        let store = run_decoding(vec![SourceEvent::Log(flash)], 0, &abi, decoders).await;
+
+        let events = store.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "Flash");
+        assert_eq!(events[0].fields["sender"], format!("0x{:040x}", 0xaa));
+        assert_eq!(events[0].fields["fee0"], "5");
+        assert_eq!(events[0].fields["transaction_hash"], format!("0x{}", hex::encode([1u8; 32])));
+    }
+
+    #[tokio::test]
+    async fn test_writer_never_stores_reorged_swaps() {
+        let store = InMemoryStore::new();
+        let metrics = Metrics::default();
+        let quotes = PriceQuotes::default();
+        let writer = Writer { confirmations: 2, metrics: Some(&metrics), ..Writer::new(&store, &quotes, &NoTimestamps) };
+
+        let (writer_tx, writer_rx) = mpsc::channel(8);
+        for message in [
+            WriterMessage::Swap(log_in_block(1, 100), decoded_swap(1)),
+            WriterMessage::Swap(log_in_block(2, 100), decoded_swap(2)),
+            WriterMessage::Removed(log_in_block(2, 100)),
+            WriterMessage::Block(101),
+            WriterMessage::Swap(log_in_block(3, 101), decoded_swap(3)),
+            WriterMessage::Block(102),
+        ] {
+            writer_tx.send(message).await.unwrap();
+        }
+        drop(writer_tx);
+        writer.run(writer_rx).await;
+
+        // Swap 2 was reorged out and swap 3 only has one confirmation
+        let hashes: Vec<_> = store.records().into_iter().map(|d| d.transaction_hash).collect();
+        assert_eq!(hashes, vec!["1"]);
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.removed_logs, snapshot.reorgs, snapshot.retracted_swaps), (1, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_reorg_drops_the_replaced_swaps() {
+        let in_block = |tx: u8, block_number: u64, hash: u8| Log { block_hash: Some(H256::repeat_byte(hash)), ..log_in_block(tx, block_number) };
+        let events = vec![
+            SourceEvent::Log(in_block(1, 100, 0xa0)),
+            SourceEvent::Log(in_block(2, 101, 0xa1)),
+            // Block 101 was replaced
+            SourceEvent::Log(in_block(3, 101, 0xb1)),
+        ];
+        let (log_tx, mut log_rx) = mpsc::channel(4);
+        forward_events(futures_util::stream::iter(events), &OfflineLookups, log_tx).await;
+        let mut inputs = Vec::new();
+        while let Some(input) = log_rx.recv().await {
+            inputs.push(input);
+        }
+        // The reorg is pushed ahead of the log replacing block 101
+        let [WorkerInput::Log(first), WorkerInput::Log(second), WorkerInput::Writer(WriterMessage::Reorg(101)), WorkerInput::Log(third)] = <[_; 4]>::try_from(inputs).unwrap() else {
+            panic!("Expected two logs, the reorg and the new log");
+        };
+
+        let store = InMemoryStore::new();
+        let metrics = Metrics::default();
+        let quotes = PriceQuotes::default();
+        let writer = Writer { confirmations: 2, metrics: Some(&metrics), ..Writer::new(&store, &quotes, &NoTimestamps) };
+        let (writer_tx, writer_rx) = mpsc::channel(8);
+        writer_tx.send(WriterMessage::Swap(first, decoded_swap(1))).await.unwrap();
+        writer_tx.send(WriterMessage::Swap(second, decoded_swap(2))).await.unwrap();
+        writer_tx.send(WriterMessage::Reorg(101)).await.unwrap();
+        writer_tx.send(WriterMessage::Swap(third, decoded_swap(3))).await.unwrap();
+        writer_tx.send(WriterMessage::Block(103)).await.unwrap();
+        drop(writer_tx);
+        writer.run(writer_rx).await;
+
+        let hashes: Vec<_> = store.records().into_iter().map(|d| d.transaction_hash).collect();
+        assert_eq!(hashes, vec!["1", "3"]);
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.removed_logs, snapshot.reorgs, snapshot.retracted_swaps), (0, 1, 1));
+    }
+
+    // The new chain's logs of a range of blocks, as the node answers after a reorg.
+    struct NewChain(Vec<Log>);
+
+    #[async_trait]
+    impl LogRange for NewChain {
+        async fn logs_in_range(&self, from_block: u64, to_block: u64) -> Result<Vec<Log>, Box<dyn Error + Send + Sync>> {
+            let in_range = |log: &&Log| (from_block..=to_block).contains(&log.block_number.unwrap().as_u64());
+            Ok(self.0.iter().filter(in_range).cloned().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reorg_fetches_the_replaced_blocks_again() {
+        let in_block = |tx: u8, block_number: u64, hash: u8, index: u64| Log {
+            block_hash: Some(H256::repeat_byte(hash)),
+            log_index: Some(U256::from(index)),
+            ..log_in_block(tx, block_number)
+        };
+        let events = vec![
+            SourceEvent::Log(in_block(1, 101, 0xa1, 0)),
+            SourceEvent::Log(in_block(2, 102, 0xa2, 0)),
+            // Block 101 was replaced, the subscription only delivers one of its new logs
+            SourceEvent::Log(in_block(3, 101, 0xb1, 0)),
+            SourceEvent::Log(in_block(5, 103, 0xb3, 0)),
+        ];
+        let new_chain = NewChain(vec![in_block(3, 101, 0xb1, 0), in_block(4, 101, 0xb1, 1), in_block(5, 103, 0xb3, 0)]);
+        let (log_tx, mut log_rx) = mpsc::channel(8);
+        forward_events(futures_util::stream::iter(events), &new_chain, log_tx).await;
+
+        let mut inputs = Vec::new();
+        while let Some(input) = log_rx.recv().await {
+            inputs.push(match input {
+                WorkerInput::Log(log) => format!("log {}", log.transaction_hash.unwrap().0[0]),
+                WorkerInput::Writer(WriterMessage::Reorg(first_block)) => format!("reorg {}", first_block),
+                _ => panic!("Expected logs and a reorg"),
+            });
+        }
+        // Blocks 101 and 102 are fetched again, without the subscription's copy of log 3
+        assert_eq!(inputs, vec!["log 1", "log 2", "reorg 101", "log 3", "log 4", "log 5"]);
+    }
+
+    #[tokio::test]
+    async fn test_removed_logs_are_never_stored_without_confirmations() {
+        let removed = Log { removed: Some(true), ..log_in_block(1, 100) };
+        let store = run_scripted(vec![SourceEvent::Log(removed), SourceEvent::Block(101)], 0).await;
+        assert_eq!(store.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_writer_confirms_with_the_head_channel() {
+        // Stores what is confirmed once a swap of block 100 is followed by `latest_block` as the head
+        async fn stored_at_head(latest_block: u64) -> usize {
+            let store = InMemoryStore::new();
+            let (head_tx, head_rx) = watch::channel(None);
+            let quotes = PriceQuotes::default();
+            let writer = Writer { confirmations: 2, head: Some(head_rx), ..Writer::new(&store, &quotes, &NoTimestamps) };
+
+            let (writer_tx, writer_rx) = mpsc::channel(8);
+            writer_tx.send(WriterMessage::Swap(log_in_block(1, 100), decoded_swap(1))).await.unwrap();
+            head_tx.send(Some(ChainHead { number: latest_block, timestamp: 0, hash: H256::zero(), parent_hash: H256::zero() })).unwrap();
+            drop(writer_tx);
+            writer.run(writer_rx).await;
+            store.len()
+        }
+
+        assert_eq!(stored_at_head(101).await, 0);
+        assert_eq!(stored_at_head(102).await, 1);
+    }
+
+    // Block timestamps 12 seconds apart.
+    struct BlockTimes;
+
+    #[async_trait]
+    impl BlockTimestamps for BlockTimes {
+        async fn block_timestamp(&self, block_number: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+            Ok(Some(block_number * 12))
+        }
+    }
+
+    // Records the volumes stored, with their pool.
+    #[derive(Default)]
+    struct VolumeRecorder(StdMutex<Vec<(String, NumeraireVolume)>>);
+
+    #[async_trait]
+    impl VolumeStore for VolumeRecorder {
+        async fn store_volume(&self, address: &str, volume: &NumeraireVolume) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.0.lock().unwrap().push((address.to_string(), volume.clone()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_volumes_are_valued_at_the_reference_pool() {
+        use crate::numeraire::{Numeraire, PoolToken, PoolValuation};
+
+        // The first pool's token0 is the numeraire, it prices the token1 of the second one
+        let valuations = [
+            PoolValuation { pool: "0xusdc".to_string(), valuation: Valuation::Native(PoolToken::Token0), reference: None },
+            PoolValuation { pool: "0xother".to_string(), valuation: Valuation::Feed(PoolToken::Token1), reference: Some("0xusdc".to_string()) },
+        ];
+        let settings = VolumeSettings::new(Numeraire::Usd, 60, 0, &valuations).unwrap();
+        let (store, volumes) = (InMemoryStore::new(), VolumeRecorder::default());
+        let quotes = PriceQuotes::default();
+        let writer = Writer { volumes: Some((&volumes, &settings)), ..Writer::new(&store, &quotes, &BlockTimes) };
+
+        // 4 token0 per token1 after the first swap
+        let swaps = [
+            ("0xusdc", DecodedData { amount0_abs: 2_000, sqrtPriceX96: U256::one() << 95, ..decoded_swap(1) }),
+            ("0xother", DecodedData { amount1_abs: 10, ..decoded_swap(2) }),
+            ("0xunvalued", DecodedData { amount1_abs: 10, ..decoded_swap(3) }),
+        ];
+        let (writer_tx, writer_rx) = mpsc::channel(8);
+        for (tx, (pool, data)) in (1..).zip(swaps) {
+            writer_tx.send(WriterMessage::Swap(log_in_block(tx, 10), DecodedData { pool: pool.to_string(), ..data })).await.unwrap();
+        }
+        drop(writer_tx);
+        writer.run(writer_rx).await;
+
+        let mut stored: Vec<_> = volumes.0.into_inner().unwrap().into_iter().map(|(pool, volume)| (pool, volume.volume, volume.swaps)).collect();
+        stored.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(stored, vec![("0xother".to_string(), 40.0, 1), ("0xusdc".to_string(), 2_000.0, 1)]);
+    }
+}
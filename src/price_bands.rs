@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::log_processing::parse_address;
+
+
+/// PriceBand The plausible prices of one pool, given as `<pool address>:<min>:<max>`, e.g.
+/// `0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:500:20000` for USDC per WETH.
+///
+/// The prices are quoted like the metrics and candles: scaled by the pool's decimals and
+/// oriented by `--invert-price`. A decoded swap priced outside its band most likely
+/// means a decoding bug, such as a truncated `sqrtPriceX96`, rather than a real trade.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceBand {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl PriceBand {
+    /// Whether `price` is within the band, bounds included. NaN never is.
+    pub fn contains(&self, price: f64) -> bool {
+        self.min <= price && price <= self.max
+    }
+}
+
+impl fmt::Display for PriceBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..={}", self.min, self.max)
+    }
+}
+
+
+/// PoolPriceBand The `PriceBand` of the pool at `pool`, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolPriceBand {
+    /// The pool address, lowercase.
+    pub pool: String,
+    pub band: PriceBand,
+}
+
+impl FromStr for PoolPriceBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid price band {:?}, expected <pool>:<min>:<max>", s);
+        let mut parts = s.split(':').map(str::trim);
+        let (Some(pool), Some(min), Some(max), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        parse_address(pool).map_err(|e| e.to_string())?;
+        let min: f64 = min.parse().map_err(|_| invalid())?;
+        let max: f64 = max.parse().map_err(|_| invalid())?;
+        if !min.is_finite() || !max.is_finite() || min >= max {
+            return Err(format!("Invalid price band {:?}, the minimum must be below the maximum", s));
+        }
+        Ok(PoolPriceBand { pool: pool.to_lowercase(), band: PriceBand { min, max } })
+    }
+}
+
+
+/// PriceBands The `PriceBand` of every pool with one. Pools without a band aren't checked.
+#[derive(Debug, Clone, Default)]
+pub struct PriceBands {
+    pools: HashMap<String, PriceBand>,
+}
+
+impl PriceBands {
+    pub fn new(bands: &[PoolPriceBand]) -> Self {
+        PriceBands { pools: bands.iter().map(|band| (band.pool.to_lowercase(), band.band)).collect() }
+    }
+
+    /// The band of the pool at `pool`, in any case.
+    pub fn for_pool(&self, pool: &str) -> Option<PriceBand> {
+        self.pools.get(&pool.to_lowercase()).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_price_band() {
+        let band: PoolPriceBand = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:500:2e4".parse().unwrap();
+        assert_eq!(band.pool, "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640");
+        assert_eq!(band.band, PriceBand { min: 500.0, max: 20_000.0 });
+
+        let bands = PriceBands::new(&[band]);
+        let band = bands.for_pool("0x88E6A0C2DDD26FEEB64F039A2C41296FCB3F5640").unwrap();
+        assert!(band.contains(500.0) && band.contains(3_000.0) && band.contains(20_000.0));
+        assert!(!band.contains(499.9) && !band.contains(f64::NAN) && !band.contains(f64::INFINITY));
+        assert_eq!(bands.for_pool("0x0000000000000000000000000000000000000001"), None);
+
+        for invalid in [
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:500",
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:500:abc",
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:2000:500",
+            "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:0:inf",
+            "0x88e6:500:2000",
+        ] {
+            assert!(invalid.parse::<PoolPriceBand>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, PubsubClient};
+use ethers::types::U256;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::TaskError;
+
+
+/// RateLimiter A token bucket spacing out the requests sent to one RPC endpoint.
+///
+/// The bucket holds up to `burst` tokens and refills at `requests_per_second`. Every
+/// request takes a token, waiting for the next one when the bucket is empty, so bursts
+/// are smoothed to the rate while a quiet period lets the next `burst` requests through
+/// at once. Waiting requests are served in the order they asked.
+///
+/// This caps the total load on the endpoint, whatever the API key, unlike the per-key
+/// rotation of `ApiKeys`.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // Negative while requests wait for tokens not refilled yet
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter allowing `requests_per_second`, starting full with `burst` tokens.
+    pub fn new(requests_per_second: f64, burst: u32) -> Result<Self, TaskError> {
+        if !requests_per_second.is_finite() || requests_per_second <= 0.0 {
+            return Err(TaskError::InvalidConfig(format!("the RPC rate limit must be above 0 requests per second, got {}", requests_per_second)));
+        }
+        let burst = f64::from(burst.max(1));
+        Ok(RateLimiter { requests_per_second, burst, bucket: Mutex::new(Bucket { tokens: burst, refilled: Instant::now() }) })
+    }
+
+    /// Take a token, waiting until one is available.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.requests_per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst);
+            bucket.refilled = now;
+            // Reserve the token now, so later requests queue up behind this one
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / self.requests_per_second)
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RateLimiter({} requests/s, burst {})", self.requests_per_second, self.burst)
+    }
+}
+
+
+/// RateLimited A transport taking a token of a shared `RateLimiter` before every request.
+///
+/// Every connection to an endpoint shares one limiter, so their requests, the logs, the
+/// block timestamps, the receipts and the token decimals alike, add up to its rate.
+/// Without a limiter requests are sent right away.
+#[derive(Debug)]
+pub struct RateLimited<C> {
+    inner: C,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<C> RateLimited<C> {
+    pub fn new(inner: C, limiter: Option<Arc<RateLimiter>>) -> Self {
+        RateLimited { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for RateLimited<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+        self.inner.request(method, params).await
+    }
+}
+
+// Notifications are pushed by the node, only the subscribe requests take a token.
+impl<C: PubsubClient> PubsubClient for RateLimited<C> {
+    type NotificationStream = C::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_are_spaced_by_the_rate() {
+        let limiter = RateLimiter::new(20.0, 2).unwrap();
+        let started = Instant::now();
+        let mut taken = Vec::new();
+        for _ in 0..6 {
+            limiter.acquire().await;
+            taken.push(started.elapsed());
+        }
+
+        // The burst goes through at once, then a request every 50ms
+        assert!(taken[1] < Duration::from_millis(25), "{:?}", taken);
+        for pair in taken[1..].windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(45), "{:?}", taken);
+        }
+        assert!(taken[5] >= Duration::from_millis(195), "{:?}", taken);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_the_rate() {
+        let limiter = Arc::new(RateLimiter::new(50.0, 1).unwrap());
+        let started = Instant::now();
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // One right away, the other four 20ms apart
+        assert!(started.elapsed() >= Duration::from_millis(78), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_invalid_rate() {
+        assert!(RateLimiter::new(0.0, 1).is_err());
+        assert!(RateLimiter::new(f64::NAN, 1).is_err());
+    }
+}
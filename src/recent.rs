@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+use crate::data_store::DecodedData;
+
+
+/// RecentSwap A decoded swap and the pool it was emitted by.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentSwap {
+    pub pool: String,
+    #[serde(flatten)]
+    pub swap: DecodedData,
+}
+
+/// RecentSwaps The latest `capacity` decoded swaps, oldest first.
+///
+/// A bounded ring buffer behind a mutex, so the decode workers can push into one shared
+/// buffer while a dashboard takes `snapshot`s. Once full, every push drops the oldest
+/// swap. A capacity of 0 keeps nothing.
+///
+/// Every pushed swap is also broadcast to the receivers taken with `subscribe`, whatever
+/// the capacity. Pushing never waits for them: a receiver more than
+/// `BROADCAST_CAPACITY` swaps behind misses the oldest ones.
+#[derive(Debug)]
+pub struct RecentSwaps {
+    capacity: usize,
+    swaps: Mutex<VecDeque<RecentSwap>>,
+    live: broadcast::Sender<DecodedData>,
+}
+
+impl RecentSwaps {
+    /// How many swaps a receiver of `subscribe` can fall behind before it lags.
+    pub const BROADCAST_CAPACITY: usize = 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        let (live, _) = broadcast::channel(RecentSwaps::BROADCAST_CAPACITY);
+        RecentSwaps { capacity, swaps: Mutex::new(VecDeque::with_capacity(capacity)), live }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Add a swap of the pool at `pool`, dropping the oldest one if the buffer is full.
+    pub fn push(&self, pool: &str, swap: &DecodedData) {
+        // Sending only fails without receivers, and is skipped then to save the clone
+        if self.live.receiver_count() > 0 {
+            let _ = self.live.send(swap.clone());
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let mut swaps = self.lock();
+        if swaps.len() == self.capacity {
+            swaps.pop_front();
+        }
+        // The swap's own pool would repeat the one flattened next to it
+        let swap = DecodedData { pool: String::new(), ..swap.clone() };
+        swaps.push_back(RecentSwap { pool: pool.to_string(), swap });
+    }
+
+    /// A receiver of the swaps pushed from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<DecodedData> {
+        self.live.subscribe()
+    }
+
+    /// A copy of the buffered swaps, oldest first.
+    pub fn snapshot(&self) -> Vec<RecentSwap> {
+        self.lock().iter().cloned().collect()
+    }
+
+    // A panic while holding the lock can't leave the deque half-updated, so ignore poisoning.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<RecentSwap>> {
+        self.swaps.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for RecentSwaps {
+    fn default() -> Self {
+        RecentSwaps::new(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+
+    #[test]
+    fn test_keeps_the_latest_swaps() {
+        let recent = RecentSwaps::new(2);
+        for tx in ["a", "b", "c"] {
+            recent.push("0xpool", &test_swap(tx, 1, -1));
+        }
+        let hashes: Vec<_> = recent.snapshot().into_iter().map(|recent| recent.swap.transaction_hash).collect();
+        assert_eq!(hashes, vec!["b", "c"]);
+
+        let disabled = RecentSwaps::default();
+        disabled.push("0xpool", &test_swap("a", 1, -1));
+        assert!(disabled.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_broadcasts_to_subscribers() {
+        let recent = RecentSwaps::default();
+        recent.push("0xpool", &test_swap("before", 1, -1));
+        let mut live = recent.subscribe();
+        recent.push("0xpool", &test_swap("a", 1, -1));
+
+        assert_eq!(live.try_recv().unwrap().transaction_hash, "a");
+        assert!(live.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_serializes_flat() {
+        let json = serde_json::to_value(RecentSwap { pool: "0xpool".to_string(), swap: test_swap("a", 1, -1) }).unwrap();
+        assert_eq!(json["pool"], "0xpool");
+        assert_eq!(json["transaction_hash"], "a");
+        assert_eq!(json["amount1"], "-1");
+    }
+}
@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::data_store::{is_json_array, load_decoded_data, read_decoded_data, DataStore};
+
+
+/// replay Feeds the swaps stored by a `JsonStore` into another store, e.g. to fill a new
+/// Postgres database from the JSON files instead of fetching the logs again.
+///
+/// `input_path` is one file, or a directory whose swap files are all replayed, in the
+/// flat or the per-pool layout. The other files written next to them, such as candles,
+/// volumes, events and block summaries, are skipped. The swaps of a file are stored in
+/// the order they were written, which is block order, and a pool's files oldest day
+/// first. Each swap is stored under its own `pool`, or for files written before swaps
+/// had one, the pool address of its file, taken from the file name or its per-pool
+/// directory. `pool` overrides both.
+///
+/// # Returns
+///
+/// The number of swaps replayed, once `store` has been flushed.
+pub async fn replay(input_path: &Path, store: &dyn DataStore, pool: Option<&str>) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let files = if input_path.is_dir() {
+        let mut files = Vec::new();
+        collect_swap_files(input_path, &mut files)?;
+        // Dates sort in order within a pool with the default date format
+        files.sort_by_key(|file| (pool_of(file), file.clone()));
+        files
+    } else {
+        vec![input_path.to_path_buf()]
+    };
+
+    let mut replayed = 0;
+    for file in files {
+        let file_pool = pool.map(str::to_string).or_else(|| pool_of(&file));
+        // Arrays are only read whole, lines are streamed
+        let swaps: Box<dyn Iterator<Item = std::io::Result<_>>> = if is_json_array(&file)? {
+            Box::new(load_decoded_data(&file)?.into_iter().map(Ok))
+        } else {
+            Box::new(read_decoded_data(&file)?)
+        };
+        for swap in swaps {
+            let mut swap = swap?;
+            // Swaps from files written before they carried their pool take the file's
+            if pool.is_some() || swap.pool.is_empty() {
+                swap.pool = match &file_pool {
+                    Some(address) => address.clone(),
+                    None => return Err(format!("Can't tell the pool of {}, pass it with --pool", file.display()).into()),
+                };
+            }
+            store.store(&swap.pool, &swap).await?;
+            replayed += 1;
+        }
+    }
+    store.flush().await?;
+    Ok(replayed)
+}
+
+// The swap files under `dir`, leaving out the other files written next to them.
+fn collect_swap_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_swap_files(&path, files)?;
+        } else if is_swap_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Whether `file` is named like a `JsonStore` swap file: `<address>_<date>_decoded_swaps.json`,
+// or a bare `<address>/<date>.json`. The other files of a pool directory add a suffix to
+// the date, such as `_candles_60s`, `_volumes_3600s` or `_events`.
+fn is_swap_file(file: &Path) -> bool {
+    let Some(name) = file.file_name().and_then(|name| name.to_str()) else { return false };
+    let Some(stem) = name.strip_suffix(".json.gz").or_else(|| name.strip_suffix(".json")) else { return false };
+    if stem.ends_with("_decoded_swaps") {
+        return true;
+    }
+    let in_pool_dir = file.parent().and_then(|dir| dir.file_name()).and_then(|dir| dir.to_str()).is_some_and(|dir| dir.starts_with("0x"));
+    in_pool_dir && !stem.split('_').any(|part| part.starts_with(|c: char| c.is_ascii_lowercase()))
+}
+
+// The pool a swap file belongs to: the start of `<address>_<date>_decoded_swaps.json`,
+// or the directory of `<address>/<date>.json`.
+fn pool_of(file: &Path) -> Option<String> {
+    let name = file.file_name()?.to_str()?;
+    let address = if name.contains("_decoded_swaps.") {
+        name.split('_').next()?
+    } else {
+        file.parent()?.file_name()?.to_str()?
+    };
+    address.starts_with("0x").then(|| address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use crate::block_summaries::{BlockSummary, BlockSummaryStore};
+    use crate::candles::{Candle, CandleStore};
+    use crate::data_store::{test_swap, DecodedData, EventRecord, JsonStore};
+    use crate::numeraire::{Numeraire, NumeraireVolume, VolumeStore};
+
+    // Records the pool each swap was stored under
+    #[derive(Default)]
+    struct PoolRecorder(Mutex<Vec<(String, i128)>>);
+
+    #[async_trait]
+    impl DataStore for PoolRecorder {
+        async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.0.lock().unwrap().push((address.to_string(), data.amount0));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pool_of() {
+        assert_eq!(pool_of(Path::new("data/0xab_2024-03-05_decoded_swaps.json")).as_deref(), Some("0xab"));
+        assert_eq!(pool_of(Path::new("data/0xab/2024-03-05.json.gz")).as_deref(), Some("0xab"));
+        assert_eq!(pool_of(Path::new("swaps.json")), None);
+    }
+
+    #[test]
+    fn test_is_swap_file() {
+        assert!(is_swap_file(Path::new("data/0xab_2024_03_05_decoded_swaps.json")));
+        assert!(is_swap_file(Path::new("data/0xab/2024_03_05.json.gz")));
+        assert!(!is_swap_file(Path::new("data/0xab_2024_03_05_events.json")));
+        assert!(!is_swap_file(Path::new("data/0xab/2024_03_05_candles_60s.json")));
+        assert!(!is_swap_file(Path::new("data/0xab/2024_03_05_blocks.json")));
+        assert!(!is_swap_file(Path::new("data/0xab_2024_03_05_volumes_3600s.json.gz")));
+        assert!(!is_swap_file(Path::new("data/0xab/2024_03_05.parquet")));
+    }
+
+    #[tokio::test]
+    async fn test_replay_directory_into_store() {
+        for partition_by_pool in [false, true] {
+            let output_dir = std::env::temp_dir().join(format!("replay_{}_{}", std::process::id(), partition_by_pool));
+            let json = JsonStore::new(&output_dir, partition_by_pool);
+            for n in 1..=3 {
+                json.store("0xpool", &test_swap(&format!("0x{:02}", n), n, -n)).await.unwrap();
+            }
+            json.store("0xother", &test_swap("0x04", 4, -4)).await.unwrap();
+            // The files written next to the swaps aren't replayed
+            let candle = Candle { open_time: 0, interval: 60, open: 1.0, high: 1.0, low: 1.0, close: 1.0, volume0: 1, volume1: 1, swaps: 1 };
+            json.store_candle("0xpool", &candle).await.unwrap();
+            let volume = NumeraireVolume { open_time: 0, interval: 3600, numeraire: Numeraire::Usd, volume: 1.0, swaps: 1, unpriced: 0 };
+            json.store_volume("0xpool", &volume).await.unwrap();
+            let transfer = EventRecord { event: "Transfer".to_string(), address: "0xpool".to_string(), fields: serde_json::json!({ "value": "1" }) };
+            json.store_event(&transfer).await.unwrap();
+            let summary = BlockSummary { block_number: 1, swaps: 1, net_amount0: 1, net_amount1: -1, close: 1.0 };
+            json.store_block_summary("0xpool", &summary).await.unwrap();
+
+            let target = PoolRecorder::default();
+            let replayed = replay(&output_dir, &target, None).await.unwrap();
+            std::fs::remove_dir_all(&output_dir).unwrap();
+
+            assert_eq!(replayed, 4);
+            let stored = target.0.into_inner().unwrap();
+            let pool = |address: &str| -> Vec<i128> {
+                stored.iter().filter(|(pool, _)| pool == address).map(|(_, amount0)| *amount0).collect()
+            };
+            assert_eq!(pool("0xpool"), vec![1, 2, 3]);
+            assert_eq!(pool("0xother"), vec![4]);
+        }
+    }
+}
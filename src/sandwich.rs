@@ -0,0 +1,217 @@
+use ethers::types::Log;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::data_store::DecodedData;
+use crate::log_processing::to_hex;
+
+
+/// SandwichSuspect A swap that looks sandwiched: bracketed in its block and pool by a
+/// front-running swap and a back-running swap of a related address.
+///
+/// This is a heuristic, not proof: two unrelated swaps routed through the same router
+/// contract look related, and an attack spread over several pools or disguised behind
+/// fresh addresses is missed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SandwichSuspect {
+    /// Always `sandwich_suspected`, telling the annotation apart from the swaps.
+    pub annotation: &'static str,
+    pub block_number: u64,
+    pub pool: String,
+    /// The address shared by the front and back runs, their sender or else their recipient.
+    pub attacker: String,
+    pub front_run: String,
+    pub victim: String,
+    pub back_run: String,
+}
+
+
+// A swap of a block waiting to be checked, with where it sits in the block.
+struct BlockSwap {
+    pool: String,
+    log_index: u64,
+    data: DecodedData,
+}
+
+
+/// SandwichDetector Buffers the swaps of each block and looks for sandwiches once the
+/// block is complete.
+///
+/// Within a block and a pool, in log order, a swap is suspected of being sandwiched
+/// when an earlier swap and a later one:
+/// * share their sender or their recipient, the attacker, which the victim doesn't,
+/// * go in opposite directions, the earlier one the same way as the victim,
+/// * come from transactions other than the victim's.
+///
+/// Like the block summaries, a block is complete once the head is more than one block
+/// past it, see `close_before`. Swaps arriving for a block already checked are ignored.
+#[derive(Default)]
+pub struct SandwichDetector {
+    blocks: BTreeMap<u64, Vec<BlockSwap>>,
+    // The last block checked, later swaps of it or of older blocks are too late
+    last_closed: Option<u64>,
+}
+
+impl SandwichDetector {
+    pub fn new() -> Self {
+        SandwichDetector::default()
+    }
+
+    /// Buffer the swap decoded from `log` until its block is complete.
+    pub fn push(&mut self, log: &Log, data: &DecodedData) {
+        let block_number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+        if self.last_closed.is_some_and(|closed| block_number <= closed) {
+            return;
+        }
+        self.blocks.entry(block_number).or_default().push(BlockSwap {
+            pool: data.pool.clone(),
+            log_index: log.log_index.map(|index| index.low_u64()).unwrap_or_default(),
+            data: data.clone(),
+        });
+    }
+
+    /// Drop the buffered swap decoded from `log`, which has been removed by a reorg.
+    pub fn remove(&mut self, log: &Log) {
+        let block_number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+        let Some(swaps) = self.blocks.get_mut(&block_number) else { return };
+        let transaction_hash = log.transaction_hash.map(|hash| to_hex(&hash.0)).unwrap_or_default();
+        let log_index = log.log_index.map(|index| index.low_u64()).unwrap_or_default();
+        swaps.retain(|swap| !(swap.log_index == log_index && swap.data.transaction_hash.eq_ignore_ascii_case(&transaction_hash)));
+    }
+
+    /// Drop the buffered swaps of block `first_block` and later, which a reorg replaced.
+    pub fn remove_from(&mut self, first_block: u64) {
+        self.blocks.split_off(&first_block);
+    }
+
+    /// Check the blocks the chain head `latest_block` is more than one block past,
+    /// returning the sandwiches found in them.
+    pub fn close_before(&mut self, latest_block: u64) -> Vec<SandwichSuspect> {
+        let still_open = self.blocks.split_off(&latest_block.saturating_sub(1));
+        let complete = std::mem::replace(&mut self.blocks, still_open);
+        self.check(complete)
+    }
+
+    /// Check every buffered block, e.g. on shutdown, complete or not.
+    pub fn flush(&mut self) -> Vec<SandwichSuspect> {
+        let complete = std::mem::take(&mut self.blocks);
+        self.check(complete)
+    }
+
+    fn check(&mut self, blocks: BTreeMap<u64, Vec<BlockSwap>>) -> Vec<SandwichSuspect> {
+        if let Some(&block_number) = blocks.keys().next_back() {
+            self.last_closed = Some(self.last_closed.map_or(block_number, |closed| closed.max(block_number)));
+        }
+        blocks
+            .into_iter()
+            .flat_map(|(block_number, mut swaps)| {
+                swaps.sort_by(|a, b| a.pool.cmp(&b.pool).then(a.log_index.cmp(&b.log_index)));
+                find_sandwiches(block_number, &swaps)
+            })
+            .collect()
+    }
+}
+
+
+// The sandwiches among the swaps of one block, sorted by pool then log index.
+fn find_sandwiches(block_number: u64, swaps: &[BlockSwap]) -> Vec<SandwichSuspect> {
+    let mut suspects = Vec::new();
+    for (front_index, front) in swaps.iter().enumerate() {
+        for (back_offset, back) in swaps[front_index + 1..].iter().enumerate() {
+            if back.pool != front.pool {
+                break;
+            }
+            let Some(attacker) = shared_address(&front.data, &back.data) else { continue };
+            if front.data.zero_for_one == back.data.zero_for_one {
+                continue;
+            }
+            let victims = &swaps[front_index + 1..front_index + 1 + back_offset];
+            for victim in victims {
+                let unrelated = shared_address(&victim.data, &front.data).is_none();
+                let other_transaction = victim.data.transaction_hash != front.data.transaction_hash
+                    && victim.data.transaction_hash != back.data.transaction_hash;
+                if unrelated && other_transaction && victim.data.zero_for_one == front.data.zero_for_one {
+                    suspects.push(SandwichSuspect {
+                        annotation: "sandwich_suspected",
+                        block_number,
+                        pool: front.pool.clone(),
+                        attacker: attacker.to_string(),
+                        front_run: front.data.transaction_hash.clone(),
+                        victim: victim.data.transaction_hash.clone(),
+                        back_run: back.data.transaction_hash.clone(),
+                    });
+                }
+            }
+        }
+    }
+    suspects
+}
+
+// The sender, or else the recipient, both swaps share.
+fn shared_address<'a>(a: &'a DecodedData, b: &DecodedData) -> Option<&'a str> {
+    if a.sender.eq_ignore_ascii_case(&b.sender) {
+        Some(&a.sender)
+    } else if a.recipient.eq_ignore_ascii_case(&b.recipient) {
+        Some(&a.recipient)
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use ethers::types::{H160, U256, U64};
+
+    fn swap(tx: &str, sender: &str, zero_for_one: bool) -> DecodedData {
+        let amount0 = if zero_for_one { 1 } else { -1 };
+        DecodedData { sender: sender.to_string(), recipient: sender.to_string(), pool: "0xpool".to_string(), ..test_swap(tx, amount0, -amount0) }
+    }
+
+    fn log(block_number: u64, log_index: u64) -> Log {
+        Log {
+            address: H160::repeat_byte(0x88),
+            block_number: Some(U64::from(block_number)),
+            log_index: Some(U256::from(log_index)),
+            ..Log::default()
+        }
+    }
+
+    #[test]
+    fn test_sandwich_is_suspected() {
+        let mut detector = SandwichDetector::new();
+        // Pushed out of order, as the workers may hand them over
+        detector.push(&log(10, 3), &swap("0xback", "0xbot", false));
+        detector.push(&log(10, 1), &swap("0xfront", "0xbot", true));
+        detector.push(&log(10, 2), &swap("0xvictim", "0xuser", true));
+        detector.push(&log(11, 1), &swap("0xother", "0xuser", true));
+
+        assert!(detector.close_before(11).is_empty());
+        let suspects = detector.close_before(12);
+        assert_eq!(suspects.len(), 1);
+        assert_eq!((suspects[0].front_run.as_str(), suspects[0].victim.as_str(), suspects[0].back_run.as_str()), ("0xfront", "0xvictim", "0xback"));
+        assert_eq!((suspects[0].block_number, suspects[0].attacker.as_str(), suspects[0].pool.as_str()), (10, "0xbot", "0xpool"));
+
+        // Block 10 was checked, its late swaps are dropped
+        detector.push(&log(10, 4), &swap("0xlate", "0xbot", true));
+        assert!(detector.flush().is_empty());
+    }
+
+    #[test]
+    fn test_unrelated_or_same_direction_swaps_are_not_suspected() {
+        let mut detector = SandwichDetector::new();
+        detector.push(&log(10, 1), &swap("0xa", "0xbot", true));
+        detector.push(&log(10, 2), &swap("0xb", "0xuser", true));
+        detector.push(&log(10, 3), &swap("0xc", "0xbot", true));
+        detector.push(&log(10, 4), &swap("0xd", "0xother", false));
+        assert!(detector.flush().is_empty());
+
+        // The victim going the other way is no sandwich
+        detector.push(&log(20, 1), &swap("0xa", "0xbot", true));
+        detector.push(&log(20, 2), &swap("0xb", "0xuser", false));
+        detector.push(&log(20, 3), &swap("0xc", "0xbot", false));
+        assert!(detector.flush().is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use ethers::abi::{ethabi, Abi, Token};
+use ethers::types::{Log, I256};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::decoders::{token_to_string, EventDecoder};
+use crate::error::TaskError;
+use crate::log_processing::to_hex;
+
+
+/// FieldType How a schema field is written in the JSON of a decoded event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    /// An address param, as lowercase 0x-prefixed hex.
+    Address,
+    /// An unsigned integer param of any size, as a decimal string.
+    Uint,
+    /// A signed integer param of any size, as a decimal string.
+    Int,
+    /// An integer param as a JSON number. Fails for values beyond 64 bits.
+    Number,
+    Bool,
+    /// A bytes param, as 0x-prefixed hex.
+    Bytes,
+    /// Any param, stringified by `token_to_string`.
+    String,
+}
+
+impl FieldType {
+    /// The JSON of `token` as this type, None when the token isn't of the type.
+    pub fn to_json(self, token: &Token) -> Option<Value> {
+        match (self, token) {
+            (FieldType::Address, Token::Address(address)) => Some(Value::String(to_hex(&address.0))),
+            (FieldType::Uint, Token::Uint(value)) => Some(Value::String(value.to_string())),
+            (FieldType::Int, Token::Int(value)) => Some(Value::String(I256::from_raw(*value).to_string())),
+            (FieldType::Number, Token::Uint(value)) => (value.bits() <= 64).then(|| Value::from(value.as_u64())),
+            (FieldType::Number, Token::Int(value)) => i64::try_from(I256::from_raw(*value)).ok().map(Value::from),
+            (FieldType::Bool, Token::Bool(value)) => Some(Value::Bool(*value)),
+            (FieldType::Bytes, Token::Bytes(bytes) | Token::FixedBytes(bytes)) => Some(Value::String(to_hex(bytes))),
+            (FieldType::String, token) => Some(Value::String(token_to_string(token))),
+            _ => None,
+        }
+    }
+}
+
+
+/// SchemaField One param of an event extracted into the decoded JSON: the ABI param
+/// `param`, written as `field`, typed as `type`. `field` defaults to the param name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaField {
+    pub param: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+}
+
+impl SchemaField {
+    fn new(param: &str, field: &str, kind: FieldType) -> Self {
+        SchemaField { param: param.to_string(), field: Some(field.to_string()), kind }
+    }
+
+    /// The name the param is written as.
+    pub fn field_name(&self) -> &str {
+        self.field.as_deref().unwrap_or(&self.param)
+    }
+}
+
+
+/// SchemaRegistry Which params of each event are decoded, and how they're typed, keyed by
+/// event name, so new events are decoded without code changes.
+///
+/// Loaded from a JSON file mapping event names to their fields, e.g.
+///
+/// ```json
+/// {"Flash": [{"param": "sender", "type": "address"}, {"param": "paid0", "field": "fee0", "type": "uint"}]}
+/// ```
+///
+/// on top of the built-in `Swap` schema, which the file may replace. Each event is decoded
+/// by a `SchemaDecoder` into a JSON object of the transaction hash and its fields, except
+/// for the built-in `Swap` schema, which describes the typed `SwapDecoder` and keeps
+/// its fast path to the stores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, Vec<SchemaField>>,
+}
+
+impl SchemaRegistry {
+    /// The fields of the Uniswap V3 `Swap` event, as `SwapDecoder` decodes them.
+    pub fn swap_schema() -> Vec<SchemaField> {
+        vec![
+            SchemaField::new("sender", "sender", FieldType::Address),
+            SchemaField::new("recipient", "recipient", FieldType::Address),
+            SchemaField::new("amount0", "amount0", FieldType::Int),
+            SchemaField::new("amount1", "amount1", FieldType::Int),
+            SchemaField::new("sqrtPriceX96", "sqrt_price_x96", FieldType::Uint),
+            SchemaField::new("liquidity", "liquidity", FieldType::Uint),
+            SchemaField::new("tick", "tick", FieldType::Number),
+        ]
+    }
+
+    /// The registry with the built-in `Swap` schema only.
+    pub fn builtin() -> Self {
+        SchemaRegistry { schemas: BTreeMap::from([("Swap".to_string(), Self::swap_schema())]) }
+    }
+
+    /// The built-in schemas, with those of the registry `json` added or replacing them.
+    pub fn from_json(json: &str) -> Result<Self, TaskError> {
+        let schemas: BTreeMap<String, Vec<SchemaField>> =
+            serde_json::from_str(json).map_err(|e| TaskError::InvalidConfig(format!("invalid schema registry: {}", e)))?;
+        let mut registry = SchemaRegistry::builtin();
+        for (event_name, fields) in schemas {
+            if fields.is_empty() {
+                return Err(TaskError::InvalidConfig(format!("the schema of {} has no fields", event_name)));
+            }
+            registry.schemas.insert(event_name, fields);
+        }
+        Ok(registry)
+    }
+
+    /// The registry of the JSON file at `path`, see `from_json`.
+    pub fn load(path: &Path) -> Result<Self, TaskError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| TaskError::InvalidConfig(format!("failed to read the schema registry {}: {}", path.display(), e)))?;
+        SchemaRegistry::from_json(&json)
+    }
+
+    pub fn get(&self, event_name: &str) -> Option<&[SchemaField]> {
+        self.schemas.get(event_name).map(Vec::as_slice)
+    }
+
+    /// The schemas keyed by event name, in name order.
+    pub fn schemas(&self) -> impl Iterator<Item = (&str, &[SchemaField])> {
+        self.schemas.iter().map(|(name, fields)| (name.as_str(), fields.as_slice()))
+    }
+
+    /// Whether `event_name` is decoded by a typed decoder rather than a `SchemaDecoder`.
+    pub fn is_typed(&self, event_name: &str) -> bool {
+        event_name == "Swap" && self.get(event_name) == Some(Self::swap_schema().as_slice())
+    }
+
+    /// Fail unless every event decoded by its schema is in `abi` with every param the
+    /// schema reads. The events left to typed decoders aren't checked.
+    pub fn check(&self, abi: &Abi) -> Result<(), TaskError> {
+        for (event_name, fields) in self.schemas().filter(|(event_name, _)| !self.is_typed(event_name)) {
+            let event = abi
+                .event(event_name)
+                .map_err(|_| TaskError::InvalidConfig(format!("the schema of {} is for an event not in the ABI", event_name)))?;
+            if let Some(field) = fields.iter().find(|field| !event.inputs.iter().any(|input| input.name == field.param)) {
+                return Err(TaskError::InvalidConfig(format!("the schema of {} reads {}, which the event doesn't have", event_name, field.param)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// SchemaDecoder Decodes the logs of one event as its schema says: a JSON object of the
+/// transaction hash and every schema field. A log whose params don't have the schema's
+/// types doesn't decode.
+pub struct SchemaDecoder {
+    event_name: String,
+    fields: Vec<SchemaField>,
+}
+
+impl SchemaDecoder {
+    pub fn new(event_name: &str, fields: &[SchemaField]) -> Self {
+        SchemaDecoder { event_name: event_name.to_string(), fields: fields.to_vec() }
+    }
+}
+
+impl EventDecoder for SchemaDecoder {
+    fn event_name(&self) -> &str {
+        &self.event_name
+    }
+
+    fn decode(&self, decoded: ethabi::Log, log: &Log) -> Option<Value> {
+        let mut object = Map::new();
+        object.insert("transaction_hash".to_string(), Value::String(to_hex(&log.transaction_hash?.0)));
+        for field in &self.fields {
+            let param = decoded.params.iter().find(|param| param.name == field.param)?;
+            object.insert(field.field_name().to_string(), field.kind.to_json(&param.value)?);
+        }
+        Some(Value::Object(object))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::RawLog;
+    use ethers::types::{H256, U256};
+    use std::str::FromStr;
+
+    fn abi() -> Abi {
+        serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap()
+    }
+
+    // The Swap in tx 0x13f84c56...00f3 of the USDC/WETH pool, parsed with the ABI
+    fn swap_log() -> (ethabi::Log, Log) {
+        let event = abi().event("Swap").unwrap().clone();
+        let log = Log {
+            topics: vec![
+                event.signature(),
+                H256::from_str("0x000000000000000000000000d7f3fbe8c72a961a5515203eada59750437fa762").unwrap(),
+                H256::from_str("0x0000000000000000000000001c09a10047fcc944efde9226e259eddfde2c1cf0").unwrap(),
+            ],
+            data: ethers::types::Bytes::from_str("0x0000000000000000000000000000000000000000000000000000000d92cae287fffffffffffffffffffffffffffffffffffffffffffffffdfe6d04e32064349f0000000000000000000000000000000000006270c87ad64fc69a7baa1492b4f20000000000000000000000000000000000000000000000017c7599806e23275900000000000000000000000000000000000000000000000000000000000317ce").unwrap(),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            ..Default::default()
+        };
+        let decoded = event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() }).unwrap();
+        (decoded, log)
+    }
+
+    #[test]
+    fn test_builtin_swap_schema() {
+        let registry = SchemaRegistry::builtin();
+        registry.check(&abi()).unwrap();
+        assert!(registry.is_typed("Swap"));
+
+        let (decoded, log) = swap_log();
+        let fields = SchemaDecoder::new("Swap", registry.get("Swap").unwrap()).decode(decoded, &log).unwrap();
+        assert_eq!(fields["sender"], "0xd7f3fbe8c72a961a5515203eada59750437fa762");
+        assert_eq!(fields["amount0"], "58297344647");
+        assert_eq!(fields["amount1"], "-37006917189485972321");
+        assert_eq!(fields["sqrt_price_x96"], "1996611740862433600358475292128498");
+        assert_eq!(fields["tick"], 202702);
+    }
+
+    #[test]
+    fn test_schema_registry_file() {
+        let registry = SchemaRegistry::from_json(r#"{
+            "Flash": [{"param": "sender", "type": "address"}, {"param": "paid0", "field": "fee0", "type": "number"}],
+            "Swap": [{"param": "tick", "type": "string"}]
+        }"#).unwrap();
+        registry.check(&abi()).unwrap();
+        assert_eq!(registry.schemas().map(|(name, _)| name).collect::<Vec<_>>(), ["Flash", "Swap"]);
+        assert_eq!(registry.get("Flash").unwrap()[1].field_name(), "fee0");
+        // The Swap schema was replaced, it's no longer the typed one
+        assert!(!registry.is_typed("Swap"));
+        let (decoded, log) = swap_log();
+        assert_eq!(SchemaDecoder::new("Swap", registry.get("Swap").unwrap()).decode(decoded, &log).unwrap()["tick"], "202702");
+
+        // A param of the wrong type doesn't decode
+        let (decoded, log) = swap_log();
+        let wrong = [SchemaField { param: "amount0".to_string(), field: None, kind: FieldType::Uint }];
+        assert_eq!(SchemaDecoder::new("Swap", &wrong).decode(decoded, &log), None);
+
+        for (json, error) in [
+            (r#"{"Flash": []}"#, "has no fields"),
+            (r#"{"Flash": [{"param": "sender", "type": "u256"}]}"#, "invalid schema registry"),
+        ] {
+            assert!(SchemaRegistry::from_json(json).unwrap_err().to_string().contains(error), "{}", json);
+        }
+        for (json, error) in [
+            (r#"{"Sync": [{"param": "reserve0", "type": "uint"}]}"#, "not in the ABI"),
+            (r#"{"Flash": [{"param": "fee", "type": "uint"}]}"#, "reads fee"),
+        ] {
+            assert!(SchemaRegistry::from_json(json).unwrap().check(&abi()).unwrap_err().to_string().contains(error), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_number_field_bounds() {
+        assert_eq!(FieldType::Number.to_json(&Token::Uint(U256::from(u64::MAX))), Some(Value::from(u64::MAX)));
+        assert_eq!(FieldType::Number.to_json(&Token::Uint(U256::from(u64::MAX) + 1)), None);
+        assert_eq!(FieldType::Number.to_json(&Token::Int(I256::from(-5).into_raw())), Some(Value::from(-5)));
+        assert_eq!(FieldType::Bool.to_json(&Token::Uint(U256::one())), None);
+    }
+}
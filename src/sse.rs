@@ -0,0 +1,121 @@
+use hyper::body::Sender;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use crate::data_store::DecodedData;
+use crate::metrics::Metrics;
+use crate::recent::RecentSwaps;
+
+
+/// serve_sse Serves `GET /events` on `addr`, a Server-Sent Events stream sending every
+/// swap decoded from then on as a `data:` event holding its JSON.
+///
+/// Each client is fed from its own receiver of the `Metrics::recent_swaps` broadcast, so the
+/// decode workers never wait on a client: one that falls `RecentSwaps::BROADCAST_CAPACITY`
+/// swaps behind is disconnected instead. Once `shutdown` completes every stream is
+/// ended, and the server returns once the clients are gone.
+pub async fn serve_sse(addr: SocketAddr, metrics: Arc<Metrics>, shutdown: impl Future<Output = ()>) -> Result<(), hyper::Error> {
+    let (closed_tx, closed_rx) = watch::channel(false);
+    let make_service = make_service_fn(move |_| {
+        let (metrics, closed) = (Arc::clone(&metrics), closed_rx.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = sse_response(&request, metrics.recent_swaps(), closed.clone());
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    Server::try_bind(&addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            // The streams never end by themselves
+            let _ = closed_tx.send(true);
+        })
+        .await
+}
+
+// Answer a request to the SSE server, streaming the swaps to `/events` until `closed`.
+fn sse_response(request: &Request<Body>, recent: &RecentSwaps, closed: watch::Receiver<bool>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/events" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let (sender, body) = Body::channel();
+    tokio::spawn(stream_swaps(recent.subscribe(), sender, closed));
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/event-stream"));
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    // The web UI is usually served from another origin
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, header::HeaderValue::from_static("*"));
+    response
+}
+
+// Send the swaps of `swaps` to one client until it disconnects, lags or the server closes.
+async fn stream_swaps(mut swaps: broadcast::Receiver<DecodedData>, mut client: Sender, mut closed: watch::Receiver<bool>) {
+    while !*closed.borrow() {
+        let swap = tokio::select! {
+            swap = swaps.recv() => swap,
+            _ = closed.changed() => return,
+        };
+        let swap = match swap {
+            Ok(swap) => swap,
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!("Warning: dropping an SSE client {} swaps behind", missed);
+                return;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let json = match serde_json::to_string(&swap) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Error serializing swap {} for SSE: {}", swap.transaction_hash, e);
+                continue;
+            }
+        };
+        // Fails once the client is gone
+        if client.send_data(format!("data: {}\n\n", json).into()).await.is_err() {
+            return;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_store::test_swap;
+    use hyper::body::HttpBody;
+
+    #[tokio::test]
+    async fn test_events_stream() {
+        let recent = RecentSwaps::default();
+        let (closed_tx, closed_rx) = watch::channel(false);
+        let request = Request::get("/events").body(Body::empty()).unwrap();
+        let mut response = sse_response(&request, &recent, closed_rx.clone());
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        recent.push("0xpool", &DecodedData { pool: "0xpool".to_string(), ..test_swap("a", 1, -1) });
+        let event = response.body_mut().data().await.unwrap().unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        let json: serde_json::Value = serde_json::from_str(event.strip_prefix("data: ").unwrap().trim_end()).unwrap();
+        assert_eq!((json["transaction_hash"].as_str(), json["pool"].as_str()), (Some("a"), Some("0xpool")));
+
+        // Closing ends the stream
+        closed_tx.send(true).unwrap();
+        assert!(response.body_mut().data().await.is_none());
+
+        let request = Request::get("/recent").body(Body::empty()).unwrap();
+        assert_eq!(sse_response(&request, &recent, closed_rx).status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,20 +1,11 @@
-use tiny_keccak::Keccak;
-use tiny_keccak::Hasher;
 use hex_literal::hex;
 
-fn keccak256(data: &[u8]) -> [u8; 32] {
-    let mut keccak = Keccak::v256();
-    keccak.update(data);
-    let mut output = [0u8; 32];
-    keccak.finalize(&mut output);
-    output
-}
+use crate::utils::event_signature_hash;
 
 pub fn test_hash() {
-    // guess: The following won't match because it is the event signature and not the ABI
-    // event signature, re the map creation
-    let signature = "Swap(address,address,uint256,uint256,uint256,uint256,uint256)";
-    let computed_hash = keccak256(signature.as_bytes());
+    // The ABI signature, with the exact param types, hashes to the topic0 of the Swap logs
+    let signature = "Swap(address,address,int256,int256,uint160,uint128,int24)";
+    let computed_hash = event_signature_hash(signature);
     let expected_hash = hex!("c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67");
     println!("Computed hash: {:x?}", computed_hash);
     println!("Expected hash: {:x?}", expected_hash);
@@ -0,0 +1,174 @@
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, TransactionRequest};
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::candles::PriceQuote;
+use crate::log_processing::parse_address;
+
+
+// Selectors of the Uniswap V3 pool `token0()`/`token1()` and ERC-20 `decimals()` getters
+const TOKEN0_SELECTOR: [u8; 4] = [0x0d, 0xfe, 0x16, 0x81];
+const TOKEN1_SELECTOR: [u8; 4] = [0xd2, 0x12, 0x20, 0xa7];
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+
+/// PoolDecimals The decimals of the two tokens of one pool, given as
+/// `<pool address>:<token0 decimals>:<token1 decimals>`, e.g.
+/// `0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:6:18` for USDC/WETH.
+///
+/// They are trusted as given: decimals that don't match the tokens skew every price
+/// of the pool by a power of ten, without any error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolDecimals {
+    /// The pool address, lowercase.
+    pub pool: String,
+    pub decimals0: u8,
+    pub decimals1: u8,
+}
+
+impl FromStr for PoolDecimals {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pool decimals {:?}, expected <pool>:<decimals0>:<decimals1>", s);
+        let mut parts = s.split(':').map(str::trim);
+        let (Some(pool), Some(decimals0), Some(decimals1), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        if pool.is_empty() {
+            return Err(invalid());
+        }
+        parse_address(pool).map_err(|e| e.to_string())?;
+        Ok(PoolDecimals {
+            pool: pool.to_lowercase(),
+            decimals0: decimals0.parse().map_err(|_| invalid())?,
+            decimals1: decimals1.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+
+/// PriceQuotes The `PriceQuote` of every pool, for prices of swaps of several pools.
+///
+/// A pool without decimals of its own is quoted with `default`.
+#[derive(Debug, Clone, Default)]
+pub struct PriceQuotes {
+    default: PriceQuote,
+    pools: HashMap<String, PriceQuote>,
+}
+
+impl PriceQuotes {
+    /// Quotes oriented like `default`, with the decimals of `pools` for their pool.
+    pub fn new(default: PriceQuote, pools: &[PoolDecimals]) -> Self {
+        let mut quotes = PriceQuotes { default, pools: HashMap::new() };
+        for pool in pools {
+            quotes.insert(pool);
+        }
+        quotes
+    }
+
+    /// Quote the pool of `decimals` with them, replacing any decimals it had.
+    pub fn insert(&mut self, decimals: &PoolDecimals) {
+        let quote = PriceQuote { decimals0: decimals.decimals0, decimals1: decimals.decimals1, ..self.default };
+        self.pools.insert(decimals.pool.to_lowercase(), quote);
+    }
+
+    /// The quote of the pool at `pool`, in any case.
+    pub fn for_pool(&self, pool: &str) -> PriceQuote {
+        self.pools.get(&pool.to_lowercase()).copied().unwrap_or(self.default)
+    }
+
+    /// Whether `pool` has decimals of its own.
+    pub fn has_pool(&self, pool: &str) -> bool {
+        self.pools.contains_key(&pool.to_lowercase())
+    }
+}
+
+
+/// fetch_pool_decimals Looks the decimals of a pool's tokens up on chain: the pool's
+/// `token0()` and `token1()`, then each token's `decimals()`.
+///
+/// Four `eth_call`s, so known pools are better given with `PoolDecimals`.
+pub async fn fetch_pool_decimals<M: Middleware>(provider: &M, pool: Address) -> Result<PoolDecimals, Box<dyn Error + Send + Sync>>
+where
+    M::Error: 'static,
+{
+    let token0 = word_to_address(&call_word(provider, pool, TOKEN0_SELECTOR).await?);
+    let token1 = word_to_address(&call_word(provider, pool, TOKEN1_SELECTOR).await?);
+    Ok(PoolDecimals {
+        pool: format!("{:?}", pool),
+        decimals0: word_to_u8(&call_word(provider, token0, DECIMALS_SELECTOR).await?)?,
+        decimals1: word_to_u8(&call_word(provider, token1, DECIMALS_SELECTOR).await?)?,
+    })
+}
+
+// Call the argument-less getter `selector` of the contract at `to`, returning the first word of the output.
+async fn call_word<M: Middleware>(provider: &M, to: Address, selector: [u8; 4]) -> Result<[u8; 32], Box<dyn Error + Send + Sync>>
+where
+    M::Error: 'static,
+{
+    let tx: TypedTransaction = TransactionRequest::new().to(to).data(selector.to_vec()).into();
+    let output = provider.call(&tx, None).await?;
+    output
+        .get(..32)
+        .and_then(|word| <[u8; 32]>::try_from(word).ok())
+        .ok_or_else(|| format!("Call to {:?} returned {} bytes, expected a 32 byte word", to, output.len()).into())
+}
+
+// An ABI-encoded address is right-aligned in its word.
+fn word_to_address(word: &[u8; 32]) -> Address {
+    Address::from_slice(&word[12..])
+}
+
+// An ABI-encoded uint8, rejecting words that don't fit one.
+fn word_to_u8(word: &[u8; 32]) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if word[..31].iter().any(|&byte| byte != 0) {
+        return Err("decimals() returned a value above 255".into());
+    }
+    Ok(word[31])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pool_decimals() {
+        let decimals: PoolDecimals = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640:6:18".parse().unwrap();
+        assert_eq!(decimals, PoolDecimals { pool: "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640".to_string(), decimals0: 6, decimals1: 18 });
+
+        assert!("0xpool:6:18".parse::<PoolDecimals>().unwrap_err().contains("invalid address \"0xpool\""));
+        assert!("0xpool:6".parse::<PoolDecimals>().is_err());
+        assert!("0xpool:6:18:1".parse::<PoolDecimals>().is_err());
+        assert!("0xpool:6:256".parse::<PoolDecimals>().is_err());
+        assert!(":6:18".parse::<PoolDecimals>().is_err());
+    }
+
+    #[test]
+    fn test_price_quotes_per_pool() {
+        let default = PriceQuote { invert: true, decimals0: 0, decimals1: 0 };
+        let (lowercase, uppercase) = (format!("0x{}", "ab".repeat(20)), format!("0x{}", "AB".repeat(20)));
+        let quotes = PriceQuotes::new(default, &[format!("{}:6:18", uppercase).parse().unwrap()]);
+
+        let quote = quotes.for_pool(&lowercase);
+        assert_eq!((quote.invert, quote.decimals0, quote.decimals1), (true, 6, 18));
+        assert!(quotes.has_pool(&uppercase));
+        assert_eq!(quotes.for_pool("0xcd").decimals0, 0);
+    }
+
+    #[test]
+    fn test_decode_words() {
+        let mut word = [0u8; 32];
+        word[31] = 18;
+        assert_eq!(word_to_u8(&word).unwrap(), 18);
+        word[30] = 1;
+        assert!(word_to_u8(&word).is_err());
+
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&[0xa0; 20]);
+        assert_eq!(word_to_address(&word), Address::from_slice(&[0xa0; 20]));
+    }
+}
@@ -1,16 +1,17 @@
 use ethers::{
-    core::types::Log,
+    abi::{Event, EventExt},
+    core::types::{Log, H256},
+    utils::keccak256,
 };
 use crate::log_processing::to_hex;
 use std::env;
-use std::path::{PathBuf};
 
 pub fn root_dir() -> Option<String> {
     // Get the current directory
     let current_dir = env::current_dir().ok()?;
 
     // Define the path to the root directory
-    let mut root_dir = PathBuf::from(current_dir);
+    let mut root_dir = current_dir;
 
     // Iterate upwards from the current directory to find the root directory
     while !root_dir.join(".git").exists() {
@@ -23,6 +24,21 @@ pub fn root_dir() -> Option<String> {
     Some(root_dir.to_string_lossy().into_owned())
 }
 
+/// event_signature_hash The Keccak-256 hash of an event signature such as
+/// `Swap(address,address,int256,int256,uint160,uint128,int24)`, which is the topic0 of
+/// the event's logs. The signature holds the param types only, as `Event::abi_signature`
+/// writes it, without names, `indexed` or spaces.
+pub fn event_signature_hash(signature: &str) -> [u8; 32] {
+    keccak256(signature.as_bytes())
+}
+
+/// verify_event_signature Whether the logs of the ABI event `event` have
+/// `expected_topic0` as their topic0, e.g. to check an ABI against the logs of the chain.
+/// Anonymous events have no signature topic and never match.
+pub fn verify_event_signature(event: &Event, expected_topic0: H256) -> bool {
+    !event.anonymous && H256(event_signature_hash(&event.abi_signature())) == expected_topic0
+}
+
 pub fn pretty_print_log(log: &Log)  {
     println!("Address: {}", to_hex(&log.address.0)); // Assuming Address is H160 type
     println!("Topics:");
@@ -55,3 +71,36 @@ pub fn pretty_print_log(log: &Log)  {
         println!("Removed: {}", removed);
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{EventParam, ParamType};
+    use hex_literal::hex;
+
+    const SWAP_TOPIC0: [u8; 32] = hex!("c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67");
+
+    #[test]
+    fn test_event_signature_hash() {
+        assert_eq!(event_signature_hash("Swap(address,address,int256,int256,uint160,uint128,int24)"), SWAP_TOPIC0);
+        // The types must be the exact ABI ones
+        assert_ne!(event_signature_hash("Swap(address,address,uint256,uint256,uint256,uint256,uint256)"), SWAP_TOPIC0);
+    }
+
+    #[test]
+    fn test_verify_event_signature() {
+        let abi: ethers::abi::Abi = serde_json::from_str(&std::fs::read_to_string("src/abi.json").unwrap()).unwrap();
+        let swap = abi.event("Swap").unwrap();
+        assert!(verify_event_signature(swap, H256(SWAP_TOPIC0)));
+        assert!(!verify_event_signature(abi.event("Mint").unwrap(), H256(SWAP_TOPIC0)));
+
+        let anonymous = Event { anonymous: true, ..swap.clone() };
+        assert!(!verify_event_signature(&anonymous, H256(SWAP_TOPIC0)));
+        let renamed = Event {
+            inputs: vec![EventParam { name: "amount".to_string(), kind: ParamType::Uint(256), indexed: false }],
+            ..swap.clone()
+        };
+        assert!(!verify_event_signature(&renamed, H256(SWAP_TOPIC0)));
+    }
+}
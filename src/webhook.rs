@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+use crate::data_store::{DataStore, DecodedData};
+
+
+/// WebhookStore POSTs every decoded swap as JSON to an HTTP endpoint.
+///
+/// It is a `DataStore` like the file stores, so it can be used next to them. The swaps
+/// are queued and POSTed in order from a spawned task, like `filters::webhook_callback`
+/// does, so a slow endpoint doesn't hold up storage. Each request times out after
+/// `timeout` and a failed POST is retried up to `retries` times with an exponential
+/// backoff, after which the swap is logged and given up on. Once `QUEUE_CAPACITY` swaps
+/// wait, the next ones are dropped with an error. `flush` waits for the queued swaps.
+pub struct WebhookStore {
+    url: String,
+    // The swaps waiting to be POSTed, None once flushed
+    queue: Mutex<Option<mpsc::Sender<(String, DecodedData)>>>,
+    // The task POSTing the queued swaps
+    poster: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebhookStore {
+    /// How many swaps wait to be POSTed at most.
+    pub const QUEUE_CAPACITY: usize = 1024;
+
+    /// A webhook POSTing to `url` from a task spawned on the current runtime.
+    pub fn new(url: impl Into<String>, timeout: Duration, retries: u32) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        let url = url.into();
+        let (queue, swaps) = mpsc::channel(Self::QUEUE_CAPACITY);
+        let poster = tokio::spawn(post_swaps(client, url.clone(), retries, swaps));
+        Ok(WebhookStore { url, queue: Mutex::new(Some(queue)), poster: Mutex::new(Some(poster)) })
+    }
+}
+
+#[async_trait]
+impl DataStore for WebhookStore {
+    async fn store(&self, address: &str, data: &DecodedData) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let Some(queue) = queue else {
+            return Err(format!("the webhook to {} was already flushed", self.url).into());
+        };
+        match queue.try_send((address.to_string(), data.clone())) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                Err(format!("{} swaps are waiting for the webhook, dropping swap {}", Self::QUEUE_CAPACITY, data.transaction_hash).into())
+            }
+            Err(TrySendError::Closed(_)) => Err(format!("the webhook to {} has stopped", self.url).into()),
+        }
+    }
+
+    async fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Closing the queue ends the task once it has POSTed what is left
+        drop(self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take());
+        let poster = self.poster.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(poster) = poster {
+            poster.await?;
+        }
+        Ok(())
+    }
+}
+
+// POST the queued swaps in order until the queue is closed. A swap whose POST failed
+// through every retry is logged and given up on.
+async fn post_swaps(client: reqwest::Client, url: String, retries: u32, mut swaps: mpsc::Receiver<(String, DecodedData)>) {
+    while let Some((address, data)) = swaps.recv().await {
+        if let Err(e) = post_swap(&client, &url, retries, &address, &data).await {
+            tracing::warn!(tx_hash = %data.transaction_hash, error = %e, "Failed to POST swap to the webhook");
+        }
+    }
+}
+
+// POST one swap, retrying up to `retries` times.
+async fn post_swap(client: &reqwest::Client, url: &str, retries: u32, address: &str, data: &DecodedData) -> Result<(), reqwest::Error> {
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .post(url)
+            .query(&[("address", address)])
+            .json(data)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < retries => {
+                tracing::warn!(url, attempt, error = %e, "Webhook POST failed, retrying");
+                tokio::time::sleep(retry_delay(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Wait 500ms before the first retry, doubling for every following one.
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500).saturating_mul(2u32.saturating_pow(attempt))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(500));
+        assert_eq!(retry_delay(3), Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn test_a_slow_endpoint_doesnt_hold_up_storage() {
+        // Takes 300ms to answer each POST
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/swaps", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut hashes = Vec::new();
+            while hashes.len() < 3 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // The body is the last part of the request, a JSON object
+                while !request.ends_with(b"}") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    assert!(read > 0, "the request ended early");
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let body = String::from_utf8_lossy(&request);
+                let swap: DecodedData = serde_json::from_str(&body[body.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+                hashes.push(swap.transaction_hash);
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.unwrap();
+            }
+            hashes
+        });
+
+        let webhook = WebhookStore::new(url, Duration::from_secs(10), 0).unwrap();
+        let started = Instant::now();
+        for tx in ["0x1", "0x2", "0x3"] {
+            let swap = DecodedData { transaction_hash: tx.to_string(), ..DecodedData::default() };
+            webhook.store("0xpool", &swap).await.unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        // Flushing waits for every queued swap, POSTed in order
+        webhook.flush().await.unwrap();
+        assert_eq!(server.await.unwrap(), ["0x1", "0x2", "0x3"]);
+        assert!(webhook.store("0xpool", &DecodedData::default()).await.unwrap_err().to_string().contains("already flushed"));
+    }
+}
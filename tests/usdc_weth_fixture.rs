@@ -0,0 +1,82 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tasks::abi::default_abi;
+use tasks::candles::PriceQuote;
+use tasks::data_store::InMemoryStore;
+use tasks::decoders::DecoderRegistry;
+use tasks::log_processing::build_event_map;
+use tasks::log_sources::{feed_logs, read_log_file, OfflineLookups};
+use tasks::metrics::Metrics;
+use tasks::pipeline::{spawn_workers, Writer};
+use tasks::token_decimals::PriceQuotes;
+use tokio::sync::mpsc;
+
+const POOL: &str = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+// 100 swaps of the USDC/WETH 0.05% pool recorded on 2023-10-11, the first ones of the
+// file in `data`, as the logs they were decoded from. Their block numbers and log
+// indexes are placeholders, four logs a block, the topics and data are the real ones.
+const FIXTURE: &str = "tests/fixtures/usdc_weth_swaps.json";
+
+#[tokio::test]
+async fn test_recorded_swaps_decode_and_store() {
+    check_recorded_swaps().await;
+}
+
+// The workers decode in parallel, the final head must still reach the writer behind
+// every swap
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_recorded_swaps_decode_and_store_in_parallel() {
+    for _ in 0..10 {
+        check_recorded_swaps().await;
+    }
+}
+
+async fn check_recorded_swaps() {
+    let recorded = read_log_file(Path::new(FIXTURE)).unwrap();
+    assert_eq!(recorded.len(), 100);
+
+    let event_map = Arc::new(build_event_map(default_abi()).unwrap());
+    let metrics = Arc::new(Metrics::default());
+    let store = InMemoryStore::new();
+    let (log_tx, log_rx) = mpsc::channel(16);
+    let (writer_tx, writer_rx) = mpsc::channel(16);
+    let workers = spawn_workers(4, log_rx, event_map, Arc::new(DecoderRegistry::default()), Arc::clone(&metrics), None, writer_tx);
+    let quotes = PriceQuotes::default();
+    let writer = Writer { confirmations: 2, metrics: Some(&metrics), ..Writer::new(&store, &quotes, &OfflineLookups) };
+    tokio::join!(feed_logs(recorded.clone(), 2, log_tx), writer.run(writer_rx));
+    for worker in workers {
+        worker.await.unwrap();
+    }
+
+    // Every log decodes, and is stored once
+    let snapshot = metrics.snapshot();
+    assert_eq!((snapshot.logs_seen, snapshot.decoded, snapshot.skipped, snapshot.errors), (100, 100, 0, 0));
+    let mut swaps = store.records();
+    assert_eq!(swaps.len(), 100);
+    swaps.sort_by(|a, b| a.transaction_hash.cmp(&b.transaction_hash));
+    swaps.dedup_by(|a, b| a.transaction_hash == b.transaction_hash);
+    assert_eq!(swaps.len(), 100);
+
+    let mut hashes: Vec<String> = recorded.iter().map(|log| format!("{:?}", log.transaction_hash.unwrap())).collect();
+    hashes.sort();
+    let usdc_per_weth = PriceQuote { invert: true, decimals0: 6, decimals1: 18 };
+    for (swap, hash) in swaps.iter().zip(&hashes) {
+        assert_eq!(&swap.transaction_hash, hash);
+        assert!(is_hash(&swap.transaction_hash), "{}", swap.transaction_hash);
+        assert_eq!(swap.pool, POOL.to_lowercase());
+        // One token goes in, the other out
+        assert!(swap.amount0.signum() == -swap.amount1.signum() && swap.amount0 != 0, "{:?}", swap);
+        assert_eq!(swap.zero_for_one, swap.amount0 > 0);
+        assert_eq!((swap.amount0_abs, swap.amount1_abs), (swap.amount0.unsigned_abs(), swap.amount1.unsigned_abs()));
+
+        let price = usdc_per_weth.price(swap.sqrtPriceX96);
+        assert!((1_000.0..3_000.0).contains(&price), "{} USDC per WETH in {}", price, swap.transaction_hash);
+        assert!((202_000..203_500).contains(&swap.tick), "{:?}", swap);
+    }
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 66 && hash.starts_with("0x") && hash[2..].chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}